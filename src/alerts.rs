@@ -0,0 +1,489 @@
+use crate::{cancel::CancelToken, notifications::Notification};
+use chrono::{DateTime, Utc};
+use ring::hmac;
+use serde_json::Value;
+use std::{
+    collections::VecDeque,
+    io::{BufRead, BufReader, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::Sender,
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+// a request not sent within this time is dropped
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+// larger bodies are refused, the events are a few kilobytes
+const MAX_BODY: usize = 64 * 1024;
+// longer request or header lines are refused, and requests with more headers
+const MAX_LINE: usize = 8 * 1024;
+const MAX_HEADERS: usize = 64;
+// requests read at the same time, the next connections are closed at once
+const MAX_CONNECTIONS: usize = 16;
+// twitch messages older than this are refused, a captured message can't be replayed later
+const MAX_MESSAGE_AGE: chrono::TimeDelta = chrono::TimeDelta::minutes(10);
+// how often the cancellation is checked while no request comes
+const ACCEPT_POLL: Duration = Duration::from_millis(100);
+// twitch sends a message again when it is not acknowledged in time, the same alert is shown once
+const SEEN_MESSAGES: usize = 32;
+
+struct Request {
+    method: String,
+    headers: Vec<(String, String)>, // names in lowercase
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(x, _)| x == name)
+            .map(|(_, x)| x.as_str())
+    }
+}
+
+// what is answered to a request, and the alert it gives
+struct Response {
+    status: &'static str,
+    body: String,
+    alert: Option<(String, String)>, // title and message
+}
+
+impl Response {
+    fn empty(status: &'static str) -> Response {
+        Response {
+            status,
+            body: String::new(),
+            alert: None,
+        }
+    }
+}
+
+// the port is taken before playing, so that a port in use is reported at once. Without a secret,
+// anybody could post alerts: they are only received from this host
+pub fn listen(port: u16, secret: &Option<String>) -> Result<TcpListener, String> {
+    let address = match secret {
+        Some(_) => "0.0.0.0",
+        None => "127.0.0.1",
+    };
+    TcpListener::bind((address, port))
+        .map_err(|e| format!("Unable to receive the alerts on port {}: {}", port, e))
+}
+
+// a line of at most MAX_LINE bytes
+fn read_line<R: BufRead>(reader: &mut R, line: &mut String) -> Result<(), String> {
+    line.clear();
+    reader
+        .take(MAX_LINE as u64)
+        .read_line(line)
+        .map_err(|e| e.to_string())?;
+    match line.ends_with('\n') {
+        true => Ok(()),
+        false => Err(String::from("Line too long or incomplete")),
+    }
+}
+
+fn read_request(stream: &TcpStream) -> Result<Request, String> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    read_line(&mut reader, &mut line)?;
+    let method = line
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_string();
+
+    let mut headers = Vec::new();
+    loop {
+        read_line(&mut reader, &mut line)?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if headers.len() == MAX_HEADERS {
+            return Err(String::from("Too many headers"));
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            headers.push((name.trim().to_lowercase(), value.trim().to_string()));
+        }
+    }
+
+    let length = headers
+        .iter()
+        .find(|(x, _)| x == "content-length")
+        .and_then(|(_, x)| x.parse::<usize>().ok())
+        .unwrap_or(0);
+    if length > MAX_BODY {
+        return Err(String::from("Request too large"));
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).map_err(|e| e.to_string())?;
+    Ok(Request {
+        method,
+        headers,
+        body,
+    })
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|n| u8::from_str_radix(text.get(n..n + 2)?, 16).ok())
+        .collect()
+}
+
+// the twitch signature (hmac-sha256 of the message id, the timestamp and the body) of a recent
+// message, or the secret as a bearer token, for the other webhooks
+fn is_authorized(request: &Request, secret: &str, now: DateTime<Utc>) -> bool {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    // the token is compared through its signature, in a time not depending on where it differs
+    if let Some(token) = request
+        .header("authorization")
+        .and_then(|x| x.strip_prefix("Bearer "))
+    {
+        return hmac::verify(
+            &key,
+            token.as_bytes(),
+            hmac::sign(&key, secret.as_bytes()).as_ref(),
+        )
+        .is_ok();
+    }
+    let (Some(id), Some(timestamp), Some(signature)) = (
+        request.header("twitch-eventsub-message-id"),
+        request.header("twitch-eventsub-message-timestamp"),
+        request.header("twitch-eventsub-message-signature"),
+    ) else {
+        return false;
+    };
+    let Some(signature) = signature.strip_prefix("sha256=").and_then(decode_hex) else {
+        return false;
+    };
+    match DateTime::parse_from_rfc3339(timestamp) {
+        Ok(x) if now.signed_duration_since(x) <= MAX_MESSAGE_AGE => (),
+        _ => return false,
+    }
+    let message = [id.as_bytes(), timestamp.as_bytes(), &request.body].concat();
+    hmac::verify(&key, &message, &signature).is_ok()
+}
+
+fn text(value: &Value, name: &str) -> String {
+    match value.get(name) {
+        Some(Value::String(x)) => x.clone(),
+        Some(Value::Null) | None => String::new(),
+        Some(x) => x.to_string(),
+    }
+}
+
+// the name of the user, anonymous for the anonymous gifts and cheers
+fn user(event: &Value) -> String {
+    match event.get("is_anonymous").and_then(|x| x.as_bool()) {
+        Some(true) => String::from("anonymous"),
+        _ => text(event, "user_name"),
+    }
+}
+
+// the title and the message of a twitch eventsub notification
+fn twitch_alert(value: &Value) -> (String, String) {
+    let kind = value
+        .get("subscription")
+        .map(|x| text(x, "type"))
+        .unwrap_or_default();
+    let event = value.get("event").cloned().unwrap_or_default();
+    match kind.as_str() {
+        "channel.follow" => (String::from("NEW FOLLOWER"), user(&event)),
+        "channel.subscribe" => (String::from("NEW SUB"), user(&event)),
+        "channel.subscription.message" => (
+            String::from("RESUB"),
+            format!(
+                "{} {} months",
+                user(&event),
+                text(&event, "cumulative_months")
+            ),
+        ),
+        "channel.subscription.gift" => (
+            String::from("GIFT SUBS"),
+            format!("{} x{}", user(&event), text(&event, "total")),
+        ),
+        "channel.cheer" => (format!("{} BITS", text(&event, "bits")), user(&event)),
+        "channel.raid" => (
+            String::from("RAID"),
+            format!(
+                "{} +{}",
+                text(&event, "from_broadcaster_user_name"),
+                text(&event, "viewers")
+            ),
+        ),
+        _ => (kind, user(&event)),
+    }
+}
+
+fn handle_request(request: &Request, secret: &Option<String>, now: DateTime<Utc>) -> Response {
+    if request.method != "POST" {
+        return Response::empty("405 Method Not Allowed");
+    }
+    if let Some(secret) = secret
+        && !is_authorized(request, secret, now)
+    {
+        return Response::empty("403 Forbidden");
+    }
+    let value: Value = match serde_json::from_slice(&request.body) {
+        Ok(x) => x,
+        Err(_) => return Response::empty("400 Bad Request"),
+    };
+
+    match request.header("twitch-eventsub-message-type") {
+        // the subscription is confirmed by sending the challenge back
+        Some("webhook_callback_verification") => Response {
+            status: "200 OK",
+            body: text(&value, "challenge"),
+            alert: None,
+        },
+        Some("notification") => Response {
+            status: "204 No Content",
+            body: String::new(),
+            alert: Some(twitch_alert(&value)),
+        },
+        Some(_) => Response::empty("204 No Content"),
+        // the other webhooks: {"title": "NEW FOLLOWER", "message": "somebody"}
+        None => {
+            let (title, message) = (text(&value, "title"), text(&value, "message"));
+            match title.is_empty() && message.is_empty() {
+                true => Response::empty("400 Bad Request"),
+                false => Response {
+                    status: "204 No Content",
+                    body: String::new(),
+                    alert: Some((title, message)),
+                },
+            }
+        }
+    }
+}
+
+// reads a request and answers it, the alert is sent unless the message was already received.
+// False once the alerts aren't received anymore
+fn handle_connection(
+    mut stream: TcpStream,
+    secret: &Option<String>,
+    seen: &Mutex<VecDeque<String>>,
+    alerts: &Sender<Notification>,
+) -> bool {
+    let _ = stream.set_read_timeout(Some(READ_TIMEOUT));
+    let request = match read_request(&stream) {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("Invalid alert request: {}", e);
+            return true;
+        }
+    };
+    let response = handle_request(&request, secret, Utc::now());
+    let answer = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        response.body.len(),
+        response.body
+    );
+    let _ = stream.write_all(answer.as_bytes());
+
+    let Some((summary, body)) = response.alert else {
+        return true;
+    };
+    if let Some(id) = request.header("twitch-eventsub-message-id") {
+        let mut seen = seen.lock().unwrap();
+        if seen.contains(&id.to_string()) {
+            return true;
+        }
+        seen.push_back(id.to_string());
+        if seen.len() > SEEN_MESSAGES {
+            seen.pop_front();
+        }
+    }
+    let alert = Notification {
+        summary,
+        body,
+        icon: None,
+        timeout: None,
+        flash: true,
+    };
+    alerts.send(alert).is_ok()
+}
+
+// the alerts received by the webhook, each request read in its own thread so that a slow client
+// doesn't delay the others, until nothing is played anymore
+pub fn serve(
+    listener: TcpListener,
+    secret: Option<String>,
+    alerts: Sender<Notification>,
+    cancel: &CancelToken,
+) {
+    // not blocked in accept, to stop with the playing
+    if let Err(e) = listener.set_nonblocking(true) {
+        eprintln!("Unable to receive the alerts: {}", e);
+        return;
+    }
+    let secret = Arc::new(secret);
+    let seen = Arc::new(Mutex::new(VecDeque::new()));
+    let connections = Arc::new(AtomicUsize::new(0));
+    let closed = Arc::new(AtomicBool::new(false));
+    while !closed.load(Ordering::Relaxed) {
+        let stream = match listener.accept() {
+            Ok((x, _)) => x,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                if cancel.sleep(ACCEPT_POLL) {
+                    return;
+                }
+                continue;
+            }
+            Err(_) => continue,
+        };
+        if stream.set_nonblocking(false).is_err() {
+            continue;
+        }
+        if connections.load(Ordering::Relaxed) >= MAX_CONNECTIONS {
+            continue;
+        }
+        connections.fetch_add(1, Ordering::Relaxed);
+        let (secret, seen, connections, closed, alerts) = (
+            secret.clone(),
+            seen.clone(),
+            connections.clone(),
+            closed.clone(),
+            alerts.clone(),
+        );
+        thread::spawn(move || {
+            if !handle_connection(stream, &secret, &seen, &alerts) {
+                closed.store(true, Ordering::Relaxed);
+            }
+            connections.fetch_sub(1, Ordering::Relaxed);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(headers: &[(&str, &str)], body: &str) -> Request {
+        Request {
+            method: String::from("POST"),
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn twitch() {
+        let body = r#"{"subscription": {"type": "channel.raid"}, "event": {"from_broadcaster_user_name": "Pinball", "viewers": 42}}"#;
+        let timestamp = "2024-01-01T12:00:00.123456789Z";
+        let now = DateTime::parse_from_rfc3339("2024-01-01T12:05:00Z")
+            .unwrap()
+            .to_utc();
+        let key = hmac::Key::new(hmac::HMAC_SHA256, b"secret");
+        let signature = hmac::sign(&key, format!("id1{}{}", timestamp, body).as_bytes());
+        let signature: String = signature
+            .as_ref()
+            .iter()
+            .map(|x| format!("{:02x}", x))
+            .collect();
+        let mut headers = vec![
+            ("twitch-eventsub-message-id", "id1"),
+            ("twitch-eventsub-message-timestamp", timestamp),
+            ("twitch-eventsub-message-type", "notification"),
+        ];
+        let secret = Some(String::from("secret"));
+        assert_eq!(
+            handle_request(&request(&headers, body), &secret, now).status,
+            "403 Forbidden"
+        );
+
+        let signature = format!("sha256={}", signature);
+        headers.push(("twitch-eventsub-message-signature", &signature));
+        let response = handle_request(&request(&headers, body), &secret, now);
+        assert_eq!(response.status, "204 No Content");
+        assert_eq!(
+            response.alert,
+            Some((String::from("RAID"), String::from("Pinball +42")))
+        );
+
+        // the same message, replayed later
+        let later = now + chrono::TimeDelta::minutes(10);
+        assert_eq!(
+            handle_request(&request(&headers, body), &secret, later).status,
+            "403 Forbidden"
+        );
+
+        let challenge = request(
+            &[(
+                "twitch-eventsub-message-type",
+                "webhook_callback_verification",
+            )],
+            r#"{"challenge": "pogchamp-kappa-360noscope-vohiyo"}"#,
+        );
+        assert_eq!(
+            handle_request(&challenge, &None, now).body,
+            "pogchamp-kappa-360noscope-vohiyo"
+        );
+    }
+
+    #[test]
+    fn generic() {
+        let body = r#"{"title": "NEW FOLLOWER", "message": "somebody"}"#;
+        let secret = Some(String::from("secret"));
+        let response = handle_request(
+            &request(&[("authorization", "Bearer secret")], body),
+            &secret,
+            Utc::now(),
+        );
+        assert_eq!(
+            response.alert,
+            Some((String::from("NEW FOLLOWER"), String::from("somebody")))
+        );
+        for token in ["Bearer secre", "Bearer secret2", "Bearer", "secret"] {
+            let response = handle_request(
+                &request(&[("authorization", token)], body),
+                &secret,
+                Utc::now(),
+            );
+            assert_eq!(response.status, "403 Forbidden");
+        }
+        assert_eq!(
+            handle_request(&request(&[], "{}"), &None, Utc::now()).status,
+            "400 Bad Request"
+        );
+    }
+
+    #[test]
+    fn request_limits() {
+        let read = |request: String| {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let address = listener.local_addr().unwrap();
+            let client = thread::spawn(move || {
+                let mut stream = TcpStream::connect(address).unwrap();
+                let _ = stream.write_all(request.as_bytes());
+            });
+            let (stream, _) = listener.accept().unwrap();
+            client.join().unwrap();
+            read_request(&stream).map(|x| x.body)
+        };
+        let body = "{}";
+        let request = format!("POST / HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}", body);
+        assert_eq!(read(request), Ok(body.as_bytes().to_vec()));
+
+        let long = format!("POST / HTTP/1.1\r\nX: {}\r\n\r\n", "a".repeat(MAX_LINE));
+        assert!(read(long).is_err());
+        let many = format!(
+            "POST / HTTP/1.1\r\n{}\r\n",
+            "X: a\r\n".repeat(MAX_HEADERS + 1)
+        );
+        assert!(read(many).is_err());
+    }
+}
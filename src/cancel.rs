@@ -0,0 +1,109 @@
+use signal_hook::{
+    consts::{SIGINT, SIGTERM},
+    iterator::Signals,
+};
+use std::{
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+// time given to the playing to stop by itself, before dmd-play exits anyway
+const GRACE: Duration = Duration::from_secs(2);
+// longest delay before a sleeping loop sees it is cancelled
+const STEP: Duration = Duration::from_millis(50);
+
+// set once the playing has to stop: the long-running loops it is given to return as if they were
+// done, so that what follows the playing (preview, status, statistics) is done like at the end.
+// the clones share the flag, the next playing gets a new token
+#[derive(Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+    parent: Option<Arc<CancelToken>>,
+}
+
+impl CancelToken {
+    // a token cancelled with this one, or by itself for a part of the playing
+    pub fn child(&self) -> CancelToken {
+        CancelToken {
+            cancelled: Arc::default(),
+            parent: Some(Arc::new(self.clone())),
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+            || self.parent.as_ref().is_some_and(|x| x.is_cancelled())
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    // sleep like thread::sleep, returns true (and sooner) when cancelled meanwhile
+    pub fn sleep(&self, duration: Duration) -> bool {
+        let end = Instant::now() + duration;
+        loop {
+            if self.is_cancelled() {
+                return true;
+            }
+            let now = Instant::now();
+            if now >= end {
+                return false;
+            }
+            thread::sleep(STEP.min(end - now));
+        }
+    }
+}
+
+// --duration: the playing is cancelled like by a signal once the time is over
+pub fn cancel_after(cancel: &CancelToken, duration: Duration) {
+    let cancel = cancel.clone();
+    thread::spawn(move || {
+        if !cancel.sleep(duration) {
+            cancel.cancel();
+            thread::sleep(GRACE);
+            process::exit(0);
+        }
+    });
+}
+
+// SIGINT and SIGTERM cancel the playing, the loops that do not stop are ended after a grace time
+pub fn handle_signals(cancel: &CancelToken) -> Result<(), String> {
+    let mut signals = match Signals::new([SIGINT, SIGTERM]) {
+        Ok(x) => x,
+        Err(e) => return Err(format!("Unable to handle the signals: {}", e)),
+    };
+    let cancel = cancel.clone();
+    thread::spawn(move || {
+        if let Some(signal) = signals.forever().next() {
+            cancel.cancel();
+            thread::sleep(GRACE);
+            process::exit(128 + signal);
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a child is cancelled with its parent, not the other way round
+    #[test]
+    fn child() {
+        let parent = CancelToken::default();
+        let child = parent.child();
+        child.cancel();
+        assert!(!parent.is_cancelled());
+
+        let child = parent.child();
+        parent.cancel();
+        assert!(child.is_cancelled());
+        assert!(child.clone().sleep(Duration::from_secs(10)));
+    }
+}
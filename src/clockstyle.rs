@@ -0,0 +1,386 @@
+use crate::pixelfont;
+use image::{Rgba, RgbaImage};
+
+// the flip of the cards whose digit changed, frames of FLIP_FRAME_DURATION ms
+pub const FLIP_FRAMES: u32 = 8;
+pub const FLIP_FRAME_DURATION: u32 = 40;
+
+// the segments a to g of the digits: top, top right, bottom right, bottom, bottom left, top left, middle
+const SEGMENTS: [u8; 10] = [0x3f, 0x06, 0x5b, 0x4f, 0x66, 0x6d, 0x7d, 0x07, 0x7f, 0x6f];
+const GHOST: f32 = 0.12; // unlit segments, and the nixie tubes
+const GLOW: f32 = 0.35; // around the lit nixie cathodes
+const CARD: Rgba<u8> = Rgba([40, 40, 40, 255]);
+
+// digits drawn like a hardware clock instead of with the font, or the time written in words
+pub enum ClockStyle {
+    SEGMENTS,
+    NIXIE,
+    FLIP,
+    BINARY, // a column of 4 bits per digit
+    WORDS,  // "IT IS TEN PAST FIVE", rendered with the font like a text
+}
+
+pub fn parse_clock_style(style: &str) -> Result<ClockStyle, String> {
+    match style {
+        "7seg" => Ok(ClockStyle::SEGMENTS),
+        "nixie" => Ok(ClockStyle::NIXIE),
+        "flip" => Ok(ClockStyle::FLIP),
+        "binary" => Ok(ClockStyle::BINARY),
+        "words" => Ok(ClockStyle::WORDS),
+        _ => Err(String::from(
+            "Invalid clock style value (7seg, nixie, flip, binary or words)",
+        )),
+    }
+}
+
+// a character of the clock, drawn from x, width pixels wide
+struct Cell {
+    c: char,
+    x: i32,
+    width: i32,
+}
+
+// the cells of the text centered on the image, their top and height
+fn layout(text: &str, width: u32, height: u32) -> (Vec<Cell>, i32, i32) {
+    let margin = height as i32 / 16;
+    let cell_height = (height as i32 - 2 * margin).max(1);
+    let gap = (cell_height / 12).max(1);
+    let cell_width = |c: char| match c {
+        ':' | '.' => (cell_height / 5).max(2),
+        ' ' => (cell_height * 5 / 18).max(1),
+        _ => (cell_height * 5 / 9).max(3),
+    };
+    let widths: Vec<i32> = text.chars().map(cell_width).collect();
+    let total = widths.iter().sum::<i32>() + gap * (widths.len() as i32 - 1).max(0);
+    // narrower cells when the text is wider than the dmd
+    let (scale, total) = match total > width as i32 {
+        true => (width as f32 / total as f32, width as i32),
+        false => (1.0, total),
+    };
+
+    let mut x = (width as i32 - total) / 2;
+    let mut cells = Vec::new();
+    for (c, cell_width) in text.chars().zip(widths) {
+        let cell_width = ((cell_width as f32 * scale) as i32).max(1);
+        cells.push(Cell {
+            c,
+            x,
+            width: cell_width,
+        });
+        x += cell_width + ((gap as f32 * scale) as i32);
+    }
+    (cells, margin, cell_height)
+}
+
+// the color over the pixel, level 1.0 covering it
+fn mix(img: &mut RgbaImage, x: i32, y: i32, color: Rgba<u8>, level: f32) {
+    if x < 0 || y < 0 || x >= img.width() as i32 || y >= img.height() as i32 {
+        return;
+    }
+    let pixel = img.get_pixel_mut(x as u32, y as u32);
+    for c in 0..3 {
+        pixel[c] = (pixel[c] as f32 * (1.0 - level) + color[c] as f32 * level).round() as u8;
+    }
+}
+
+fn fill(img: &mut RgbaImage, x: i32, y: i32, width: i32, height: i32, color: Rgba<u8>, level: f32) {
+    for y in y..y + height {
+        for x in x..x + width {
+            mix(img, x, y, color, level);
+        }
+    }
+}
+
+// the lit pixels of the 5x7 glyph scaled to the size
+fn glyph_mask(c: char, width: i32, height: i32) -> Vec<bool> {
+    let columns = pixelfont::columns(c).unwrap_or(&[0; 5]);
+    (0..height * width)
+        .map(|i| {
+            let (x, y) = (i % width, i / width);
+            columns[(x * 5 / width) as usize] & (1 << (y * 7 / height)) != 0
+        })
+        .collect()
+}
+
+fn draw_mask(img: &mut RgbaImage, mask: &[bool], x: i32, y: i32, width: i32, color: Rgba<u8>) {
+    for (i, _) in mask.iter().enumerate().filter(|(_, lit)| **lit) {
+        mix(img, x + i as i32 % width, y + i as i32 / width, color, 1.0);
+    }
+}
+
+fn draw_segments(
+    img: &mut RgbaImage,
+    digit: usize,
+    cell: &Cell,
+    top: i32,
+    height: i32,
+    color: Rgba<u8>,
+) {
+    let (x, width) = (cell.x, cell.width);
+    let thickness = (width.min(height) / 5).max(1);
+    let upper = (height - 3 * thickness) / 2;
+    let lower = height - 3 * thickness - upper;
+    let segments = [
+        (x + thickness, top, width - 2 * thickness, thickness),
+        (x + width - thickness, top + thickness, thickness, upper),
+        (
+            x + width - thickness,
+            top + 2 * thickness + upper,
+            thickness,
+            lower,
+        ),
+        (
+            x + thickness,
+            top + height - thickness,
+            width - 2 * thickness,
+            thickness,
+        ),
+        (x, top + 2 * thickness + upper, thickness, lower),
+        (x, top + thickness, thickness, upper),
+        (
+            x + thickness,
+            top + thickness + upper,
+            width - 2 * thickness,
+            thickness,
+        ),
+    ];
+    for (n, (x, y, width, height)) in segments.into_iter().enumerate() {
+        let level = match SEGMENTS[digit] & (1 << n) != 0 {
+            true => 1.0,
+            false => GHOST,
+        };
+        fill(img, x, y, width, height, color, level);
+    }
+}
+
+fn draw_nixie(img: &mut RgbaImage, c: char, cell: &Cell, top: i32, height: i32, color: Rgba<u8>) {
+    let (x, width) = (cell.x, cell.width);
+    // the glass of the tube
+    fill(img, x, top, width, 1, color, GHOST);
+    fill(img, x, top + height - 1, width, 1, color, GHOST);
+    fill(img, x, top + 1, 1, height - 2, color, GHOST);
+    fill(img, x + width - 1, top + 1, 1, height - 2, color, GHOST);
+
+    let (inner_width, inner_height) = ((width - 4).max(1), (height - 4).max(1));
+    let mask = glyph_mask(c, inner_width, inner_height);
+    let lit = |x: i32, y: i32| {
+        (0..inner_width).contains(&x)
+            && (0..inner_height).contains(&y)
+            && mask[(y * inner_width + x) as usize]
+    };
+    for y in -1..=inner_height {
+        for x in -1..=inner_width {
+            let glow = (-1..=1).any(|dy| (-1..=1).any(|dx| lit(x + dx, y + dy)));
+            if glow && !lit(x, y) {
+                mix(img, cell.x + 2 + x, top + 2 + y, color, GLOW);
+            }
+        }
+    }
+    draw_mask(img, &mask, cell.x + 2, top + 2, inner_width, color);
+}
+
+fn draw_card(img: &mut RgbaImage, c: char, cell: &Cell, top: i32, height: i32, color: Rgba<u8>) {
+    let (x, width) = (cell.x, cell.width);
+    fill(img, x, top, width, height, CARD, 1.0);
+    let margin = (width / 8).max(1);
+    let (inner_width, inner_height) = ((width - 2 * margin).max(1), (height - 2 * margin).max(1));
+    let mask = glyph_mask(c, inner_width, inner_height);
+    draw_mask(img, &mask, x + margin, top + margin, inner_width, color);
+    // the hinge between the two flaps
+    fill(
+        img,
+        x,
+        top + height / 2,
+        width,
+        1,
+        Rgba([0, 0, 0, 255]),
+        1.0,
+    );
+}
+
+const HOURS: [&str; 12] = [
+    "TWELVE", "ONE", "TWO", "THREE", "FOUR", "FIVE", "SIX", "SEVEN", "EIGHT", "NINE", "TEN",
+    "ELEVEN",
+];
+
+// the time of a word clock, to the five minutes below, on two lines ("\\n" like the texts)
+pub fn time_in_words(hour: u32, minute: u32) -> String {
+    let minutes = match minute / 5 {
+        1 | 11 => "FIVE",
+        2 | 10 => "TEN",
+        3 | 9 => "QUARTER",
+        4 | 8 => "TWENTY",
+        5 | 7 => "TWENTY FIVE",
+        6 => "HALF",
+        _ => "",
+    };
+    match minute / 5 {
+        0 => format!("IT IS\\n{} O'CLOCK", HOURS[hour as usize % 12]),
+        1..=6 => format!("IT IS {}\\nPAST {}", minutes, HOURS[hour as usize % 12]),
+        _ => format!("IT IS {}\\nTO {}", minutes, HOURS[(hour as usize + 1) % 12]),
+    }
+}
+
+// the digits of the time as columns of bits (8 at the top), the groups of digits (hours, minutes
+// and seconds) apart
+fn render_binary(text: &str, img: &mut RgbaImage, color: Rgba<u8>) {
+    let groups: Vec<&str> = text
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|x| !x.is_empty())
+        .collect();
+    let columns = groups.iter().map(|x| x.len()).sum::<usize>() as i32;
+    if columns == 0 {
+        return;
+    }
+    // a half column between the groups
+    let (width, height) = (img.width() as i32, img.height() as i32);
+    let units = 2 * columns + groups.len() as i32 - 1;
+    let pitch = (height / 4).min(2 * width / units).max(1);
+    let size = (pitch * 3 / 4).max(1);
+    let mut x = (width - pitch * units / 2) / 2 + (pitch - size) / 2;
+    let top = (height - pitch * 4) / 2 + (pitch - size) / 2;
+
+    for group in groups {
+        for digit in group.chars().filter_map(|c| c.to_digit(10)) {
+            for bit in 0..4 {
+                let level = match digit & (8 >> bit) != 0 {
+                    true => 1.0,
+                    false => GHOST,
+                };
+                fill(img, x, top + bit * pitch, size, size, color, level);
+            }
+            x += pitch;
+        }
+        x += pitch / 2;
+    }
+}
+
+// the text (a time) drawn in the style over the background
+pub fn render_clock(
+    style: &ClockStyle,
+    text: &str,
+    background: &RgbaImage,
+    color: Rgba<u8>,
+) -> RgbaImage {
+    let mut img = background.clone();
+    if let ClockStyle::BINARY = style {
+        render_binary(text, &mut img, color);
+        return img;
+    }
+    let (cells, top, height) = layout(text, img.width(), img.height());
+    for cell in &cells {
+        match (cell.c, style) {
+            (' ', _) => {}
+            (':', _) | ('.', _) => {
+                let size = cell.width.min(height / 7).max(1);
+                let x = cell.x + (cell.width - size) / 2;
+                if cell.c == ':' {
+                    fill(
+                        &mut img,
+                        x,
+                        top + height / 3 - size / 2,
+                        size,
+                        size,
+                        color,
+                        1.0,
+                    );
+                }
+                fill(
+                    &mut img,
+                    x,
+                    top + height * 2 / 3 - size / 2,
+                    size,
+                    size,
+                    color,
+                    1.0,
+                );
+            }
+            ('0'..='9', ClockStyle::SEGMENTS) => {
+                let digit = cell.c as usize - '0' as usize;
+                draw_segments(&mut img, digit, cell, top, height, color);
+            }
+            ('0'..='9', ClockStyle::NIXIE) => {
+                draw_nixie(&mut img, cell.c, cell, top, height, color)
+            }
+            ('0'..='9', ClockStyle::FLIP) => draw_card(&mut img, cell.c, cell, top, height, color),
+            _ => {
+                let mask = glyph_mask(cell.c, cell.width, height);
+                draw_mask(&mut img, &mask, cell.x, top, cell.width, color);
+            }
+        }
+    }
+    img
+}
+
+// the cards of the characters changed from the previous text, flipped at progress (0.0 to 1.0):
+// the top flap of the previous one falls on the middle, then the bottom flap of the new one unfolds
+pub fn flip_frame(
+    previous: &str,
+    text: &str,
+    progress: f32,
+    background: &RgbaImage,
+    color: Rgba<u8>,
+) -> RgbaImage {
+    let old = render_clock(&ClockStyle::FLIP, previous, background, color);
+    let new = render_clock(&ClockStyle::FLIP, text, background, color);
+    let mut img = new.clone();
+    let (cells, top, height) = layout(text, img.width(), img.height());
+    let (previous_cells, _, _) = layout(previous, img.width(), img.height());
+    let middle = top + height / 2;
+
+    for (cell, previous_cell) in cells.iter().zip(previous_cells.iter()) {
+        if cell.c == previous_cell.c
+            || cell.x != previous_cell.x
+            || cell.width != previous_cell.width
+        {
+            continue;
+        }
+        for y in top..top + height {
+            // the row of the old (or new) card seen at y, none for the new one behind
+            let source = match progress < 0.5 {
+                true if y < middle => {
+                    let scale = 1.0 - 2.0 * progress;
+                    let from = middle as f32 - (middle - y) as f32 / scale.max(0.001);
+                    (from >= top as f32).then_some((&old, from as i32))
+                }
+                true => Some((&old, y)),
+                false if y >= middle => {
+                    let scale = 2.0 * progress - 1.0;
+                    let from = middle as f32 + (y - middle) as f32 / scale.max(0.001);
+                    match from < (top + height) as f32 {
+                        true => Some((&new, from as i32)),
+                        false => Some((&old, y)),
+                    }
+                }
+                false => None,
+            };
+            let Some((source, from)) = source else {
+                continue;
+            };
+            let row: Vec<Rgba<u8>> = (cell.x..cell.x + cell.width)
+                .filter(|x| (0..img.width() as i32).contains(x))
+                .map(|x| *source.get_pixel(x as u32, from.clamp(0, img.height() as i32 - 1) as u32))
+                .collect();
+            for (x, pixel) in (cell.x.max(0)..).zip(row) {
+                if y >= 0 && y < img.height() as i32 {
+                    img.put_pixel(x as u32, y as u32, pixel);
+                }
+            }
+        }
+    }
+    img
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn words() {
+        assert_eq!(time_in_words(17, 0), "IT IS\\nFIVE O'CLOCK");
+        assert_eq!(time_in_words(17, 12), "IT IS TEN\\nPAST FIVE");
+        assert_eq!(time_in_words(0, 30), "IT IS HALF\\nPAST TWELVE");
+        assert_eq!(time_in_words(11, 45), "IT IS QUARTER\\nTO TWELVE");
+        assert_eq!(time_in_words(23, 59), "IT IS FIVE\\nTO TWELVE");
+    }
+}
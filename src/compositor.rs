@@ -0,0 +1,207 @@
+use crate::{clock_text, imageutils, rss, send_frame, ClockOptions, Dmd, TextOptions};
+use chrono::Local;
+use image::{DynamicImage, Frame, Rgba, RgbaImage};
+use std::time::{Duration, Instant};
+
+// what is displayed behind the ticker
+pub enum BaseLayer {
+    CLOCK,
+    IMAGE(Vec<Frame>),
+}
+
+// where the text of the ticker comes from
+pub enum TickerSource {
+    TEXT(String),
+    RSS(String),
+}
+
+// the scrolling text at the bottom of the dmd
+pub struct Ticker {
+    pub source: TickerSource,
+    pub height: u32, // pixels
+    pub color: Rgba<u8>,
+    pub refresh: u64, // seconds between two fetches of a feed
+}
+
+fn ticker_text(ticker: &TickerSource) -> Result<String, String> {
+    match ticker {
+        TickerSource::TEXT(text) => Ok(text.replace("\\n", " ")),
+        TickerSource::RSS(url) => Ok(rss::fetch_titles(url)?.join(" - ")),
+    }
+}
+
+// render the whole ticker text on a single line, returns the image, the start point and the width
+fn render_ticker(
+    text: &str,
+    style: &imageutils::TextStyle,
+    height: u32,
+) -> Result<(DynamicImage, u32, u32), String> {
+    let text_ratio = imageutils::get_text_ratio(text, style, height);
+    let width = ((height as f32 * text_ratio) as u32).max(1);
+
+    imageutils::generate_text_image(text, style, &None, width, height)
+}
+
+// base images are prepared once, at the dmd size (already oriented by fit_image)
+fn base_images(
+    frames: &[Frame],
+    image_options: &imageutils::ImageOptions,
+    dmd_width: u32,
+    dmd_height: u32,
+) -> Result<Vec<(RgbaImage, u32)>, String> {
+    let black = RgbaImage::from_pixel(dmd_width, dmd_height, Rgba([0, 0, 0, 255]));
+    let mut images = Vec::new();
+
+    for frame in frames {
+        let (x, y) = frame.delay().numer_denom_ms();
+        let duration = (x as f32 / y as f32) as u32;
+        let fit_img = imageutils::fit_image(frame.buffer(), image_options, dmd_width, dmd_height)?;
+        let dmd_img = imageutils::image2dmdsize(
+            &fit_img,
+            &imageutils::TextAlign::CENTER,
+            dmd_width,
+            dmd_height,
+        );
+        images.push((imageutils::apply_background(&dmd_img, &black), duration));
+    }
+    Ok(images)
+}
+
+// pick the frame of an animated base according to the elapsed time
+fn base_image_at(images: &[(RgbaImage, u32)], elapsed: Duration) -> &RgbaImage {
+    let total: u64 = images.iter().map(|(_, duration)| *duration as u64).sum();
+    if total == 0 {
+        return &images[0].0;
+    }
+
+    let mut position = elapsed.as_millis() as u64 % total;
+    for (img, duration) in images {
+        if position < *duration as u64 {
+            return img;
+        }
+        position -= *duration as u64;
+    }
+    &images[0].0
+}
+
+// blend a scrolling ticker over a base layer (clock or image) and send the result as a single stream
+pub fn play_layers(
+    dmd: &Dmd,
+    base: &BaseLayer,
+    ticker: &Ticker,
+    options: &TextOptions,
+    clock: &ClockOptions,
+    once: bool,
+) -> Result<(), String> {
+    let (dmd_width, dmd_height, image_options) = (dmd.width, dmd.height, dmd.image_options);
+    let orientation = &image_options.orientation;
+    let (render_width, render_height) =
+        imageutils::oriented_size(orientation, dmd_width, dmd_height);
+    let ticker_height = ticker.height.min(render_height);
+    if ticker_height == 0 {
+        return Err(String::from("Invalid ticker height"));
+    }
+    // a single line, from the left
+    let ticker_style = imageutils::TextStyle {
+        color: ticker.color,
+        align: imageutils::TextAlign::LEFT,
+        line_spacing: 0,
+        ..options.style.clone()
+    };
+
+    let base_images = match base {
+        BaseLayer::CLOCK => Vec::new(),
+        BaseLayer::IMAGE(frames) => base_images(frames, image_options, dmd_width, dmd_height)?,
+    };
+    if matches!(base, BaseLayer::IMAGE(_)) && base_images.is_empty() {
+        return Err(String::from("Missing base image"));
+    }
+
+    let mut text = ticker_text(&ticker.source)?;
+    let mut last_refresh = Instant::now();
+    let (mut strip, mut start, mut strip_width) =
+        render_ticker(&text, &ticker_style, ticker_height)?;
+
+    let started = Instant::now();
+    let mut previous_clock = String::new();
+    let mut clock_img = RgbaImage::new(dmd_width, dmd_height);
+
+    loop {
+        let mut x = render_width as i32;
+        while x > -(strip_width as i32) {
+            let base_img = match base {
+                BaseLayer::CLOCK => {
+                    let localtime = clock_text(&Local::now(), clock);
+                    if localtime != previous_clock {
+                        let (dyn_img, _start, _new_width) = imageutils::generate_text_image(
+                            &localtime,
+                            &options.style,
+                            &options.gradient,
+                            render_width,
+                            render_height,
+                        )?;
+                        let rgba_img =
+                            imageutils::apply_background(&dyn_img.to_rgba8(), &options.background);
+                        clock_img = imageutils::orient_image(&rgba_img, orientation);
+                        previous_clock = localtime;
+                    }
+                    &clock_img
+                }
+                BaseLayer::IMAGE(_) => base_image_at(&base_images, started.elapsed()),
+            };
+
+            // the ticker runs at the bottom of the dmd
+            let mut layer = RgbaImage::new(render_width, render_height);
+            imageutils::copy_image(
+                &strip,
+                &mut layer,
+                x - start as i32,
+                (render_height - ticker_height) as i32,
+            );
+            let layer = imageutils::orient_image(&layer, orientation);
+            let frame = imageutils::apply_background(&layer, base_img);
+
+            let img565 = imageutils::image2dmdimage(
+                &frame,
+                &imageutils::TextAlign::CENTER,
+                &image_options.colors,
+                dmd_width,
+                dmd_height,
+            )?;
+            match send_frame(dmd.client, dmd.header, &img565) {
+                Ok(_) => {}
+                Err(e) => {
+                    return Err(e.to_string());
+                }
+            };
+
+            if dmd.cancel.sleep(Duration::from_millis(
+                (options.speed as f32 / dmd.rate) as u64,
+            )) {
+                return Ok(());
+            }
+            x -= 1;
+        }
+
+        if once {
+            return Ok(());
+        }
+
+        // feeds are fetched again between two passes only, so the text never jumps
+        if matches!(ticker.source, TickerSource::RSS(_))
+            && last_refresh.elapsed() >= Duration::from_secs(ticker.refresh)
+        {
+            match ticker_text(&ticker.source) {
+                Ok(x) => {
+                    text = x;
+                    (strip, start, strip_width) =
+                        render_ticker(&text, &ticker_style, ticker_height)?;
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                }
+            }
+            last_refresh = Instant::now();
+        }
+    }
+}
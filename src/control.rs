@@ -0,0 +1,80 @@
+use crate::cancel::CancelToken;
+use std::{
+    io::{stdin, BufRead},
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+// longest delay before a paused playing sees it is cancelled
+const PAUSE_POLL: Duration = Duration::from_millis(50);
+
+#[derive(Default)]
+struct PauseState {
+    paused: bool,
+    steps: u32, // frames shown while paused, by next-frame
+}
+
+// --control-stdin: the animations are paused, resumed or moved forward a frame at a time.
+// the clones share the state
+#[derive(Clone, Default)]
+pub struct PlayControl(Arc<(Mutex<PauseState>, Condvar)>);
+
+impl PlayControl {
+    // pause, resume or next-frame (the next frame is shown, then the animation is paused)
+    pub fn command(&self, command: &str) -> Result<(), String> {
+        let (state, changed) = &*self.0;
+        let mut state = match state.lock() {
+            Ok(x) => x,
+            Err(_) => return Err(String::from("Playback control lost")),
+        };
+        match command {
+            "pause" => state.paused = true,
+            "resume" => (state.paused, state.steps) = (false, 0),
+            "next-frame" => (state.paused, state.steps) = (true, state.steps + 1),
+            _ => return Err(format!("Invalid command: {}", command)),
+        }
+        changed.notify_all();
+        Ok(())
+    }
+
+    // wait while paused, until the next frame can be shown or the playing is cancelled.
+    // returns the time waited, by which the clock of the animation is delayed
+    pub fn wait(&self, cancel: &CancelToken) -> Duration {
+        let started = Instant::now();
+        let (state, changed) = &*self.0;
+        let mut state = match state.lock() {
+            Ok(x) => x,
+            Err(_) => return Duration::ZERO,
+        };
+        while state.paused && state.steps == 0 && !cancel.is_cancelled() {
+            state = match changed.wait_timeout(state, PAUSE_POLL) {
+                Ok((x, _)) => x,
+                Err(_) => return started.elapsed(),
+            };
+        }
+        if state.paused && state.steps > 0 {
+            state.steps -= 1;
+        }
+        started.elapsed()
+    }
+}
+
+// the commands read from stdin, one per line, until its end
+pub fn read_commands(control: &PlayControl) {
+    let control = control.clone();
+    thread::spawn(move || {
+        for line in stdin().lock().lines() {
+            let line = match line {
+                Ok(x) => x,
+                Err(_) => return,
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            if let Err(e) = control.command(line.trim()) {
+                eprintln!("{}", e);
+            }
+        }
+    });
+}
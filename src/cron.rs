@@ -0,0 +1,143 @@
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Timelike};
+
+// a cron expression: "minute hour day-of-month month day-of-week" (0 20 * * FRI)
+pub struct CronSchedule {
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    days: Vec<bool>,
+    months: Vec<bool>,
+    weekdays: Vec<bool>, // 0 is sunday
+    any_day: bool,
+    any_weekday: bool,
+}
+
+const MONTH_NAMES: [&str; 12] = [
+    "JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC",
+];
+const WEEKDAY_NAMES: [&str; 7] = ["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"];
+
+// occurrences are searched this number of days ahead (29th of february on a monday is rare)
+const MAX_DAYS: i64 = 366 * 28;
+
+fn parse_value(value: &str, names: &[&str], first: u32) -> Option<u32> {
+    match names.iter().position(|x| x.eq_ignore_ascii_case(value)) {
+        Some(x) => Some(x as u32 + first),
+        None => value.parse::<u32>().ok(),
+    }
+}
+
+// a field is a list of values, ranges (1-5) and steps (*/15, 1-10/2)
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<Vec<bool>, String> {
+    let mut values = vec![false; max as usize + 1];
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => match step.parse::<u32>() {
+                Ok(x) if x > 0 => (range, x),
+                _ => return Err(format!("Invalid cron step: {}", part)),
+            },
+            None => (part, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else {
+            let (start, end) = range.split_once('-').unwrap_or((range, range));
+            match (parse_value(start, names, min), parse_value(end, names, min)) {
+                (Some(start), Some(end)) if start <= end => (start, end),
+                _ => return Err(format!("Invalid cron value: {}", part)),
+            }
+        };
+        if start < min || end > max {
+            return Err(format!("Invalid cron value: {} ({}-{})", part, min, max));
+        }
+        // a single value with a step means "from this value"
+        let end = if step > 1 && !range.contains('-') && range != "*" {
+            max
+        } else {
+            end
+        };
+        for value in (start..=end).step_by(step as usize) {
+            values[value as usize] = true;
+        }
+    }
+    Ok(values)
+}
+
+// the days of a weekday field (MON-FRI), 0 is sunday
+pub fn parse_weekdays(field: &str) -> Result<Vec<bool>, String> {
+    let mut weekdays = parse_field(field, 0, 7, &WEEKDAY_NAMES)?;
+    // 7 is also sunday
+    if weekdays[7] {
+        weekdays[0] = true;
+    }
+    weekdays.truncate(7);
+    Ok(weekdays)
+}
+
+pub fn parse_cron(expression: &str) -> Result<CronSchedule, String> {
+    let fields: Vec<&str> = expression.split_whitespace().collect();
+    if fields.len() != 5 {
+        return Err(format!(
+            "Invalid cron expression: {} (expected minute hour day month weekday)",
+            expression
+        ));
+    }
+
+    let weekdays = parse_weekdays(fields[4])?;
+
+    Ok(CronSchedule {
+        minutes: parse_field(fields[0], 0, 59, &[])?,
+        hours: parse_field(fields[1], 0, 23, &[])?,
+        days: parse_field(fields[2], 1, 31, &[])?,
+        months: parse_field(fields[3], 1, 12, &MONTH_NAMES)?,
+        weekdays,
+        any_day: fields[2] == "*",
+        any_weekday: fields[4] == "*",
+    })
+}
+
+impl CronSchedule {
+    fn matches_date(&self, date: &NaiveDate) -> bool {
+        let day = self.days[date.day() as usize];
+        let weekday = self.weekdays[date.weekday().num_days_from_sunday() as usize];
+        // like cron, when both are restricted, either the day or the weekday matches
+        let day_matches = match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        };
+        self.months[date.month() as usize] && day_matches
+    }
+
+    // the first occurrence strictly after the given time, in its time zone
+    pub fn next_after<T: TimeZone>(&self, after: &DateTime<T>) -> Option<DateTime<T>> {
+        let start = after.naive_local() + Duration::minutes(1);
+        let start_date = start.date();
+
+        for day in 0..MAX_DAYS {
+            let date = start_date + Duration::days(day);
+            if !self.matches_date(&date) {
+                continue;
+            }
+            for hour in 0..24 {
+                if !self.hours[hour as usize] || (day == 0 && hour < start.hour()) {
+                    continue;
+                }
+                for minute in 0..60 {
+                    if !self.minutes[minute as usize]
+                        || (day == 0 && hour == start.hour() && minute < start.minute())
+                    {
+                        continue;
+                    }
+                    // times skipped by a daylight saving change don't exist
+                    if let Some(x) = date
+                        .and_hms_opt(hour, minute, 0)
+                        .and_then(|x| after.timezone().from_local_datetime(&x).earliest())
+                    {
+                        return Some(x);
+                    }
+                }
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,155 @@
+use std::{
+    cell::Cell,
+    f64::consts::PI,
+    fs,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+// the brightness is computed again at most this often, the frames in between use the same one
+const UPDATE_PERIOD: Duration = Duration::from_secs(1);
+// full brightness from this lux (a lit room), the minimum in the dark, logarithmic in between
+const FULL_LUX: f64 = 400.0;
+// full brightness with the sun above the horizon, the minimum once it is this far below (civil twilight)
+const TWILIGHT_ELEVATION: f64 = -6.0;
+
+// what --auto-dim follows
+pub enum AutoDim {
+    SUN(f64, f64),  // latitude and longitude, in degrees
+    SENSOR(String), // file with the lux of a light sensor
+}
+
+// the brightness of the frames, in %, kept for UPDATE_PERIOD
+pub struct DimController {
+    auto_dim: AutoDim,
+    min: u8,
+    level: Cell<u8>,
+    updated: Cell<Option<Instant>>,
+    failing: Cell<bool>, // the sensor could not be read the last time, the error is printed once
+}
+
+// elevation of the sun in degrees, at a time in seconds since the epoch
+fn sun_elevation(latitude: f64, longitude: f64, time: f64) -> f64 {
+    let d = time / 86400.0 - 10957.5; // days since 2000-01-01 12:00 UTC
+    let g = (357.529 + 0.98560028 * d).to_radians(); // mean anomaly
+    let q = 280.459 + 0.98564736 * d; // mean longitude
+    let l = (q + 1.915 * g.sin() + 0.020 * (2.0 * g).sin()).to_radians(); // ecliptic longitude
+    let e = (23.439 - 0.00000036 * d).to_radians(); // obliquity of the ecliptic
+    let right_ascension = (e.cos() * l.sin()).atan2(l.cos());
+    let declination = (e.sin() * l.sin()).asin();
+    let sidereal_time = (18.697374558 + 24.06570982441908 * d) * 15.0;
+    let hour_angle = (sidereal_time + longitude).to_radians() - right_ascension;
+    let latitude = latitude.to_radians();
+    (latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * hour_angle.cos())
+        .asin()
+        * 180.0
+        / PI
+}
+
+// between 0.0 (night or dark) and 1.0 (day or lit)
+fn daylight(elevation: f64) -> f64 {
+    (1.0 - elevation / TWILIGHT_ELEVATION).clamp(0.0, 1.0)
+}
+
+fn lux_level(lux: f64) -> f64 {
+    ((1.0 + lux.max(0.0)).ln() / (1.0 + FULL_LUX).ln()).clamp(0.0, 1.0)
+}
+
+// sun, or the path of the sensor file
+pub fn parse_auto_dim(
+    auto_dim: &str,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+) -> Result<AutoDim, String> {
+    match (auto_dim, latitude, longitude) {
+        ("sun", Some(latitude), Some(longitude)) => {
+            if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+                return Err(String::from("Invalid latitude or longitude"));
+            }
+            Ok(AutoDim::SUN(latitude, longitude))
+        }
+        ("sun", _, _) => Err(String::from(
+            "--auto-dim sun requires --latitude and --longitude",
+        )),
+        (path, _, _) => Ok(AutoDim::SENSOR(path.to_string())),
+    }
+}
+
+impl DimController {
+    pub fn new(auto_dim: AutoDim, min: u8) -> DimController {
+        DimController {
+            auto_dim,
+            min: min.min(100),
+            level: Cell::new(100),
+            updated: Cell::new(None),
+            failing: Cell::new(false),
+        }
+    }
+
+    pub fn level(&self) -> u8 {
+        if self
+            .updated
+            .get()
+            .is_some_and(|x| x.elapsed() < UPDATE_PERIOD)
+        {
+            return self.level.get();
+        }
+        self.updated.set(Some(Instant::now()));
+
+        let ratio = match &self.auto_dim {
+            AutoDim::SUN(latitude, longitude) => {
+                let now = match SystemTime::now().duration_since(UNIX_EPOCH) {
+                    Ok(x) => x.as_secs_f64(),
+                    Err(_) => return self.level.get(),
+                };
+                daylight(sun_elevation(*latitude, *longitude, now))
+            }
+            // the last level is kept while the sensor cannot be read
+            AutoDim::SENSOR(path) => match fs::read_to_string(path)
+                .map_err(|e| e.to_string())
+                .and_then(|x| x.trim().parse::<f64>().map_err(|e| e.to_string()))
+            {
+                Ok(lux) => {
+                    self.failing.set(false);
+                    lux_level(lux)
+                }
+                Err(e) => {
+                    if !self.failing.replace(true) {
+                        eprintln!("Error: {}: {}", path, e);
+                    }
+                    return self.level.get();
+                }
+            },
+        };
+        self.level
+            .set(self.min + ((100 - self.min) as f64 * ratio).round() as u8);
+        self.level.get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // paris, 2024-06-21: sunrise at 03:47 UTC, noon at 11:52, sunset at 19:58
+    #[test]
+    fn sun_elevation_paris() {
+        let (latitude, longitude) = (48.85, 2.35);
+        let noon = 1718970720.0;
+        assert!((sun_elevation(latitude, longitude, noon) - 64.6).abs() < 0.5);
+        let sunrise = 1718941620.0;
+        assert!(sun_elevation(latitude, longitude, sunrise).abs() < 1.0);
+        let sunset = 1718999880.0;
+        assert!(sun_elevation(latitude, longitude, sunset).abs() < 1.0);
+        let midnight = 1718928000.0;
+        assert!(sun_elevation(latitude, longitude, midnight) < TWILIGHT_ELEVATION);
+    }
+
+    #[test]
+    fn levels() {
+        assert_eq!(daylight(10.0), 1.0);
+        assert_eq!(daylight(-3.0), 0.5);
+        assert_eq!(daylight(-20.0), 0.0);
+        assert_eq!(lux_level(0.0), 0.0);
+        assert_eq!(lux_level(1000.0), 1.0);
+    }
+}
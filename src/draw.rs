@@ -0,0 +1,132 @@
+use crate::{imageutils, parse_color};
+use image::{Rgba, RgbaImage};
+
+// --draw mini-language: commands separated by ';' or new lines, arguments by spaces
+//   color R,G,B          color of the next commands (default is the text color)
+//   line X1 Y1 X2 Y2
+//   rect X Y W H         border only
+//   fill X Y W H
+//   circle X Y RADIUS    border only
+//   disc X Y RADIUS
+//   polygon X,Y X,Y X,Y ...
+pub fn draw_script(
+    img: &mut RgbaImage,
+    script: &str,
+    default_color: Rgba<u8>,
+) -> Result<(), String> {
+    let mut color = Rgba([default_color[0], default_color[1], default_color[2], 255]);
+
+    for command in script.split([';', '\n']) {
+        let args: Vec<&str> = command.split_whitespace().collect();
+        let Some((name, args)) = args.split_first() else {
+            continue;
+        };
+
+        match *name {
+            "color" => {
+                expect_args(command, args, 1)?;
+                color = parse_color(args[0])?;
+            }
+            "line" => {
+                let values = numbers(command, args, 4)?;
+                imageutils::draw_line(img, values[0], values[1], values[2], values[3], color);
+            }
+            "rect" | "fill" => {
+                let values = numbers(command, args, 4)?;
+                if values[2] < 0 || values[3] < 0 {
+                    return Err(format!(
+                        "Invalid draw command: {} (negative size)",
+                        command.trim()
+                    ));
+                }
+                if *name == "rect" {
+                    imageutils::draw_rect(
+                        img,
+                        values[0],
+                        values[1],
+                        values[2] as u32,
+                        values[3] as u32,
+                        color,
+                    );
+                } else {
+                    imageutils::fill_rect(
+                        img,
+                        values[0],
+                        values[1],
+                        values[2] as u32,
+                        values[3] as u32,
+                        color,
+                    );
+                }
+            }
+            "circle" | "disc" => {
+                let values = numbers(command, args, 3)?;
+                if values[2] < 0 {
+                    return Err(format!(
+                        "Invalid draw command: {} (negative radius)",
+                        command.trim()
+                    ));
+                }
+                imageutils::draw_circle(
+                    img,
+                    values[0],
+                    values[1],
+                    values[2] as u32,
+                    color,
+                    *name == "disc",
+                );
+            }
+            "polygon" => {
+                if args.len() < 3 {
+                    return Err(format!(
+                        "Invalid draw command: {} (at least 3 points)",
+                        command.trim()
+                    ));
+                }
+                let mut points = Vec::new();
+                for point in args {
+                    let values = point
+                        .split_once(',')
+                        .and_then(|(x, y)| Some((x.parse::<i32>().ok()?, y.parse::<i32>().ok()?)));
+                    match values {
+                        Some(x) => points.push(x),
+                        None => {
+                            return Err(format!("Invalid draw point: {} (expected X,Y)", point))
+                        }
+                    }
+                }
+                imageutils::fill_polygon(img, &points, color);
+            }
+            _ => {
+                return Err(format!("Unknown draw command: {}", name));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn expect_args(command: &str, args: &[&str], count: usize) -> Result<(), String> {
+    if args.len() != count {
+        return Err(format!(
+            "Invalid draw command: {} ({} arguments expected)",
+            command.trim(),
+            count
+        ));
+    }
+    Ok(())
+}
+
+fn numbers(command: &str, args: &[&str], count: usize) -> Result<Vec<i32>, String> {
+    expect_args(command, args, count)?;
+    args.iter()
+        .map(|x| {
+            x.parse::<i32>().map_err(|_| {
+                format!(
+                    "Invalid draw command: {} (not a number: {})",
+                    command.trim(),
+                    x
+                )
+            })
+        })
+        .collect()
+}
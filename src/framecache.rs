@@ -0,0 +1,291 @@
+use crate::imageutils;
+use ring::digest;
+use std::{
+    env, fs,
+    fs::File,
+    io::{BufWriter, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU32, Ordering},
+    time::SystemTime,
+};
+
+// start of the cache files, changed with their layout or with the conversion of the frames
+const MAGIC: &[u8] = b"DMDFRAMES1\n";
+
+// numbers the files being written, a file converted twice at the same time is written twice
+static WRITERS: AtomicU32 = AtomicU32::new(0);
+
+// the rgb565 frames and their durations
+type Animation = (Vec<Box<[u8]>>, Vec<u32>);
+
+// the converted rgb565 frames of the played files, by the hash of their content and of the
+// settings of the conversion: the same file played again is not decoded
+// nor resized. The least recently played files are removed beyond max_size
+pub struct FrameCache {
+    dir: PathBuf,
+    settings: String, // everything changing the frames of a file: dmd size, image options, colors
+    width: u32,
+    height: u32,
+    max_size: u64, // bytes
+}
+
+// the frames of a file being converted, the cache file appears once all of them are written
+pub struct CacheWriter {
+    path: PathBuf,
+    tmp: PathBuf,
+    file: BufWriter<File>,
+    dir: PathBuf,
+    max_size: u64,
+}
+
+fn default_cache_dir() -> String {
+    match env::var("XDG_CACHE_HOME") {
+        Ok(x) => format!("{}/dmd-play/frames", x),
+        Err(_) => format!(
+            "{}/.cache/dmd-play/frames",
+            env::var("HOME").unwrap_or_default()
+        ),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+// the oldest cache files are removed until they take max_size bytes at most, the time of
+// modification of a file is the last time it was played
+fn evict(dir: &Path, max_size: u64) -> Result<(), String> {
+    let entries = fs::read_dir(dir).map_err(|e| format!("Error: {}: {}", dir.display(), e))?;
+    let mut files: Vec<(SystemTime, u64, PathBuf)> = entries
+        .flatten()
+        .filter(|x| x.path().extension().is_some_and(|x| x == "frames"))
+        .filter_map(|x| {
+            let metadata = x.metadata().ok()?;
+            Some((metadata.modified().ok()?, metadata.len(), x.path()))
+        })
+        .collect();
+    files.sort();
+    let mut size: u64 = files.iter().map(|(_, len, _)| len).sum();
+    for (_, len, path) in files {
+        if size <= max_size {
+            break;
+        }
+        // already removed by another dmd-play sharing the cache, or kept
+        if fs::remove_file(&path).is_ok() {
+            size -= len;
+        }
+    }
+    Ok(())
+}
+
+impl FrameCache {
+    pub fn new(
+        dir: &Option<String>,
+        settings: String,
+        width: u32,
+        height: u32,
+        max_size: u64,
+    ) -> FrameCache {
+        FrameCache {
+            dir: PathBuf::from(dir.clone().unwrap_or_else(default_cache_dir)),
+            settings: format!("{}x{}; {}", width, height, settings),
+            width,
+            height,
+            max_size,
+        }
+    }
+
+    // the cache file of a file for the settings and the way it is played (range, durations),
+    // by the hash of its content: a file changed is converted again, a copy of it is not
+    pub fn path(&self, file: &str, variant: &str) -> Result<PathBuf, String> {
+        let error = |e: std::io::Error| format!("Error: {}: {}", file, e);
+        let mut content = digest::Context::new(&digest::SHA256);
+        let mut reader = File::open(file).map_err(error)?;
+        let mut buffer = [0u8; 64 * 1024];
+        loop {
+            match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => content.update(&buffer[..n]),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(error(e)),
+            }
+        }
+
+        let mut context = digest::Context::new(&digest::SHA256);
+        for part in [
+            self.settings.as_bytes(),
+            variant.as_bytes(),
+            content.finish().as_ref(),
+        ] {
+            context.update(&(part.len() as u64).to_be_bytes());
+            context.update(part);
+        }
+        Ok(self
+            .dir
+            .join(format!("{}.frames", hex(context.finish().as_ref()))))
+    }
+
+    // none when the file is missing or not of this dmd size. A file loaded is kept longer
+    pub fn load(&self, path: &Path) -> Option<Animation> {
+        let data = fs::read(path).ok()?;
+        if let Ok(file) = File::options().append(true).open(path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+        let data = data.strip_prefix(MAGIC)?;
+        let (size, records) = data.split_at_checked(4)?;
+        if size
+            != [self.width as u16, self.height as u16]
+                .map(u16::to_be_bytes)
+                .concat()
+        {
+            return None;
+        }
+        let frame_size = imageutils::get_dmd_buffer_size(self.width, self.height) as usize;
+        if records.is_empty() || records.len() % (4 + frame_size) != 0 {
+            return None;
+        }
+        Some(
+            records
+                .chunks(4 + frame_size)
+                .map(|record| {
+                    let (duration, img565) = record.split_at(4);
+                    let duration = u32::from_be_bytes(duration.try_into().unwrap_or_default());
+                    (Box::from(img565), duration)
+                })
+                .unzip(),
+        )
+    }
+
+    pub fn writer(&self, path: &Path) -> Result<CacheWriter, String> {
+        fs::create_dir_all(&self.dir)
+            .map_err(|e| format!("Error: {}: {}", self.dir.display(), e))?;
+        let tmp = path.with_extension(format!(
+            "tmp{}-{}",
+            std::process::id(),
+            WRITERS.fetch_add(1, Ordering::Relaxed)
+        ));
+        let fd = File::create(&tmp).map_err(|e| format!("Error: {}: {}", tmp.display(), e))?;
+        let mut writer = CacheWriter {
+            path: path.to_path_buf(),
+            tmp,
+            file: BufWriter::new(fd),
+            dir: self.dir.clone(),
+            max_size: self.max_size,
+        };
+        writer.write(MAGIC)?;
+        writer.write(&(self.width as u16).to_be_bytes())?;
+        writer.write(&(self.height as u16).to_be_bytes())?;
+        Ok(writer)
+    }
+
+    pub fn store(&self, path: &Path, frames: &Animation) -> Result<(), String> {
+        let mut writer = self.writer(path)?;
+        for (img565, duration) in frames.0.iter().zip(&frames.1) {
+            writer.push(img565, *duration)?;
+        }
+        writer.finish()
+    }
+}
+
+impl CacheWriter {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), String> {
+        self.file
+            .write_all(bytes)
+            .map_err(|e| format!("Error: {}: {}", self.tmp.display(), e))
+    }
+
+    pub fn push(&mut self, img565: &[u8], duration: u32) -> Result<(), String> {
+        self.write(&duration.to_be_bytes())?;
+        self.write(img565)
+    }
+
+    pub fn finish(mut self) -> Result<(), String> {
+        self.file
+            .flush()
+            .map_err(|e| format!("Error: {}: {}", self.tmp.display(), e))?;
+        fs::rename(&self.tmp, &self.path)
+            .map_err(|e| format!("Error: {}: {}", self.path.display(), e))?;
+        evict(&self.dir, self.max_size)
+    }
+}
+
+// an incomplete conversion leaves nothing
+impl Drop for CacheWriter {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.tmp);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let dir = env::temp_dir().join(format!("dmd-play-frames-{}", std::process::id()));
+        let source = env::temp_dir().join(format!("dmd-play-source-{}", std::process::id()));
+        fs::write(&source, b"gif").unwrap();
+        let source = source.to_string_lossy().to_string();
+        let dir_name = Some(dir.to_string_lossy().to_string());
+
+        let cache = FrameCache::new(&dir_name, String::from("lanczos"), 2, 1, 1024);
+        let path = cache.path(&source, "").unwrap();
+        assert_eq!(cache.load(&path), None);
+        let frames = (
+            vec![Box::from([1u8, 2, 3, 4]), Box::from([5u8, 6, 7, 8])],
+            vec![40, 60],
+        );
+        cache.store(&path, &frames).unwrap();
+        assert_eq!(cache.load(&path), Some(frames));
+
+        // other settings, or another way to play it, are other frames
+        assert_ne!(cache.path(&source, "fps 30").unwrap(), path);
+        let nearest = FrameCache::new(&dir_name, String::from("nearest"), 2, 1, 1024);
+        assert_ne!(nearest.path(&source, "").unwrap(), path);
+
+        // a copy is the same file, a file changed is another one even with the same size
+        let copy = format!("{}-copy", source);
+        fs::copy(&source, &copy).unwrap();
+        assert_eq!(cache.path(&copy, "").unwrap(), path);
+        let _ = fs::remove_file(&copy);
+        fs::write(&source, b"GIF").unwrap();
+        assert_ne!(cache.path(&source, "").unwrap(), path);
+
+        // an interrupted conversion leaves no file
+        let writer = cache
+            .writer(&cache.path(&source, "fps 30").unwrap())
+            .unwrap();
+        drop(writer);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        let _ = fs::remove_dir_all(&dir);
+        let _ = fs::remove_file(&source);
+    }
+
+    #[test]
+    fn eviction() {
+        let dir = env::temp_dir().join(format!("dmd-play-evict-{}", std::process::id()));
+        let dir_name = Some(dir.to_string_lossy().to_string());
+        // a file of a frame takes 11 + 4 + 4 + 4 bytes, 2 of them fit
+        let cache = FrameCache::new(&dir_name, String::new(), 2, 1, 50);
+        let frames = (vec![Box::from([1u8, 2, 3, 4])], vec![40]);
+        let paths: Vec<PathBuf> = (0..3).map(|n| dir.join(format!("{}.frames", n))).collect();
+
+        cache.store(&paths[0], &frames).unwrap();
+        cache.store(&paths[1], &frames).unwrap();
+        // the first one is played again, the second one is now the oldest
+        let past = SystemTime::now() - std::time::Duration::from_secs(60);
+        File::options()
+            .append(true)
+            .open(&paths[1])
+            .unwrap()
+            .set_modified(past)
+            .unwrap();
+        assert!(cache.load(&paths[0]).is_some());
+        cache.store(&paths[2], &frames).unwrap();
+        let exists: Vec<bool> = paths.iter().map(|x| x.exists()).collect();
+        assert_eq!(exists, [true, false, true]);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,384 @@
+// golden frames: the frames rendered with the builtin font are compared to the images of
+// tests/golden. after a wanted change of the rendering, UPDATE_GOLDEN=1 cargo test writes them again
+use crate::{
+    cancel::CancelToken, clockstyle, control::PlayControl, get_dmd_animation_from_text, get_header,
+    imageutils, is_text_to_animate, pixelfont, send_image_text, sink::CaptureSink,
+    systemd::Watchdog, ColorCycle, DMDLayer, Dmd, TextAnimation, TextOptions, ANIMATE_THRESHOLD,
+};
+use image::{Rgba, RgbaImage};
+use std::{env, fs, path::PathBuf};
+
+// per channel: a step of red or blue in rgb565, the rounding of the resize may differ a bit
+const TOLERANCE: u8 = 8;
+
+fn golden_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{}.png", name))
+}
+
+fn assert_golden(name: &str, frame: &[u8], width: u32, height: u32) {
+    let img = imageutils::dmdimage2image(frame, width, height);
+    let path = golden_path(name);
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        img.save(&path).unwrap();
+        return;
+    }
+
+    let golden = match image::open(&path) {
+        Ok(x) => x.to_rgba8(),
+        Err(e) => panic!("{}: {} (UPDATE_GOLDEN=1 creates it)", path.display(), e),
+    };
+    assert_eq!(golden.dimensions(), (width, height), "{}: size", name);
+    let differences = golden
+        .pixels()
+        .zip(img.pixels())
+        .filter(|(a, b)| (0..3).any(|c| a[c].abs_diff(b[c]) > TOLERANCE))
+        .count();
+    if differences > 0 {
+        let actual = env::temp_dir().join(format!("{}.png", name));
+        img.save(&actual).unwrap();
+        panic!(
+            "{}: {} pixels differ from the golden frame, got {}",
+            name,
+            differences,
+            actual.display()
+        );
+    }
+}
+
+fn image_options() -> imageutils::ImageOptions {
+    imageutils::ImageOptions {
+        fit: imageutils::ImageFit::CONTAIN,
+        crop: None,
+        orientation: imageutils::Orientation {
+            rotate: 0,
+            flip_h: false,
+            flip_v: false,
+        },
+        palette: None,
+        scale: imageutils::ImageScale::SMOOTH,
+        filter: image::imageops::FilterType::Lanczos3,
+        transparent_color: None,
+        colors: None,
+    }
+}
+
+fn text_animation() -> TextAnimation {
+    TextAnimation {
+        subpixel: 1,
+        gap: None,
+        separator: String::new(),
+        continuous: false,
+        reveal: None,
+        reveal_duration: 0,
+        hold: None,
+        min_text_height: None,
+        animate_threshold: ANIMATE_THRESHOLD,
+        color_cycle: None,
+        cycle_speed: 90,
+    }
+}
+
+// a fixed text of the builtin font, with a line spacing of 1, over black
+fn text_options(
+    color: Rgba<u8>,
+    text_align: imageutils::TextAlign,
+    letter_spacing: i8,
+    tabular_digits: bool,
+    width: u32,
+    height: u32,
+) -> TextOptions {
+    TextOptions {
+        style: imageutils::TextStyle {
+            font: imageutils::load_font(pixelfont::NAME).unwrap(),
+            color,
+            align: text_align,
+            line_spacing: 1,
+            letter_spacing,
+            tabular_digits,
+        },
+        gradient: None,
+        background: RgbaImage::from_pixel(width, height, Rgba([0, 0, 0, 255])),
+        moving: false,
+        fixed: true,
+        speed: 20,
+        animation: text_animation(),
+    }
+}
+
+fn header(width: u32, height: u32) -> [u8; crate::DMD_HEADER_SIZE] {
+    get_header(
+        width as u16,
+        height as u16,
+        DMDLayer::MAIN,
+        imageutils::get_dmd_buffer_size(width, height),
+        1,
+    )
+}
+
+// a fixed text in white over black, as sent by --text
+fn text_frame(
+    text: &str,
+    width: u32,
+    height: u32,
+    text_align: imageutils::TextAlign,
+    letter_spacing: i8,
+    tabular_digits: bool,
+) -> Box<[u8]> {
+    let sink = CaptureSink::default();
+    let dmd = Dmd {
+        client: &sink,
+        header: header(width, height),
+        width,
+        height,
+        image_options: &image_options(),
+        rate: 1.0,
+        control: &PlayControl::default(),
+        watchdog: &Watchdog::default(),
+        cancel: &CancelToken::default(),
+    };
+    let options = text_options(
+        // the alpha of the text color is 0, like the one of the command line
+        Rgba([255, 255, 255, 0]),
+        text_align,
+        letter_spacing,
+        tabular_digits,
+        width,
+        height,
+    );
+    let animated = send_image_text(&dmd, text, &options, true).unwrap();
+    assert!(!animated);
+
+    let mut frames = sink.frames();
+    assert_eq!(frames.len(), 1);
+    let (frame_header, frame) = frames.remove(0);
+    assert_eq!(frame_header, header(width, height));
+    frame
+}
+
+#[test]
+fn text_alignment() {
+    for (name, text_align) in [
+        ("text_left", imageutils::TextAlign::LEFT),
+        ("text_center", imageutils::TextAlign::CENTER),
+        ("text_right", imageutils::TextAlign::RIGHT),
+    ] {
+        let frame = text_frame("Hi", 128, 32, text_align, 0, false);
+        assert_golden(name, &frame, 128, 32);
+    }
+}
+
+#[test]
+fn text_lines() {
+    let frame = text_frame(
+        "TOP\\nbottom",
+        128,
+        32,
+        imageutils::TextAlign::CENTER,
+        0,
+        false,
+    );
+    assert_golden("text_lines", &frame, 128, 32);
+}
+
+#[test]
+fn text_tabular_digits() {
+    let frame = text_frame("12:01", 128, 32, imageutils::TextAlign::CENTER, 1, true);
+    assert_golden("text_tabular_digits", &frame, 128, 32);
+}
+
+#[test]
+fn text_small_panel() {
+    let frame = text_frame("DMD!", 32, 8, imageutils::TextAlign::CENTER, 0, false);
+    assert_golden("text_small_panel", &frame, 32, 8);
+}
+
+// more lines than the dmd has room for with the line spacing
+#[test]
+fn text_lines_small_panel() {
+    for (name, text, width, height) in [
+        ("text_lines_32x8", "AB\\nCD", 32, 8),
+        ("text_lines_64x16", "ONE\\nTWO\\nSIX\\nTEN\\nEND", 64, 16),
+    ] {
+        let frame = text_frame(text, width, height, imageutils::TextAlign::CENTER, 0, false);
+        assert_golden(name, &frame, width, height);
+    }
+}
+
+// the empty text of --clear is a black frame, not a scrolling one
+#[test]
+fn text_empty() {
+    let frame = text_frame("", 128, 32, imageutils::TextAlign::CENTER, 0, false);
+    assert!(frame.iter().all(|x| *x == 0));
+}
+
+// the text rendered once and colored at each frame, like a text of the gradient of the phase
+#[test]
+fn text_color_cycle() {
+    let options = text_options(
+        Rgba([255, 255, 255, 0]),
+        imageutils::TextAlign::CENTER,
+        0,
+        false,
+        128,
+        32,
+    );
+    let cycle_options = TextOptions {
+        animation: TextAnimation {
+            color_cycle: Some(ColorCycle::RAINBOW),
+            // a full cycle in 160 ms: 4 frames of 40 ms, then the colors of the first one
+            cycle_speed: 2250,
+            ..text_animation()
+        },
+        ..options.clone()
+    };
+    let gradient_options = TextOptions {
+        gradient: Some(imageutils::rainbow_gradient(128, 32, 0.0)),
+        ..options
+    };
+
+    let frames = [cycle_options, gradient_options].map(|options| {
+        let sink = CaptureSink::default();
+        let dmd = Dmd {
+            client: &sink,
+            header: header(128, 32),
+            width: 128,
+            height: 32,
+            image_options: &image_options(),
+            rate: 1.0,
+            control: &PlayControl::default(),
+            watchdog: &Watchdog::default(),
+            cancel: &CancelToken::default(),
+        };
+        send_image_text(&dmd, "Hi", &options, true).unwrap();
+        sink.frames()
+    });
+    assert_eq!(frames[0].len(), 5);
+    assert!(frames[0][0].1 == frames[1][0].1);
+    assert!(frames[0][1].1 != frames[0][0].1);
+    assert!(frames[0][4].1 == frames[0][0].1);
+}
+
+#[test]
+fn text_scroll() {
+    let text = "A SCROLLING TEXT";
+    let options = TextOptions {
+        moving: true,
+        fixed: false,
+        ..text_options(
+            Rgba([255, 255, 0, 255]),
+            imageutils::TextAlign::CENTER,
+            0,
+            false,
+            128,
+            32,
+        )
+    };
+    let (_, text_width) =
+        is_text_to_animate(text, &options.style, 128, 32, true, None, ANIMATE_THRESHOLD);
+    let sink = CaptureSink::default();
+    let dmd = Dmd {
+        client: &sink,
+        header: header(128, 32),
+        width: 128,
+        height: 32,
+        image_options: &image_options(),
+        rate: 1.0,
+        control: &PlayControl::default(),
+        watchdog: &Watchdog::default(),
+        cancel: &CancelToken::default(),
+    };
+    let (frames, durations) =
+        get_dmd_animation_from_text(&dmd, text, &None, text_width, &options).unwrap();
+    assert_eq!(frames.len(), durations.len());
+    assert_golden("text_scroll_start", &frames[0], 128, 32);
+    assert_golden("text_scroll_middle", &frames[frames.len() / 2], 128, 32);
+}
+
+// a square image, resized to the height of the dmd and centered
+#[test]
+fn image_contain() {
+    let img = RgbaImage::from_fn(64, 64, |x, y| {
+        Rgba([(x * 4) as u8, (y * 4) as u8, ((x + y) * 2) as u8, 255])
+    });
+    let frame =
+        imageutils::image2dmdimage(&img, &imageutils::TextAlign::CENTER, &None, 128, 32).unwrap();
+    assert_golden("image_contain", &frame, 128, 32);
+}
+
+#[test]
+fn test_pattern_rgb() {
+    let img = imageutils::test_pattern(&imageutils::TestPattern::RGB, 128, 32, 0);
+    let frame =
+        imageutils::image2dmdimage(&img, &imageutils::TextAlign::CENTER, &None, 128, 32).unwrap();
+    assert_golden("test_pattern_rgb", &frame, 128, 32);
+}
+
+// every rgb565 color comes back the same from an image at the dmd size
+#[test]
+fn rgb565_round_trip() {
+    let frame: Vec<u8> = (0..=u16::MAX).flat_map(|x| x.to_be_bytes()).collect();
+    let img = imageutils::dmdimage2image(&frame, 256, 256);
+    let converted =
+        imageutils::image2dmdimage(&img, &imageutils::TextAlign::CENTER, &None, 256, 256).unwrap();
+    assert!(frame[..] == converted[..]);
+}
+
+// odd panel sizes and thin images are padded or cut, never out of the dmd
+#[test]
+fn image_odd_sizes() {
+    for (dmd_width, dmd_height) in [(128, 16), (1, 1), (3, 2), (200, 7)] {
+        for (width, height) in [(64, 64), (1000, 1), (1, 1000), (129, 17)] {
+            let img = RgbaImage::from_pixel(width, height, Rgba([255, 0, 0, 255]));
+            for text_align in [imageutils::TextAlign::CENTER, imageutils::TextAlign::RIGHT] {
+                let frame =
+                    imageutils::image2dmdimage(&img, &text_align, &None, dmd_width, dmd_height)
+                        .unwrap();
+                assert_eq!(
+                    frame.len() as u32,
+                    imageutils::get_dmd_buffer_size(dmd_width, dmd_height)
+                );
+            }
+        }
+    }
+    let img = RgbaImage::from_pixel(64, 64, Rgba([255, 255, 255, 255]));
+    let frame =
+        imageutils::image2dmdimage(&img, &imageutils::TextAlign::CENTER, &None, 128, 16).unwrap();
+    assert_golden("image_contain_128x16", &frame, 128, 16);
+
+    assert!(imageutils::check_dmd_size(128, 16).is_ok());
+    assert!(imageutils::check_dmd_size(0, 32).is_err());
+    assert!(imageutils::check_dmd_size(70000, 1).is_err());
+}
+
+#[test]
+fn clock_styles() {
+    let background = RgbaImage::from_pixel(128, 32, Rgba([0, 0, 0, 255]));
+    let color = Rgba([255, 88, 0, 255]);
+    for (name, style) in [
+        ("clock_7seg", clockstyle::ClockStyle::SEGMENTS),
+        ("clock_nixie", clockstyle::ClockStyle::NIXIE),
+        ("clock_flip", clockstyle::ClockStyle::FLIP),
+    ] {
+        let img = clockstyle::render_clock(&style, "12:34", &background, color);
+        let frame =
+            imageutils::image2dmdimage(&img, &imageutils::TextAlign::CENTER, &None, 128, 32)
+                .unwrap();
+        assert_golden(name, &frame, 128, 32);
+    }
+    let img = clockstyle::flip_frame("12:34", "12:35", 0.75, &background, color);
+    let frame =
+        imageutils::image2dmdimage(&img, &imageutils::TextAlign::CENTER, &None, 128, 32).unwrap();
+    assert_golden("clock_flip_step", &frame, 128, 32);
+    let img = clockstyle::render_clock(
+        &clockstyle::ClockStyle::BINARY,
+        "12:34:56",
+        &background,
+        color,
+    );
+    let frame =
+        imageutils::image2dmdimage(&img, &imageutils::TextAlign::CENTER, &None, 128, 32).unwrap();
+    assert_golden("clock_binary", &frame, 128, 32);
+}
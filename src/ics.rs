@@ -0,0 +1,260 @@
+use chrono::{
+    DateTime, Datelike, Local, Months, NaiveDate, NaiveDateTime, TimeDelta, TimeZone, Utc,
+};
+use chrono_tz::Tz;
+use std::{fs, time::Duration};
+
+// occurrences of a recurring event looked at, after that it is over
+const MAX_OCCURRENCES: u32 = 10000;
+
+// the repetition of an event (RRULE), only FREQ, INTERVAL, COUNT and UNTIL are followed
+struct Repeat {
+    frequency: Frequency,
+    interval: u32,
+    count: Option<u32>,
+    until: Option<DateTime<Utc>>,
+}
+
+enum Frequency {
+    DAILY,
+    WEEKLY,
+    MONTHLY,
+    YEARLY,
+}
+
+// an event of the calendar (VEVENT)
+pub struct Event {
+    summary: String,
+    start: DateTime<Utc>,
+    repeat: Option<Repeat>,
+}
+
+// a calendar file, or its url
+pub fn load_calendar(source: &str) -> Result<Vec<Event>, String> {
+    let content = if source.starts_with("http://") || source.starts_with("https://") {
+        match ureq::get(source).timeout(Duration::from_secs(10)).call() {
+            Ok(response) => match response.into_string() {
+                Ok(x) => x,
+                Err(e) => return Err(format!("Error: {}: {}", source, e)),
+            },
+            Err(e) => return Err(e.to_string()),
+        }
+    } else {
+        match fs::read_to_string(source) {
+            Ok(x) => x,
+            Err(e) => return Err(format!("Error: {}: {}", source, e)),
+        }
+    };
+    parse_calendar(&content).map_err(|e| format!("Error: {}: {}", source, e))
+}
+
+// \, \; \\ and \n (on a single line) of the text values
+fn unescape(value: &str) -> String {
+    let mut result = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => result.push(' '),
+            Some(x) => result.push(x),
+            None => {}
+        }
+    }
+    result
+}
+
+// 20240101T100000Z in utc, 20240101T100000 in the time zone of TZID (or the local one), 20240101 for
+// a whole day, from its midnight
+fn parse_date(value: &str, tzid: Option<&str>) -> Result<DateTime<Utc>, String> {
+    let invalid = || format!("invalid date {}", value);
+    if let Some(value) = value.strip_suffix('Z') {
+        let date = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").map_err(|_| invalid())?;
+        return Ok(date.and_utc());
+    }
+    let date = match NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S") {
+        Ok(x) => x,
+        Err(_) => NaiveDate::parse_from_str(value, "%Y%m%d")
+            .map_err(|_| invalid())?
+            .and_hms_opt(0, 0, 0)
+            .ok_or_else(invalid)?,
+    };
+    let date = match tzid.and_then(|x| x.parse::<Tz>().ok()) {
+        Some(tz) => tz.from_local_datetime(&date).earliest().map(|x| x.to_utc()),
+        None => Local
+            .from_local_datetime(&date)
+            .earliest()
+            .map(|x| x.to_utc()),
+    };
+    date.ok_or_else(invalid)
+}
+
+fn parse_repeat(value: &str) -> Result<Repeat, String> {
+    let mut repeat = Repeat {
+        frequency: Frequency::DAILY,
+        interval: 1,
+        count: None,
+        until: None,
+    };
+    let mut frequency = None;
+    for part in value.split(';') {
+        let invalid = || format!("invalid RRULE {}", value);
+        match part.split_once('=') {
+            Some(("FREQ", x)) => {
+                frequency = Some(match x {
+                    "DAILY" => Frequency::DAILY,
+                    "WEEKLY" => Frequency::WEEKLY,
+                    "MONTHLY" => Frequency::MONTHLY,
+                    "YEARLY" => Frequency::YEARLY,
+                    _ => return Err(invalid()),
+                })
+            }
+            Some(("INTERVAL", x)) => repeat.interval = x.parse().map_err(|_| invalid())?,
+            Some(("COUNT", x)) => repeat.count = Some(x.parse().map_err(|_| invalid())?),
+            Some(("UNTIL", x)) => repeat.until = Some(parse_date(x, None)?),
+            _ => {}
+        }
+    }
+    repeat.frequency = frequency.ok_or_else(|| format!("invalid RRULE {}", value))?;
+    repeat.interval = repeat.interval.max(1);
+    Ok(repeat)
+}
+
+pub fn parse_calendar(content: &str) -> Result<Vec<Event>, String> {
+    // the long lines are folded, continued by a line starting with a space or a tab
+    let mut lines: Vec<String> = Vec::new();
+    for line in content.lines() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(x), Some(last)) => last.push_str(x),
+            _ => lines.push(line.to_string()),
+        }
+    }
+
+    let mut events = Vec::new();
+    let mut event: Option<(String, Option<DateTime<Utc>>, Option<Repeat>)> = None;
+    for line in lines {
+        // NAME;PARAM=X;PARAM=Y:VALUE
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let mut params = name.split(';');
+        let name = params.next().unwrap_or("");
+        let tzid = params.find_map(|x| x.strip_prefix("TZID="));
+        match (name, event.as_mut()) {
+            ("BEGIN", _) if value == "VEVENT" => event = Some((String::new(), None, None)),
+            ("END", Some(_)) if value == "VEVENT" => {
+                if let Some((summary, Some(start), repeat)) = event.take() {
+                    events.push(Event {
+                        summary,
+                        start,
+                        repeat,
+                    });
+                }
+            }
+            ("SUMMARY", Some(x)) => x.0 = unescape(value),
+            ("DTSTART", Some(x)) => x.1 = Some(parse_date(value, tzid)?),
+            ("RRULE", Some(x)) => x.2 = Some(parse_repeat(value)?),
+            _ => {}
+        }
+    }
+    Ok(events)
+}
+
+impl Event {
+    // the first start after the date, none once the event is over
+    fn next_start(&self, after: &DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let Some(repeat) = &self.repeat else {
+            return Some(self.start).filter(|x| x > after);
+        };
+        for n in 0..repeat.count.unwrap_or(MAX_OCCURRENCES).min(MAX_OCCURRENCES) {
+            let steps = n * repeat.interval;
+            let months = match repeat.frequency {
+                Frequency::DAILY | Frequency::WEEKLY => 0,
+                Frequency::MONTHLY => steps,
+                Frequency::YEARLY => steps * 12,
+            };
+            let start = match repeat.frequency {
+                Frequency::DAILY => Some(self.start + TimeDelta::days(steps as i64)),
+                Frequency::WEEKLY => Some(self.start + TimeDelta::weeks(steps as i64)),
+                // the 31st is skipped in the shorter months
+                _ => self
+                    .start
+                    .checked_add_months(Months::new(months))
+                    .filter(|x| x.day() == self.start.day()),
+            };
+            let Some(start) = start else {
+                continue;
+            };
+            if repeat.until.is_some_and(|x| start > x) {
+                return None;
+            }
+            if start > *after {
+                return Some(start);
+            }
+        }
+        None
+    }
+}
+
+// the title and the start of the next event
+pub fn next_event(events: &[Event], after: &DateTime<Utc>) -> Option<(String, DateTime<Utc>)> {
+    events
+        .iter()
+        .filter_map(|x| x.next_start(after).map(|start| (x.summary.clone(), start)))
+        .min_by_key(|(_, start)| *start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CALENDAR: &str = "BEGIN:VCALENDAR\r
+BEGIN:VEVENT\r
+SUMMARY:Retro night\\, with\r
+  friends\r
+DTSTART:20240105T190000Z\r
+RRULE:FREQ=WEEKLY;COUNT=3\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+SUMMARY:Tournament\r
+DTSTART;TZID=Europe/Paris:20240110T100000\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+    fn at(day: u32, hour: u32) -> DateTime<Utc> {
+        NaiveDate::from_ymd_opt(2024, 1, day)
+            .unwrap()
+            .and_hms_opt(hour, 0, 0)
+            .unwrap()
+            .and_utc()
+    }
+
+    #[test]
+    fn next_events() {
+        let events = parse_calendar(CALENDAR).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].summary, "Retro night, with friends");
+
+        let next = |day, hour| next_event(&events, &at(day, hour));
+        assert_eq!(
+            next(1, 0),
+            Some((String::from("Retro night, with friends"), at(5, 19)))
+        );
+        assert_eq!(next(6, 0).unwrap().1, at(10, 9)); // 10:00 in paris
+        assert_eq!(next(10, 12).unwrap().1, at(12, 19));
+        assert_eq!(next(20, 0), None); // after the 3 occurrences
+
+        let events = parse_calendar(
+            "BEGIN:VEVENT\nSUMMARY:Pay day\nDTSTART;VALUE=DATE:20240131\nRRULE:FREQ=MONTHLY\nEND:VEVENT\n",
+        )
+        .unwrap();
+        let start = next_event(&events, &at(31, 12)).unwrap().1;
+        assert_eq!(
+            start.with_timezone(&Local).date_naive().to_string(),
+            "2024-03-31"
+        );
+    }
+}
@@ -1,14 +1,220 @@
 use image::{imageops, DynamicImage, GenericImageView, Rgba, RgbaImage};
 use imageproc::drawing::draw_text_mut;
-use rusttype::{point, Font, Scale};
+use rusttype::{point, Font, GlyphId, Scale};
 use std::{fs::read, path::Path};
 
+use crate::pixelfont;
+
+#[derive(Clone)]
 pub enum TextAlign {
     CENTER,
     LEFT,
     RIGHT,
 }
 
+// how an image is mapped onto the dmd
+#[derive(Clone)]
+pub enum ImageFit {
+    CONTAIN, // scale to fit, keep the ratio (black borders)
+    COVER,   // scale to fill, keep the ratio (edges are cropped)
+    STRETCH, // scale to fill, ignore the ratio
+    CROP,    // no scaling, keep the center
+}
+
+// how an image is resized onto the dmd
+#[derive(Clone)]
+pub enum ImageScale {
+    SMOOTH,               // lanczos filter
+    NEAREST,              // crisp pixels
+    INTEGER(Option<u32>), // crisp pixels, by a whole factor (the largest one fitting by default)
+}
+
+// calibration patterns, to check the pixels and the wiring of the panel
+pub enum TestPattern {
+    GRID,      // lines every 8 pixels, a border and the center
+    GRADIENT,  // red, green, blue and white ramps
+    RGB,       // color bars
+    PIXELWALK, // a single pixel going through the panel, white, red, green then blue
+}
+
+// how the content is turned to match the way the panel is mounted
+#[derive(Clone)]
+pub struct Orientation {
+    pub rotate: u32, // clockwise, in degrees: 0, 90, 180 or 270
+    pub flip_h: bool,
+    pub flip_v: bool,
+}
+
+#[derive(Clone)]
+pub struct ImageOptions {
+    pub fit: ImageFit,
+    pub crop: Option<(u32, u32, u32, u32)>, // x, y, width, height in the source image
+    pub orientation: Orientation,
+    pub palette: Option<Vec<Rgba<u8>>>, // shades to recolor grayscale content
+    pub scale: ImageScale,
+    pub filter: imageops::FilterType, // used by the smooth scale
+    pub transparent_color: Option<Rgba<u8>>, // color key of the source image
+    pub colors: Option<ColorAdjustment>, // applied to the frames before the rgb565 conversion
+}
+
+// the filters of the colors of all the frames, applied before the rgb565 conversion
+#[derive(Clone)]
+pub struct ColorAdjustment {
+    pub saturation: f32,               // 0.0 is gray, 1.0 unchanged
+    pub contrast: f32,                 // around the middle gray, 1.0 unchanged
+    pub invert: bool,                  // negative colors
+    pub mono_threshold: Option<u8>,    // pixels on from this brightness, off below
+    pub mono_color: Rgba<u8>,          // color of the pixels on
+    pub mono_dither: bool,             // error diffusion
+    pub matrix: Option<[[f32; 3]; 3]>, // --color-correct, applied last
+}
+
+// how a text is drawn, the font is loaded once for all the texts
+#[derive(Clone)]
+pub struct TextStyle {
+    pub font: Font<'static>,
+    pub color: Rgba<u8>, // its alpha is not used
+    pub align: TextAlign,
+    pub line_spacing: u8,   // pixels between the lines
+    pub letter_spacing: i8, // pixels added between the letters
+    pub tabular_digits: bool,
+}
+
+// 3 gains (R,G,B) or the 9 values of a matrix, row by row: the first row gives the red from R,G,B
+pub fn parse_color_matrix(values: &[f32]) -> Result<[[f32; 3]; 3], String> {
+    match values {
+        [r, g, b] => Ok([[*r, 0.0, 0.0], [0.0, *g, 0.0], [0.0, 0.0, *b]]),
+        [a, b, c, d, e, f, g, h, i] => Ok([[*a, *b, *c], [*d, *e, *f], [*g, *h, *i]]),
+        _ => Err(format!(
+            "Invalid color correction: {} values (3 gains R,G,B or the 9 values of a matrix)",
+            values.len()
+        )),
+    }
+}
+
+// everything changing the conversion of an image into dmd frames, for the frame cache
+pub fn conversion_settings(options: &ImageOptions) -> String {
+    let color = |x: &Rgba<u8>| format!("{},{},{},{}", x[0], x[1], x[2], x[3]);
+    let fit = match options.fit {
+        ImageFit::CONTAIN => "contain",
+        ImageFit::COVER => "cover",
+        ImageFit::STRETCH => "stretch",
+        ImageFit::CROP => "crop",
+    };
+    let scale = match options.scale {
+        ImageScale::SMOOTH => String::from("smooth"),
+        ImageScale::NEAREST => String::from("nearest"),
+        ImageScale::INTEGER(None) => String::from("integer"),
+        ImageScale::INTEGER(Some(x)) => format!("{}x", x),
+    };
+    let filter = match options.filter {
+        imageops::FilterType::Nearest => "nearest",
+        imageops::FilterType::Triangle => "triangle",
+        imageops::FilterType::CatmullRom => "catmullrom",
+        imageops::FilterType::Gaussian => "gaussian",
+        imageops::FilterType::Lanczos3 => "lanczos",
+    };
+    let orientation = &options.orientation;
+    let mut settings = vec![
+        format!("fit {}", fit),
+        format!("scale {} {}", scale, filter),
+        format!(
+            "rotate {} flip {} {}",
+            orientation.rotate, orientation.flip_h, orientation.flip_v
+        ),
+    ];
+    if let Some((x, y, width, height)) = options.crop {
+        settings.push(format!("crop {},{},{},{}", x, y, width, height));
+    }
+    if let Some(palette) = &options.palette {
+        let colors: Vec<String> = palette.iter().map(color).collect();
+        settings.push(format!("palette {}", colors.join(" ")));
+    }
+    if let Some(x) = &options.transparent_color {
+        settings.push(format!("transparent {}", color(x)));
+    }
+    if let Some(colors) = &options.colors {
+        settings.push(format!(
+            "saturation {} contrast {} invert {}",
+            colors.saturation, colors.contrast, colors.invert
+        ));
+        if let Some(threshold) = colors.mono_threshold {
+            settings.push(format!(
+                "mono {} {} dither {}",
+                threshold,
+                color(&colors.mono_color),
+                colors.mono_dither
+            ));
+        }
+        if let Some(matrix) = colors.matrix {
+            let values: Vec<String> = matrix.iter().flatten().map(|x| x.to_string()).collect();
+            settings.push(format!("matrix {}", values.join(",")));
+        }
+    }
+    settings.join("; ")
+}
+
+fn luma(pixel: &Rgba<u8>) -> f32 {
+    0.299 * pixel[0] as f32 + 0.587 * pixel[1] as f32 + 0.114 * pixel[2] as f32
+}
+
+// each pixel on or off, with the floyd-steinberg error diffusion when dithered
+fn binarize(img: &mut RgbaImage, threshold: u8, color: Rgba<u8>, dither: bool) {
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    let mut levels: Vec<f32> = img.pixels().map(luma).collect();
+    for y in 0..height {
+        for x in 0..width {
+            let level = levels[y * width + x];
+            let on = level >= threshold as f32;
+            if dither {
+                let error = level - if on { 255.0 } else { 0.0 };
+                for (dx, dy, weight) in [(1, 0, 7.0), (-1, 1, 3.0), (0, 1, 5.0), (1, 1, 1.0)] {
+                    let (nx, ny) = (x as i32 + dx, y + dy);
+                    if (0..width as i32).contains(&nx) && ny < height {
+                        levels[ny * width + nx as usize] += error * weight / 16.0;
+                    }
+                }
+            }
+            let pixel = img.get_pixel_mut(x as u32, y as u32);
+            *pixel = match on {
+                true => Rgba([color[0], color[1], color[2], pixel[3]]),
+                false => Rgba([0, 0, 0, pixel[3]]),
+            };
+        }
+    }
+}
+
+fn adjust_image(img: &mut RgbaImage, adjustment: &ColorAdjustment) {
+    for pixel in img.pixels_mut() {
+        let luma = luma(pixel);
+        for c in 0..3 {
+            let mut value = luma + (pixel[c] as f32 - luma) * adjustment.saturation;
+            value = 128.0 + (value - 128.0) * adjustment.contrast;
+            if adjustment.invert {
+                value = 255.0 - value;
+            }
+            pixel[c] = value.round().clamp(0.0, 255.0) as u8;
+        }
+    }
+    if let Some(threshold) = adjustment.mono_threshold {
+        binarize(
+            img,
+            threshold,
+            adjustment.mono_color,
+            adjustment.mono_dither,
+        );
+    }
+    if let Some(matrix) = &adjustment.matrix {
+        for pixel in img.pixels_mut() {
+            let rgb = [pixel[0] as f32, pixel[1] as f32, pixel[2] as f32];
+            for (c, row) in matrix.iter().enumerate() {
+                let value = row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2];
+                pixel[c] = value.round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+}
+
 fn rgb888_to_rgb565(r: u8, g: u8, b: u8) -> u16 {
     let r5 = (r as u16) >> 3;
     let g6 = (g as u16) >> 2;
@@ -16,98 +222,397 @@ fn rgb888_to_rgb565(r: u8, g: u8, b: u8) -> u16 {
     (r5 << 11) | (g6 << 5) | b5
 }
 
+fn rgb565_to_rgb888(val: u16) -> (u8, u8, u8) {
+    let r5 = ((val >> 11) & 0x1f) as u8;
+    let g6 = ((val >> 5) & 0x3f) as u8;
+    let b5 = (val & 0x1f) as u8;
+    // replicate the high bits in the low ones so that 0x1f gives 0xff
+    (
+        (r5 << 3) | (r5 >> 2),
+        (g6 << 2) | (g6 >> 4),
+        (b5 << 3) | (b5 >> 2),
+    )
+}
+
+// the rgb565 frame at a percentage of its brightness, 0 is black
+pub fn dim_dmdimage(frame: &mut [u8], brightness: u8) {
+    let level = brightness.min(100) as u16;
+    for pixel in frame.chunks_exact_mut(2) {
+        let value = u16::from_be_bytes([pixel[0], pixel[1]]);
+        let r5 = ((value >> 11) & 0x1f) * level / 100;
+        let g6 = ((value >> 5) & 0x3f) * level / 100;
+        let b5 = (value & 0x1f) * level / 100;
+        pixel.copy_from_slice(&((r5 << 11) | (g6 << 5) | b5).to_be_bytes());
+    }
+}
+
+// the rgb565 frames mixed, step of steps from the first one to the second one
+pub fn blend_dmdimage(from: &[u8], to: &[u8], step: u32, steps: u32) -> Vec<u8> {
+    let (step, steps) = (step.min(steps), steps.max(1));
+    let mix = |a: u16, b: u16| ((a as u32 * (steps - step) + b as u32 * step) / steps) as u16;
+    from.chunks_exact(2)
+        .zip(to.chunks_exact(2))
+        .flat_map(|(a, b)| {
+            let a = u16::from_be_bytes([a[0], a[1]]);
+            let b = u16::from_be_bytes([b[0], b[1]]);
+            let r5 = mix((a >> 11) & 0x1f, (b >> 11) & 0x1f);
+            let g6 = mix((a >> 5) & 0x3f, (b >> 5) & 0x3f);
+            let b5 = mix(a & 0x1f, b & 0x1f);
+            ((r5 << 11) | (g6 << 5) | b5).to_be_bytes()
+        })
+        .collect()
+}
+
+// the rgb565 frame moved by dx and dy pixels, black where it is uncovered
+pub fn shift_dmdimage(frame: &[u8], width: u32, height: u32, dx: i32, dy: i32) -> Vec<u8> {
+    let mut shifted = vec![0u8; frame.len()];
+    for y in 0..height as i32 {
+        for x in 0..width as i32 {
+            let (sx, sy) = (x - dx, y - dy);
+            if (0..width as i32).contains(&sx) && (0..height as i32).contains(&sy) {
+                let to = (y as usize * width as usize + x as usize) * 2;
+                let from = (sy as usize * width as usize + sx as usize) * 2;
+                shifted[to..to + 2].copy_from_slice(&frame[from..from + 2]);
+            }
+        }
+    }
+    shifted
+}
+
 pub fn get_dmd_buffer_size(width: u32, height: u32) -> u32 {
     return (width * height * 2) as u32;
 }
 
+// the header has the width and height on 16 bits and the size of the frame on 32 bits
+pub fn check_dmd_size(width: u32, height: u32) -> Result<(), String> {
+    if width == 0 || height == 0 {
+        return Err(format!("Invalid dmd size: {}x{} (empty)", width, height));
+    }
+    if width > u16::MAX as u32
+        || height > u16::MAX as u32
+        || width as u64 * height as u64 * 2 > u32::MAX as u64
+    {
+        return Err(format!(
+            "Invalid dmd size: {}x{} (too large)",
+            width, height
+        ));
+    }
+    Ok(())
+}
+
 pub fn image2dmdimage<T: GenericImageView<Pixel = Rgba<u8>>>(
     orig_img: &T,
     text_align: &TextAlign,
+    colors: &Option<ColorAdjustment>,
     dmd_width: u32,
     dmd_height: u32,
 ) -> Result<Box<[u8]>, String> {
-    // resize the image to something below 128x32
-    let (orig_width, orig_height) = orig_img.dimensions();
+    let mut dmd_img = image2dmdsize(orig_img, text_align, dmd_width, dmd_height);
+    if let Some(adjustment) = colors {
+        adjust_image(&mut dmd_img, adjustment);
+    }
+    let mut bytes: Box<[u8]> =
+        vec![0u8; get_dmd_buffer_size(dmd_width, dmd_height) as usize].into_boxed_slice();
 
-    let new_width;
-    let new_height;
+    for (x, y, pixel) in dmd_img.enumerate_pixels() {
+        let idx = (((y * dmd_width) + x) * 2) as usize;
+        let val: u16 = rgb888_to_rgb565(pixel[0], pixel[1], pixel[2]);
+        bytes[idx..idx + 2].copy_from_slice(&val.to_be_bytes());
+    }
+    Ok(bytes)
+}
 
-    if (orig_width as f32 / orig_height as f32) < (dmd_width as f32 / dmd_height as f32) {
-        new_height = dmd_height;
-        new_width = (orig_width as f32 * new_height as f32 / orig_height as f32) as u32;
+// largest size with the ratio of the image fitting in the dmd, at least a pixel on each side
+// for a very thin image, and never above the dmd because of the rounding
+fn contain_size(width: u32, height: u32, dmd_width: u32, dmd_height: u32) -> (u32, u32) {
+    if (width as f32 / height as f32) < (dmd_width as f32 / dmd_height as f32) {
+        (
+            ((width as f32 * dmd_height as f32 / height as f32) as u32).clamp(1, dmd_width),
+            dmd_height,
+        )
     } else {
-        new_width = dmd_width;
-        new_height = (orig_height as f32 * new_width as f32 / orig_width as f32) as u32;
+        (
+            dmd_width,
+            ((height as f32 * dmd_width as f32 / width as f32) as u32).clamp(1, dmd_height),
+        )
     }
+}
 
-    let resized_img = imageops::resize(
-        orig_img,
-        new_width,
-        new_height,
-        imageops::FilterType::Lanczos3,
-    );
+// resize and place the image on a dmd sized image, borders are transparent
+pub fn image2dmdsize<T: GenericImageView<Pixel = Rgba<u8>>>(
+    orig_img: &T,
+    text_align: &TextAlign,
+    dmd_width: u32,
+    dmd_height: u32,
+) -> RgbaImage {
+    // resize the image to something below 128x32
+    let (orig_width, orig_height) = orig_img.dimensions();
+    let (new_width, new_height) = contain_size(orig_width, orig_height, dmd_width, dmd_height);
+
+    // an image already at the dmd size is kept as it is
+    let resized_img = if (orig_width, orig_height) == (dmd_width, dmd_height) {
+        let mut img = RgbaImage::new(orig_width, orig_height);
+        imageops::replace(&mut img, orig_img, 0, 0);
+        img
+    } else {
+        imageops::resize(
+            orig_img,
+            new_width,
+            new_height,
+            imageops::FilterType::Lanczos3,
+        )
+    };
 
     // create the dmd image
     let (width, height) = resized_img.dimensions();
 
-    let mut bytes: Box<[u8]> =
-        vec![0u8; get_dmd_buffer_size(dmd_width, dmd_height) as usize].into_boxed_slice();
-
-    // init to 0
-    for i in 0..bytes.len() {
-        bytes[i] = 0;
-    }
+    let mut dmd_img = RgbaImage::new(dmd_width, dmd_height);
 
+    // an image larger than the dmd is cut on the right and bottom
     let x_offset = match text_align {
-        TextAlign::CENTER => (dmd_width - width) / 2,
+        TextAlign::CENTER => dmd_width.saturating_sub(width) / 2,
         TextAlign::LEFT => 0,
-        TextAlign::RIGHT => dmd_width - width,
+        TextAlign::RIGHT => dmd_width.saturating_sub(width),
     };
 
-    let y_offset = (dmd_height - height) / 2;
-
-    for y in 0..dmd_height {
-        if y >= y_offset && y < (height + y_offset) {
-            for x in 0..dmd_width {
-                let idx_u32: u32 = ((y * dmd_width) + x) * 2;
-                let idx: usize = idx_u32 as usize;
-                if x >= x_offset && x < (width + x_offset) {
-                    let pixel = resized_img.get_pixel(x - x_offset, y - y_offset);
-                    let val: u16 = rgb888_to_rgb565(pixel[0], pixel[1], pixel[2]);
-                    bytes[idx..idx + 2].copy_from_slice(&val.to_be_bytes());
-                }
+    let y_offset = dmd_height.saturating_sub(height) / 2;
+
+    imageops::replace(&mut dmd_img, &resized_img, x_offset as i64, y_offset as i64);
+    dmd_img
+}
+
+// size of the content as seen by the viewer, before orientation
+pub fn oriented_size(orientation: &Orientation, dmd_width: u32, dmd_height: u32) -> (u32, u32) {
+    match orientation.rotate {
+        90 | 270 => (dmd_height, dmd_width),
+        _ => (dmd_width, dmd_height),
+    }
+}
+
+pub fn orient_image(img: &RgbaImage, orientation: &Orientation) -> RgbaImage {
+    let mut new_img = match orientation.rotate {
+        90 => imageops::rotate90(img),
+        180 => imageops::rotate180(img),
+        270 => imageops::rotate270(img),
+        _ => img.clone(),
+    };
+    if orientation.flip_h {
+        imageops::flip_horizontal_in_place(&mut new_img);
+    }
+    if orientation.flip_v {
+        imageops::flip_vertical_in_place(&mut new_img);
+    }
+    new_img
+}
+
+// apply the crop and fit options, the result is then to be converted by image2dmdimage
+pub fn fit_image(
+    orig_img: &RgbaImage,
+    options: &ImageOptions,
+    dmd_width: u32,
+    dmd_height: u32,
+) -> Result<RgbaImage, String> {
+    let img = match options.crop {
+        Some((x, y, width, height)) => {
+            let cropped = imageops::crop_imm(orig_img, x, y, width, height).to_image();
+            if cropped.width() == 0 || cropped.height() == 0 {
+                return Err(String::from("Crop area outside of the image"));
             }
+            cropped
+        }
+        None => orig_img.clone(),
+    };
+    let img = match options.transparent_color {
+        Some(color) => {
+            let mut img = img;
+            make_transparent(&mut img, color);
+            img
         }
+        None => img,
+    };
+    let img = match options.palette {
+        Some(ref palette) => {
+            let mut img = img;
+            recolor(&mut img, palette);
+            img
+        }
+        None => img,
+    };
+    let img = orient_image(&img, &options.orientation);
+    let (width, height) = img.dimensions();
+    let filter = match options.scale {
+        ImageScale::SMOOTH => options.filter,
+        _ => imageops::FilterType::Nearest,
+    };
+
+    match options.fit {
+        ImageFit::CONTAIN => match options.scale {
+            // image2dmdimage resizes with lanczos
+            ImageScale::SMOOTH if filter == imageops::FilterType::Lanczos3 => Ok(img),
+            ImageScale::SMOOTH | ImageScale::NEAREST => {
+                let (new_width, new_height) = contain_size(width, height, dmd_width, dmd_height);
+                let resized_img = imageops::resize(&img, new_width, new_height, filter);
+                Ok(center_image(resized_img, dmd_width, dmd_height))
+            }
+            ImageScale::INTEGER(factor) => {
+                // an image larger than the dmd is reduced like with nearest
+                let largest = u32::min(dmd_width / width.max(1), dmd_height / height.max(1)).max(1);
+                let factor = factor.unwrap_or(largest).min(largest);
+                let resized_img = if width * factor > dmd_width || height * factor > dmd_height {
+                    let ratio = f32::min(
+                        dmd_width as f32 / width as f32,
+                        dmd_height as f32 / height as f32,
+                    );
+                    imageops::resize(
+                        &img,
+                        ((width as f32 * ratio) as u32).max(1),
+                        ((height as f32 * ratio) as u32).max(1),
+                        filter,
+                    )
+                } else {
+                    imageops::resize(&img, width * factor, height * factor, filter)
+                };
+                Ok(center_image(resized_img, dmd_width, dmd_height))
+            }
+        },
+        ImageFit::STRETCH => Ok(imageops::resize(&img, dmd_width, dmd_height, filter)),
+        ImageFit::COVER => {
+            let ratio = f32::max(
+                dmd_width as f32 / width as f32,
+                dmd_height as f32 / height as f32,
+            );
+            let new_width = ((width as f32 * ratio).round() as u32).max(dmd_width);
+            let new_height = ((height as f32 * ratio).round() as u32).max(dmd_height);
+            let resized_img = imageops::resize(&img, new_width, new_height, filter);
+            Ok(imageops::crop_imm(
+                &resized_img,
+                (new_width - dmd_width) / 2,
+                (new_height - dmd_height) / 2,
+                dmd_width,
+                dmd_height,
+            )
+            .to_image())
+        }
+        ImageFit::CROP => Ok(center_image(img, dmd_width, dmd_height)),
     }
-    Ok(bytes)
 }
 
-// for an unknown reason, this compute a too large width. sum of advance_width is not the total size
-fn get_text_width(font: &Font, scale: Scale, text: &str) -> u32 {
-    let mut width = 0.0;
-    let mut n = 0;
-    let mut last_rsb: f32 = 0.0;
+// always return the dmd size so that image2dmdimage doesn't scale
+fn center_image(img: RgbaImage, dmd_width: u32, dmd_height: u32) -> RgbaImage {
+    let (width, height) = img.dimensions();
+    let mut new_img = RgbaImage::new(dmd_width, dmd_height);
+    let dyn_img = DynamicImage::ImageRgba8(img);
+    copy_image(
+        &dyn_img,
+        &mut new_img,
+        (dmd_width as i32 - width as i32) / 2,
+        (dmd_height as i32 - height as i32) / 2,
+    );
+    new_img
+}
 
-    for glyph in font.layout(text, scale, point(0.0, 0.0)) {
-        // remove the left side bearing for the first letter LSB
-        if n == 0 {
-            width -= glyph.unpositioned().h_metrics().left_side_bearing;
+// the brightness of each pixel selects a color of the palette, like the shades of a dmd
+pub fn recolor(img: &mut RgbaImage, palette: &[Rgba<u8>]) {
+    for pixel in img.pixels_mut() {
+        let luma = (pixel[0] as u32 * 299 + pixel[1] as u32 * 587 + pixel[2] as u32 * 114) / 1000;
+        let shade = (luma as usize * palette.len() / 256).min(palette.len() - 1);
+        let color = palette[shade];
+        *pixel = Rgba([color[0], color[1], color[2], pixel[3]]);
+    }
+}
+
+// pixels of the color become transparent black, which the overlay layer doesn't display
+pub fn make_transparent(img: &mut RgbaImage, color: Rgba<u8>) {
+    for pixel in img.pixels_mut() {
+        if pixel[0] == color[0] && pixel[1] == color[1] && pixel[2] == color[2] {
+            *pixel = Rgba([0, 0, 0, 0]);
         }
+    }
+}
 
-        let glyph_width = match glyph.pixel_bounding_box() {
-            Some(x) => x.width() as f32,
-            None => 0.0,
-        };
+// convert a rgb565 dmd buffer back into an image (reverse of image2dmdimage without resizing)
+pub fn dmdimage2image(bytes: &[u8], width: u32, height: u32) -> RgbaImage {
+    let mut img = RgbaImage::new(width, height);
+
+    for (n, pixel) in bytes.chunks_exact(2).enumerate() {
+        let x = n as u32 % width;
+        let y = n as u32 / width;
+        if y >= height {
+            break;
+        }
+        let (r, g, b) = rgb565_to_rgb888(u16::from_be_bytes([pixel[0], pixel[1]]));
+        img.put_pixel(x, y, Rgba([r, g, b, 255]));
+    }
+    img
+}
+
+// advance of the widest digit, given to all the digits with tabular digits
+fn digit_width(font: &Font, scale: Scale) -> f32 {
+    ('0'..='9')
+        .map(|c| font.glyph(c).scaled(scale).h_metrics().advance_width)
+        .fold(0.0, f32::max)
+}
+
+// x of each letter: the layout of the font, moved by the letter spacing and, with tabular digits,
+// each digit centered in the width of the widest one, without kerning between digits.
+// returns the padding of each letter too
+fn letter_positions(
+    font: &Font,
+    scale: Scale,
+    text: &str,
+    letter_spacing: f32,
+    tabular_digits: bool,
+) -> Vec<(f32, f32)> {
+    let digit_width = digit_width(font, scale);
+    let mut caret = 0.0;
+    let mut previous: Option<(char, GlyphId)> = None;
+    let mut positions = Vec::new();
+
+    for c in text.chars() {
+        let glyph = font.glyph(c).scaled(scale);
+        let is_digit = tabular_digits && c.is_ascii_digit();
+        if let Some((previous_char, previous_id)) = previous {
+            caret += letter_spacing;
+            if !(is_digit && previous_char.is_ascii_digit()) {
+                caret += font.pair_kerning(scale, previous_id, glyph.id());
+            }
+        }
 
-        width += glyph.unpositioned().h_metrics().advance_width;
-        last_rsb = glyph.unpositioned().h_metrics().advance_width
-            - glyph.unpositioned().h_metrics().left_side_bearing
-            - glyph_width;
-        n = n + 1;
+        let advance = glyph.h_metrics().advance_width;
+        let padding = if is_digit { digit_width - advance } else { 0.0 };
+        positions.push((caret + padding / 2.0, padding));
+        caret += advance + padding;
+        previous = Some((c, glyph.id()));
     }
-    width = width - last_rsb;
+    positions
+}
+
+// the letters at the positions of letter_positions(), from the left side bearing of the first one to
+// the right side bearing of the last one
+fn get_text_width(
+    font: &Font,
+    scale: Scale,
+    text: &str,
+    letter_spacing: f32,
+    tabular_digits: bool,
+) -> u32 {
+    let positions = letter_positions(font, scale, text, letter_spacing, tabular_digits);
+    let (Some(first), Some(last), Some((position, padding))) =
+        (text.chars().next(), text.chars().last(), positions.last())
+    else {
+        return 1;
+    };
+    let first = font.glyph(first).scaled(scale).h_metrics();
+    let last = font.glyph(last).scaled(scale);
+    let metrics = last.h_metrics();
+    let glyph_width = match last.positioned(point(*position, 0.0)).pixel_bounding_box() {
+        Some(x) => x.width() as f32,
+        None => 0.0,
+    };
 
-    width.round() as u32
+    let end = position - padding / 2.0 + metrics.advance_width + padding;
+    let last_rsb = metrics.advance_width - metrics.left_side_bearing - glyph_width;
+    (end - first.left_side_bearing - last_rsb).round().max(1.0) as u32
 }
 
 fn get_text_height(font: &Font, scale: Scale, text: &str) -> u32 {
@@ -149,63 +654,50 @@ fn get_text_x(font: &Font, scale: Scale, text: &str) -> i32 {
     0
 }
 
+// the height of each line of a text and the spacing between the lines. on a small dmd, the spacing
+// is reduced first, then each line keeps a pixel at least and the lines below the dmd are cut
+pub fn text_sections(height: u32, nlines: u32, line_spacing: u8) -> (u32, u32) {
+    if nlines <= 1 {
+        return (height.max(1), 0);
+    }
+    let spacing = (line_spacing as u32).min(height.saturating_sub(nlines) / (nlines - 1));
+    let section_height = ((height - spacing * (nlines - 1)) / nlines).max(1);
+    (section_height, spacing)
+}
+
 pub fn generate_text_image(
     text: &str,
-    font_path: &str,
+    style: &TextStyle,
     gradient: &Option<DynamicImage>,
     width: u32,
     height: u32,
-    background_color: Rgba<u8>,
-    text_color: Rgba<u8>,
-    text_align: &TextAlign,
-    line_spacing: u8,
 ) -> Result<(DynamicImage, u32, u32), String> {
     let lines = text.split("\\n");
     let nlines = lines.clone().count() as u32;
 
     // single line
     if nlines == 1 {
-        let (mut dyn_img, start, new_width) = generate_text_image_single_line(
-            text,
-            font_path,
-            width,
-            height,
-            background_color,
-            text_color,
-            text_align,
-        )?;
-
-        match gradient {
-            Some(x) => {
-                dyn_img = apply_gradient(&dyn_img, &x);
-            }
-            None => {}
-        }
+        let (mut dyn_img, start, new_width) =
+            generate_text_image_single_line(text, style, width, height)?;
+
+        dyn_img = text_alpha(&dyn_img, style.color, gradient);
         Ok((dyn_img, start, new_width))
     } else {
         // multiple lines
-        let spaces = line_spacing as u32 * (nlines - 1);
-        let section_height = (height - spaces) / nlines;
+        let (section_height, line_spacing) = text_sections(height, nlines, style.line_spacing);
         let mut rgba_img = RgbaImage::new(width, height);
         let mut smallest_start = width - 1;
         let mut biggest_end = 0;
 
         let mut n: i32 = 0;
         for line in lines {
-            let (dyn_img, start, new_width) = generate_text_image_single_line(
-                line,
-                font_path,
-                width,
-                section_height,
-                background_color,
-                text_color,
-                text_align,
-            )?;
+            let (dyn_img, start, new_width) =
+                generate_text_image_single_line(line, style, width, section_height)?;
             copy_image(
                 &dyn_img,
                 &mut rgba_img,
                 0,
-                section_height as i32 * n + n * line_spacing as i32,
+                (section_height + line_spacing) as i32 * n,
             );
             if start < smallest_start {
                 smallest_start = start;
@@ -218,105 +710,729 @@ pub fn generate_text_image(
 
         let mut dyn_img = DynamicImage::ImageRgba8(rgba_img);
 
-        match gradient {
-            Some(x) => {
-                dyn_img = apply_gradient(&dyn_img, &x);
-            }
-            None => {}
+        dyn_img = text_alpha(&dyn_img, style.color, gradient);
+
+        Ok((
+            dyn_img,
+            smallest_start,
+            biggest_end.saturating_sub(smallest_start),
+        ))
+    }
+}
+
+// the colors of each letter of each line, None for the text color
+pub type LetterColors = Vec<Vec<Option<Rgba<u8>>>>;
+
+// a text with {color:NAME}...{/color} parts (the color until the next one without {/color}):
+// the text without the markup and the colors of its letters, None when there is no markup
+pub fn parse_color_markup(text: &str) -> Result<(String, Option<LetterColors>), String> {
+    if !text.contains("{color:") {
+        return Ok((text.to_string(), None));
+    }
+    let mut plain = String::new();
+    let mut letters = Vec::new();
+    let mut color = None;
+    let mut rest = text;
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("{/color}") {
+            color = None;
+            rest = after;
+            continue;
+        }
+        if let Some(after) = rest.strip_prefix("{color:")
+            && let Some((name, after)) = after.split_once('}')
+        {
+            color = Some(gradient_color(name)?);
+            rest = after;
+            continue;
         }
+        let c = rest.chars().next().unwrap_or_default();
+        plain.push(c);
+        letters.push(color);
+        rest = &rest[c.len_utf8()..];
+    }
 
-        Ok((dyn_img, smallest_start, biggest_end - smallest_start))
+    // the lines are separated by \n, 2 letters without color
+    let mut colors = Vec::new();
+    let mut n = 0;
+    for line in plain.split("\\n") {
+        let nletters = line.chars().count();
+        colors.push(letters[n..n + nletters].to_vec());
+        n += nletters + 2;
     }
+    Ok((plain, Some(colors)))
 }
 
-fn apply_gradient(img: &DynamicImage, gradient: &DynamicImage) -> DynamicImage {
-    let width_img = img.width();
-    let height_img = img.height();
-    let width_gradient = gradient.width();
-    let height_gradient = gradient.height();
-
-    let mut new_img = RgbaImage::new(width_img, height_img);
-
-    for y in 0..height_img {
-        for x in 0..width_img {
-            if x < width_gradient && y < height_gradient {
-                let img_pixel = img.get_pixel(x, y);
-                let gradient_pixel = gradient.get_pixel(x, y);
-                let new_pixel;
-                let min_value = 15;
-                let max_alpha = 245;
-                if (img_pixel[0] > min_value
-                    || img_pixel[1] > min_value
-                    || img_pixel[2] > min_value)
-                    && img_pixel[3] < max_alpha
-                {
-                    new_pixel = Rgba([
-                        gradient_pixel[0],
-                        gradient_pixel[1],
-                        gradient_pixel[2],
-                        img_pixel[3],
-                    ]);
-                } else {
-                    new_pixel = Rgba([0, 0, 0, 0]);
+// a gradient for generate_text_image() giving each letter its color, the letters without one keep
+// the color of the gradient, or the text color
+pub fn markup_gradient(
+    text: &str,
+    colors: &LetterColors,
+    style: &TextStyle,
+    gradient: &Option<DynamicImage>,
+    width: u32,
+    height: u32,
+) -> Result<DynamicImage, String> {
+    let text_color = style.color;
+    let mut img = RgbaImage::from_fn(width, height, |x, y| match gradient {
+        Some(gradient) if x < gradient.width() && y < gradient.height() => gradient.get_pixel(x, y),
+        _ => Rgba([text_color[0], text_color[1], text_color[2], 255]),
+    });
+
+    let lines: Vec<&str> = text.split("\\n").collect();
+    let (section_height, line_spacing) =
+        text_sections(height, lines.len() as u32, style.line_spacing);
+    for (n, line) in lines.iter().enumerate() {
+        // where generate_text_image() puts the line, the letters share it like in the layout
+        let (_, start, line_width) =
+            generate_text_image_single_line(line, style, width, section_height)?;
+        let ends = get_letter_ends(line, style, section_height);
+        let top = n as u32 * (section_height + line_spacing);
+        let bottom = (top + section_height).min(height);
+        for x in start..(start + line_width).min(width) {
+            let position = (x - start) as f32 / line_width.max(1) as f32;
+            let letter = ends
+                .iter()
+                .position(|end| position < *end)
+                .unwrap_or(ends.len().saturating_sub(1));
+            if let Some(color) = colors.get(n).and_then(|x| x.get(letter)).copied().flatten() {
+                for y in top..bottom {
+                    img.put_pixel(x, y, color);
                 }
-                new_img.put_pixel(x, y, new_pixel);
             }
         }
     }
-    return DynamicImage::ImageRgba8(new_img);
+    Ok(DynamicImage::ImageRgba8(img))
+}
+
+const COLOR_NAMES: [(&str, [u8; 3]); 16] = [
+    ("black", [0, 0, 0]),
+    ("white", [255, 255, 255]),
+    ("red", [255, 0, 0]),
+    ("green", [0, 255, 0]),
+    ("blue", [0, 0, 255]),
+    ("yellow", [255, 255, 0]),
+    ("cyan", [0, 255, 255]),
+    ("magenta", [255, 0, 255]),
+    ("orange", [255, 165, 0]),
+    ("gold", [255, 215, 0]),
+    ("purple", [128, 0, 128]),
+    ("pink", [255, 192, 203]),
+    ("gray", [128, 128, 128]),
+    ("grey", [128, 128, 128]),
+    ("silver", [192, 192, 192]),
+    ("brown", [165, 42, 42]),
+];
+
+// a color name, hexadecimal (ff0000 or #ff0000) or R,G,B
+fn gradient_color(color: &str) -> Result<Rgba<u8>, String> {
+    if let Some((_, [r, g, b])) = COLOR_NAMES
+        .iter()
+        .find(|(x, _)| x.eq_ignore_ascii_case(color))
+    {
+        return Ok(Rgba([*r, *g, *b, 255]));
+    }
+    let hex = color.strip_prefix('#').unwrap_or(color);
+    if hex.len() == 6
+        && let Ok(x) = u32::from_str_radix(hex, 16)
+    {
+        return Ok(Rgba([(x >> 16) as u8, (x >> 8) as u8, x as u8, 255]));
+    }
+    let values: Vec<u8> = color
+        .split(',')
+        .filter_map(|x| x.trim().parse::<u8>().ok())
+        .collect();
+    match values[..] {
+        [r, g, b] => Ok(Rgba([r, g, b, 255])),
+        _ => Err(format!("Invalid gradient color: {}", color)),
+    }
+}
+
+// "linear:ff0000-0000ff" (left to right), "vertical:gold-red" (top to bottom) or "diagonal:...",
+// with 2 colors or more evenly spread. None when the gradient is an image path file
+pub fn generate_gradient(
+    spec: &str,
+    width: u32,
+    height: u32,
+) -> Option<Result<DynamicImage, String>> {
+    let (direction, colors) = spec.split_once(':')?;
+    if !["linear", "horizontal", "vertical", "diagonal"].contains(&direction) {
+        return None;
+    }
+    let colors = match colors
+        .split('-')
+        .map(gradient_color)
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(x) if x.len() >= 2 => x,
+        Ok(_) => {
+            return Some(Err(format!(
+                "Invalid gradient: {} (2 colors at least)",
+                spec
+            )))
+        }
+        Err(e) => return Some(Err(e)),
+    };
+
+    let position = |x: u32, y: u32| {
+        let fx = x as f32 / (width.max(2) - 1) as f32;
+        let fy = y as f32 / (height.max(2) - 1) as f32;
+        match direction {
+            "vertical" => fy,
+            "diagonal" => (fx + fy) / 2.0,
+            _ => fx,
+        }
+    };
+    let img = RgbaImage::from_fn(width, height, |x, y| {
+        let position = position(x, y) * (colors.len() - 1) as f32;
+        let index = (position as usize).min(colors.len() - 2);
+        let t = position - index as f32;
+        let (a, b) = (colors[index], colors[index + 1]);
+        let channel = |c: usize| (a[c] as f32 + (b[c] as f32 - a[c] as f32) * t).round() as u8;
+        Rgba([channel(0), channel(1), channel(2), 255])
+    });
+    Some(Ok(DynamicImage::ImageRgba8(img)))
 }
 
-pub fn get_text_ratio(text: &str, font_path: &str, height: u32) -> Result<f32, String> {
-    let font_data = match read(Path::new(&font_path)) {
-        Ok(x) => x,
-        Err(_) => return Err(String::from("Unable to read font")),
+// full saturation and value color of a hue (0-360)
+fn hue_color(hue: f32) -> Rgba<u8> {
+    let hue = hue.rem_euclid(360.0) / 60.0;
+    let x = ((1.0 - (hue % 2.0 - 1.0).abs()) * 255.0).round() as u8;
+    match hue as u32 {
+        0 => Rgba([255, x, 0, 255]),
+        1 => Rgba([x, 255, 0, 255]),
+        2 => Rgba([0, 255, x, 255]),
+        3 => Rgba([0, x, 255, 255]),
+        4 => Rgba([x, 0, 255, 255]),
+        _ => Rgba([255, 0, x, 255]),
+    }
+}
+
+// all the hues from left to right, starting at phase (0.0-1.0 for a full cycle)
+pub fn rainbow_gradient(width: u32, height: u32, phase: f32) -> DynamicImage {
+    let img = RgbaImage::from_fn(width, height, |x, _| {
+        hue_color((x as f32 / width.max(1) as f32 + phase) * 360.0)
+    });
+    DynamicImage::ImageRgba8(img)
+}
+
+// the gradient moved to the left by phase (0.0-1.0 for its full width), wrapping around
+pub fn shift_gradient(gradient: &DynamicImage, phase: f32) -> DynamicImage {
+    let width = gradient.width().max(1);
+    let shift = (phase.rem_euclid(1.0) * width as f32) as u32;
+    let img = RgbaImage::from_fn(gradient.width(), gradient.height(), |x, y| {
+        gradient.get_pixel((x + shift) % width, y)
+    });
+    DynamicImage::ImageRgba8(img)
+}
+
+// the text is rendered on black: its coverage becomes the alpha channel, for the text color or the
+// color of the gradient at each pixel
+fn text_alpha(
+    img: &DynamicImage,
+    text_color: Rgba<u8>,
+    gradient: &Option<DynamicImage>,
+) -> DynamicImage {
+    let text_max = text_color[0].max(text_color[1]).max(text_color[2]) as f32;
+    let mut new_img = img.to_rgba8();
+
+    for (x, y, pixel) in new_img.enumerate_pixels_mut() {
+        let max = pixel[0].max(pixel[1]).max(pixel[2]);
+        // the alpha of the text color is not used, the one of the gradient hides the text
+        let color = match gradient {
+            Some(gradient) if x < gradient.width() && y < gradient.height() => {
+                Some(gradient.get_pixel(x, y)).filter(|x| x[3] > 0)
+            }
+            Some(_) => None,
+            None => Some(text_color),
+        };
+        if let (Some(color), true) = (color, max > 0 && text_max > 0.0) {
+            let coverage = (max as f32 / text_max).min(1.0);
+            *pixel = Rgba([
+                color[0],
+                color[1],
+                color[2],
+                (coverage * 255.0).round() as u8,
+            ]);
+        } else {
+            *pixel = Rgba([0, 0, 0, 0]);
+        }
+    }
+    DynamicImage::ImageRgba8(new_img)
+}
+
+// a text of generate_text_image without gradient, in the colors of the gradient at each pixel
+// like with it: the text is rendered once, then colored again at each frame of a color cycle
+pub fn recolor_text(img: &RgbaImage, gradient: &DynamicImage) -> RgbaImage {
+    RgbaImage::from_fn(img.width(), img.height(), |x, y| {
+        let coverage = img.get_pixel(x, y)[3];
+        let color = match x < gradient.width() && y < gradient.height() {
+            true => gradient.get_pixel(x, y),
+            false => Rgba([0, 0, 0, 0]),
+        };
+        match coverage > 0 && color[3] > 0 {
+            true => Rgba([color[0], color[1], color[2], coverage]),
+            false => Rgba([0, 0, 0, 0]),
+        }
+    })
+}
+
+// blend a transparent image over the background, the result is opaque
+pub fn apply_background(img: &RgbaImage, background: &RgbaImage) -> RgbaImage {
+    let mut new_img = RgbaImage::new(img.width(), img.height());
+
+    for (x, y, pixel) in img.enumerate_pixels() {
+        let bg_pixel = if x < background.width() && y < background.height() {
+            *background.get_pixel(x, y)
+        } else {
+            Rgba([0, 0, 0, 255])
+        };
+        let alpha = pixel[3] as f32 / 255.0;
+        let mut new_pixel = Rgba([0, 0, 0, 255]);
+        for c in 0..3 {
+            new_pixel[c] =
+                (pixel[c] as f32 * alpha + bg_pixel[c] as f32 * (1.0 - alpha)).round() as u8;
+        }
+        new_img.put_pixel(x, y, new_pixel);
+    }
+    new_img
+}
+
+// fill a rectangle, the parts out of the image are ignored
+pub fn fill_rect(img: &mut RgbaImage, x: i32, y: i32, width: u32, height: u32, color: Rgba<u8>) {
+    let x_start = x.max(0);
+    let y_start = y.max(0);
+    let x_end = (x as i64 + width as i64).min(img.width() as i64) as i32;
+    let y_end = (y as i64 + height as i64).min(img.height() as i64) as i32;
+
+    for py in y_start..y_end {
+        for px in x_start..x_end {
+            img.put_pixel(px as u32, py as u32, color);
+        }
+    }
+}
+
+// draw the 1 pixel border of a rectangle
+pub fn draw_rect(img: &mut RgbaImage, x: i32, y: i32, width: u32, height: u32, color: Rgba<u8>) {
+    if width == 0 || height == 0 {
+        return;
+    }
+    // the last column and row, out of the image when they are past i32
+    let right = (x as i64 + width as i64 - 1).min(i32::MAX as i64) as i32;
+    let bottom = (y as i64 + height as i64 - 1).min(i32::MAX as i64) as i32;
+    fill_rect(img, x, y, width, 1, color);
+    fill_rect(img, x, bottom, width, 1, color);
+    fill_rect(img, x, y, 1, height, color);
+    fill_rect(img, right, y, 1, height, color);
+}
+
+// set a pixel, the ones out of the image are ignored
+fn put_pixel_clipped(img: &mut RgbaImage, x: i32, y: i32, color: Rgba<u8>) {
+    if x >= 0 && y >= 0 && x < img.width() as i32 && y < img.height() as i32 {
+        img.put_pixel(x as u32, y as u32, color);
+    }
+}
+
+// the part of a segment in the image (Liang-Barsky), None when it is out of it
+fn clip_line(img: &RgbaImage, x1: i64, y1: i64, x2: i64, y2: i64) -> Option<(i64, i64, i64, i64)> {
+    let (width, height) = (img.width() as i64, img.height() as i64);
+    let inside = |x: i64, y: i64| x >= 0 && y >= 0 && x < width && y < height;
+    if inside(x1, y1) && inside(x2, y2) {
+        return Some((x1, y1, x2, y2));
+    }
+
+    let (dx, dy) = ((x2 - x1) as f64, (y2 - y1) as f64);
+    let (mut t_start, mut t_end) = (0.0f64, 1.0f64);
+    for (p, q) in [
+        (-dx, x1 as f64),
+        (dx, (width - 1 - x1) as f64),
+        (-dy, y1 as f64),
+        (dy, (height - 1 - y1) as f64),
+    ] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+            continue;
+        }
+        let t = q / p;
+        if p < 0.0 {
+            t_start = t_start.max(t);
+        } else {
+            t_end = t_end.min(t);
+        }
+        if t_start > t_end {
+            return None;
+        }
+    }
+    Some((
+        (x1 as f64 + t_start * dx).round() as i64,
+        (y1 as f64 + t_start * dy).round() as i64,
+        (x1 as f64 + t_end * dx).round() as i64,
+        (y1 as f64 + t_end * dy).round() as i64,
+    ))
+}
+
+// bresenham line, both ends included, only its part in the image is walked
+pub fn draw_line(img: &mut RgbaImage, x1: i32, y1: i32, x2: i32, y2: i32, color: Rgba<u8>) {
+    let Some((x1, y1, x2, y2)) = clip_line(img, x1 as i64, y1 as i64, x2 as i64, y2 as i64) else {
+        return;
+    };
+    let dx = (x2 - x1).abs();
+    let dy = -(y2 - y1).abs();
+    let step_x = if x1 < x2 { 1 } else { -1 };
+    let step_y = if y1 < y2 { 1 } else { -1 };
+    let mut error = dx + dy;
+    let (mut x, mut y) = (x1, y1);
+
+    loop {
+        put_pixel_clipped(img, x as i32, y as i32, color);
+        if x == x2 && y == y2 {
+            break;
+        }
+        let e2 = 2 * error;
+        if e2 >= dy {
+            error += dy;
+            x += step_x;
+        }
+        if e2 <= dx {
+            error += dx;
+            y += step_y;
+        }
+    }
+}
+
+// midpoint circle, filled draws horizontal lines between the symmetric points
+pub fn draw_circle(
+    img: &mut RgbaImage,
+    cx: i32,
+    cy: i32,
+    radius: u32,
+    color: Rgba<u8>,
+    filled: bool,
+) {
+    let (cx, cy, radius) = (cx as i64, cy as i64, radius as i64);
+    let (width, height) = (img.width() as i64, img.height() as i64);
+    if cx + radius < 0 || cy + radius < 0 || cx - radius >= width || cy - radius >= height {
+        return;
+    }
+    // an image within the circle is filled or left, whatever the radius
+    let far_x = cx.max(width - 1 - cx).max(0) as i128;
+    let far_y = cy.max(height - 1 - cy).max(0) as i128;
+    let inner = (radius - 1) as i128;
+    if radius > 1 && far_x * far_x + far_y * far_y < inner * inner {
+        if filled {
+            fill_rect(img, 0, 0, width as u32, height as u32, color);
+        }
+        return;
+    }
+
+    let mut x = radius;
+    let mut y = 0;
+    let mut error = 1 - x;
+    // the centers of the lines are in i32, the lines are clipped
+    let line = |img: &mut RgbaImage, x1: i64, x2: i64, y: i64| {
+        if y >= 0 && y < height {
+            draw_line(
+                img,
+                x1.max(-1) as i32,
+                y as i32,
+                x2.min(width) as i32,
+                y as i32,
+                color,
+            );
+        }
     };
-    let font = match Font::try_from_bytes(&font_data) {
-        Some(x) => x,
-        None => return Err(String::from("Unable to read font")),
+
+    while x >= y {
+        if filled {
+            line(img, cx - x, cx + x, cy + y);
+            line(img, cx - x, cx + x, cy - y);
+            line(img, cx - y, cx + y, cy + x);
+            line(img, cx - y, cx + y, cy - x);
+        } else {
+            for (px, py) in [
+                (x, y),
+                (y, x),
+                (-y, x),
+                (-x, y),
+                (-x, -y),
+                (-y, -x),
+                (y, -x),
+                (x, -y),
+            ] {
+                let (px, py) = (cx + px, cy + py);
+                if px >= 0 && py >= 0 && px < width && py < height {
+                    img.put_pixel(px as u32, py as u32, color);
+                }
+            }
+        }
+        y += 1;
+        if error < 0 {
+            error += 2 * y + 1;
+        } else {
+            x -= 1;
+            error += 2 * (y - x) + 1;
+        }
+    }
+}
+
+// scanline fill (even-odd rule), the points are the corners in order
+pub fn fill_polygon(img: &mut RgbaImage, points: &[(i32, i32)], color: Rgba<u8>) {
+    if points.len() < 3 {
+        return;
+    }
+    let y_min = points.iter().map(|p| p.1).min().unwrap_or(0).max(0);
+    let y_max = points
+        .iter()
+        .map(|p| p.1)
+        .max()
+        .unwrap_or(0)
+        .min(img.height() as i32 - 1);
+
+    for y in y_min..=y_max {
+        // crossings of the pixel center line with each edge
+        let yc = y as f32 + 0.5;
+        let mut crossings = Vec::new();
+        for i in 0..points.len() {
+            let (x1, y1) = points[i];
+            let (x2, y2) = points[(i + 1) % points.len()];
+            let (y1, y2, x1, x2) = (y1 as f32, y2 as f32, x1 as f32, x2 as f32);
+            if (y1 <= yc && y2 > yc) || (y2 <= yc && y1 > yc) {
+                crossings.push(x1 + (yc - y1) * (x2 - x1) / (y2 - y1));
+            }
+        }
+        crossings.sort_by(|a, b| a.total_cmp(b));
+        for pair in crossings.chunks(2) {
+            if let [start, end] = pair {
+                let x_start = ((start - 0.5).ceil() as i32).max(0);
+                let x_end = ((end - 0.5).floor() as i32).min(img.width() as i32 - 1);
+                for x in x_start..=x_end {
+                    put_pixel_clipped(img, x, y, color);
+                }
+            }
+        }
+    }
+}
+
+// a bordered bar filled from the left, ratio is between 0.0 and 1.0
+pub fn draw_progress_bar(
+    img: &mut RgbaImage,
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+    ratio: f32,
+    color: Rgba<u8>,
+) {
+    draw_rect(img, x, y, width, height, color);
+    if width <= 2 || height <= 2 {
+        return;
+    }
+    let filled = ((width - 2) as f32 * ratio.clamp(0.0, 1.0)).round() as u32;
+    fill_rect(img, x + 1, y + 1, filled, height - 2, color);
+}
+
+// the test pattern at this step (only the pixel walk moves, one pixel per step)
+pub fn test_pattern(pattern: &TestPattern, width: u32, height: u32, step: u32) -> RgbaImage {
+    let black = Rgba([0, 0, 0, 255]);
+    let mut img = RgbaImage::from_pixel(width, height, black);
+    match pattern {
+        TestPattern::GRID => {
+            let gray = Rgba([96, 96, 96, 255]);
+            for x in (0..width).step_by(8) {
+                fill_rect(&mut img, x as i32, 0, 1, height, gray);
+            }
+            for y in (0..height).step_by(8) {
+                fill_rect(&mut img, 0, y as i32, width, 1, gray);
+            }
+            // the border shows the first and last rows and columns, the center shows the middle
+            draw_rect(&mut img, 0, 0, width, height, Rgba([255, 255, 255, 255]));
+            let (cx, cy) = (width as i32 / 2, height as i32 / 2);
+            draw_line(&mut img, cx - 2, cy, cx + 1, cy, Rgba([255, 0, 0, 255]));
+            draw_line(&mut img, cx, cy - 2, cx, cy + 1, Rgba([255, 0, 0, 255]));
+            // the top left corner, to see a flipped or rotated panel
+            fill_rect(&mut img, 1, 1, 3, 3, Rgba([0, 255, 0, 255]));
+        }
+        TestPattern::GRADIENT => {
+            let ramps = [[1, 0, 0], [0, 1, 0], [0, 0, 1], [1, 1, 1]];
+            let ramp_height = height.div_ceil(ramps.len() as u32).max(1);
+            for (x, y, pixel) in img.enumerate_pixels_mut() {
+                let ramp = ramps[((y / ramp_height) as usize).min(ramps.len() - 1)];
+                let value = (x * 255 / (width - 1).max(1)) as u8;
+                *pixel = Rgba([value * ramp[0], value * ramp[1], value * ramp[2], 255]);
+            }
+        }
+        TestPattern::RGB => {
+            let bars = [
+                [255, 255, 255],
+                [255, 255, 0],
+                [0, 255, 255],
+                [0, 255, 0],
+                [255, 0, 255],
+                [255, 0, 0],
+                [0, 0, 255],
+                [0, 0, 0],
+            ];
+            for (x, _, pixel) in img.enumerate_pixels_mut() {
+                let bar = bars[(x as usize * bars.len() / width as usize).min(bars.len() - 1)];
+                *pixel = Rgba([bar[0], bar[1], bar[2], 255]);
+            }
+        }
+        TestPattern::PIXELWALK => {
+            let colors = [
+                Rgba([255, 255, 255, 255]),
+                Rgba([255, 0, 0, 255]),
+                Rgba([0, 255, 0, 255]),
+                Rgba([0, 0, 255, 255]),
+            ];
+            let size = (width * height).max(1);
+            let position = step % size;
+            let color = colors[((step / size) as usize) % colors.len()];
+            if width > 0 && height > 0 {
+                img.put_pixel(position % width, position / width, color);
+            }
+        }
+    }
+    img
+}
+
+pub fn load_font(font_path: &str) -> Result<Font<'static>, String> {
+    #[cfg(any(test, feature = "builtin-font"))]
+    let font_data = if font_path == pixelfont::NAME {
+        Ok(pixelfont::ttf())
+    } else {
+        read(Path::new(&font_path))
     };
+    #[cfg(not(any(test, feature = "builtin-font")))]
+    let font_data = read(Path::new(&font_path));
+
+    match font_data.ok().and_then(Font::try_from_vec) {
+        Some(x) => Ok(x),
+        None => Err(String::from("Unable to read font")),
+    }
+}
+
+// the pixel font, for the modes never writing a text, whatever the features
+pub fn builtin_font() -> Font<'static> {
+    Font::try_from_vec(pixelfont::ttf()).expect("builtin font")
+}
+
+pub fn get_text_ratio(text: &str, style: &TextStyle, height: u32) -> f32 {
+    let font = &style.font;
+    let scale = Scale::uniform((height * 5) as f32); // 5x for a nicer image (more precision)
+
+    let genheight = get_text_height(font, scale, text);
+    // an empty text (like the one of --clear) has no width to scroll
+    if genheight == 0 {
+        return 0.0;
+    }
+    let spacing = render_spacing(style.letter_spacing, genheight, height);
+    let genwidth = get_text_width(font, scale, text, spacing, style.tabular_digits);
+
+    genwidth as f32 / genheight as f32
+}
+
+// end of each letter of a line, from 0.0 (the start of the line) to 1.0 (its end)
+pub fn get_letter_ends(text: &str, style: &TextStyle, height: u32) -> Vec<f32> {
+    let font = &style.font;
     let scale = Scale::uniform((height * 5) as f32); // 5x for a nicer image (more precision)
 
-    let genwidth = get_text_width(&font, scale, text);
-    let genheight = get_text_height(&font, scale, text);
+    let genheight = get_text_height(font, scale, text);
+    let spacing = render_spacing(style.letter_spacing, genheight, height);
+    let ends: Vec<f32> = text
+        .chars()
+        .zip(letter_positions(
+            font,
+            scale,
+            text,
+            spacing,
+            style.tabular_digits,
+        ))
+        .map(|(c, (position, padding))| {
+            position + padding / 2.0 + font.glyph(c).scaled(scale).h_metrics().advance_width
+        })
+        .collect();
+
+    let total = ends.last().copied().unwrap_or(0.0).max(1.0);
+    ends.iter().map(|x| (x / total).clamp(0.0, 1.0)).collect()
+}
 
-    return Ok(genwidth as f32 / genheight as f32);
+// the letter spacing is given in dmd pixels, the text is rendered larger, then resized to the height
+fn render_spacing(letter_spacing: i8, genheight: u32, height: u32) -> f32 {
+    letter_spacing as f32 * genheight as f32 / height.max(1) as f32
 }
 
 fn generate_text_image_single_line(
     text: &str,
-    font_path: &str,
+    style: &TextStyle,
     width: u32,
     height: u32,
-    background_color: Rgba<u8>,
-    text_color: Rgba<u8>,
-    text_align: &TextAlign,
 ) -> Result<(DynamicImage, u32, u32), String> {
-    let font_data = match read(Path::new(&font_path)) {
-        Ok(x) => x,
-        Err(_) => return Err(String::from("Unable to read font")),
-    };
-    let font = match Font::try_from_bytes(&font_data) {
-        Some(x) => x,
-        None => return Err(String::from("Unable to read font")),
-    };
+    let (font, text_color, letter_spacing, tabular_digits) = (
+        &style.font,
+        style.color,
+        style.letter_spacing,
+        style.tabular_digits,
+    );
     let scale = Scale::uniform((height * 5) as f32); // 5x for a nicer image (more precision)
 
-    let genwidth = get_text_width(&font, scale, text);
-    let genheight = get_text_height(&font, scale, text);
-    let img = RgbaImage::from_pixel(genwidth, genheight, background_color);
+    // with tabular digits, the digits have the same height too, and the first one starts at its box
+    let tabular_text = format!("{}0123456789", text);
+    let measured_text = if tabular_digits { &tabular_text } else { text };
+    let genheight = get_text_height(font, scale, measured_text);
+    let spacing = render_spacing(letter_spacing, genheight, height);
+    let mut genwidth = get_text_width(font, scale, text, spacing, tabular_digits);
+    let x = match text.chars().next() {
+        Some(c) if tabular_digits && c.is_ascii_digit() => 0,
+        _ => get_text_x(font, scale, text),
+    };
+    let y = get_text_y(font, scale, measured_text);
+
+    let positions = letter_positions(font, scale, text, spacing, tabular_digits);
+    // with tabular digits, a text ending with a digit ends with the width of the widest digit,
+    // whatever the last digit is
+    let tabular_end = match (positions.last(), text.chars().last()) {
+        (Some((position, padding)), Some(c)) if tabular_digits && c.is_ascii_digit() => {
+            let end = x as f32 + position - padding / 2.0 + digit_width(font, scale);
+            Some(end.ceil().max(1.0) as u32)
+        }
+        _ => None,
+    };
+    if let Some(end) = tabular_end {
+        genwidth = genwidth.max(end);
+    }
 
+    // rendered on black, text_alpha() makes the background transparent at the end
+    let img = RgbaImage::from_pixel(genwidth, genheight, Rgba([0, 0, 0, 255]));
     let mut dyn_img = DynamicImage::ImageRgba8(img);
-    let x = get_text_x(&font, scale, text);
-    let y = get_text_y(&font, scale, text);
 
-    draw_text_mut(&mut dyn_img, text_color, x, y, scale, &font, text);
+    if letter_spacing == 0 && !tabular_digits {
+        draw_text_mut(&mut dyn_img, text_color, x, y, scale, font, text);
+    } else {
+        // letter by letter, at the position given by the layout plus the spacing
+        for (c, (position, _padding)) in text.chars().zip(positions) {
+            draw_text_mut(
+                &mut dyn_img,
+                text_color,
+                x + position.round() as i32,
+                y,
+                scale,
+                font,
+                &c.to_string(),
+            );
+        }
+    }
 
-    // hack: now, crop width cause we know that get_text_width returns too large (for an unknown reason)
-    dyn_img = crop_width_right(&dyn_img)?;
+    dyn_img = match tabular_end {
+        Some(end) => dyn_img.crop_imm(0, 0, end, genheight),
+        // hack: now, crop width cause we know that get_text_width returns too large (for an unknown reason)
+        None => crop_width_right(&dyn_img)?,
+    };
     //dyn_img.save_with_format("x.png", ImageFormat::Png);
 
-    let (rgba_img_fit, start, new_width) = resize_image_to_fit(&dyn_img, width, height, text_align);
+    let (rgba_img_fit, start, new_width) =
+        resize_image_to_fit(&dyn_img, width, height, &style.align);
     let dyn_img_fit = DynamicImage::ImageRgba8(rgba_img_fit);
 
     Ok((dyn_img_fit, start, new_width))
@@ -347,6 +1463,23 @@ fn crop_width_right(dyn_img: &DynamicImage) -> Result<DynamicImage, String> {
     Ok(dyn_img.clone())
 }
 
+// each pixel is the average of factor x factor pixels of the image
+pub fn downscale_image(img: &RgbaImage, factor: u32) -> RgbaImage {
+    let area = factor * factor;
+    RgbaImage::from_fn(img.width() / factor, img.height() / factor, |x, y| {
+        let mut sum = [0u32; 4];
+        for dy in 0..factor {
+            for dx in 0..factor {
+                let pixel = img.get_pixel(x * factor + dx, y * factor + dy);
+                for c in 0..4 {
+                    sum[c] += pixel[c] as u32;
+                }
+            }
+        }
+        Rgba(sum.map(|x| (x / area) as u8))
+    })
+}
+
 pub fn copy_image(img_src: &DynamicImage, img_dst: &mut RgbaImage, x_offset: i32, y_offset: i32) {
     let width_src = img_src.width() as i32;
     let height_src = img_src.height() as i32;
@@ -367,6 +1500,34 @@ pub fn copy_image(img_src: &DynamicImage, img_dst: &mut RgbaImage, x_offset: i32
     }
 }
 
+// draw an image over another one at an offset, with source-over alpha blending
+// (copy_image replaces the pixels, even the transparent ones)
+pub fn blend_image(img_src: &RgbaImage, img_dst: &mut RgbaImage, x_offset: i32, y_offset: i32) {
+    let (width_dst, height_dst) = img_dst.dimensions();
+
+    for (x, y, src) in img_src.enumerate_pixels() {
+        let (x_dst, y_dst) = (x as i32 + x_offset, y as i32 + y_offset);
+        if x_dst < 0 || y_dst < 0 || x_dst >= width_dst as i32 || y_dst >= height_dst as i32 {
+            continue;
+        }
+        let dst = img_dst.get_pixel_mut(x_dst as u32, y_dst as u32);
+        match src[3] {
+            0 => {}
+            255 => *dst = *src,
+            _ => {
+                let src_alpha = src[3] as f32 / 255.0;
+                let dst_alpha = dst[3] as f32 / 255.0 * (1.0 - src_alpha);
+                let alpha = src_alpha + dst_alpha;
+                for c in 0..3 {
+                    dst[c] = ((src[c] as f32 * src_alpha + dst[c] as f32 * dst_alpha) / alpha)
+                        .round() as u8;
+                }
+                dst[3] = (alpha * 255.0).round() as u8;
+            }
+        }
+    }
+}
+
 // fit the image to width/height. Return the image, the start point and the width
 fn resize_image_to_fit(
     img: &DynamicImage,
@@ -385,7 +1546,8 @@ fn resize_image_to_fit(
 
     if width_img as f32 / height_img as f32 > width as f32 / height as f32 {
         let new_width = width;
-        let new_height = (height_img as f32 * new_width as f32 / width_img as f32) as u32;
+        let new_height =
+            ((height_img as f32 * new_width as f32 / width_img as f32) as u32).clamp(1, height);
         let reduced_img = img.resize_exact(new_width, new_height, imageops::FilterType::Lanczos3);
         copy_image(
             &reduced_img,
@@ -396,7 +1558,8 @@ fn resize_image_to_fit(
         (new_img, 0, new_width)
     } else {
         let new_height = height;
-        let new_width = (width_img as f32 * new_height as f32 / height_img as f32) as u32;
+        let new_width =
+            ((width_img as f32 * new_height as f32 / height_img as f32) as u32).clamp(1, width);
         let reduced_img = img.resize_exact(new_width, new_height, imageops::FilterType::Lanczos3);
         let align_x = match text_align {
             TextAlign::CENTER => (width - new_width) / 2,
@@ -0,0 +1,316 @@
+use crate::{
+    clock_text, files_to_frames, imageutils, is_text_to_animate, parse_color, send_frame,
+    ClockOptions, Dmd, TextOptions, ANIMATE_THRESHOLD, DEFAULT_DURATION,
+};
+use chrono::Local;
+use image::{DynamicImage, Rgba, RgbaImage};
+use std::time::{Duration, Instant};
+
+// what feeds a zone
+pub enum ZoneSource {
+    CLOCK(Option<String>), // optional strftime format
+    TEXT(String),
+    FILE(String),
+}
+
+// a zone, as given on the command line: "x,y,width,height;source[;option...]"
+pub struct Zone {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    source: ZoneSource,
+    color: Option<Rgba<u8>>,
+    speed: Option<u32>,
+    align: imageutils::TextAlign,
+}
+
+// what is displayed in a zone, prepared once
+enum ZoneContent {
+    FIXED(RgbaImage),
+    SCROLL(DynamicImage, u32, u32, u32), // image, start, width and speed
+    ANIMATION(Vec<(RgbaImage, u32)>),
+    CLOCK(Option<String>, String, RgbaImage), // format, current text and its image
+}
+
+// a length in pixels, or in percent of the dmd size (40%)
+pub fn parse_length(value: &str, total: u32) -> Result<u32, String> {
+    let value = value.trim();
+    match value.strip_suffix('%') {
+        Some(percent) => match percent.trim().parse::<f32>() {
+            Ok(x) => Ok((total as f32 * x / 100.0).round() as u32),
+            Err(_) => Err(format!("Invalid zone length: {}", value)),
+        },
+        None => value
+            .parse::<u32>()
+            .map_err(|_| format!("Invalid zone length: {}", value)),
+    }
+}
+
+pub fn parse_zone(spec: &str, render_width: u32, render_height: u32) -> Result<Zone, String> {
+    let mut parts = spec.split(';');
+
+    let geometry: Vec<&str> = parts.next().unwrap_or("").split(',').collect();
+    if geometry.len() != 4 {
+        return Err(format!(
+            "Invalid zone: {} (expected x,y,width,height;source)",
+            spec
+        ));
+    }
+    let x = parse_length(geometry[0], render_width)?;
+    let y = parse_length(geometry[1], render_height)?;
+    let width = parse_length(geometry[2], render_width)?;
+    let height = parse_length(geometry[3], render_height)?;
+    if width == 0 || height == 0 {
+        return Err(format!("Invalid zone: {} (empty area)", spec));
+    }
+
+    let source = match parts.next().map(|x| x.split_once('=').unwrap_or((x, ""))) {
+        Some(("clock", "")) => ZoneSource::CLOCK(None),
+        Some(("clock", format)) => ZoneSource::CLOCK(Some(format.to_string())),
+        Some(("text", text)) => ZoneSource::TEXT(text.to_string()),
+        Some(("file", path)) if !path.is_empty() => ZoneSource::FILE(path.to_string()),
+        _ => {
+            return Err(format!(
+                "Invalid zone: {} (source is clock, text=... or file=...)",
+                spec
+            ));
+        }
+    };
+
+    let mut zone = Zone {
+        x,
+        y,
+        width,
+        height,
+        source,
+        color: None,
+        speed: None,
+        align: imageutils::TextAlign::CENTER,
+    };
+
+    for option in parts {
+        match option.split_once('=') {
+            Some(("color", color)) => {
+                zone.color = Some(parse_color(color)?);
+            }
+            Some(("speed", speed)) => match speed.parse::<u32>() {
+                Ok(x) if x > 0 => zone.speed = Some(x),
+                _ => return Err(format!("Invalid zone speed: {}", speed)),
+            },
+            Some(("align", align)) => {
+                zone.align = match align {
+                    "center" => imageutils::TextAlign::CENTER,
+                    "left" => imageutils::TextAlign::LEFT,
+                    "right" => imageutils::TextAlign::RIGHT,
+                    _ => return Err(format!("Invalid zone alignment: {}", align)),
+                };
+            }
+            _ => {
+                return Err(format!("Invalid zone option: {}", option));
+            }
+        }
+    }
+
+    Ok(zone)
+}
+
+// the style of the texts of the zone, its color and its alignment over the ones of the texts
+fn zone_style(zone: &Zone, style: &imageutils::TextStyle) -> imageutils::TextStyle {
+    imageutils::TextStyle {
+        color: zone.color.unwrap_or(style.color),
+        align: zone.align.clone(),
+        ..style.clone()
+    }
+}
+
+fn render_zone_text(
+    zone: &Zone,
+    text: &str,
+    style: &imageutils::TextStyle,
+) -> Result<RgbaImage, String> {
+    let (dyn_img, _start, _new_width) =
+        imageutils::generate_text_image(text, style, &None, zone.width, zone.height)?;
+    Ok(dyn_img.to_rgba8())
+}
+
+fn prepare_zone(
+    zone: &Zone,
+    style: &imageutils::TextStyle,
+    speed: u32,
+    fps: u32,
+    raw_size: (u32, u32),
+) -> Result<ZoneContent, String> {
+    match &zone.source {
+        ZoneSource::CLOCK(format) => Ok(ZoneContent::CLOCK(
+            format.clone(),
+            String::new(),
+            RgbaImage::new(zone.width, zone.height),
+        )),
+        ZoneSource::TEXT(text) => {
+            let (should_animate, new_width) = is_text_to_animate(
+                text,
+                style,
+                zone.width,
+                zone.height,
+                false,
+                None,
+                ANIMATE_THRESHOLD,
+            );
+            if should_animate {
+                let (dyn_img, start, real_width) =
+                    imageutils::generate_text_image(text, style, &None, new_width, zone.height)?;
+                Ok(ZoneContent::SCROLL(
+                    dyn_img,
+                    start,
+                    real_width,
+                    zone.speed.unwrap_or(speed),
+                ))
+            } else {
+                Ok(ZoneContent::FIXED(render_zone_text(zone, text, style)?))
+            }
+        }
+        ZoneSource::FILE(path) => {
+            let frames = files_to_frames(path.clone(), fps, raw_size, DEFAULT_DURATION)?;
+            let mut images = Vec::new();
+            for frame in frames {
+                let (x, y) = frame.delay().numer_denom_ms();
+                let img =
+                    imageutils::image2dmdsize(frame.buffer(), &zone.align, zone.width, zone.height);
+                images.push((img, (x as f32 / y as f32) as u32));
+            }
+            if images.len() == 1 {
+                Ok(ZoneContent::FIXED(images.remove(0).0))
+            } else {
+                Ok(ZoneContent::ANIMATION(images))
+            }
+        }
+    }
+}
+
+// time for the content to be displayed completely once, 0 for static content
+fn zone_cycle(zone: &Zone, content: &ZoneContent) -> u64 {
+    match content {
+        ZoneContent::SCROLL(_, _, width, speed) => (zone.width + width) as u64 * *speed as u64,
+        ZoneContent::ANIMATION(images) => images.iter().map(|(_, x)| *x as u64).sum(),
+        _ => 0,
+    }
+}
+
+// the image of the zone at a given time (in ms since the start)
+fn zone_image(
+    zone: &Zone,
+    content: &mut ZoneContent,
+    elapsed: u64,
+    style: &imageutils::TextStyle,
+    clock: &ClockOptions,
+) -> Result<RgbaImage, String> {
+    let cycle = zone_cycle(zone, content);
+
+    match content {
+        ZoneContent::FIXED(img) => Ok(img.clone()),
+        ZoneContent::SCROLL(strip, start, _width, speed) => {
+            let offset = (elapsed % cycle.max(1)) / *speed as u64;
+            let mut img = RgbaImage::new(zone.width, zone.height);
+            imageutils::copy_image(
+                strip,
+                &mut img,
+                zone.width as i32 - offset as i32 - *start as i32,
+                0,
+            );
+            Ok(img)
+        }
+        ZoneContent::ANIMATION(images) => {
+            let mut position = elapsed % cycle.max(1);
+            for (img, duration) in images.iter() {
+                if position < *duration as u64 {
+                    return Ok(img.clone());
+                }
+                position -= *duration as u64;
+            }
+            Ok(images[0].0.clone())
+        }
+        ZoneContent::CLOCK(format, previous, img) => {
+            let clock = ClockOptions {
+                format: format.clone(),
+                ..clock.clone()
+            };
+            let localtime = clock_text(&Local::now(), &clock);
+            if localtime != *previous {
+                *img = render_zone_text(zone, &localtime, style)?;
+                *previous = localtime;
+            }
+            Ok(img.clone())
+        }
+    }
+}
+
+// compose all the zones into one frame per tick, and send it when it changes
+pub fn play_layout(
+    dmd: &Dmd,
+    zones: &[Zone],
+    options: &TextOptions,
+    clock: &ClockOptions,
+    fps: u32,
+    raw_size: (u32, u32),
+    once: bool,
+) -> Result<(), String> {
+    let styles: Vec<imageutils::TextStyle> = zones
+        .iter()
+        .map(|zone| zone_style(zone, &options.style))
+        .collect();
+    let mut contents = Vec::new();
+    for (zone, style) in zones.iter().zip(&styles) {
+        contents.push(prepare_zone(zone, style, options.speed, fps, raw_size)?);
+    }
+
+    // with --once, stop when every zone was displayed completely
+    let cycle = zones
+        .iter()
+        .zip(contents.iter())
+        .map(|(zone, content)| zone_cycle(zone, content))
+        .max()
+        .unwrap_or(0);
+
+    let started = Instant::now();
+    let mut previous_frame: Option<Box<[u8]>> = None;
+
+    loop {
+        let elapsed = (started.elapsed().as_millis() as f32 * dmd.rate) as u64;
+
+        let mut frame = options.background.clone();
+        for ((zone, content), style) in zones.iter().zip(contents.iter_mut()).zip(&styles) {
+            let img = zone_image(zone, content, elapsed, style, clock)?;
+            imageutils::blend_image(&img, &mut frame, zone.x as i32, zone.y as i32);
+        }
+
+        let frame = imageutils::orient_image(&frame, &dmd.image_options.orientation);
+        let img565 = imageutils::image2dmdimage(
+            &frame,
+            &imageutils::TextAlign::CENTER,
+            &dmd.image_options.colors,
+            dmd.width,
+            dmd.height,
+        )?;
+
+        if previous_frame.as_ref() != Some(&img565) {
+            match send_frame(dmd.client, dmd.header, &img565) {
+                Ok(_) => {}
+                Err(e) => {
+                    return Err(e.to_string());
+                }
+            };
+            previous_frame = Some(img565);
+        }
+
+        if once && elapsed >= cycle {
+            return Ok(());
+        }
+
+        if dmd.cancel.sleep(Duration::from_millis(
+            (options.speed as f32 / dmd.rate) as u64,
+        )) {
+            return Ok(());
+        }
+    }
+}
@@ -1,27 +1,301 @@
-use chrono::{Local, NaiveDateTime, TimeDelta, TimeZone};
-use clap::Parser;
+use chrono::{DateTime, Local, NaiveDateTime, NaiveTime, TimeDelta, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+use clap::{parser::ValueSource, ArgMatches, CommandFactory, FromArgMatches, Parser};
 use image::{
     codecs::gif::GifDecoder, imageops, io::Reader, AnimationDecoder, Delay, DynamicImage, Frame,
     Rgba, RgbaImage,
 };
-use std::{fs::File, io::BufReader, io::Write, net::TcpStream, thread, time::Duration};
+use rusttype::Font;
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt, fs,
+    fs::File,
+    io,
+    io::BufReader,
+    io::Read,
+    io::Write,
+    iter,
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    os::unix::process::CommandExt,
+    path::{Path, PathBuf},
+    process::{self, Command, Stdio},
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    thread,
+    time::{Duration, Instant},
+};
 
+mod alerts;
+mod cancel;
+mod clockstyle;
+mod compositor;
+mod control;
+mod cron;
+mod dimming;
+mod draw;
+mod framecache;
+#[cfg(test)]
+mod golden;
+mod ics;
 mod imageutils;
+mod layout;
+mod marquee;
+mod mock;
+mod netinfo;
+mod notifications;
+mod palette;
+mod panel;
+mod particles;
+// the name of the font is only needed by the golden tests and --font builtin
+#[cfg_attr(not(any(test, feature = "builtin-font")), allow(dead_code))]
+mod pixelfont;
+mod playback;
+mod poll;
+mod preview;
+mod protocol;
+mod relay;
+mod rss;
+mod schedule;
+mod screensaver;
+mod scripting;
+mod sink;
+mod slideshow;
+mod source;
+mod systemd;
+mod template;
+mod ticker;
+mod tls;
+mod visualizer;
+mod watch;
+
+use sink::{FrameSink, ServerSink, ServerStream, TickedSink};
 
 #[derive(Parser)]
 struct Cli {
-    /// dmd server host
-    #[arg(long, default_value = "localhost")]
-    host: String,
-    /// network connexion port
-    #[arg(short, long, default_value_t = 6789)]
-    port: u16,
-    /// image path file
+    /// dmd server host (localhost by default)
+    #[arg(long, env = "DMD_HOST", default_value = None)]
+    host: Option<String>,
+    /// network connexion port (6789 by default)
+    #[arg(short, long, env = "DMD_PORT", default_value = None)]
+    port: Option<u16>,
+    /// server of a tile of the dmd, HOST[:PORT] (repeatable): the dmd (--width) is split in equal tiles
+    /// from left to right, one per server, like 3 chained 128x32 panels for a 384x32 dmd
+    #[arg(long, default_value = None)]
+    tile: Vec<String>,
+    /// panel of the config file to play on, with its host, port and size ([panel.topper] sections)
+    #[arg(long, default_value = None)]
+    panel: Option<String>,
+    /// config file of the panels
+    #[arg(long, default_value = panel::DEFAULT_CONFIG)]
+    config: String,
+    /// maximum time to connect to the server (in milliseconds)
+    #[arg(long, default_value = None)]
+    connect_timeout: Option<u32>,
+    /// number of connection attempts after the first one fails
+    #[arg(long, default_value_t = 0)]
+    retries: u32,
+    /// time between the connection attempts (in milliseconds)
+    #[arg(long, default_value_t = 1000)]
+    retry_delay: u32,
+    /// wait until the server accepts the connection, at boot, trying again less and less often
+    #[arg(long, default_value_t = false)]
+    wait_for_server: bool,
+    /// maximum time to wait for the server (in seconds)
+    #[arg(long, default_value_t = 60)]
+    wait_timeout: u32,
+    /// encrypt the connection to the server with tls
+    #[arg(long, default_value_t = false)]
+    tls: bool,
+    /// tls: pem file of the certificate authorities to check the server against (default the usual ones)
+    #[arg(long, default_value = None)]
+    tls_ca: Option<String>,
+    /// tls: do not check the certificate of the server
+    #[arg(long, default_value_t = false)]
+    insecure: bool,
+    /// token sent to the servers requiring an authentication, the protocol version is negotiated
+    /// (the token required by --mock-server and from the sources of --relay)
+    #[arg(long, default_value = None)]
+    auth_token: Option<String>,
+    /// version of the DMDStream protocol (1 by default, the highest one proposed with --auth-token or --compress)
+    #[arg(long, default_value = None)]
+    protocol_version: Option<u8>,
+    /// compression of the frames (zlib or lz4), when the server accepts it in the handshake
+    #[arg(long, default_value = None)]
+    compress: Option<String>,
+    /// write the DMDStream frames to a device instead of a server: serial port (configured beforehand) or fifo
+    #[arg(long, default_value = None)]
+    serial: Option<String>,
+    /// image path file (or a directory of numbered png frames)
     #[arg(short, long, default_value=None)]
     file: Option<String>,
-    /// text
+    /// file and template: play the file again each time it is written by another program (animations are
+    /// played once), or apply the changes of the template without reconnecting
+    #[arg(long, default_value_t = false)]
+    watch: bool,
+    /// display the marquee of a game, searched by name in the marquee directories
+    #[arg(long, default_value=None)]
+    game: Option<String>,
+    /// game: system of the game (snes), replaces {system} in the marquee directories
+    #[arg(long, default_value=None)]
+    system: Option<String>,
+    /// game: directory of marquee images (repeatable, default /userdata/system/configs/dmd/marquees/{system} and /userdata/roms/{system}/images)
+    #[arg(long)]
+    marquee_dir: Vec<String>,
+    /// game: url of an image when no marquee is found, {game} and {system} are replaced
+    #[arg(long, default_value=None)]
+    marquee_url: Option<String>,
+    /// game: directory of the downloaded marquees (default ~/.cache/dmd-play/marquees)
+    #[arg(long, default_value=None)]
+    marquee_cache: Option<String>,
+    /// live frames of an emulator: named pipe, or shared memory file (/dev/shm/...) read --fps times per second
+    #[arg(long, default_value=None)]
+    source: Option<String>,
+    /// source: pixel format of the frames, gray2, gray4, rgb24 or rgb565
+    #[arg(long, default_value = "gray4")]
+    source_format: String,
+    /// listen on this port for DMDStream connections and forward their frames, rescaled to the dmd size
+    /// (the socket of this port can be passed by systemd socket activation).
+    /// without --auth-token, only the sources of this host are accepted
+    #[arg(long, default_value=None)]
+    relay: Option<u16>,
+    /// act as a dmd server listening on this port, printing and checking the frames received (port 0: any free port).
+    /// with --once, it stops after the first client, with an error exit code for an invalid frame
+    #[arg(long, default_value = None)]
+    mock_server: Option<u16>,
+    /// mock server: display the frames in the terminal
+    #[arg(long, default_value_t = false)]
+    mock_show: bool,
+    /// relay: another port for the sources of a priority, PORT:PRIORITY[:POLICY] (repeatable, --relay is priority 0).
+    /// a source of higher priority (game over) cuts the others (marquee), which resume afterwards.
+    /// policy when the dmd is busy with a source of higher or equal priority: interrupt (default), queue or drop
+    #[arg(long, default_value = None)]
+    relay_channel: Vec<String>,
+    /// relay: image, gif or directory of frames played while no source is connected
+    #[arg(long, default_value = None)]
+    idle_file: Option<String>,
+    /// relay: clock displayed while no source is connected
+    #[arg(long, default_value_t = false)]
+    idle_clock: bool,
+    /// frames per second for a directory of frames or a raw file (12 by default), and instead of
+    /// the delays of the animations when given
+    #[arg(long, default_value = None)]
+    fps: Option<u32>,
+    /// animation: first frame played (from 0)
+    #[arg(long, default_value_t = 0)]
+    start_frame: usize,
+    /// animation: last frame played
+    #[arg(long, default_value = None)]
+    end_frame: Option<usize>,
+    /// animation: milliseconds played from the first frame, the next frames are left out
+    #[arg(long, default_value = None)]
+    trim_ms: Option<u32>,
+    /// animation: played once, then its last frame stays displayed until dmd-play is stopped
+    #[arg(long, default_value_t = false)]
+    hold_last: bool,
+    /// keep the converted frames of the files played, by the hash of their content: a file with the
+    /// same content and the same settings is played again without decoding nor resizing it
+    #[arg(long, default_value_t = false)]
+    frame_cache: bool,
+    /// frame cache: directory of the converted frames (default ~/.cache/dmd-play/frames)
+    #[arg(long, default_value=None)]
+    frame_cache_dir: Option<String>,
+    /// frame cache: size in MB, the least recently played files are removed beyond it
+    #[arg(long, default_value_t = 512)]
+    frame_cache_size: u64,
+    /// size of the frames of a .raw rgb565 file or a source (128x32, default is the dmd size)
+    #[arg(long, default_value=None)]
+    raw_size: Option<String>,
+    /// image fit: contain, cover, stretch or crop
+    #[arg(long, default_value=None)]
+    fit: Option<String>,
+    /// image scaling: smooth, nearest (crisp pixels), integer (crisp pixels by a whole factor) or a factor like 2x
+    #[arg(long, default_value = "smooth")]
+    scale: String,
+    /// image resize filter for the smooth scale: nearest, triangle, catmullrom, gaussian or lanczos
+    #[arg(long, default_value = "lanczos")]
+    filter: String,
+    /// image area to display: x,y,width,height
+    #[arg(long, default_value=None)]
+    crop: Option<String>,
+    /// rotate the content clockwise: 90, 180 or 270
+    #[arg(long, default_value_t = 0)]
+    rotate: u32,
+    /// color of the images displayed as transparent (black) on the overlay layer, R,G,B (255,0,255)
+    #[arg(long, default_value=None)]
+    transparent_color: Option<String>,
+    /// recolor grayscale images with a pin2dmd palette (.pal), or one R,G,B color per line from
+    /// black to white. only the palette is used: the colorizations (pin2dmd mappings, serum .cRZ)
+    /// are not supported
+    #[arg(long, default_value=None)]
+    palette: Option<String>,
+    /// palette: index of the palette in a pin2dmd file (default is its default palette)
+    #[arg(long, default_value=None)]
+    palette_index: Option<u16>,
+    /// mirror the content horizontally
+    #[arg(long, default_value_t = false)]
+    flip_h: bool,
+    /// mirror the content vertically
+    #[arg(long, default_value_t = false)]
+    flip_v: bool,
+    /// write the rgb565 frames played to a .raw file instead of a server, once like with --once
+    /// (played again with --file and --fps)
+    #[arg(long, default_value=None)]
+    dump_raw: Option<String>,
+    /// sprite sheet image path file, sliced into an animation
+    #[arg(long, default_value=None)]
+    spritesheet: Option<String>,
+    /// sprite sheet: size of each sprite (32x32)
+    #[arg(long, default_value=None)]
+    sprite_size: Option<String>,
+    /// sprite sheet: frames per second
+    #[arg(long, default_value_t = 12)]
+    sprite_fps: u32,
+    /// text, with {env:NAME}, {time:%H:%M}, {file:PATH} and {name} (see --var) placeholders, and
+    /// {color:NAME}...{/color} around the parts of another color
     #[arg(short, long, default_value=None)]
     text: Option<String>,
+    /// texts displayed one after the other, separated by | ("A|B|C"), with the placeholders of --text
+    #[arg(long, default_value=None)]
+    texts: Option<String>,
+    /// texts: seconds each fixed text is displayed, a scrolling one is scrolled once
+    #[arg(long, default_value_t = 5)]
+    cycle: u32,
+    /// shell command whose output is displayed as text (sensors | head -1). it is killed after 10
+    /// seconds, and an error exit status stops the playing
+    #[arg(long, default_value=None)]
+    exec: Option<String>,
+    /// url of a json api (scores, queues, downloads) whose values are displayed as text
+    #[arg(long, default_value = None)]
+    poll_json: Option<String>,
+    /// poll json: path of the value displayed, like jq (.games[0].home.score)
+    #[arg(long, default_value = None)]
+    jq_path: Option<String>,
+    /// poll json: text with {json:PATH} replaced by the values, and the placeholders of --text
+    #[arg(long, default_value = None)]
+    poll_template: Option<String>,
+    /// stock or crypto symbols whose quotes scroll, colored by their change (BTC-USD,AAPL)
+    #[arg(long, default_value = None)]
+    ticker: Option<String>,
+    /// ticker: number of seconds between two fetches of the quotes
+    #[arg(long, default_value_t = 60)]
+    ticker_interval: u32,
+    /// display the ip address, the wifi network with its signal and the ping time of --ping-host
+    #[arg(long, default_value_t = false)]
+    netinfo: bool,
+    /// netinfo: host pinged, its round trip time is displayed
+    #[arg(long, default_value = None)]
+    ping_host: Option<String>,
+    /// text, exec, poll json and netinfo: number of seconds between each evaluation of the text
+    /// (once by default, 30 for poll json, 5 for netinfo)
+    #[arg(long, default_value=None)]
+    refresh: Option<u32>,
+    /// text modes: animate the colors of the text, rainbow or gradient (moves --gradient). a fixed
+    /// text is played a full cycle before its refresh (or with --once)
+    #[arg(long, default_value=None)]
+    color_cycle: Option<String>,
+    /// color cycle: hue degrees per second, a full cycle is 360
+    #[arg(long, default_value_t = 90)]
+    cycle_speed: u32,
     /// display current time
     #[arg(long, default_value_t = false)]
     clock: bool,
@@ -34,13 +308,172 @@ struct Cli {
     /// clock: 12-hour format with AM and PM (default it 24h)
     #[arg(long, default_value_t = false)]
     h12: bool,
-    /// display a countdown (2050-06-30 15:00:00)
+    /// clock: digits drawn like 7seg (segments), nixie (tubes), flip (cards flipping when they change)
+    /// or binary (columns of bits) instead of with the font, or words (IT IS TEN PAST FIVE)
+    #[arg(long, default_value = None)]
+    clock_style: Option<String>,
+    /// clock: time of an alarm (07:30), the clock is displayed until then
+    #[arg(long, default_value=None)]
+    alarm: Option<String>,
+    /// alarm: text flashing when the alarm rings
+    #[arg(long, default_value = "ALARM")]
+    alarm_text: String,
+    /// alarm: number of seconds the alarm rings
+    #[arg(long, default_value_t = 60)]
+    alarm_duration: u32,
+    /// alarm: shell command run when the alarm rings
+    #[arg(long, default_value=None)]
+    alarm_command: Option<String>,
+    /// clock and countdown: IANA time zone (Europe/Paris), the system one by default
+    #[arg(long, default_value=None)]
+    timezone: Option<String>,
+    /// layers: base layer displayed behind the ticker, clock or an image path file
+    #[arg(long, default_value=None)]
+    base: Option<String>,
+    /// layers: text of the ticker
+    #[arg(long, default_value=None)]
+    ticker_text: Option<String>,
+    /// layers: rss feed url, the titles of its items are displayed in the ticker
+    #[arg(long, default_value=None)]
+    ticker_rss: Option<String>,
+    /// layers: ticker height in pixels (default is half the dmd height)
+    #[arg(long, default_value=None)]
+    ticker_height: Option<u32>,
+    /// layers: ticker color R,G,B (default is the text color)
+    #[arg(long, default_value=None)]
+    ticker_color: Option<String>,
+    /// layers: time between two updates of the rss feed, in seconds
+    #[arg(long, default_value_t = 600)]
+    ticker_refresh: u64,
+    /// layout: zone of the dmd with its content, "x,y,width,height;source[;option...]" (repeatable)
+    /// - x,y,width,height in pixels or percent (0,0,40%,100%)
+    /// - source: clock, clock=FORMAT, text=TEXT or file=PATH
+    /// - options: color=R,G,B, speed=MS, align=left|center|right
+    #[arg(long, verbatim_doc_comment)]
+    zone: Vec<String>,
+    /// template: toml or json file describing the elements of a screen (text, image, rect, progress)
+    #[arg(long, default_value=None)]
+    template: Option<String>,
+    /// value of a variable, name=value (repeatable), used as {name} in texts and templates and variable("name") in scripts
+    #[arg(long)]
+    var: Vec<String>,
+    /// display a progress bar (0-100)
     #[arg(long, default_value=None)]
-    countdown: Option<String>,
+    progress: Option<f32>,
+    /// progress: text displayed above the bar
+    #[arg(long, default_value=None)]
+    progress_label: Option<String>,
+    /// json file polled for a progress bar, {"label": "Downloading", "percent": 42} or a number
+    #[arg(long, default_value = None)]
+    progress_file: Option<String>,
+    /// shell command polled for a progress bar, printing the status like --progress-file
+    #[arg(long, default_value = None)]
+    progress_exec: Option<String>,
+    /// progress file and exec: text above the bar, {name} replaced by the values of the status
+    #[arg(long, default_value = "{label}")]
+    progress_template: String,
+    /// draw shapes, commands separated by ';' (line 0 0 127 31; color 0,255,0; disc 64 16 8)
+    /// - color R,G,B
+    /// - line X1 Y1 X2 Y2
+    /// - rect X Y W H, fill X Y W H
+    /// - circle X Y RADIUS, disc X Y RADIUS
+    /// - polygon X,Y X,Y X,Y ...
+    #[arg(long, default_value=None, verbatim_doc_comment)]
+    draw: Option<String>,
+    /// display the audio: spectrum or vu
+    #[arg(long, default_value=None)]
+    visualizer: Option<String>,
+    /// visualizer: command writing the audio on its output (s16le, mono, 44100 Hz), - to read stdin
+    #[arg(
+        long,
+        default_value = "parec --format=s16le --channels=1 --rate=44100 --latency-msec=20"
+    )]
+    audio_command: String,
+    /// visualizer: frames per second
+    #[arg(long, default_value_t = 30)]
+    visualizer_fps: u32,
+    /// procedural animation: life, plasma, fire or matrix
+    #[arg(long, default_value=None)]
+    screensaver: Option<String>,
+    /// screensaver: frames per second
+    #[arg(long, default_value_t = 25)]
+    screensaver_fps: u32,
+    /// particle effect: fireworks, snow or starfield
+    #[arg(long, default_value=None)]
+    effect: Option<String>,
+    /// effect: number of particles for a 128x32 dmd, rockets per minute for fireworks
+    #[arg(long, default_value_t = 50)]
+    effect_density: u32,
+    /// effect: speed of the particles (1.0 is the normal speed)
+    #[arg(long, default_value_t = 1.0)]
+    effect_speed: f32,
+    /// effect: colors of the particles, R,G,B;R,G,B... (default is white, random for fireworks)
+    #[arg(long, default_value=None)]
+    effect_colors: Option<String>,
+    /// cycle through the images and gifs of a directory and its subdirectories, the images added are played too
+    #[arg(long, default_value = None)]
+    slideshow: Option<String>,
+    /// slideshow: seconds each image is displayed
+    #[arg(long, default_value_t = 15)]
+    interval: u32,
+    /// slideshow: random order, the images drawn by their weight ([[weight]] files="sf2*" weight=3
+    /// in the slideshow.toml file of the directory)
+    #[arg(long, default_value_t = false)]
+    shuffle: bool,
+    /// slideshow: the last N images are not drawn again by --shuffle
+    #[arg(long, default_value_t = 1)]
+    no_repeat_last: usize,
+    /// slideshow: frames of the crossfade from an image to the next one (30 per second), none by default
+    #[arg(long, default_value_t = 0)]
+    crossfade: u32,
+    /// slideshow: only the files matching one of these patterns (*.gif, sf2-*), all the images by default
+    #[arg(long)]
+    include: Vec<String>,
+    /// slideshow: convert all the images into the frame cache while the first ones are played, printing
+    /// the progress on stderr, the next images are then displayed at once (enables --frame-cache).
+    /// only the slideshow directory is preloaded, not the --texts, --file lists or the relay idle file
+    #[arg(long, default_value_t = false)]
+    preload: bool,
+    /// calibration pattern: grid, gradient, rgb or pixel-walk
+    #[arg(long, default_value = None)]
+    test_pattern: Option<String>,
+    /// test pattern: pixels per second of the pixel walk
+    #[arg(long, default_value_t = 50)]
+    test_pattern_fps: u32,
+    /// display the size of the dmd, the server and the numbered corners, to know which panel is which
+    #[arg(long, default_value_t = false)]
+    identify: bool,
+    /// identify: display time (in seconds), the dmd is cleared after
+    #[arg(long, default_value_t = 5)]
+    identify_duration: u32,
+    /// rhai script generating the frames, with a render(frame, time) function
+    #[arg(long, default_value=None)]
+    script: Option<String>,
+    /// script: frames per second
+    #[arg(long, default_value_t = 25)]
+    script_fps: u32,
+    /// display a countdown (2050-06-30 15:00:00), repeatable, with an optional label (Party=2050-06-30 15:00:00)
+    #[arg(long)]
+    countdown: Vec<String>,
+    /// countdown to the next occurrence of a cron expression, minute hour day month weekday (0 20 * * FRI), repeatable, with an optional label (Friday=0 20 * * FRI)
+    #[arg(long)]
+    countdown_cron: Vec<String>,
+    /// countdown to the next event of a calendar (.ics file or url), its title as label, repeatable
+    #[arg(long)]
+    ics: Vec<String>,
+    /// ics: minutes between two reads of the calendars
+    #[arg(long, default_value_t = 15)]
+    ics_refresh: u32,
+    /// countdowns: number of seconds each countdown is displayed when there are several
+    #[arg(long, default_value_t = 5)]
+    countdown_cycle: u32,
+    /// countdowns: display all the countdowns at once, one per line
+    #[arg(long, default_value_t = false)]
+    countdown_lines: bool,
     /// equivalent of changing all format with a prefix
     #[arg(long, default_value=None)]
     countdown_header: Option<String>,
-    /// countdown format
+    /// countdown format: {W} weeks, {D} days, {H} hours, {M} minutes, {S} seconds, {TH}/{TM} total hours/minutes, {M:02} pads with zeros
     #[arg(long, default_value = "{D:2}d {H:2}:{M:02}:{S:02}")]
     countdown_format: String,
     /// countdown format when less than 1 day
@@ -52,8 +485,12 @@ struct Cli {
     /// countdown format when less than 1 minute
     #[arg(long, default_value = "{S:02}")]
     countdown_format_0_minute: String,
-    /// path to the font file
-    #[arg(long, default_value = "/usr/share/fonts/dejavu/DejaVuSans.ttf")]
+    /// path to the font file (builtin: the 5x7 pixel font, with the builtin-font feature)
+    #[arg(
+        long,
+        env = "DMD_FONT",
+        default_value = "/usr/share/fonts/dejavu/DejaVuSans.ttf"
+    )]
     font: String,
     /// text alignment: center, left or right
     #[arg(short, long, default_value=None)]
@@ -61,6 +498,12 @@ struct Cli {
     /// number of pixels between each line of text
     #[arg(short, long, default_value_t = 2)]
     line_spacing: u8,
+    /// space added between the letters, in pixels (negative for a condensed text)
+    #[arg(long, default_value_t = 0, allow_negative_numbers = true)]
+    letter_spacing: i8,
+    /// same width for all the digits, so that a clock or a score does not move when they change
+    #[arg(long, default_value_t = false)]
+    tabular_digits: bool,
     /// red text color level (0-255)
     #[arg(short, long, default_value_t = 255)]
     red: u8,
@@ -73,15 +516,37 @@ struct Cli {
     /// don't loop forever
     #[arg(long, default_value_t = false)]
     once: bool,
+    /// play any mode for this time (in seconds), then exit
+    #[arg(long, default_value = None)]
+    duration: Option<u32>,
+    /// duration: clear the dmd once done
+    #[arg(long, default_value_t = false)]
+    duration_clear: bool,
     /// clear the screen
     #[arg(long, default_value_t = false)]
     clear: bool,
     /// restore the previous frames once finished
     #[arg(long, default_value_t = false)]
     overlay: bool,
-    /// time to pause fixed images for the overlay in ms
+    /// time to pause fixed images for the overlay in ms, and to show the texts of the overlay file
     #[arg(long, default_value_t = 1000)]
     overlay_time: u64,
+    /// show the text written in this file (then removed) over what is played, which resumes afterwards
+    /// where it was: no other process is needed on the main layer, unlike --overlay
+    #[arg(long, default_value = None)]
+    overlay_file: Option<String>,
+    /// display the desktop notifications of the session or system bus (org.freedesktop.Notifications),
+    /// over what is played or alone, during their timeout (--overlay-time by default)
+    #[arg(long, default_value = None)]
+    notifications: Option<String>,
+    /// receive alerts on this port, flashed like the notifications: twitch eventsub webhooks (behind
+    /// a https proxy), or {"title": "...", "message": "..."} posted by any other service. Only from
+    /// this host without --alerts-secret
+    #[arg(long, default_value = None)]
+    alerts: Option<u16>,
+    /// alerts: secret of the twitch subscriptions checked in the signatures, or bearer token
+    #[arg(long, default_value = None)]
+    alerts_secret: Option<String>,
     /// convert text in all caps
     #[arg(long, default_value_t = false)]
     caps: bool,
@@ -94,18 +559,133 @@ struct Cli {
     /// sleep time during each text position (in milliseconds)
     #[arg(short, long, default_value_t = 30)]
     speed: u32,
-    /// hd format (256x64 dmd size)
+    /// scrolling text: number of steps per pixel, the text glides between the pixels (4 is smooth)
+    #[arg(long, default_value_t = 1)]
+    subpixel_scroll: u32,
+    /// scrolling text: pixels between the end of the text and its return (the dmd width by default)
+    #[arg(long, default_value = None)]
+    scroll_gap: Option<u32>,
+    /// scrolling text: displayed between the end of the text and its return (" *** ")
+    #[arg(long, default_value = None)]
+    scroll_separator: Option<String>,
+    /// scrolling text: the end of the text is followed by its start, like a ticker, the screen is never empty
+    #[arg(long, default_value_t = false)]
+    scroll_continuous: bool,
+    /// scrolling text: display the start of the text during this time (in milliseconds), then scroll
+    /// to its end and display it during this time too, for a text slightly larger than the dmd
+    #[arg(long, default_value = None)]
+    hold_ms: Option<u32>,
+    /// text: scroll instead of displaying letters smaller than this height (in pixels)
+    #[arg(long, default_value = None)]
+    min_text_height: Option<u32>,
+    /// text: scroll when the text is larger than this number of times the dmd ratio
+    /// (when the text would use less than 1/3 of the height by default)
+    #[arg(long, default_value_t = ANIMATE_THRESHOLD)]
+    animate_threshold: f32,
+    /// render the frames in the terminal instead of sending them to the server
+    #[arg(long, default_value_t = false)]
+    dry_run: bool,
+    /// save the last frame sent as a png file (updated every second at most), to monitor the dmd
+    #[arg(long, default_value = None)]
+    snapshot: Option<String>,
+    /// send the current frame again after this number of seconds without a new one,
+    /// so that the connection is not dropped while a fixed image is displayed
+    #[arg(long, default_value = None)]
+    keepalive: Option<u32>,
+    /// only display during these hours, [WEEKDAYS] HH:MM-HH:MM (repeatable, MON-FRI 07:00-23:00, SAT,SUN 09:00-01:00),
+    /// the dmd is blanked outside of them and restored afterwards
+    #[arg(long, default_value = None)]
+    active_hours: Vec<String>,
+    /// active hours: brightness outside of them (in %, 0 blanks the dmd)
+    #[arg(long, default_value_t = 0)]
+    inactive_brightness: u8,
+    /// adjust the brightness of the dmd: "sun" follows the daylight at --latitude and --longitude,
+    /// else a file with the lux of a light sensor, read every second (written by a sensor or mqtt script)
+    #[arg(long, default_value = None)]
+    auto_dim: Option<String>,
+    /// auto dim: lowest brightness (in %), at night or in the dark
+    #[arg(long, default_value_t = 20)]
+    auto_dim_min: u8,
+    /// auto dim: latitude of the dmd, in degrees
+    #[arg(long, default_value = None, allow_negative_numbers = true)]
+    latitude: Option<f64>,
+    /// auto dim: longitude of the dmd, in degrees (negative to the west)
+    #[arg(long, default_value = None, allow_negative_numbers = true)]
+    longitude: Option<f64>,
+    /// against the burn-in of the leds: move the content by a pixel every minute,
+    /// and show a black frame for a second every 30 minutes
+    #[arg(long, default_value_t = false)]
+    anti_burn: bool,
+    /// correct the colors of the leds: gains R,G,B (1.0,0.8,0.9), or the 9 values of a 3x3 matrix row by row
+    #[arg(long, default_value = None)]
+    color_correct: Option<String>,
+    /// saturation of the colors of any content (0.0 is gray, above 1.0 for washed out images)
+    #[arg(long, default_value_t = 1.0)]
+    saturation: f32,
+    /// contrast of the colors of any content (above 1.0 for more contrast)
+    #[arg(long, default_value_t = 1.0)]
+    contrast: f32,
+    /// negative colors
+    #[arg(long, default_value_t = false)]
+    invert: bool,
+    /// pixels fully on (in --mono-color) from this brightness (0-255), off below, like a plasma dmd or a 1-bit panel
+    #[arg(long, default_value = None)]
+    mono_threshold: Option<u8>,
+    /// mono: color of the pixels on R,G,B
+    #[arg(long, default_value = "255,88,0")]
+    mono_color: String,
+    /// mono: error diffusion (floyd-steinberg), for the photos
+    #[arg(long, default_value_t = false)]
+    mono_dither: bool,
+    /// save the status (connection, size, mode, text, frames sent, fps) as a json file,
+    /// updated every second at most, with "playing": false once finished
+    #[arg(long, default_value = None)]
+    status_json: Option<String>,
+    /// print the fps, the bytes per second, the late frames and the send latency every this number of seconds
+    #[arg(long, default_value = None)]
+    stats: Option<u32>,
+    /// save the statistics as a json file instead of printing them (every second by default)
+    #[arg(long, default_value = None)]
+    stats_json: Option<String>,
+    /// text: print the ratio of the text, its mode and its size, without sending anything
+    #[arg(long, default_value_t = false)]
+    print_layout: bool,
+    /// fixed text: how it appears, typewriter, fade-in or slide-in
+    #[arg(long, default_value = None)]
+    reveal: Option<String>,
+    /// fixed text: duration of the reveal (in milliseconds)
+    #[arg(long, default_value_t = 1000)]
+    reveal_duration: u32,
+    /// animation playback rate (0.5-4.0, 2.0 plays twice as fast)
+    #[arg(long, default_value_t = 1.0)]
+    rate: f32,
+    /// read commands from stdin, one per line, for the animations: pause, resume or next-frame
+    /// (the next frame is shown, then the animation is paused)
+    #[arg(long, default_value_t = false)]
+    control_stdin: bool,
+    /// dmd size, WIDTHxHEIGHT or dmd (128x32), hd (256x64), xl (192x64, toppers),
+    /// zedmd, zedmd-hd, pin2dmd-xl or pixelcade (128x32 by default)
+    #[arg(long, env = "DMD_SIZE", default_value = None)]
+    size: Option<String>,
+    /// same as --size hd
     #[arg(long, default_value_t = false)]
     hd: bool,
-    /// width
+    /// width of the dmd, instead of the one of --size
     #[arg(long, default_value = None)]
     width: Option<u32>,
-    /// height
+    /// height of the dmd, instead of the one of --size
     #[arg(long, default_value = None)]
     height: Option<u32>,
-    /// text alignment: center, left or right
+    /// text gradient: image path file, or colors from left to right (linear:ff0000-0000ff),
+    /// top to bottom (vertical:gold-red) or corner to corner (diagonal:...)
     #[arg(long, default_value=None)]
     gradient: Option<String>,
+    /// image displayed behind the text
+    #[arg(long, default_value=None)]
+    background_file: Option<String>,
+    /// color displayed behind the text: R,G,B (0-255)
+    #[arg(long, default_value=None)]
+    background_color: Option<String>,
     /// for compatibility only
     #[arg(long, default_value_t = false)]
     no_fit: bool,
@@ -114,90 +694,318 @@ struct Cli {
 // network package size
 const DMD_HEADER_SIZE: usize = 10 + 1 + 4 + 2 + 2 + 1 + 1 + 4;
 
+// longest time to wait for the answer to the handshake, without connect timeout (in milliseconds)
+const HANDSHAKE_TIMEOUT: u32 = 5000;
+
+// longest time between two attempts to connect while waiting for the server (in milliseconds)
+const MAX_WAIT_DELAY: u64 = 5000;
+
+// the alerts flash this number of times before being shown
+const ALERT_FLASHES: u32 = 3;
+const ALERT_FLASH: Duration = Duration::from_millis(150);
+
+// time between two checks of the overlay file
+const OVERLAY_FILE_POLL: Duration = Duration::from_millis(200);
+// how often the cancellation is checked while no notification comes
+const NOTIFICATIONS_POLL: Duration = Duration::from_millis(200);
+
+// seconds between two reads of --poll-json without --refresh
+const POLL_REFRESH: u32 = 30;
+
+// seconds between two updates of --netinfo without --refresh
+const NETINFO_REFRESH: u32 = 5;
+
+// longest run of an --exec command, and how often it is checked meanwhile
+const EXEC_TIMEOUT: Duration = Duration::from_secs(10);
+const EXEC_POLL: Duration = Duration::from_millis(50);
+
+// time between two reads of --progress-file and --progress-exec
+const PROGRESS_POLL: Duration = Duration::from_millis(500);
+
+// frames of a gif decoded ahead of the one played
+const GIF_STREAM_FRAMES: usize = 8;
+
+// frames per second of the slideshow crossfades
+const CROSSFADE_FPS: u32 = 30;
+
+// duration in ms of the frames of a fixed text whose colors cycle (25 fps)
+const COLOR_CYCLE_FRAME: u32 = 40;
+
+// frames per second of the directories of frames and the raw files without --fps
+const DEFAULT_FPS: u32 = 12;
+
+// milliseconds of a single image mixed with animations
+const DEFAULT_DURATION: u32 = 2000;
+
+// names of the common dmd sizes for --size
+const DMD_SIZES: [(&str, u32, u32); 7] = [
+    ("dmd", 128, 32),
+    ("hd", 256, 64),
+    ("xl", 192, 64), // toppers
+    ("zedmd", 128, 32),
+    ("zedmd-hd", 256, 64),
+    ("pin2dmd-xl", 192, 64),
+    ("pixelcade", 128, 32),
+];
+
+// animate the texts using less than 1/3 of the height
+pub const ANIMATE_THRESHOLD: f32 = 3.0;
+
+// rgb565 frames with their duration in ms
+type DMDAnimation = (Vec<Box<[u8]>>, Vec<u32>);
+
+// transparent images at the oriented size with their duration in ms, before the background
+type TextImages = Vec<(RgbaImage, u32)>;
+
+// how a text appears when it fits in the dmd
+#[derive(Clone)]
+enum TextReveal {
+    TYPEWRITER, // letter by letter
+    FADEIN,     // the whole text
+    SLIDEIN,    // from the right
+}
+
+// the part of an animation played, and its pace
+struct FrameRange {
+    start: usize,         // first frame
+    end: Option<usize>,   // last frame, included
+    trim_ms: Option<u32>, // the frames starting after this time are left out
+    fps: Option<u32>,     // instead of the delays of the frames
+}
+
+// how the image files are read
+struct FileOptions {
+    fps: u32,             // of the directories and raw files
+    raw_size: (u32, u32), // of the raw files
+    range: FrameRange,
+    cache: Option<framecache::FrameCache>,
+}
+
+// how the connections to the servers are opened
+struct UpstreamOptions<'a> {
+    tls: bool,
+    tls_ca: &'a Option<String>,
+    insecure: bool, // the certificate of the server is not checked
+    token: &'a Option<String>,
+    version: u8,                                     // the highest one proposed
+    compression: Option<protocol::FrameCompression>, // proposed to the servers
+}
+
+// how a text larger than the dmd scrolls, and how a fixed one appears
+#[derive(Clone)]
+struct TextAnimation {
+    subpixel: u32,                   // steps per pixel
+    gap: Option<u32>,                // pixels before the text comes back, the dmd width by default
+    separator: String,               // after the text
+    continuous: bool,                // the text is never out of the screen
+    reveal: Option<TextReveal>,      // none displays the text at once
+    reveal_duration: u32,            // milliseconds
+    hold: Option<u32>,               // milliseconds at the start and at the end, instead of a loop
+    min_text_height: Option<u32>,    // pixels, smaller texts scroll
+    animate_threshold: f32,          // texts wider than this number of times the dmd scroll
+    color_cycle: Option<ColorCycle>, // colors moving at each frame, a fixed text is animated
+    cycle_speed: u32,                // hue degrees per second of the color cycle
+}
+
+// the dmd the frames are played on: where they go with their header, its size, how the images
+// are converted for it, the playback rate, its pause and the watchdog of the service
+struct Dmd<'a> {
+    client: &'a dyn FrameSink,
+    header: [u8; DMD_HEADER_SIZE],
+    width: u32,
+    height: u32,
+    image_options: &'a imageutils::ImageOptions,
+    rate: f32,
+    control: &'a control::PlayControl,
+    watchdog: &'a systemd::Watchdog,
+    cancel: &'a cancel::CancelToken,
+}
+
+// how the texts are displayed
+#[derive(Clone)]
+struct TextOptions {
+    style: imageutils::TextStyle,
+    gradient: Option<DynamicImage>, // instead of the text color
+    background: RgbaImage,          // behind the text, at the oriented size
+    moving: bool,                   // scrolls, even when it fits
+    fixed: bool,                    // never scrolls
+    speed: u32,                     // milliseconds per pixel of the scrolling
+    animation: TextAnimation,
+}
+
+// how the time is written
+#[derive(Clone)]
+struct ClockOptions {
+    format: Option<String>, // strftime, instead of h12 and no_seconds
+    h12: bool,
+    no_seconds: bool,
+    timezone: Option<Tz>, // the system one by default
+}
+
+// the alarm of the clock
+struct Alarm {
+    time: NaiveTime,
+    text: String,            // flashing while it rings
+    duration: u32,           // seconds
+    command: Option<String>, // run when it rings
+}
+
+// the countdowns, and how they are written
+struct Countdowns {
+    targets: Vec<(Option<String>, CountdownTarget)>, // with their label
+    cycle: u32,                                      // seconds each one is displayed
+    lines: bool,                                     // all at once, one per line
+    header: Option<String>,
+    format: String,
+    format_0_day: String,
+    format_0_hour: String,
+    format_0_minute: String,
+    timezone: Option<Tz>,
+    ics_refresh: u32, // minutes
+}
+
+// how the overlay texts and the notifications are rendered, before being shown over what is played
+#[derive(Clone)]
+struct OverlayStyle {
+    header: [u8; DMD_HEADER_SIZE],
+    width: u32,
+    height: u32,
+    style: imageutils::TextStyle, // centered, without spacing
+    background: Rgba<u8>,
+    image_options: imageutils::ImageOptions,
+}
+
+// a text displayed with --text, --texts or --exec
+enum TextSource {
+    TEXT(String),            // with placeholders
+    TEXTS(Vec<String>),      // with placeholders, one after the other
+    EXEC(String),            // shell command
+    POLL(String, String),    // url of the json and template
+    TICKER(Vec<String>),     // symbols of the quotes
+    NETINFO(Option<String>), // host pinged
+}
+
+// colors of a text changing at each frame
+#[derive(Clone)]
+enum ColorCycle {
+    RAINBOW,  // all the hues, moving to the left
+    GRADIENT, // the gradient, moving to the left
+}
+
+// the alarm of the clock, rung once a day
+enum AlarmState {
+    WAITING,
+    RINGING(Instant), // since
+    RUNG,             // until the alarm minute is over
+}
+
+// where the status of a progress bar is read
+enum ProgressSource {
+    FILE(String), // json file
+    EXEC(String), // shell command printing the json
+}
+
+// what a countdown counts down to
+enum CountdownTarget {
+    DATE(String),             // 2050-06-30 15:00:00
+    CRON(cron::CronSchedule), // the next occurrence of a schedule
+    ICS(String),              // the next event of a calendar (file or url), its title as label
+}
+
+// what the relay plays while no client is connected
+enum RelayIdle {
+    FILE(String),
+    CLOCK, // the time, rendered each second
+}
+
+// the one thing played, validated before connecting to the servers
+enum Play {
+    FILE(String),
+    SOURCE(String, source::SourceFormat),
+    RELAY(Vec<relay::RelayChannel>, Option<RelayIdle>),
+    SPRITESHEET(String, (u32, u32)), // with the size of a sprite
+    TEXT(TextSource, Option<ColorCycle>),
+    LAYOUT(Vec<layout::Zone>),
+    PROGRESS(f32),
+    WATCHPROGRESS(ProgressSource),
+    DRAW(RgbaImage),
+    VISUALIZER(visualizer::VisualizerMode),
+    SCREENSAVER(screensaver::Screensaver),
+    SLIDESHOW(slideshow::Slideshow),
+    TESTPATTERN(imageutils::TestPattern),
+    IDENTIFY(String, u16), // the host and the port written
+    EFFECT(particles::Effect, Vec<Rgba<u8>>),
+    SCRIPT(String),
+    TEMPLATE(template::Template),
+    LAYERS(compositor::BaseLayer, compositor::Ticker),
+    CLOCK(Option<clockstyle::ClockStyle>, Option<Alarm>),
+    COUNTDOWN(Countdowns),
+    CLEAR,
+    NOTIFICATIONS, // only, the dmd stays black between them
+}
+
 enum DMDLayer {
     MAIN,
     SECOND,
 }
 
 fn send_frame(
-    mut client: &TcpStream,
+    client: &dyn FrameSink,
     header: [u8; DMD_HEADER_SIZE],
     im: &[u8],
 ) -> Result<(), std::io::Error> {
-    client.write_all(&header)?;
-    client.write_all(im)?;
-    client.flush()?;
-    Ok(())
+    client.send(header, im)
 }
 
-fn get_header(width: u16, height: u16, layer: DMDLayer, nbytes: u32) -> [u8; DMD_HEADER_SIZE] {
-    let mut bytes: [u8; DMD_HEADER_SIZE] = [0; DMD_HEADER_SIZE];
-
-    let version: u8 = 1;
-    let keyword: &[u8] = "DMDStream".as_bytes();
-    let mode: u32 = 3; // force rgb565
-    let buffered: u8;
-    let disconnect_others: u8;
-
-    if matches!(layer, DMDLayer::MAIN) {
-        buffered = 1;
-        disconnect_others = 1;
-    } else {
-        buffered = 0;
-        disconnect_others = 0;
-    }
-
-    let mut n = 0;
-    let len = keyword.len();
-    bytes[..len].copy_from_slice(keyword);
-    n += len + 1;
-    bytes[n] = version;
-    n += 1;
-    bytes[n..n + 4].copy_from_slice(&mode.to_be_bytes());
-    n += 4;
-    bytes[n..n + 2].copy_from_slice(&width.to_be_bytes());
-    n += 2;
-    bytes[n..n + 2].copy_from_slice(&height.to_be_bytes());
-    n += 2;
-    bytes[n] = buffered;
-    n += 1;
-    bytes[n] = disconnect_others;
-    n += 1;
-    bytes[n..n + 4].copy_from_slice(&nbytes.to_be_bytes());
-
-    bytes
+fn get_header(
+    width: u16,
+    height: u16,
+    layer: DMDLayer,
+    nbytes: u32,
+    version: u8,
+) -> [u8; DMD_HEADER_SIZE] {
+    // the second layer is displayed over the main one, without disconnecting its client
+    let main_layer = matches!(layer, DMDLayer::MAIN);
+    protocol::DmdStreamHeader {
+        version,
+        mode: protocol::MODE_RGB565, // force rgb565
+        width,
+        height,
+        buffered: main_layer,
+        disconnect_others: main_layer,
+        compressed: false,
+        nbytes,
+    }
+    .to_bytes()
 }
 
 fn is_text_to_animate(
     text: &str,
-    font_path: &str,
-    line_spacing: u8,
+    style: &imageutils::TextStyle,
     dmd_width: u32,
     dmd_height: u32,
     force_moving_text: bool,
-) -> Result<(bool, u32), String> {
+    min_text_height: Option<u32>,
+    animate_threshold: f32,
+) -> (bool, u32) {
     let mut should_animate = false;
     let mut animation_new_width = dmd_width;
 
     let lines = text.split("\\n");
     let nlines = lines.clone().count() as u32;
 
-    // animate if we use less than 1/3 of the height
-    let accepable_ratio = 3.0;
-    let all_spaces = line_spacing as u32 * (nlines - 1);
-    let section_height = ((dmd_height - all_spaces) / nlines) as u32;
+    let (section_height, _) = imageutils::text_sections(dmd_height, nlines, style.line_spacing);
     let dmd_ratio = dmd_width as f32 / dmd_height as f32;
 
     for line in lines {
-        let text_ratio = match imageutils::get_text_ratio(line, font_path, section_height) {
-            Ok(x) => x,
-            Err(e) => {
-                return Err(e);
-            }
-        };
+        let text_ratio = imageutils::get_text_ratio(line, style, section_height);
+
+        // the height of the letters once the line is fitted in the dmd width
+        let fitted_height = (dmd_width as f32 / text_ratio).min(section_height as f32);
+        let too_small = min_text_height.is_some_and(|x| fitted_height < x as f32);
 
         // if at least one line require animation, then animate.
-        let local_should_animate = text_ratio > dmd_ratio * accepable_ratio;
+        let local_should_animate = text_ratio > dmd_ratio * animate_threshold || too_small;
         if local_should_animate || force_moving_text {
             should_animate = true;
             let local_animation_new_width = (section_height as f32 * text_ratio) as u32;
@@ -208,50 +1016,170 @@ fn is_text_to_animate(
     }
 
     // when the text is to animate, compute the real part of the animation
-    Ok((should_animate, animation_new_width))
+    (should_animate, animation_new_width)
 }
 
-fn get_dmd_animation_from_text(
+// what is_text_to_animate() decides for a text, for --print-layout
+fn print_text_layout(
     text: &str,
-    font_path: &str,
-    gradient: &Option<DynamicImage>,
+    options: &TextOptions,
     dmd_width: u32,
     dmd_height: u32,
+) -> Result<(), String> {
+    let text_animation = &options.animation;
+    let lines: Vec<&str> = text.split("\\n").collect();
+    let nlines = lines.len() as u32;
+    let (section_height, _) =
+        imageutils::text_sections(dmd_height, nlines, options.style.line_spacing);
+    let dmd_ratio = dmd_width as f32 / dmd_height as f32;
+
+    println!(
+        "dmd: {}x{}, ratio {:.2}, threshold {:.2} (scroll above a text ratio of {:.2})",
+        dmd_width,
+        dmd_height,
+        dmd_ratio,
+        text_animation.animate_threshold,
+        dmd_ratio * text_animation.animate_threshold
+    );
+    for line in &lines {
+        let text_ratio = imageutils::get_text_ratio(line, &options.style, section_height);
+        let fitted_height = (dmd_width as f32 / text_ratio).min(section_height as f32);
+        println!(
+            "line \"{}\": ratio {:.2}, letters of {:.1} pixels when fitted",
+            line, text_ratio, fitted_height
+        );
+    }
+
+    let (mut should_animate, new_width) = is_text_to_animate(
+        text,
+        &options.style,
+        dmd_width,
+        dmd_height,
+        options.moving,
+        text_animation.min_text_height,
+        text_animation.animate_threshold,
+    );
+    if !options.moving && options.fixed {
+        should_animate = false;
+    }
+    if should_animate {
+        println!("mode: scroll, size {}x{}", new_width, dmd_height);
+    } else {
+        println!("mode: fixed, size {}x{}", dmd_width, dmd_height);
+    }
+    Ok(())
+}
+
+// the positions of a scrolling text, before the background
+fn get_text_scroll_images(
+    dmd: &Dmd,
+    text: &str,
+    gradient: &Option<DynamicImage>,
     text_width: u32,
-    background_color: Rgba<u8>,
-    text_color: Rgba<u8>,
-    text_align: &imageutils::TextAlign,
-    line_spacing: u8,
-    speed: u32,
-) -> Result<(Vec<Box<[u8]>>, Vec<u32>), String> {
+    options: &TextOptions,
+) -> Result<TextImages, String> {
+    let (render_width, render_height) =
+        imageutils::oriented_size(&dmd.image_options.orientation, dmd.width, dmd.height);
+    let text_animation = &options.animation;
+
+    // with sub-pixel scrolling, the text is rendered larger, then each frame is downscaled from it
+    let subpixel = text_animation.subpixel;
+    let (strip_width, strip_height) = (render_width * subpixel, render_height * subpixel);
+    let gradient = match gradient {
+        Some(x) if subpixel > 1 => Some(x.resize_exact(
+            x.width() * subpixel,
+            x.height() * subpixel,
+            imageops::FilterType::Nearest,
+        )),
+        _ => gradient.clone(),
+    };
+    let style = imageutils::TextStyle {
+        line_spacing: (options.style.line_spacing as u32 * subpixel).min(u8::MAX as u32) as u8,
+        letter_spacing: (options.style.letter_spacing as i32 * subpixel as i32)
+            .clamp(i8::MIN as i32, i8::MAX as i32) as i8,
+        ..options.style.clone()
+    };
+
     let (dyn_img, start, real_width) = imageutils::generate_text_image(
         text,
-        font_path,
+        &style,
         &gradient,
-        text_width,
-        dmd_height,
-        background_color,
-        text_color,
-        text_align,
-        line_spacing,
+        text_width * subpixel,
+        strip_height,
     )?;
+    let duration = ((options.speed as f32 / subpixel as f32).round() as u32).max(1);
+
+    let mut images = Vec::new();
+
+    // the text enters on the right, and leaves on the left a gap before coming back.
+    // a continuous text starts on the left, the gap is a space by default
+    let gap = match (text_animation.gap, text_animation.continuous) {
+        (Some(x), _) => x,
+        (None, true) => render_height / 2,
+        (None, false) => render_width,
+    } * subpixel;
+    let period = (real_width + gap) as i32;
+    let strip = dyn_img.to_rgba8();
+
+    // where the text starts at each frame, and how long it stays there
+    let positions: Vec<(i32, u32)> = match text_animation.hold {
+        // from its start to its end, holding at both
+        Some(hold) => {
+            let overflow = real_width.saturating_sub(strip_width) as i32;
+            (0..=overflow)
+                .map(|n| match n == 0 || n == overflow {
+                    true => (-n, hold),
+                    false => (-n, duration),
+                })
+                .collect()
+        }
+        None => (0..period)
+            .map(|npixel| match text_animation.continuous {
+                true => (-npixel, duration),
+                false => (strip_width as i32 - 1 - npixel, duration),
+            })
+            .collect(),
+    };
+
+    for (x, duration) in positions {
+        let mut new_img = RgbaImage::new(strip_width, strip_height);
+        imageutils::copy_image(&dyn_img, &mut new_img, x - start as i32, 0);
+        // the next loops, visible when the text and the gap are smaller than the dmd
+        let mut next_x = x + period;
+        while next_x < strip_width as i32 {
+            imageutils::blend_image(&strip, &mut new_img, next_x - start as i32, 0);
+            next_x += period;
+        }
+        let new_img = if subpixel > 1 {
+            imageutils::downscale_image(&new_img, subpixel)
+        } else {
+            new_img
+        };
+        images.push((new_img, duration));
+    }
 
+    Ok(images)
+}
+
+fn get_dmd_animation_from_text(
+    dmd: &Dmd,
+    text: &str,
+    gradient: &Option<DynamicImage>,
+    text_width: u32,
+    options: &TextOptions,
+) -> Result<DMDAnimation, String> {
     let mut frames_dmd = Vec::new();
     let mut frames_duration = Vec::new();
 
-    for npixel in (0..dmd_width + (real_width - dmd_width) + dmd_width).rev() {
-        let mut new_img = RgbaImage::new(dmd_width, dmd_height);
-        imageutils::copy_image(
-            &dyn_img,
-            &mut new_img,
-            npixel as i32 - start as i32 - real_width as i32,
-            0,
-        );
+    for (img, duration) in get_text_scroll_images(dmd, text, gradient, text_width, options)? {
+        let img = imageutils::apply_background(&img, &options.background);
+        let img = imageutils::orient_image(&img, &dmd.image_options.orientation);
         let img565: Box<[u8]> = match imageutils::image2dmdimage(
-            &new_img,
+            &img,
             &imageutils::TextAlign::CENTER,
-            dmd_width,
-            dmd_height,
+            &dmd.image_options.colors,
+            dmd.width,
+            dmd.height,
         ) {
             Ok(img) => img,
             Err(e) => {
@@ -259,87 +1187,261 @@ fn get_dmd_animation_from_text(
             }
         };
         frames_dmd.push(img565);
-        frames_duration.push(speed);
+        frames_duration.push(duration);
     }
 
     Ok((frames_dmd, frames_duration))
 }
 
-fn send_image_text(
-    client: &TcpStream,
-    header: [u8; DMD_HEADER_SIZE],
-    dmd_width: u32,
-    dmd_height: u32,
+// a fixed text appearing, then staying on the last frame
+fn get_dmd_reveal_from_text(
+    dmd: &Dmd,
     text: &str,
-    font_path: &str,
     gradient: &Option<DynamicImage>,
-    text_color: Rgba<u8>,
-    background_color: Rgba<u8>,
-    text_align: &imageutils::TextAlign,
-    line_spacing: u8,
-    force_moving_text: bool,
-    force_fixed_text: bool,
-    speed: u32,
-    once: bool,
-) -> Result<bool, String> {
-    let mut new_width = dmd_width;
+    reveal: &TextReveal,
+    options: &TextOptions,
+) -> Result<DMDAnimation, String> {
+    let (render_width, render_height) =
+        imageutils::oriented_size(&dmd.image_options.orientation, dmd.width, dmd.height);
+    let reveal_duration = options.animation.reveal_duration;
 
-    let (mut should_animate, animation_new_width) = is_text_to_animate(
+    let (dyn_img, _start, _new_width) = imageutils::generate_text_image(
         text,
-        font_path,
-        line_spacing,
-        dmd_width,
-        dmd_height,
-        force_moving_text,
+        &options.style,
+        gradient,
+        render_width,
+        render_height,
     )?;
+    let text_img = dyn_img.to_rgba8();
 
-    if should_animate {
-        new_width = animation_new_width;
-    }
+    let mut images = Vec::new();
+    let mut frames_duration = Vec::new();
+    // fading and sliding are played at 25 fps
+    let nframes = (reveal_duration / 40).max(1);
 
-    // some options forces
-    if force_moving_text == false && force_fixed_text {
-        should_animate = false;
-    }
+    match reveal {
+        TextReveal::TYPEWRITER => {
+            // the lines are typed one after the other, a letter ends where the layout of the font ends it
+            let lines: Vec<&str> = text.split("\\n").collect();
+            let nlines = lines.len() as u32;
+            let (section_height, line_spacing) =
+                imageutils::text_sections(render_height, nlines, options.style.line_spacing);
+            let nletters = lines.iter().map(|x| x.chars().count() as u32).sum::<u32>();
+            let duration = (reveal_duration / nletters.max(1)).max(1);
+            let mut img = RgbaImage::new(render_width, render_height);
 
-    // play the animation, thus first, generate images, then play
-    if should_animate {
-        let (frames_dmd, frames_duration) = get_dmd_animation_from_text(
-            text,
-            font_path,
-            &gradient,
-            dmd_width,
-            dmd_height,
-            new_width,
-            background_color,
-            text_color,
-            text_align,
-            line_spacing,
-            speed,
+            for (n, line) in lines.iter().enumerate() {
+                let top = n as u32 * (section_height + line_spacing);
+                let bottom = (top + section_height).min(render_height);
+                let columns: Vec<u32> = (0..render_width)
+                    .filter(|x| (top..bottom).any(|y| text_img.get_pixel(*x, y)[3] > 0))
+                    .collect();
+                let (Some(left), Some(right)) = (columns.first(), columns.last()) else {
+                    continue;
+                };
+
+                let ends = imageutils::get_letter_ends(line, &options.style, section_height);
+                for end in ends {
+                    let end = left + ((right + 1 - left) as f32 * end).round() as u32;
+                    for y in top..bottom {
+                        for x in *left..end.min(render_width) {
+                            img.put_pixel(x, y, *text_img.get_pixel(x, y));
+                        }
+                    }
+                    images.push(img.clone());
+                    frames_duration.push(duration);
+                }
+            }
+        }
+        TextReveal::FADEIN => {
+            for n in 1..=nframes {
+                let mut img = text_img.clone();
+                for pixel in img.pixels_mut() {
+                    pixel[3] = (pixel[3] as u32 * n / nframes) as u8;
+                }
+                images.push(img);
+                frames_duration.push(40);
+            }
+        }
+        TextReveal::SLIDEIN => {
+            for n in 1..=nframes {
+                // slowing down at the end
+                let t = 1.0 - (1.0 - n as f32 / nframes as f32).powi(2);
+                let mut img = RgbaImage::new(render_width, render_height);
+                imageutils::blend_image(
+                    &text_img,
+                    &mut img,
+                    ((1.0 - t) * render_width as f32).round() as i32,
+                    0,
+                );
+                images.push(img);
+                frames_duration.push(40);
+            }
+        }
+    }
+
+    let mut frames_dmd = Vec::new();
+    for img in images {
+        let img = imageutils::apply_background(&img, &options.background);
+        let img = imageutils::orient_image(&img, &dmd.image_options.orientation);
+        frames_dmd.push(imageutils::image2dmdimage(
+            &img,
+            &options.style.align,
+            &dmd.image_options.colors,
+            dmd.width,
+            dmd.height,
+        )?);
+    }
+    Ok((frames_dmd, frames_duration))
+}
+
+// a fixed text over the background, oriented to the panel
+fn render_text(
+    dmd_width: u32,
+    dmd_height: u32,
+    text: &str,
+    gradient: &Option<DynamicImage>,
+    options: &TextOptions,
+    image_options: &imageutils::ImageOptions,
+) -> Result<Box<[u8]>, String> {
+    let (render_width, render_height) =
+        imageutils::oriented_size(&image_options.orientation, dmd_width, dmd_height);
+    let (dyn_img, _start, _new_width) = imageutils::generate_text_image(
+        text,
+        &options.style,
+        gradient,
+        render_width,
+        render_height,
+    )?;
+    let rgba_img = imageutils::apply_background(&dyn_img.to_rgba8(), &options.background);
+    let rgba_img = imageutils::orient_image(&rgba_img, &image_options.orientation);
+
+    match imageutils::image2dmdimage(
+        &rgba_img,
+        &options.style.align,
+        &image_options.colors,
+        dmd_width,
+        dmd_height,
+    ) {
+        Ok(x) => Ok(x),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn send_image_text(
+    dmd: &Dmd,
+    text: &str,
+    options: &TextOptions,
+    once: bool,
+) -> Result<bool, String> {
+    let text_animation = &options.animation;
+    // the text is rendered as seen by the viewer, then oriented to the panel
+    let (render_width, render_height) =
+        imageutils::oriented_size(&dmd.image_options.orientation, dmd.width, dmd.height);
+    let mut new_width = render_width;
+
+    // the colored parts of the text become a gradient of the size of the rendered text
+    let (text, colors) = imageutils::parse_color_markup(text)?;
+    let text = text.as_str();
+    let text_gradient = |text: &str, width: u32| match &colors {
+        Some(colors) => imageutils::markup_gradient(
+            text,
+            colors,
+            &options.style,
+            &options.gradient,
+            width,
+            render_height,
+        )
+        .map(Some),
+        None => Ok(options.gradient.clone()),
+    };
+
+    let (mut should_animate, animation_new_width) = is_text_to_animate(
+        text,
+        &options.style,
+        render_width,
+        render_height,
+        options.moving,
+        text_animation.min_text_height,
+        text_animation.animate_threshold,
+    );
+
+    if should_animate {
+        new_width = animation_new_width;
+    }
+
+    // some options forces
+    if options.moving == false && options.fixed {
+        should_animate = false;
+    }
+
+    // play the animation, thus first, generate images, then play
+    if should_animate {
+        // the separator follows each line of the text
+        let text = text
+            .split("\\n")
+            .map(|x| format!("{}{}", x, text_animation.separator))
+            .collect::<Vec<String>>()
+            .join("\\n");
+        if !text_animation.separator.is_empty() {
+            (_, new_width) = is_text_to_animate(
+                &text,
+                &options.style,
+                render_width,
+                render_height,
+                true,
+                text_animation.min_text_height,
+                text_animation.animate_threshold,
+            );
+        }
+        // the colors of the cycle replace the ones of the text, which is rendered once
+        if let Some(color_cycle) = &text_animation.color_cycle {
+            let images = get_text_scroll_images(dmd, &text, &None, new_width, options)?;
+            play_color_cycle(dmd, &images, color_cycle, options, once)?;
+            return Ok(true);
+        }
+        let (frames_dmd, frames_duration) = get_dmd_animation_from_text(
+            dmd,
+            &text,
+            &text_gradient(&text, new_width)?,
+            new_width,
+            options,
         )?;
-        play_animation(header, &client, &frames_dmd, frames_duration, once)?;
+        play_animation(dmd, &frames_dmd, frames_duration, once)?;
         Ok(true)
-    } else {
+    } else if let Some(color_cycle) = &text_animation.color_cycle {
         let (dyn_img, _start, _new_width) = imageutils::generate_text_image(
             text,
-            font_path,
-            &gradient,
-            dmd_width,
-            dmd_height,
-            background_color,
-            text_color,
-            text_align,
-            line_spacing,
+            &options.style,
+            &None,
+            render_width,
+            render_height,
+        )?;
+        let images = [(dyn_img.to_rgba8(), COLOR_CYCLE_FRAME)];
+        play_color_cycle(dmd, &images, color_cycle, options, once)?;
+        Ok(true)
+    } else if let Some(reveal) = &text_animation.reveal {
+        let (frames_dmd, frames_duration) = get_dmd_reveal_from_text(
+            dmd,
+            text,
+            &text_gradient(text, render_width)?,
+            reveal,
+            options,
+        )?;
+        // played once, the text stays like a fixed one
+        play_animation(dmd, &frames_dmd, frames_duration, true)?;
+        Ok(false)
+    } else {
+        let img565 = render_text(
+            dmd.width,
+            dmd.height,
+            text,
+            &text_gradient(text, render_width)?,
+            options,
+            dmd.image_options,
         )?;
 
-        let img565 = match imageutils::image2dmdimage(&dyn_img, text_align, dmd_width, dmd_height) {
-            Ok(x) => x,
-            Err(e) => {
-                return Err(e.to_string());
-            }
-        };
-
-        match send_frame(&client, header, &img565) {
+        match send_frame(dmd.client, dmd.header, &img565) {
             Ok(_) => {}
             Err(e) => {
                 return Err(e.to_string());
@@ -350,38 +1452,31 @@ fn send_image_text(
 }
 
 fn handle_case_file(
-    header: [u8; DMD_HEADER_SIZE],
-    dmd_width: u32,
-    dmd_height: u32,
-    client: &TcpStream,
+    dmd: &Dmd,
     file: String,
+    fps: u32,
+    raw_size: (u32, u32),
     once: bool,
-    default_duration: u32,
+    range: &FrameRange,
+    cache: Option<&framecache::FrameCache>,
 ) -> Result<bool, String> {
-    send_image_files(
-        header,
-        dmd_width,
-        dmd_height,
-        client,
-        file,
-        once,
-        default_duration,
-    )
+    send_image_files(dmd, file, fps, raw_size, once, range, cache)
 }
 
-fn frames_from_gif(file: &str) -> Result<Vec<Frame>, String> {
+fn open_gif(file: &str) -> Result<GifDecoder<BufReader<File>>, String> {
     let fd = match File::open(file) {
         Ok(x) => x,
         Err(e) => return Err(e.to_string()),
     };
     let reader = BufReader::new(fd);
-    let decoder = match GifDecoder::new(reader) {
-        Ok(x) => x,
-        Err(e) => {
-            return Err(e.to_string());
-        }
-    };
+    match GifDecoder::new(reader) {
+        Ok(x) => Ok(x),
+        Err(e) => Err(e.to_string()),
+    }
+}
 
+fn frames_from_gif(file: &str) -> Result<Vec<Frame>, String> {
+    let decoder = open_gif(file)?;
     let frames: Result<Vec<Frame>, _> = decoder.into_frames().collect_frames();
     frames.map_err(|e| format!("Error: {}: {}", file, e))
 }
@@ -409,12 +1504,102 @@ fn frame_from_image(file: &str, default_duration: u32) -> Result<Frame, String>
     ))
 }
 
-fn files_to_frames(file: String, default_duration: u32) -> Result<Vec<Frame>, String> {
+// numbered frames are sorted on the last number of their name (frame_2 before frame_10)
+fn frame_number(path: &Path) -> Option<u64> {
+    let stem = path.file_stem()?.to_str()?;
+    let digits: String = stem
+        .chars()
+        .rev()
+        .skip_while(|c| !c.is_ascii_digit())
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    digits.chars().rev().collect::<String>().parse().ok()
+}
+
+fn frames_from_directory(dir: &str, fps: u32) -> Result<Vec<Frame>, String> {
+    if fps == 0 {
+        return Err(String::from("Invalid fps value"));
+    }
+
+    let entries = match fs::read_dir(dir) {
+        Ok(x) => x,
+        Err(e) => return Err(format!("Error: {}: {}", dir, e)),
+    };
+
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.is_file()
+                && path
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case("png"))
+        })
+        .collect();
+    paths.sort_by(|a, b| (frame_number(a), a).cmp(&(frame_number(b), b)));
+
+    if paths.is_empty() {
+        return Err(format!("Error: {}: no png frame found", dir));
+    }
+
+    let mut frames = Vec::new();
+    for path in paths {
+        let frame = frame_from_image(&path.to_string_lossy(), 0)?;
+        frames.push(Frame::from_parts(
+            frame.into_buffer(),
+            0,
+            0,
+            Delay::from_numer_denom_ms(1000, fps),
+        ));
+    }
+    Ok(frames)
+}
+
+fn frames_from_raw(file: &str, raw_size: (u32, u32), fps: u32) -> Result<Vec<Frame>, String> {
+    let (width, height) = raw_size;
+    let frame_size = imageutils::get_dmd_buffer_size(width, height) as usize;
+    if frame_size == 0 || fps == 0 {
+        return Err(String::from("Invalid raw size or fps"));
+    }
+
+    let data = fs::read(file).map_err(|e| format!("Error: {}: {}", file, e))?;
+    if data.is_empty() || data.len() % frame_size != 0 {
+        return Err(format!(
+            "Error: {}: size is not a multiple of a {}x{} rgb565 frame",
+            file, width, height
+        ));
+    }
+
+    Ok(data
+        .chunks(frame_size)
+        .map(|bytes| {
+            Frame::from_parts(
+                imageutils::dmdimage2image(bytes, width, height),
+                0,
+                0,
+                Delay::from_numer_denom_ms(1000, fps),
+            )
+        })
+        .collect())
+}
+
+fn files_to_frames(
+    file: String,
+    fps: u32,
+    raw_size: (u32, u32),
+    default_duration: u32,
+) -> Result<Vec<Frame>, String> {
     let paths: Vec<&str> = file.split(':').collect();
     let mut all_frames = Vec::new();
 
     for path in paths {
-        if path.len() >= 4 && &path[path.len() - 4..] == ".gif" {
+        if Path::new(path).is_dir() {
+            let frames = frames_from_directory(path, fps)?;
+            all_frames.extend(frames);
+        } else if path.ends_with(".raw") {
+            let frames = frames_from_raw(path, raw_size, fps)?;
+            all_frames.extend(frames);
+        } else if path.len() >= 4 && &path[path.len() - 4..] == ".gif" {
             let frames = frames_from_gif(path).map_err(|e| e.to_string())?;
             all_frames.extend(frames);
         } else {
@@ -431,49 +1616,303 @@ fn files_to_frames(file: String, default_duration: u32) -> Result<Vec<Frame>, St
     Ok(all_frames)
 }
 
-fn send_image_files(
-    header: [u8; DMD_HEADER_SIZE],
-    dmd_width: u32,
-    dmd_height: u32,
-    client: &TcpStream,
-    file: String,
-    once: bool,
-    default_duration: u32,
-) -> Result<bool, String> {
-    let mut frames_dmd = Vec::new();
-    let mut frames_duration = Vec::new();
-    match files_to_frames(file, default_duration) {
-        Ok(frames) => {
-            // build the animation array
-            for frame in frames {
+// the frames of the range at the fps of the range, the last one kept starts before the trim time.
+// the errors go through
+fn frames_in_range<'a>(
+    frames: impl Iterator<Item = Result<Frame, String>> + 'a,
+    range: &'a FrameRange,
+) -> impl Iterator<Item = Result<Frame, String>> + 'a {
+    let mut elapsed = 0;
+    frames
+        .take(range.end.map_or(usize::MAX, |x| x + 1))
+        .skip(range.start)
+        .map(|frame| match (frame, range.fps) {
+            (Ok(frame), Some(fps)) => Ok(Frame::from_parts(
+                frame.into_buffer(),
+                0,
+                0,
+                Delay::from_numer_denom_ms(playback::frame_duration(fps), 1),
+            )),
+            (frame, _) => frame,
+        })
+        .take_while(move |frame| match frame {
+            Ok(frame) => {
+                let starts = elapsed;
                 let (x, y) = frame.delay().numer_denom_ms();
-                let duration = (x as f32 / y as f32) as u32;
+                elapsed += x / y.max(1);
+                range.trim_ms.is_none_or(|trim| starts < trim)
+            }
+            Err(_) => true,
+        })
+}
 
-                let orig_img = frame.into_buffer();
+fn select_frames(frames: Vec<Frame>, range: &FrameRange) -> Result<Vec<Frame>, String> {
+    let nframes = frames.len();
+    if range.start >= nframes {
+        return Err(format!(
+            "Invalid start frame: {} ({} frames)",
+            range.start, nframes
+        ));
+    }
+    frames_in_range(frames.into_iter().map(Ok), range).collect()
+}
 
-                let img565: Box<[u8]> = match imageutils::image2dmdimage(
-                    &orig_img,
-                    &imageutils::TextAlign::CENTER,
-                    dmd_width,
-                    dmd_height,
-                ) {
-                    Ok(img) => img,
+// a single gif played while a thread decodes and converts its frames, a few ones ahead: a long or
+// large gif starts at once and is never held in memory, it is decoded again at each loop.
+// the frames of the first pass go to the frame cache writer. returns true when it was animated
+fn stream_gif(
+    dmd: &Dmd,
+    file: &str,
+    once: bool,
+    range: &FrameRange,
+    writer: Option<framecache::CacheWriter>,
+) -> Result<bool, String> {
+    thread::scope(|scope| {
+        let (sender, receiver) = mpsc::sync_channel(GIF_STREAM_FRAMES);
+        let decoder = scope.spawn(move || {
+            let mut writer = writer;
+            loop {
+                let frames = match open_gif(file) {
+                    Ok(decoder) => decoder.into_frames(),
                     Err(e) => {
-                        return Err(e.to_string());
+                        let _ = sender.send(Err(e));
+                        return 0;
                     }
                 };
+                let frames = frames.map(|x| x.map_err(|e| format!("Error: {}: {}", file, e)));
+                let mut nframes = 0;
+                for frame in frames_in_range(frames, range) {
+                    let frame = frame
+                        .and_then(|x| frame_to_dmd(&x, dmd.image_options, dmd.width, dmd.height))
+                        .map(|(img565, duration)| (Cow::Owned(img565.into_vec()), duration));
+                    if let (Some(cache), Ok((img565, duration))) = (writer.as_mut(), &frame)
+                        && let Err(e) = cache.push(img565, *duration)
+                    {
+                        eprintln!("{}", e);
+                        writer = None;
+                    }
+                    let failed = frame.is_err();
+                    // the player is gone: stopped, or failed to send
+                    if sender.send(frame).is_err() || failed {
+                        return nframes;
+                    }
+                    nframes += 1;
+                }
+                if nframes == 0 {
+                    let _ = sender.send(Err(format!(
+                        "Invalid start frame: {} (after the last frame)",
+                        range.start
+                    )));
+                }
+                if let Some(Err(e)) = writer.take().filter(|_| nframes > 0).map(|x| x.finish()) {
+                    eprintln!("{}", e);
+                }
+                if once || nframes <= 1 {
+                    return nframes;
+                }
+            }
+        });
+        playback::play_in_time(
+            dmd.client,
+            dmd.header,
+            receiver.into_iter(),
+            dmd.rate,
+            dmd.control,
+            dmd.cancel,
+        )?;
+        Ok(decoder.join().unwrap_or(0) > 1)
+    })
+}
+
+fn send_image_files(
+    dmd: &Dmd,
+    file: String,
+    fps: u32,
+    raw_size: (u32, u32),
+    once: bool,
+    range: &FrameRange,
+    cache: Option<&framecache::FrameCache>,
+) -> Result<bool, String> {
+    // a single file goes through the frame cache, a list of files or a directory is converted each time
+    let cached = match cache.filter(|_| Path::new(&file).is_file()) {
+        Some(cache) => {
+            let variant = format!(
+                "frames {}-{} trim {} fps {} default fps {} duration {} raw {}x{}",
+                range.start,
+                range.end.map_or(String::new(), |x| x.to_string()),
+                range.trim_ms.map_or(String::new(), |x| x.to_string()),
+                range.fps.map_or(String::new(), |x| x.to_string()),
+                fps,
+                DEFAULT_DURATION,
+                raw_size.0,
+                raw_size.1
+            );
+            Some((cache, cache.path(&file, &variant)?))
+        }
+        None => None,
+    };
+    if let Some((cache, path)) = &cached
+        && let Some(frames) = cache.load(path)
+    {
+        return send_animation(dmd, frames, once);
+    }
 
-                frames_dmd.push(img565);
-                frames_duration.push(duration);
+    if !file.contains(':') && file.ends_with(".gif") {
+        let writer = match cached.map(|(cache, path)| cache.writer(&path)) {
+            Some(Ok(x)) => Some(x),
+            Some(Err(e)) => {
+                eprintln!("{}", e);
+                None
             }
+            None => None,
+        };
+        return stream_gif(dmd, &file, once, range, writer);
+    }
+    let frames = files_to_frames(file, fps, raw_size, DEFAULT_DURATION)?;
+    let frames = select_frames(frames, range)?;
+    let frames = frames_to_dmd(frames, dmd.image_options, dmd.width, dmd.height)?;
+    if let Some((cache, path)) = cached
+        && let Err(e) = cache.store(&path, &frames)
+    {
+        eprintln!("{}", e);
+    }
+    send_animation(dmd, frames, once)
+}
+
+fn frames_from_spritesheet(
+    file: &str,
+    sprite_width: u32,
+    sprite_height: u32,
+    fps: u32,
+) -> Result<Vec<Frame>, String> {
+    let sheet = match Reader::open(file) {
+        Ok(x) => x,
+        Err(e) => {
+            return Err(e.to_string());
         }
+    };
+    let sheet = match sheet.decode() {
+        Ok(x) => x.to_rgba8(),
         Err(e) => {
             return Err(e.to_string());
         }
+    };
+
+    if sprite_width == 0 || sprite_height == 0 || fps == 0 {
+        return Err(String::from("Invalid sprite size or fps"));
+    }
+
+    let columns = sheet.width() / sprite_width;
+    let rows = sheet.height() / sprite_height;
+    if columns == 0 || rows == 0 {
+        return Err(format!(
+            "Error: {}: sprite size larger than the sheet ({}x{})",
+            file,
+            sheet.width(),
+            sheet.height()
+        ));
+    }
+
+    // cells are read left to right, then top to bottom
+    let mut frames = Vec::new();
+    for row in 0..rows {
+        for column in 0..columns {
+            let sprite = imageops::crop_imm(
+                &sheet,
+                column * sprite_width,
+                row * sprite_height,
+                sprite_width,
+                sprite_height,
+            )
+            .to_image();
+
+            // sheets are often not completely filled, skip the empty cells
+            if sprite.pixels().all(|pixel| pixel[3] == 0) {
+                continue;
+            }
+
+            frames.push(Frame::from_parts(
+                sprite,
+                0,
+                0,
+                Delay::from_numer_denom_ms(1000, fps),
+            ));
+        }
+    }
+
+    if frames.is_empty() {
+        return Err(format!("Error: {}: no sprite found", file));
+    }
+    Ok(frames)
+}
+
+fn send_spritesheet(
+    dmd: &Dmd,
+    file: &str,
+    sprite_size: (u32, u32),
+    fps: u32,
+    once: bool,
+) -> Result<bool, String> {
+    let frames = frames_from_spritesheet(file, sprite_size.0, sprite_size.1, fps)?;
+    send_frames(dmd, frames, once)
+}
+
+fn frames_to_dmd(
+    frames: Vec<Frame>,
+    image_options: &imageutils::ImageOptions,
+    dmd_width: u32,
+    dmd_height: u32,
+) -> Result<DMDAnimation, String> {
+    let mut frames_dmd = Vec::new();
+    let mut frames_duration = Vec::new();
+
+    // build the animation array
+    for frame in frames {
+        let (img565, duration) = frame_to_dmd(&frame, image_options, dmd_width, dmd_height)?;
+        frames_dmd.push(img565);
+        frames_duration.push(duration);
+    }
+
+    Ok((frames_dmd, frames_duration))
+}
+
+// the rgb565 image of a frame, and its duration
+fn frame_to_dmd(
+    frame: &Frame,
+    image_options: &imageutils::ImageOptions,
+    dmd_width: u32,
+    dmd_height: u32,
+) -> Result<(Box<[u8]>, u32), String> {
+    let (x, y) = frame.delay().numer_denom_ms();
+    let duration = (x as f32 / y as f32) as u32;
+
+    let orig_img = imageutils::fit_image(frame.buffer(), image_options, dmd_width, dmd_height)?;
+
+    match imageutils::image2dmdimage(
+        &orig_img,
+        &imageutils::TextAlign::CENTER,
+        &image_options.colors,
+        dmd_width,
+        dmd_height,
+    ) {
+        Ok(img) => Ok((img, duration)),
+        Err(e) => Err(e.to_string()),
     }
+}
+
+fn send_frames(dmd: &Dmd, frames: Vec<Frame>, once: bool) -> Result<bool, String> {
+    let frames = frames_to_dmd(frames, dmd.image_options, dmd.width, dmd.height)?;
+    send_animation(dmd, frames, once)
+}
 
+// a single frame is sent, more are played. returns true when it was animated
+fn send_animation(
+    dmd: &Dmd,
+    (frames_dmd, frames_duration): DMDAnimation,
+    once: bool,
+) -> Result<bool, String> {
     if frames_dmd.len() == 1 {
-        match send_frame(&client, header, &frames_dmd[0]) {
+        match send_frame(dmd.client, dmd.header, &frames_dmd[0]) {
             Ok(_) => {}
             Err(e) => {
                 return Err(e.to_string());
@@ -481,41 +1920,46 @@ fn send_image_files(
         };
         Ok(false)
     } else {
-        play_animation(header, &client, &frames_dmd, frames_duration, once)?;
+        play_animation(dmd, &frames_dmd, frames_duration, once)?;
         Ok(true)
     }
 }
 
 fn play_animation(
-    header: [u8; DMD_HEADER_SIZE],
-    client: &TcpStream,
-    frames_dmd: &Vec<Box<[u8]>>,
+    dmd: &Dmd,
+    frames_dmd: &[Box<[u8]>],
     frames_duration: Vec<u32>,
     once: bool,
 ) -> Result<(), String> {
-    let mut n;
-
-    loop {
-        n = 0;
-        for img565 in frames_dmd {
-            match send_frame(&client, header, &img565) {
-                Ok(_) => {}
-                Err(e) => {
-                    return Err(e.to_string());
-                }
-            };
-
-            thread::sleep(Duration::from_millis(frames_duration[n] as u64));
-            n = n + 1;
-        }
+    let frames = frames_dmd
+        .iter()
+        .zip(frames_duration)
+        .map(|(img565, duration)| Ok((Cow::Borrowed(&img565[..]), duration)));
 
-        if once {
-            return Ok(());
-        }
+    if once {
+        playback::play_in_time(
+            dmd.client,
+            dmd.header,
+            frames,
+            dmd.rate,
+            dmd.control,
+            dmd.cancel,
+        )
+    } else {
+        playback::play_in_time(
+            dmd.client,
+            dmd.header,
+            frames.cycle(),
+            dmd.rate,
+            dmd.control,
+            dmd.cancel,
+        )
     }
 }
 
-fn strfdelta(duration: TimeDelta, format: &str) -> String {
+// {W} weeks, {D} days, {H} hours, {M} minutes, {S} seconds, {TH} and {TM} total hours and minutes
+// a width pads with zeros ({M:02}), days are the remaining ones after the weeks when {W} is used
+fn strfdelta(duration: TimeDelta, format: &str) -> Result<String, String> {
     let total_seconds = duration.num_seconds();
     let days = total_seconds / 86400;
     let remaining_seconds = total_seconds % 86400;
@@ -523,278 +1967,2458 @@ fn strfdelta(duration: TimeDelta, format: &str) -> String {
     let remaining_seconds = remaining_seconds % 3600;
     let minutes = remaining_seconds / 60;
     let seconds = remaining_seconds % 60;
+    let with_weeks = format.contains("{W}") || format.contains("{W:");
 
-    format
-        .replace("{D:2}", &format!("{:02}", days))
-        .replace("{D}", &days.to_string())
-        .replace("{H:2}", &format!("{:02}", hours))
-        .replace("{H}", &hours.to_string())
-        .replace("{M:02}", &format!("{:02}", minutes))
-        .replace("{M}", &minutes.to_string())
-        .replace("{S:02}", &format!("{:02}", seconds))
-        .replace("{S}", &seconds.to_string())
+    let mut result = String::new();
+    let mut rest = format;
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        let end = match rest[start..].find('}') {
+            Some(x) => start + x,
+            None => return Err(format!("Unclosed placeholder in format: {}", format)),
+        };
+        let placeholder = &rest[start + 1..end];
+        let (name, width) = match placeholder.split_once(':') {
+            Some((name, width)) => match width.parse::<usize>() {
+                Ok(x) => (name, x),
+                Err(_) => return Err(format!("Invalid placeholder width: {{{}}}", placeholder)),
+            },
+            None => (placeholder, 0),
+        };
+        let value = match name {
+            "W" => days / 7,
+            "D" if with_weeks => days % 7,
+            "D" => days,
+            "H" => hours,
+            "M" => minutes,
+            "S" => seconds,
+            "TH" => total_seconds / 3600,
+            "TM" => total_seconds / 60,
+            _ => return Err(format!("Unknown placeholder: {{{}}}", placeholder)),
+        };
+        result.push_str(&format!("{:0width$}", value, width = width));
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
 }
 
-fn handle_clock(
-    client: &TcpStream,
-    header: [u8; DMD_HEADER_SIZE],
-    dmd_width: u32,
-    dmd_height: u32,
-    font_path: &str,
-    gradient: &Option<DynamicImage>,
-    text_color: Rgba<u8>,
-    background_color: Rgba<u8>,
-    text_align: &imageutils::TextAlign,
-    line_spacing: u8,
-    moving_text: bool,
-    fixed_text: bool,
-    speed: u32,
-    clock_format: Option<String>,
-    h12: bool,
-    no_seconds: bool,
-) {
-    let mut previous_txt = String::new();
-    let mut localtime;
-
-    loop {
-        let now = Local::now();
+fn clock_text(now: &DateTime<Local>, clock: &ClockOptions) -> String {
+    match clock.timezone {
+        Some(tz) => format_time(&now.with_timezone(&tz), clock),
+        None => format_time(now, clock),
+    }
+}
 
-        match clock_format {
-            Some(ref x) => {
-                localtime = now.format(&x).to_string();
-            }
-            None => {
-                if h12 {
-                    if no_seconds {
-                        localtime = now.format("%-I:%M %p").to_string();
-                    } else {
-                        localtime = now.format("%-I:%M:%S %p").to_string();
-                    }
+fn format_time<T: TimeZone>(now: &DateTime<T>, clock: &ClockOptions) -> String
+where
+    T::Offset: fmt::Display,
+{
+    match &clock.format {
+        Some(x) => now.format(x).to_string(),
+        None => {
+            if clock.h12 {
+                if clock.no_seconds {
+                    now.format("%-I:%M %p").to_string()
                 } else {
-                    if no_seconds {
-                        localtime = now.format("%H:%M").to_string();
-                    } else {
-                        localtime = now.format("%H:%M:%S").to_string();
-                    }
+                    now.format("%-I:%M:%S %p").to_string()
                 }
+            } else if clock.no_seconds {
+                now.format("%H:%M").to_string()
+            } else {
+                now.format("%H:%M:%S").to_string()
             }
         }
+    }
+}
 
-        if previous_txt != localtime {
-            previous_txt = localtime.clone();
+// display a text computed again every refresh seconds, sent when it changes
+// returns true when the text was animated
+fn handle_refreshed_text<F>(
+    dmd: &Dmd,
+    options: &TextOptions,
+    once: bool,
+    refresh: Option<u32>,
+    mut next_text: F,
+) -> Result<bool, String>
+where
+    F: FnMut() -> Result<String, String>,
+{
+    let mut previous_txt = String::new();
+    let mut was_animation = false;
 
-            let _ = match send_image_text(
-                &client,
-                header,
-                dmd_width,
-                dmd_height,
-                &localtime,
-                &font_path,
-                &gradient,
-                text_color,
-                background_color,
-                &text_align,
-                line_spacing,
-                moving_text,
-                fixed_text,
-                speed,
-                true,
-            ) {
-                Ok(_) => {}
+    loop {
+        // a text whose evaluation was cut short by the cancellation is not displayed
+        let text = match next_text() {
+            Ok(x) => x,
+            Err(_) if dmd.cancel.is_cancelled() => return Ok(was_animation),
+            Err(e) => return Err(e),
+        };
+
+        // a scrolling text is played again, a fixed one only when it changes
+        if text != previous_txt || was_animation {
+            was_animation = send_image_text(dmd, &text, options, once || refresh.is_some())?;
+            previous_txt = text;
+        }
+
+        match refresh {
+            Some(refresh) => {
+                // a scrolling text was cut short by the cancellation, a fixed one waits for it
+                let cancelled = match was_animation {
+                    true => dmd.cancel.is_cancelled(),
+                    false => dmd.cancel.sleep(Duration::from_secs(refresh as u64)),
+                };
+                if cancelled {
+                    return Ok(was_animation);
+                }
+            }
+            None => return Ok(was_animation),
+        }
+    }
+}
+
+// display the texts one after the other, a fixed one for cycle seconds and a scrolling one scrolled
+// once, in a loop unless once. returns true when the texts were animated
+fn play_text_cycle<F>(
+    dmd: &Dmd,
+    texts: &[String],
+    cycle: u32,
+    options: &TextOptions,
+    once: bool,
+    expand_text: F,
+) -> Result<bool, String>
+where
+    F: Fn(&str) -> String,
+{
+    loop {
+        for (n, text) in texts.iter().enumerate() {
+            let was_animation = send_image_text(dmd, &expand_text(text), options, true)?;
+            // the last fixed text stays displayed with once
+            let last = once && n == texts.len() - 1;
+            let cancelled = match !was_animation && !last {
+                true => dmd
+                    .cancel
+                    .sleep(Duration::from_secs_f32(cycle as f32 / dmd.rate)),
+                false => dmd.cancel.is_cancelled(),
+            };
+            if cancelled {
+                return Ok(true);
+            }
+        }
+        if once {
+            return Ok(true);
+        }
+    }
+}
+
+// play the images of a text in the colors of the cycle. the phase follows the time of the animation,
+// not the clock, so the colors move with the rate and never jump when the sending is late.
+// with once, the images are played once, a single one for a full cycle of the colors
+fn play_color_cycle(
+    dmd: &Dmd,
+    images: &[(RgbaImage, u32)],
+    color_cycle: &ColorCycle,
+    options: &TextOptions,
+    once: bool,
+) -> Result<(), String> {
+    let (render_width, render_height) =
+        imageutils::oriented_size(&dmd.image_options.orientation, dmd.width, dmd.height);
+    let cycle_speed = options.animation.cycle_speed.max(1) as u64;
+    let nframes = match (once, images.len()) {
+        (false, _) => usize::MAX,
+        // up to the first frame at the end of a full cycle
+        (true, 1) => (360_000 / COLOR_CYCLE_FRAME as u64).div_ceil(cycle_speed) as usize + 1,
+        (true, n) => n,
+    };
+
+    let mut elapsed = 0;
+    let frames = images.iter().cycle().take(nframes).map(|(img, duration)| {
+        // ms of the animation times degrees per second, 360000 for a full cycle
+        let phase = (elapsed * cycle_speed % 360_000) as f32 / 360_000.0;
+        elapsed += *duration as u64;
+        let gradient = match (color_cycle, &options.gradient) {
+            (ColorCycle::RAINBOW, _) => {
+                imageutils::rainbow_gradient(render_width, render_height, phase)
+            }
+            (ColorCycle::GRADIENT, Some(gradient)) => imageutils::shift_gradient(gradient, phase),
+            (ColorCycle::GRADIENT, None) => {
+                return Err(String::from("The gradient color cycle requires --gradient"));
+            }
+        };
+        let img = imageutils::recolor_text(img, &gradient);
+        let img = imageutils::apply_background(&img, &options.background);
+        render_frame(dmd.width, dmd.height, &img, dmd.image_options)
+            .map(|x| (Cow::Owned(x.into_vec()), *duration))
+    });
+
+    playback::play(
+        dmd.client,
+        dmd.header,
+        frames,
+        dmd.rate,
+        dmd.control,
+        dmd.cancel,
+    )
+}
+
+// an output of a command, read meanwhile so that the command is not blocked writing it
+fn read_output<R: Read + Send + 'static>(output: Option<R>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut bytes = Vec::new();
+        if let Some(mut output) = output {
+            let _ = output.read_to_end(&mut bytes);
+        }
+        bytes
+    })
+}
+
+// the output of a shell command, one line of text per line of output. the command is killed,
+// with what it started, after EXEC_TIMEOUT or once the playing is cancelled. it fails on an
+// error exit status, with its error output
+fn exec_text(command: &str, cancel: &cancel::CancelToken) -> Result<String, String> {
+    let mut child = match Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()
+    {
+        Ok(x) => x,
+        Err(e) => return Err(format!("Unable to run {}: {}", command, e)),
+    };
+    let stdout = read_output(child.stdout.take());
+    let stderr = read_output(child.stderr.take());
+
+    let started = Instant::now();
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(x)) => break Ok(x),
+            Ok(None) if started.elapsed() >= EXEC_TIMEOUT => {
+                break Err(format!(
+                    "{}: still running after {} seconds",
+                    command,
+                    EXEC_TIMEOUT.as_secs()
+                ));
+            }
+            Ok(None) if cancel.sleep(EXEC_POLL) => break Err(format!("{}: cancelled", command)),
+            Ok(None) => {}
+            Err(e) => break Err(format!("Unable to run {}: {}", command, e)),
+        }
+    };
+    // the group of the shell, so that nothing it started keeps its outputs open
+    unsafe {
+        libc::kill(-(child.id() as i32), libc::SIGKILL);
+    }
+    let _ = child.wait();
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+
+    let status = status?;
+    if !status.success() {
+        return Err(format!(
+            "{}: {}: {}",
+            command,
+            status,
+            String::from_utf8_lossy(&stderr).trim_end()
+        ));
+    }
+    let stdout = String::from_utf8_lossy(&stdout);
+    Ok(stdout.trim_end().lines().collect::<Vec<&str>>().join("\\n"))
+}
+
+fn handle_clock(
+    dmd: &Dmd,
+    options: &TextOptions,
+    clock: &ClockOptions,
+    clock_style: &Option<clockstyle::ClockStyle>,
+    alarm: &Option<Alarm>,
+) {
+    let mut previous_txt = String::new();
+    let mut alarm_state = AlarmState::WAITING;
+    let mut flash = false;
+    // the alarm text never scrolls
+    let alarm_options = TextOptions {
+        moving: false,
+        fixed: true,
+        ..options.clone()
+    };
+    let alarm_clock = ClockOptions {
+        format: Some(String::from("%H:%M")),
+        ..clock.clone()
+    };
+
+    loop {
+        let now = Local::now();
+        let localtime = match clock_style {
+            Some(clockstyle::ClockStyle::WORDS) => {
+                let time = match clock.timezone {
+                    Some(tz) => now.with_timezone(&tz).time(),
+                    None => now.time(),
+                };
+                clockstyle::time_in_words(time.hour(), time.minute())
+            }
+            _ => clock_text(&now, clock),
+        };
+
+        if let Some(alarm) = alarm {
+            let minute = clock_text(&now, &alarm_clock);
+            let alarm_minute = minute == alarm.time.format("%H:%M").to_string();
+            alarm_state = match alarm_state {
+                AlarmState::WAITING if alarm_minute => {
+                    if let Some(alarm_command) = &alarm.command {
+                        // the command runs in the background, the alarm keeps flashing
+                        if let Err(e) = Command::new("sh").arg("-c").arg(alarm_command).spawn() {
+                            eprintln!("Unable to run {}: {}", alarm_command, e);
+                        }
+                    }
+                    AlarmState::RINGING(Instant::now())
+                }
+                AlarmState::RINGING(since)
+                    if since.elapsed().as_secs() >= alarm.duration as u64 =>
+                {
+                    previous_txt = String::new();
+                    AlarmState::RUNG
+                }
+                AlarmState::RUNG if !alarm_minute => AlarmState::WAITING,
+                x => x,
+            };
+
+            if let AlarmState::RINGING(_) = alarm_state {
+                // the text and the background alternate
+                flash = !flash;
+                let result = if flash {
+                    send_image_text(dmd, &alarm.text, &alarm_options, true).map(|_| ())
+                } else {
+                    send_render(dmd, &options.background)
+                };
+                if let Err(e) = result {
+                    eprintln!("{}", e);
+                }
+                if dmd.cancel.sleep(Duration::from_millis(500)) {
+                    return;
+                }
+                continue;
+            }
+        }
+
+        // the words are a text
+        if let Some(style) = clock_style
+            .as_ref()
+            .filter(|x| !matches!(x, clockstyle::ClockStyle::WORDS) && previous_txt != localtime)
+        {
+            let text_color = options.style.color;
+            let color = Rgba([text_color[0], text_color[1], text_color[2], 255]);
+            // the cards of the digits changed flip to the new ones
+            if matches!(style, clockstyle::ClockStyle::FLIP) && !previous_txt.is_empty() {
+                for step in 1..clockstyle::FLIP_FRAMES {
+                    let progress = step as f32 / clockstyle::FLIP_FRAMES as f32;
+                    let img = clockstyle::flip_frame(
+                        &previous_txt,
+                        &localtime,
+                        progress,
+                        &options.background,
+                        color,
+                    );
+                    if let Err(e) = send_render(dmd, &img) {
+                        eprintln!("{}", e);
+                    }
+                    if dmd.cancel.sleep(Duration::from_millis(
+                        (clockstyle::FLIP_FRAME_DURATION as f32 / dmd.rate) as u64,
+                    )) {
+                        return;
+                    }
+                }
+            }
+            previous_txt = localtime.clone();
+            let img = clockstyle::render_clock(style, &localtime, &options.background, color);
+            if let Err(e) = send_render(dmd, &img) {
+                eprintln!("{}", e);
+            }
+        } else if previous_txt != localtime {
+            previous_txt = localtime.clone();
+
+            let _ = match send_image_text(dmd, &localtime, options, true) {
+                Ok(_) => {}
                 Err(e) => {
                     eprintln!("{}", e.to_string());
                 }
             };
         }
 
-        thread::sleep(Duration::from_millis(1000));
+        if dmd.cancel.sleep(Duration::from_millis(1000)) {
+            return;
+        }
     }
 }
 
 fn handle_countdown(
-    client: &TcpStream,
-    header: [u8; DMD_HEADER_SIZE],
-    dmd_width: u32,
-    dmd_height: u32,
-    font_path: &str,
-    gradient: &Option<DynamicImage>,
-    text_color: Rgba<u8>,
-    background_color: Rgba<u8>,
-    text_align: &imageutils::TextAlign,
-    line_spacing: u8,
-    moving_text: bool,
-    fixed_text: bool,
-    speed: u32,
-    countdown: String,
-    countdown_header: Option<String>,
-    countdown_format: String,
-    countdown_format_0_minute: String,
-    countdown_format_0_hour: String,
-    countdown_format_0_day: String,
+    dmd: &Dmd,
+    options: &TextOptions,
+    countdowns: &Countdowns,
 ) -> Result<(), String> {
-    match NaiveDateTime::parse_from_str(&countdown.to_string(), "%Y-%m-%d %H:%M:%S") {
-        Ok(target) => {
-            let mut previous_txt = String::new();
-            let mut countdown_str: String;
+    let timezone = &countdowns.timezone;
+    let mut labels: Vec<Option<String>> =
+        countdowns.targets.iter().map(|(x, _)| x.clone()).collect();
+    // none for a calendar without upcoming event
+    let mut target_datetimes: Vec<Option<DateTime<Local>>> = Vec::new();
+    for ((_, target), label) in countdowns.targets.iter().zip(labels.iter_mut()) {
+        target_datetimes.push(match target {
+            CountdownTarget::DATE(countdown) => {
+                let target = match NaiveDateTime::parse_from_str(countdown, "%Y-%m-%d %H:%M:%S") {
+                    Ok(x) => x,
+                    Err(e) => {
+                        return Err(format!("{}: {}", countdown, e));
+                    }
+                };
+                // the date is given in the time zone of the countdown
+                let target_datetime = match timezone {
+                    Some(tz) => tz
+                        .from_local_datetime(&target)
+                        .earliest()
+                        .map(|x| x.with_timezone(&Local)),
+                    None => Local.from_local_datetime(&target).earliest(),
+                };
+                match target_datetime {
+                    Some(x) => Some(x),
+                    None => {
+                        return Err(String::from("Error parsing"));
+                    }
+                }
+            }
+            CountdownTarget::CRON(schedule) => {
+                Some(next_occurrence(schedule, &Local::now(), timezone)?)
+            }
+            CountdownTarget::ICS(source) => next_calendar_event(source)?.map(|(title, start)| {
+                *label = Some(title);
+                start
+            }),
+        });
+    }
+
+    let started = Instant::now();
+    let mut refreshed = Instant::now();
+    let mut previous_txt = String::new();
+
+    loop {
+        let now = Local::now();
+        let refresh = refreshed.elapsed().as_secs() >= countdowns.ics_refresh as u64 * 60;
+        if refresh {
+            refreshed = Instant::now();
+        }
+
+        let mut lines = Vec::new();
+        for (((_, target), target_datetime), label) in countdowns
+            .targets
+            .iter()
+            .zip(target_datetimes.iter_mut())
+            .zip(labels.iter_mut())
+        {
+            match target {
+                // a schedule rolls over to its next occurrence once reached
+                CountdownTarget::CRON(schedule) if target_datetime.is_some_and(|x| now >= x) => {
+                    *target_datetime = Some(next_occurrence(schedule, &now, timezone)?);
+                }
+                // the calendar is read again from time to time, and once its event started
+                CountdownTarget::ICS(source)
+                    if refresh || target_datetime.is_some_and(|x| now >= x) =>
+                {
+                    match next_calendar_event(source) {
+                        Ok(Some((title, start))) => {
+                            *label = Some(title);
+                            *target_datetime = Some(start);
+                        }
+                        Ok(None) => {
+                            *label = None;
+                            *target_datetime = None;
+                        }
+                        Err(e) => eprintln!("{}", e),
+                    }
+                }
+                _ => {}
+            }
 
-            let target_datetime = match Local.from_local_datetime(&target).earliest() {
-                Some(x) => x,
-                None => {
-                    return Err(String::from("Error parsing"));
+            let value = match target_datetime {
+                Some(target_datetime) => {
+                    countdown_text((*target_datetime - now).abs(), countdowns)?
                 }
+                None => String::from("No upcoming event"),
             };
+            lines.push((&*label, value));
+        }
 
-            loop {
-                let now = Local::now();
+        let countdown_str = if countdowns.lines {
+            // one line per countdown, prefixed by its label
+            let mut txt: Vec<String> = lines
+                .iter()
+                .map(|(label, value)| match label {
+                    Some(label) => format!("{} {}", label, value),
+                    None => value.clone(),
+                })
+                .collect();
+            if let Some(ref countdown_header) = countdowns.header {
+                txt.insert(0, countdown_header.clone());
+            }
+            txt.join("\\n")
+        } else {
+            // one countdown at a time, the label replaces the header
+            let shown =
+                (started.elapsed().as_secs() / countdowns.cycle as u64) as usize % lines.len();
+            let (label, value) = &lines[shown];
+            match label.as_ref().or(countdowns.header.as_ref()) {
+                Some(title) => title.to_owned() + "\\n" + value.as_str(),
+                None => value.clone(),
+            }
+        };
 
-                let delta = (target_datetime - now).abs();
-                let total_seconds = delta.num_seconds();
+        if previous_txt != countdown_str {
+            previous_txt = countdown_str.clone();
 
-                if (total_seconds >= 0 && total_seconds < 60)
-                    || (total_seconds < 0 && total_seconds > -60)
-                {
-                    countdown_str = strfdelta(delta, &countdown_format_0_minute.to_string());
-                } else if (total_seconds > 0 && total_seconds < 3600)
-                    || (total_seconds < 0 && total_seconds > -3600)
-                {
-                    countdown_str = strfdelta(delta, &countdown_format_0_hour.to_string());
-                } else if (total_seconds > 0 && total_seconds < 86400)
-                    || (total_seconds < 0 && total_seconds > -86400)
-                {
-                    countdown_str = strfdelta(delta, &countdown_format_0_day.to_string());
-                } else {
-                    countdown_str = strfdelta(delta, &countdown_format.to_string());
+            let _ = match send_image_text(dmd, &countdown_str, options, true) {
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("{}", e.to_string());
                 }
-                match countdown_header {
-                    Some(ref countdown_header) => {
-                        countdown_str = countdown_header.to_owned() + "\\n" + &countdown_str;
-                    }
-                    None => {}
-                }
-
-                if previous_txt != countdown_str {
-                    previous_txt = countdown_str.clone();
-
-                    let _ = match send_image_text(
-                        &client,
-                        header,
-                        dmd_width,
-                        dmd_height,
-                        &countdown_str,
-                        &font_path,
-                        &gradient,
-                        text_color,
-                        background_color,
-                        &text_align,
-                        line_spacing,
-                        moving_text,
-                        fixed_text,
-                        speed,
-                        true,
-                    ) {
-                        Ok(_) => {}
-                        Err(e) => {
-                            eprintln!("{}", e.to_string());
-                        }
-                    };
+            };
+        }
+
+        if dmd.cancel.sleep(Duration::from_millis(1000)) {
+            return Ok(());
+        }
+    }
+}
+
+// the format depends on the remaining time
+fn countdown_text(delta: TimeDelta, countdowns: &Countdowns) -> Result<String, String> {
+    let total_seconds = delta.num_seconds();
+
+    if (total_seconds >= 0 && total_seconds < 60) || (total_seconds < 0 && total_seconds > -60) {
+        strfdelta(delta, &countdowns.format_0_minute)
+    } else if (total_seconds > 0 && total_seconds < 3600)
+        || (total_seconds < 0 && total_seconds > -3600)
+    {
+        strfdelta(delta, &countdowns.format_0_hour)
+    } else if (total_seconds > 0 && total_seconds < 86400)
+        || (total_seconds < 0 && total_seconds > -86400)
+    {
+        strfdelta(delta, &countdowns.format_0_day)
+    } else {
+        strfdelta(delta, &countdowns.format)
+    }
+}
+
+// the title and the start of the next event of the calendar
+fn next_calendar_event(source: &str) -> Result<Option<(String, DateTime<Local>)>, String> {
+    let events = ics::load_calendar(source)?;
+    Ok(ics::next_event(&events, &Utc::now())
+        .map(|(title, start)| (title, start.with_timezone(&Local))))
+}
+
+// "label=value" or just "value"
+fn split_label(value: &str) -> (Option<String>, String) {
+    match value.split_once('=') {
+        Some((label, value)) => (Some(label.to_string()), value.to_string()),
+        None => (None, value.to_string()),
+    }
+}
+
+fn next_occurrence(
+    schedule: &cron::CronSchedule,
+    after: &DateTime<Local>,
+    timezone: &Option<Tz>,
+) -> Result<DateTime<Local>, String> {
+    // the schedule follows the time zone of the countdown
+    let next = match timezone {
+        Some(tz) => schedule
+            .next_after(&after.with_timezone(tz))
+            .map(|x| x.with_timezone(&Local)),
+        None => schedule.next_after(after),
+    };
+    next.ok_or(String::from(
+        "No next occurrence for the countdown schedule",
+    ))
+}
+
+fn handle_progress(
+    dmd: &Dmd,
+    progress: f32,
+    label: &Option<String>,
+    options: &TextOptions,
+) -> Result<(), String> {
+    let (render_width, render_height) = options.background.dimensions();
+    let mut img = options.background.clone();
+    let text_color = options.style.color;
+    let bar_color = Rgba([text_color[0], text_color[1], text_color[2], 255]);
+    let margin = (render_width / 32).max(1);
+
+    // the label takes the top of the dmd, the bar the bottom, else the bar is centered
+    let (bar_y, bar_height) = match label {
+        Some(label) => {
+            let bar_height = (render_height / 4).max(3);
+            let label_height = render_height.saturating_sub(bar_height + 2 * margin).max(1);
+            let (dyn_img, _start, _new_width) = imageutils::generate_text_image(
+                label,
+                &options.style,
+                &None,
+                render_width,
+                label_height,
+            )?;
+            imageutils::blend_image(&dyn_img.to_rgba8(), &mut img, 0, 0);
+            (
+                render_height.saturating_sub(bar_height + margin),
+                bar_height,
+            )
+        }
+        None => {
+            let bar_height = (render_height / 3).max(3);
+            (render_height.saturating_sub(bar_height) / 2, bar_height)
+        }
+    };
+
+    imageutils::draw_progress_bar(
+        &mut img,
+        margin as i32,
+        bar_y as i32,
+        render_width.saturating_sub(2 * margin),
+        bar_height,
+        progress / 100.0,
+        bar_color,
+    );
+
+    send_render(dmd, &img)
+}
+
+// the progress bar of a status polled until dmd-play is stopped, or until 100% with once.
+// the bar is sent when the status changes, the last one stays while the status cannot be read
+fn watch_progress(
+    dmd: &Dmd,
+    source: &ProgressSource,
+    progress_template: &str,
+    options: &TextOptions,
+    once: bool,
+) -> Result<(), String> {
+    let mut previous: Option<(f32, String)> = None;
+    loop {
+        let content = match source {
+            // a missing file is not an error, the updater may write it later
+            ProgressSource::FILE(path) => fs::read_to_string(path).ok(),
+            ProgressSource::EXEC(command) => {
+                match Command::new("sh").arg("-c").arg(command).output() {
+                    Ok(x) => Some(String::from_utf8_lossy(&x.stdout).to_string()),
+                    Err(e) => return Err(format!("Unable to run {}: {}", command, e)),
+                }
+            }
+        };
+        let status = content.map(|x| poll::progress_status(&x, progress_template));
+        match status {
+            Some(Ok(status)) if previous.as_ref() != Some(&status) => {
+                let (percent, label) = &status;
+                handle_progress(
+                    dmd,
+                    *percent,
+                    &Some(label.clone()).filter(|x| !x.is_empty()),
+                    options,
+                )?;
+                if once && *percent >= 100.0 {
+                    return Ok(());
                 }
+                previous = Some(status);
+            }
+            Some(Err(e)) => eprintln!("{}", e),
+            _ => {}
+        }
+        if dmd.cancel.sleep(PROGRESS_POLL) {
+            return Ok(());
+        }
+    }
+}
+
+// the frame of an image rendered at the oriented size
+fn render_frame(
+    dmd_width: u32,
+    dmd_height: u32,
+    img: &RgbaImage,
+    image_options: &imageutils::ImageOptions,
+) -> Result<Box<[u8]>, String> {
+    let img = imageutils::orient_image(img, &image_options.orientation);
+    imageutils::image2dmdimage(
+        &img,
+        &imageutils::TextAlign::CENTER,
+        &image_options.colors,
+        dmd_width,
+        dmd_height,
+    )
+}
+
+// send an image rendered at the oriented size
+fn send_render(dmd: &Dmd, img: &RgbaImage) -> Result<(), String> {
+    let img565 = render_frame(dmd.width, dmd.height, img, dmd.image_options)?;
+    match send_frame(dmd.client, dmd.header, &img565) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
 
-                thread::sleep(Duration::from_millis(1000));
+// a text over a plain background, after an icon (image file) as high as the dmd
+fn render_overlay(
+    text: &str,
+    icon: Option<&str>,
+    overlay: &OverlayStyle,
+) -> Result<Box<[u8]>, String> {
+    let image_options = &overlay.image_options;
+    let (width, height) =
+        imageutils::oriented_size(&image_options.orientation, overlay.width, overlay.height);
+    let mut img = RgbaImage::from_pixel(width, height, overlay.background);
+
+    // an icon that cannot be read is left out, the text is still displayed
+    let mut text_x = 0;
+    if let Some(icon) = icon {
+        match image::open(icon) {
+            Ok(x) => {
+                let icon = x
+                    .resize(height, height, imageops::FilterType::Lanczos3)
+                    .to_rgba8();
+                let y = (height - icon.height()) / 2;
+                imageutils::blend_image(&icon, &mut img, 0, y as i32);
+                text_x = (icon.width() + 1).min(width - 1);
             }
+            Err(e) => eprintln!("Error: {}: {}", icon, e),
         }
-        Err(e) => {
-            return Err(e.to_string());
+    }
+
+    let (text_img, _, _) =
+        imageutils::generate_text_image(text, &overlay.style, &None, width - text_x, height)?;
+    imageutils::blend_image(&text_img.to_rgba8(), &mut img, text_x as i32, 0);
+    let img = imageutils::orient_image(&img, &image_options.orientation);
+    imageutils::image2dmdimage(
+        &img,
+        &imageutils::TextAlign::CENTER,
+        &image_options.colors,
+        overlay.width,
+        overlay.height,
+    )
+}
+
+// the texts written in the overlay file, rendered for the overlay sink showing them over what is played.
+// the file is removed once read, so that the next text can be written
+fn watch_overlay_file(
+    path: &str,
+    overlay: &OverlayStyle,
+    duration: Duration,
+    overlays: Sender<preview::OverlayFrame>,
+    cancel: &cancel::CancelToken,
+) {
+    while !cancel.sleep(OVERLAY_FILE_POLL) {
+        let text = match fs::read_to_string(path) {
+            Ok(x) => x,
+            Err(_) => continue,
+        };
+        if let Err(e) = fs::remove_file(path) {
+            eprintln!("Error: {}: {}", path, e);
+            return;
+        }
+        let text = text.trim_end().replace('\n', "\\n");
+        if text.is_empty() {
+            continue;
+        }
+
+        match render_overlay(&text, None, overlay) {
+            Ok(x) => {
+                // the overlay sink is gone, once nothing is played
+                if overlays.send((overlay.header, x, duration)).is_err() {
+                    return;
+                }
+            }
+            Err(e) => eprintln!("{}", e),
         }
     }
 }
 
-fn main() {
-    let args = Cli::parse();
-    let mut was_animation = false; // set to true to disable overlay sleep time at the end
+// the summary and the body of the notifications, rendered for the overlay sink like the overlay file
+fn show_notifications(
+    notifications: Receiver<notifications::Notification>,
+    overlay: &OverlayStyle,
+    default_duration: Duration,
+    overlays: Sender<preview::OverlayFrame>,
+    cancel: &cancel::CancelToken,
+) {
+    while !cancel.is_cancelled() {
+        let notification = match notifications.recv_timeout(NOTIFICATIONS_POLL) {
+            Ok(x) => x,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
+        let text = [notification.summary, notification.body]
+            .iter()
+            .map(|x| x.trim())
+            .filter(|x| !x.is_empty())
+            .collect::<Vec<&str>>()
+            .join("\n")
+            .replace('\n', "\\n");
+        let duration = notification
+            .timeout
+            .map_or(default_duration, |x| Duration::from_millis(x as u64));
+
+        match render_overlay(&text, notification.icon.as_deref(), overlay) {
+            Ok(x) => {
+                // the flashes alternate the negative and the alert, then the alert stays
+                if notification.flash {
+                    let negative: Box<[u8]> = x.iter().map(|b| !b).collect();
+                    for _ in 0..ALERT_FLASHES {
+                        for frame in [&negative, &x] {
+                            let flash = (overlay.header, frame.clone(), ALERT_FLASH);
+                            if overlays.send(flash).is_err() {
+                                return;
+                            }
+                        }
+                    }
+                }
+                if overlays.send((overlay.header, x, duration)).is_err() {
+                    return;
+                }
+            }
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+}
+
+// display a calibration pattern, the pixel walk goes through the whole panel in each color
+// (once with once). returns whether it was animated
+fn play_test_pattern(
+    dmd: &Dmd,
+    pattern: &imageutils::TestPattern,
+    fps: u32,
+    once: bool,
+) -> Result<bool, String> {
+    let (width, height) =
+        imageutils::oriented_size(&dmd.image_options.orientation, dmd.width, dmd.height);
+    if !matches!(pattern, imageutils::TestPattern::PIXELWALK) {
+        let img = imageutils::test_pattern(pattern, width, height, 0);
+        send_render(dmd, &img)?;
+        return Ok(false);
+    }
+
+    // 4 colors
+    let steps = width * height * 4;
+    let walk = (0..steps).map(|step| {
+        let img = imageutils::test_pattern(pattern, width, height, step);
+        render_frame(dmd.width, dmd.height, &img, dmd.image_options)
+            .map(|x| (Cow::Owned(x.into_vec()), playback::frame_duration(fps)))
+    });
+
+    if once {
+        playback::play(
+            dmd.client,
+            dmd.header,
+            walk,
+            dmd.rate,
+            dmd.control,
+            dmd.cancel,
+        )?;
+    } else {
+        playback::play(
+            dmd.client,
+            dmd.header,
+            walk.cycle(),
+            dmd.rate,
+            dmd.control,
+            dmd.cancel,
+        )?;
+    }
+    Ok(true)
+}
+
+// the converted frames of an image of the slideshow, from the frame cache when it was converted before
+fn slideshow_frames(
+    file: &str,
+    interval_ms: u32,
+    image_options: &imageutils::ImageOptions,
+    dmd_width: u32,
+    dmd_height: u32,
+    cache: Option<&framecache::FrameCache>,
+) -> Result<DMDAnimation, String> {
+    let cached = match cache {
+        Some(cache) => Some((
+            cache,
+            cache.path(file, &format!("slideshow {}", interval_ms))?,
+        )),
+        None => None,
+    };
+    if let Some((cache, path)) = &cached
+        && let Some(frames) = cache.load(path)
+    {
+        return Ok(frames);
+    }
+
+    let frames = match file.to_lowercase().ends_with(".gif") {
+        true => frames_from_gif(file),
+        false => frame_from_image(file, interval_ms)
+            .map(|x| vec![x])
+            .map_err(|e| format!("Error: {}: {}", file, e)),
+    }?;
+    let frames = frames_to_dmd(frames, image_options, dmd_width, dmd_height)?;
+    if frames.0.is_empty() {
+        return Err(format!("Error: {}: no frame", file));
+    }
+    if let Some((cache, path)) = cached
+        && let Err(e) = cache.store(&path, &frames)
+    {
+        eprintln!("{}", e);
+    }
+    Ok(frames)
+}
+
+// the images of the slideshow converted into the frame cache one after the other, until they are
+// all done or cancel is cancelled
+fn preload_slideshow(
+    paths: Vec<PathBuf>,
+    interval: u32,
+    image_options: &imageutils::ImageOptions,
+    dmd_width: u32,
+    dmd_height: u32,
+    cache: &framecache::FrameCache,
+    cancel: &cancel::CancelToken,
+) {
+    let interval_ms = interval.saturating_mul(1000);
+    let total = paths.len();
+    for (n, path) in paths.iter().enumerate() {
+        if cancel.is_cancelled() {
+            return;
+        }
+        let file = path.to_string_lossy();
+        match slideshow_frames(
+            &file,
+            interval_ms,
+            image_options,
+            dmd_width,
+            dmd_height,
+            Some(cache),
+        ) {
+            // stdout has the frames of --dry-run
+            Ok(_) => eprintln!("preload: {}/{} {}", n + 1, total, file),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+}
+
+// the images of the slideshow, each one displayed for interval seconds (the animations are
+// repeated during the interval) after a crossfade of crossfade frames, until the end of the
+// first pass with once
+fn play_slideshow(
+    dmd: &Dmd,
+    slideshow: &mut slideshow::Slideshow,
+    interval: u32,
+    crossfade: u32,
+    once: bool,
+    cache: Option<&framecache::FrameCache>,
+) -> Result<(), String> {
+    let interval_ms = interval.saturating_mul(1000);
+    let mut first = true;
+    let mut last: Option<Box<[u8]>> = None; // the frame displayed, faded out by the next image
+    loop {
+        let (path, new_pass) = match slideshow.next() {
+            Some(x) => x,
+            None if first => return Err(String::from("Error: no image found for the slideshow")),
+            // the directory was emptied, wait for new images
+            None => {
+                if dmd.cancel.sleep(Duration::from_millis(interval_ms as u64)) {
+                    return Ok(());
+                }
+                continue;
+            }
+        };
+        if once && new_pass && !first {
+            return Ok(());
+        }
+        first = false;
+
+        // a broken image is skipped, the previous one stays for its interval
+        let (frames_dmd, frames_duration) = match slideshow_frames(
+            &path.to_string_lossy(),
+            interval_ms,
+            dmd.image_options,
+            dmd.width,
+            dmd.height,
+            cache,
+        ) {
+            Ok(x) => x,
+            Err(e) => {
+                eprintln!("{}", e);
+                if dmd.cancel.sleep(Duration::from_millis(interval_ms as u64)) {
+                    return Ok(());
+                }
+                continue;
+            }
+        };
+
+        // to the first frame of the image, from the last one displayed
+        if let Some(last) = last.as_ref().filter(|_| crossfade > 0) {
+            let fade = (1..=crossfade).map(|step| {
+                let img565 = imageutils::blend_dmdimage(last, &frames_dmd[0], step, crossfade + 1);
+                Ok((Cow::Owned(img565), playback::frame_duration(CROSSFADE_FPS)))
+            });
+            playback::play(
+                dmd.client,
+                dmd.header,
+                fade,
+                dmd.rate,
+                dmd.control,
+                dmd.cancel,
+            )?;
+        }
+
+        let (mut elapsed, mut played) = (0, 0);
+        let frames = frames_dmd
+            .iter()
+            .zip(frames_duration)
+            .cycle()
+            .take_while(|(_, duration)| {
+                let playing = elapsed < interval_ms;
+                elapsed += ((*duration as f32 / dmd.rate) as u32).max(1);
+                played += playing as usize;
+                playing
+            })
+            .map(|(img565, duration)| Ok((Cow::Borrowed(&img565[..]), duration)));
+        playback::play(
+            dmd.client,
+            dmd.header,
+            frames,
+            dmd.rate,
+            dmd.control,
+            dmd.cancel,
+        )?;
+        if dmd.cancel.is_cancelled() {
+            return Ok(());
+        }
+        last = Some(frames_dmd[played.saturating_sub(1) % frames_dmd.len()].clone());
+    }
+}
+
+// the size of the dmd and its server in the middle, the corners numbered clockwise from the top left
+// one, displayed for duration seconds before the dmd is cleared
+fn play_identify(
+    dmd: &Dmd,
+    host: &str,
+    port: u16,
+    font: &Font<'static>,
+    duration: u32,
+) -> Result<(), String> {
+    let (width, height) =
+        imageutils::oriented_size(&dmd.image_options.orientation, dmd.width, dmd.height);
+    let style = imageutils::TextStyle {
+        font: font.clone(),
+        color: Rgba([255, 255, 255, 255]),
+        align: imageutils::TextAlign::CENTER,
+        line_spacing: 0,
+        letter_spacing: 0,
+        tabular_digits: false,
+    };
+    let mut img = RgbaImage::from_pixel(width, height, Rgba([0, 0, 0, 255]));
+
+    // a corner and its number, in a square of a third of the height
+    let marker = (height.min(width) / 3).max(3).min(height.min(width));
+    let corners = [
+        (0, 0, Rgba([255, 0, 0, 255])),
+        (width - marker, 0, Rgba([0, 255, 0, 255])),
+        (width - marker, height - marker, Rgba([0, 96, 255, 255])),
+        (0, height - marker, Rgba([255, 255, 0, 255])),
+    ];
+    for (n, (x, y, color)) in corners.iter().enumerate() {
+        let (x, y) = (*x as i32, *y as i32);
+        let right = x + marker as i32 - 1;
+        let bottom = y + marker as i32 - 1;
+        // the two sides on the edges of the panel
+        let (corner_x, corner_y) = match n {
+            0 => (x, y),
+            1 => (right, y),
+            2 => (right, bottom),
+            _ => (x, bottom),
+        };
+        let (other_x, other_y) = (x + right - corner_x, y + bottom - corner_y);
+        imageutils::draw_line(&mut img, corner_x, corner_y, other_x, corner_y, *color);
+        imageutils::draw_line(&mut img, corner_x, corner_y, corner_x, other_y, *color);
+
+        let number_style = imageutils::TextStyle {
+            color: *color,
+            ..style.clone()
+        };
+        let (number, _, _) = imageutils::generate_text_image(
+            &(n + 1).to_string(),
+            &number_style,
+            &None,
+            marker - 2,
+            marker - 2,
+        )?;
+        imageutils::blend_image(&number.to_rgba8(), &mut img, x + 1, y + 1);
+    }
+
+    let info = format!("{}x{}\\n{}:{}", dmd.width, dmd.height, host, port);
+    let info_width = width.saturating_sub(2 * marker + 2).max(1);
+    let (info_img, _, _) =
+        imageutils::generate_text_image(&info, &style, &None, info_width, height)?;
+    imageutils::blend_image(&info_img.to_rgba8(), &mut img, (marker + 1) as i32, 0);
+
+    send_render(dmd, &img)?;
+    dmd.cancel.sleep(Duration::from_secs(duration as u64));
+    let black = RgbaImage::from_pixel(width, height, Rgba([0, 0, 0, 255]));
+    send_render(dmd, &black)
+}
+
+// parse a "WIDTHxHEIGHT" string, like 128x32
+fn parse_size(size: &str) -> Result<(u32, u32), String> {
+    let parts: Vec<&str> = size.split('x').collect();
+    if parts.len() != 2 {
+        return Err(format!("Invalid size: {} (expected WIDTHxHEIGHT)", size));
+    }
+    let width = parts[0]
+        .trim()
+        .parse::<u32>()
+        .map_err(|_| format!("Invalid size: {} (expected WIDTHxHEIGHT)", size))?;
+    let height = parts[1]
+        .trim()
+        .parse::<u32>()
+        .map_err(|_| format!("Invalid size: {} (expected WIDTHxHEIGHT)", size))?;
+    Ok((width, height))
+}
+
+// a name of DMD_SIZES, or WIDTHxHEIGHT
+fn parse_dmd_size(size: &str) -> Result<(u32, u32), String> {
+    match DMD_SIZES.iter().find(|x| x.0 == size.to_lowercase()) {
+        Some((_, width, height)) => Ok((*width, *height)),
+        None => parse_size(size).map_err(|e| {
+            let names: Vec<&str> = DMD_SIZES.iter().map(|x| x.0).collect();
+            format!("{}, or {}", e, names.join(", "))
+        }),
+    }
+}
+
+// parse a "x,y,width,height" area
+fn parse_crop(crop: &str) -> Result<(u32, u32, u32, u32), String> {
+    let values: Vec<u32> = crop
+        .split(',')
+        .map(|x| x.trim().parse::<u32>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("Invalid crop: {} (expected x,y,width,height)", crop))?;
+    match values[..] {
+        [x, y, width, height] if width > 0 && height > 0 => Ok((x, y, width, height)),
+        _ => Err(format!(
+            "Invalid crop: {} (expected x,y,width,height)",
+            crop
+        )),
+    }
+}
+
+// parse a "R,G,B" color
+fn parse_color(color: &str) -> Result<Rgba<u8>, String> {
+    let values: Vec<u8> = color
+        .split(',')
+        .map(|x| x.trim().parse::<u8>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("Invalid color: {} (expected R,G,B)", color))?;
+    match values[..] {
+        [r, g, b] => Ok(Rgba([r, g, b, 255])),
+        _ => Err(format!("Invalid color: {} (expected R,G,B)", color)),
+    }
+}
+
+// the first address of the host accepting the connection in time
+fn connect_timeout_server(host: &str, port: u16, timeout: u32) -> io::Result<TcpStream> {
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "Unknown host");
+    for address in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, Duration::from_millis(timeout as u64)) {
+            Ok(x) => return Ok(x),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+// connect to the dmd server, trying again retries times when it fails, or until wait_timeout
+// seconds with a longer delay after each attempt. cancelled, the last error is returned
+fn connect_server(
+    host: &str,
+    port: u16,
+    connect_timeout: Option<u32>,
+    retries: u32,
+    retry_delay: u32,
+    wait_timeout: Option<u32>,
+    cancel: &cancel::CancelToken,
+) -> Result<TcpStream, String> {
+    let started = Instant::now();
+    let mut attempt = 0;
+    let mut delay = retry_delay as u64;
+    loop {
+        let result = match connect_timeout {
+            Some(timeout) => connect_timeout_server(host, port, timeout),
+            None => TcpStream::connect((host, port)),
+        };
+
+        match (result, wait_timeout) {
+            (Ok(x), _) => return Ok(x),
+            (Err(e), Some(wait_timeout)) => {
+                if started.elapsed() >= Duration::from_secs(wait_timeout as u64)
+                    || cancel.sleep(Duration::from_millis(delay))
+                {
+                    return Err(e.to_string());
+                }
+                delay = (delay * 2).min(MAX_WAIT_DELAY);
+            }
+            (Err(e), None) if attempt >= retries => return Err(e.to_string()),
+            (Err(e), None) => {
+                attempt += 1;
+                if cancel.sleep(Duration::from_millis(retry_delay as u64)) {
+                    return Err(e.to_string());
+                }
+            }
+        }
+    }
+}
+
+// the version and the compression of the frames to send, negotiated up to version with the servers
+// requiring a token or when a compression is proposed
+fn negotiate_version<S: Read + Write>(
+    stream: &mut S,
+    token: &Option<String>,
+    version: u8,
+    compression: Option<protocol::FrameCompression>,
+) -> Result<(u8, Option<protocol::FrameCompression>), String> {
+    match (token, compression) {
+        (None, None) => Ok((version, None)),
+        (token, compression) => {
+            protocol::negotiate(stream, token.as_deref().unwrap_or(""), version, compression)
+        }
+    }
+}
+
+// the connection to a server, encrypted with tls, with the version and the compression
+// of the frames negotiated with it
+fn open_upstream(
+    server: TcpStream,
+    host: &str,
+    options: &UpstreamOptions,
+) -> Result<ServerSink, String> {
+    let (token, version, compression) = (options.token, options.version, options.compression);
+    let (stream, (version, compression)) = match options.tls {
+        true => {
+            let mut stream = tls::connect_tls(server, host, options.tls_ca, options.insecure)?;
+            let negotiated = negotiate_version(&mut stream, token, version, compression)?;
+            (ServerStream::Encrypted(Box::new(stream)), negotiated)
+        }
+        false => {
+            let mut server = server;
+            let negotiated = negotiate_version(&mut server, token, version, compression)?;
+            (ServerStream::Plain(server), negotiated)
+        }
+    };
+    Ok(ServerSink::new(stream, version, compression))
+}
+
+// a "HOST[:PORT]" server, on port by default
+fn parse_target(target: &str, port: u16) -> Result<(String, u16), String> {
+    match target.rsplit_once(':') {
+        Some((host, x)) => match x.parse::<u16>() {
+            Ok(x) => Ok((host.to_string(), x)),
+            Err(_) => Err(format!("Invalid port: {}", target)),
+        },
+        None => Ok((target.to_string(), port)),
+    }
+}
+
+// the value of an option, else the one of the panel. an option set by the environment is a
+// default, the panel comes first
+fn option_or_panel<T>(
+    matches: &ArgMatches,
+    id: &str,
+    option: Option<T>,
+    panel: Option<T>,
+) -> Option<T> {
+    match matches.value_source(id) {
+        Some(ValueSource::EnvVariable) => panel.or(option),
+        _ => option.or(panel),
+    }
+}
+
+// what dmd-play plays, for the status
+fn playing_mode(args: &Cli) -> &'static str {
+    if args.file.is_some() || args.game.is_some() {
+        "file"
+    } else if args.spritesheet.is_some() {
+        "spritesheet"
+    } else if args.source.is_some() {
+        "source"
+    } else if args.relay.is_some() {
+        "relay"
+    } else if args.base.is_some() {
+        "layers"
+    } else if args.text.is_some() || args.texts.is_some() {
+        "text"
+    } else if args.exec.is_some() {
+        "exec"
+    } else if args.poll_json.is_some() {
+        "poll"
+    } else if args.ticker.is_some() {
+        "ticker"
+    } else if args.netinfo {
+        "netinfo"
+    } else if !args.zone.is_empty() {
+        "layout"
+    } else if args.template.is_some() {
+        "template"
+    } else if args.progress.is_some()
+        || args.progress_file.is_some()
+        || args.progress_exec.is_some()
+    {
+        "progress"
+    } else if args.draw.is_some() {
+        "draw"
+    } else if args.visualizer.is_some() {
+        "visualizer"
+    } else if args.screensaver.is_some() {
+        "screensaver"
+    } else if args.effect.is_some() {
+        "effect"
+    } else if args.script.is_some() {
+        "script"
+    } else if args.slideshow.is_some() {
+        "slideshow"
+    } else if args.test_pattern.is_some() {
+        "test-pattern"
+    } else if args.identify {
+        "identify"
+    } else if args.clock || args.alarm.is_some() {
+        "clock"
+    } else if !args.countdown.is_empty() || !args.countdown_cron.is_empty() || !args.ics.is_empty()
+    {
+        "countdown"
+    } else if args.clear {
+        "clear"
+    } else if args.notifications.is_some() || args.alerts.is_some() {
+        "notifications"
+    } else {
+        "none"
+    }
+}
+
+// the modes reading the font, the others never write a text
+fn writes_text(play: &Play) -> bool {
+    !matches!(
+        play,
+        Play::FILE(_)
+            | Play::SOURCE(_, _)
+            | Play::RELAY(_, None | Some(RelayIdle::FILE(_)))
+            | Play::SPRITESHEET(_, _)
+            | Play::DRAW(_)
+            | Play::VISUALIZER(_)
+            | Play::SCREENSAVER(_)
+            | Play::SLIDESHOW(_)
+            | Play::TESTPATTERN(_)
+            | Play::EFFECT(_, _)
+            | Play::CLEAR
+            | Play::NOTIFICATIONS
+    )
+}
+
+// a file, played again each time it changes with --watch
+fn play_file(dmd: &Dmd, file: String, args: &Cli, files: &FileOptions) -> Result<bool, String> {
+    // the watch starts before playing, so that no change is missed
+    let mut watcher = match args.watch {
+        true => Some(watch::FileWatcher::new(&file)?),
+        false => None,
+    };
+    let mut was_animation = false;
+    loop {
+        match handle_case_file(
+            dmd,
+            file.clone(),
+            files.fps,
+            files.raw_size,
+            args.once || args.hold_last || watcher.is_some(),
+            &files.range,
+            files.cache.as_ref(),
+        ) {
+            Ok(x) => {
+                was_animation = x;
+            }
+            Err(e) => {
+                // a file being written is played once it is complete
+                eprintln!("{}", e);
+            }
+        };
+        match watcher.as_mut().map(|x| x.wait(dmd.cancel)) {
+            Some(Ok(true)) => {}
+            Some(Ok(false)) => break,
+            Some(Err(e)) => {
+                eprintln!("{}", e);
+                break;
+            }
+            None => break,
+        }
+    }
+    // the last frame stays, the server may blank the dmd once its client is gone
+    if args.hold_last && was_animation {
+        while !dmd.cancel.sleep(Duration::from_secs(1)) {}
+    }
+    Ok(was_animation)
+}
+
+// the frames of the clients of the relay, or what is played while it is idle
+fn play_relayed(
+    dmd: &Dmd,
+    channels: &[relay::RelayChannel],
+    idle: Option<RelayIdle>,
+    token: &Option<String>,
+    options: &TextOptions,
+    clock: &ClockOptions,
+    files: &FileOptions,
+) -> Result<(), String> {
+    let idle_frames = match &idle {
+        Some(RelayIdle::FILE(file)) => Some(
+            files_to_frames(file.clone(), files.fps, files.raw_size, DEFAULT_DURATION)
+                .and_then(|x| frames_to_dmd(x, dmd.image_options, dmd.width, dmd.height))?,
+        ),
+        _ => None,
+    };
+    let (dmd_width, dmd_height, image_options) = (dmd.width, dmd.height, dmd.image_options);
+    let idle: Option<Box<dyn playback::FrameSource + Send>> = match (&idle_frames, idle) {
+        (Some((frames_dmd, frames_duration)), _) => Some(Box::new(
+            frames_dmd
+                .iter()
+                .zip(frames_duration.iter())
+                .map(|(img565, duration)| Ok((Cow::Borrowed(&img565[..]), *duration)))
+                .cycle(),
+        )),
+        // the time is rendered each second, when the relay is idle
+        (None, Some(RelayIdle::CLOCK)) => Some(Box::new(iter::from_fn(move || {
+            let now = Local::now();
+            let text = clock_text(&now, clock);
+            let img565 = render_text(
+                dmd_width,
+                dmd_height,
+                &text,
+                &options.gradient,
+                options,
+                image_options,
+            );
+            let duration = 1000 - now.timestamp_subsec_millis().min(999);
+            Some(img565.map(|x| (Cow::Owned(x.into_vec()), duration)))
+        }))),
+        _ => None,
+    };
+    relay::play_relay(dmd, channels, idle, token)
+}
+
+// a text, a cycle of texts or a text evaluated again with --refresh
+fn play_text_source(
+    dmd: &Dmd,
+    text_source: TextSource,
+    color_cycle: Option<ColorCycle>,
+    args: &Cli,
+    options: &TextOptions,
+    vars: &HashMap<String, String>,
+    timezone: &Option<Tz>,
+) -> Result<bool, String> {
+    let options = &TextOptions {
+        animation: TextAnimation {
+            color_cycle,
+            ..options.animation.clone()
+        },
+        ..options.clone()
+    };
+    let caps_text = |text: String| {
+        if args.caps {
+            text.to_uppercase().replace("\\N", "\\n")
+        } else {
+            text
+        }
+    };
+    let expand_text = |text: &str| {
+        caps_text(template::expand_placeholders(
+            &template::substitute_vars(text, vars),
+            timezone,
+        ))
+    };
+    let mut last_poll: Option<String> = None;
+    let mut last_fetch: Option<Instant> = None;
+    let next_text = || match &text_source {
+        TextSource::TEXT(text) => Ok(expand_text(text)),
+        // the first one, the next ones come with the cycle
+        TextSource::TEXTS(texts) => Ok(expand_text(&texts[0])),
+        TextSource::EXEC(command) => exec_text(command, dmd.cancel).map(caps_text),
+        // the values come last, the placeholders of the api data are not expanded
+        TextSource::POLL(url, template) => match poll::fetch_json(url) {
+            Ok(value) => {
+                let text = template::expand_placeholders(
+                    &template::substitute_vars(template, vars),
+                    timezone,
+                );
+                let text = caps_text(poll::render_template(&text, &value));
+                last_poll = Some(text.clone());
+                Ok(text)
+            }
+            // the last text stays while the api cannot be reached
+            Err(e) => match &last_poll {
+                Some(x) => {
+                    eprintln!("{}", e);
+                    Ok(x.clone())
+                }
+                None => Err(e),
+            },
+        },
+        TextSource::NETINFO(host) => Ok(caps_text(netinfo::netinfo_text(host))),
+        // fetched again every interval, the scrolling ones keep the quotes in the meantime
+        TextSource::TICKER(symbols) => match (&last_poll, last_fetch) {
+            (Some(x), Some(fetch))
+                if fetch.elapsed() < Duration::from_secs(args.ticker_interval as u64) =>
+            {
+                Ok(x.clone())
+            }
+            _ => match ticker::ticker_text(symbols) {
+                Ok(text) => {
+                    last_poll = Some(text.clone());
+                    last_fetch = Some(Instant::now());
+                    Ok(text)
+                }
+                Err(e) => match &last_poll {
+                    Some(x) => Ok(x.clone()),
+                    None => Err(e),
+                },
+            },
+        },
+    };
+    let refresh = match text_source {
+        TextSource::POLL(_, _) => args.refresh.or(Some(POLL_REFRESH)),
+        TextSource::TICKER(_) => Some(args.ticker_interval),
+        TextSource::NETINFO(_) => args.refresh.or(Some(NETINFO_REFRESH)),
+        _ => args.refresh,
+    };
+    match &text_source {
+        TextSource::TEXTS(texts) => {
+            play_text_cycle(dmd, texts, args.cycle, options, args.once, expand_text)
+        }
+        _ => handle_refreshed_text(dmd, options, args.once, refresh, next_text),
+    }
+}
+
+// play what was asked, returns whether an animation was played
+fn play_mode(
+    dmd: &Dmd,
+    play: Play,
+    args: &Cli,
+    options: &TextOptions,
+    clock: &ClockOptions,
+    files: &FileOptions,
+    vars: &HashMap<String, String>,
+) -> Result<bool, String> {
+    match play {
+        Play::FILE(file) => play_file(dmd, file, args, files),
+        Play::SOURCE(path, format) => source::play_source(
+            dmd,
+            &path,
+            &format,
+            files.raw_size,
+            files.fps,
+            options.style.color,
+            args.once,
+        )
+        .map(|_| true),
+        Play::RELAY(channels, idle) => play_relayed(
+            dmd,
+            &channels,
+            idle,
+            &args.auth_token,
+            options,
+            clock,
+            files,
+        )
+        .map(|_| true),
+        Play::SPRITESHEET(file, sprite_size) => {
+            send_spritesheet(dmd, &file, sprite_size, args.sprite_fps, args.once)
+        }
+        Play::TEXT(text_source, color_cycle) => play_text_source(
+            dmd,
+            text_source,
+            color_cycle,
+            args,
+            options,
+            vars,
+            &clock.timezone,
+        ),
+        Play::LAYOUT(zones) => layout::play_layout(
+            dmd,
+            &zones,
+            options,
+            clock,
+            files.fps,
+            files.raw_size,
+            args.once,
+        )
+        .map(|_| true),
+        Play::PROGRESS(progress) => {
+            handle_progress(dmd, progress, &args.progress_label, options).map(|_| false)
+        }
+        Play::WATCHPROGRESS(source) => {
+            watch_progress(dmd, &source, &args.progress_template, options, args.once).map(|_| false)
+        }
+        Play::DRAW(drawing) => send_render(dmd, &drawing).map(|_| false),
+        Play::VISUALIZER(mode) => visualizer::play_visualizer(
+            dmd,
+            &mode,
+            &args.audio_command,
+            args.visualizer_fps,
+            &options.background,
+            &options.gradient,
+        )
+        .map(|_| true),
+        Play::SCREENSAVER(screensaver) => screensaver::play_screensaver(
+            dmd,
+            &screensaver,
+            args.screensaver_fps,
+            options.style.color,
+            &options.background,
+            args.once,
+        )
+        .map(|_| true),
+        Play::SLIDESHOW(mut slideshow) => {
+            // the preloading stops with the slideshow, or when the playing is cancelled
+            let stop = dmd.cancel.child();
+            thread::scope(|scope| {
+                if args.preload
+                    && let Some(cache) = files.cache.as_ref()
+                {
+                    let paths = slideshow.playlist();
+                    let (interval, stop) = (args.interval, &stop);
+                    let (dmd_width, dmd_height, image_options) =
+                        (dmd.width, dmd.height, dmd.image_options);
+                    scope.spawn(move || {
+                        preload_slideshow(
+                            paths,
+                            interval,
+                            image_options,
+                            dmd_width,
+                            dmd_height,
+                            cache,
+                            stop,
+                        )
+                    });
+                }
+                let result = play_slideshow(
+                    dmd,
+                    &mut slideshow,
+                    args.interval,
+                    args.crossfade,
+                    args.once,
+                    files.cache.as_ref(),
+                );
+                stop.cancel();
+                result
+            })
+            .map(|_| true)
+        }
+        Play::TESTPATTERN(pattern) => {
+            play_test_pattern(dmd, &pattern, args.test_pattern_fps, args.once)
+        }
+        Play::IDENTIFY(host, port) => play_identify(
+            dmd,
+            &host,
+            port,
+            &options.style.font,
+            args.identify_duration,
+        )
+        .map(|_| true),
+        Play::EFFECT(effect, colors) => {
+            let effect_options = particles::EffectOptions {
+                effect: &effect,
+                density: args.effect_density,
+                speed: args.effect_speed,
+                colors: &colors,
+            };
+            particles::play_effect(dmd, &effect_options, &options.background, args.once)
+                .map(|_| true)
+        }
+        Play::SCRIPT(script) => {
+            scripting::play_script(dmd, &script, vars, options, args.script_fps, args.once)
+                .map(|_| true)
+        }
+        Play::TEMPLATE(template) => {
+            let path = args.template.as_deref().unwrap_or_default();
+            // the watch starts before playing, so that no change is missed
+            let watcher = match args.watch {
+                true => Some(watch::FileWatcher::new(path)?),
+                false => None,
+            };
+            let template_options = template::TemplateOptions {
+                vars,
+                text: options,
+                clock,
+                fps: files.fps,
+                raw_size: files.raw_size,
+            };
+            template::play_template(dmd, template, path, watcher, &template_options, args.once)
+        }
+        Play::LAYERS(base, ticker) => {
+            compositor::play_layers(dmd, &base, &ticker, options, clock, args.once).map(|_| true)
+        }
+        Play::CLOCK(clock_style, alarm) => {
+            handle_clock(dmd, options, clock, &clock_style, &alarm);
+            Ok(false)
+        }
+        Play::COUNTDOWN(countdowns) => handle_countdown(dmd, options, &countdowns).map(|_| false),
+        Play::CLEAR => {
+            let black = Rgba([0, 0, 0, 255]);
+            let (render_width, render_height) = options.background.dimensions();
+            let clear = TextOptions {
+                style: imageutils::TextStyle {
+                    color: black,
+                    align: imageutils::TextAlign::CENTER,
+                    line_spacing: 0,
+                    letter_spacing: 0,
+                    tabular_digits: false,
+                    ..options.style.clone()
+                },
+                gradient: None,
+                background: RgbaImage::from_pixel(render_width, render_height, black),
+                ..options.clone()
+            };
+            if let Err(e) = send_image_text(dmd, "", &clear, args.once) {
+                eprintln!("{}", e);
+            }
+            Ok(true)
+        }
+        Play::NOTIFICATIONS => {
+            let (render_width, render_height) = options.background.dimensions();
+            let black = RgbaImage::from_pixel(render_width, render_height, Rgba([0, 0, 0, 255]));
+            send_render(dmd, &black)?;
+            while !dmd.cancel.sleep(Duration::from_secs(1)) {}
+            Ok(false)
+        }
+    }
+}
+
+fn main() {
+    let matches = Cli::command().get_matches();
+    let mut args = match Cli::from_arg_matches(&matches) {
+        Ok(x) => x,
+        Err(e) => e.exit(),
+    };
+    // the frames of a dump are written once
+    args.once |= args.dump_raw.is_some();
+    let mode = playing_mode(&args);
+
+    // stopped like at the end of the playing, not in the middle of a frame
+    let cancel = cancel::CancelToken::default();
+    if let Err(e) = cancel::handle_signals(&cancel) {
+        eprintln!("{}", e);
+        return;
+    }
+
+    // a server, nothing is played
+    if let Some(port) = args.mock_server {
+        let listener = match TcpListener::bind(("0.0.0.0", port)) {
+            Ok(x) => x,
+            Err(e) => {
+                eprintln!("Error: port {}: {}", port, e);
+                return;
+            }
+        };
+        if let Err(e) = mock::serve(
+            listener,
+            &args.auth_token,
+            args.mock_show,
+            args.once,
+            &cancel,
+        ) {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    // the options of the command line come before the ones of the panel
+    let panel = match &args.panel {
+        Some(name) => match panel::load_panel(&args.config, name) {
+            Ok(x) => x,
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
+        },
+        None => panel::Panel::default(),
+    };
+    let host = option_or_panel(&matches, "host", args.host.clone(), panel.host)
+        .unwrap_or(String::from("localhost"));
+    let port = option_or_panel(&matches, "port", args.port, panel.port).unwrap_or(6789);
+    let color_correct = match &args.color_correct {
+        Some(x) => x
+            .split(',')
+            .map(|x| x.trim().parse::<f32>())
+            .collect::<Result<Vec<f32>, _>>()
+            .map(Some)
+            .map_err(|_| format!("Invalid color correction: {} (numbers expected)", x)),
+        None => Ok(panel.color_correct),
+    };
+    let matrix = match color_correct
+        .and_then(|x| x.as_deref().map(imageutils::parse_color_matrix).transpose())
+    {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+    if !(0.0..).contains(&args.saturation) {
+        eprintln!("Invalid saturation value (0.0 or more)");
+        return;
+    }
+    if !(0.0..).contains(&args.contrast) {
+        eprintln!("Invalid contrast value (0.0 or more)");
+        return;
+    }
+    if args.mono_dither && args.mono_threshold.is_none() {
+        eprintln!("--mono-dither requires --mono-threshold");
+        return;
+    }
+    let mono_color = match parse_color(&args.mono_color) {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+    let colors = (matrix.is_some()
+        || args.saturation != 1.0
+        || args.contrast != 1.0
+        || args.invert
+        || args.mono_threshold.is_some())
+    .then_some(imageutils::ColorAdjustment {
+        saturation: args.saturation,
+        contrast: args.contrast,
+        invert: args.invert,
+        mono_threshold: args.mono_threshold,
+        mono_color,
+        mono_dither: args.mono_dither,
+        matrix,
+    });
+
+    // at least one
+    let mut nplay = 0;
+    if args.clear {
+        nplay += 1;
+    }
+    if args.file.is_some() {
+        nplay += 1;
+    }
+    if args.game.is_some() {
+        nplay += 1;
+    }
+    if args.source.is_some() {
+        nplay += 1;
+    }
+    if args.relay.is_some() {
+        nplay += 1;
+    }
+    if args.spritesheet.is_some() {
+        nplay += 1;
+    }
+    if args.text.is_some() {
+        nplay += 1;
+    }
+    if args.texts.is_some() {
+        nplay += 1;
+    }
+    if args.exec.is_some() {
+        nplay += 1;
+    }
+    if args.poll_json.is_some() {
+        nplay += 1;
+    }
+    if args.ticker.is_some() {
+        nplay += 1;
+    }
+    if args.netinfo {
+        nplay += 1;
+    }
+    if args.clock || args.alarm.is_some() {
+        nplay += 1;
+    }
+    if !args.countdown.is_empty() || !args.countdown_cron.is_empty() || !args.ics.is_empty() {
+        nplay += 1;
+    }
+    if args.base.is_some() {
+        nplay += 1;
+    }
+    if !args.zone.is_empty() {
+        nplay += 1;
+    }
+    if args.template.is_some() {
+        nplay += 1;
+    }
+    if args.progress.is_some() {
+        nplay += 1;
+    }
+    if args.progress_file.is_some() || args.progress_exec.is_some() {
+        nplay += 1;
+    }
+    if args.draw.is_some() {
+        nplay += 1;
+    }
+    if args.visualizer.is_some() {
+        nplay += 1;
+    }
+    if args.screensaver.is_some() {
+        nplay += 1;
+    }
+    if args.effect.is_some() {
+        nplay += 1;
+    }
+    if args.script.is_some() {
+        nplay += 1;
+    }
+    if args.slideshow.is_some() {
+        nplay += 1;
+    }
+    if args.test_pattern.is_some() {
+        nplay += 1;
+    }
+    if args.identify {
+        nplay += 1;
+    }
+
+    if nplay == 0 && args.notifications.is_none() && args.alerts.is_none() {
+        eprintln!("Missing something to play");
+        return;
+    }
+
+    if nplay > 1 {
+        eprintln!("Only one action required");
+        return;
+    }
+
+    if !(0.5..=4.0).contains(&args.rate) {
+        eprintln!("Invalid rate value (0.5-4.0)");
+        return;
+    }
+
+    let sprite_size = match args.sprite_size.as_deref().map(parse_size).transpose() {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    let raw_size = match args.raw_size.as_deref().map(parse_size).transpose() {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    if args.progress.is_some_and(|x| !(0.0..=100.0).contains(&x)) {
+        eprintln!("Invalid progress value (0-100)");
+        return;
+    }
+    let progress_source = match (args.progress_file.clone(), args.progress_exec.clone()) {
+        (Some(_), Some(_)) => {
+            eprintln!("Only one of --progress-file and --progress-exec");
+            return;
+        }
+        (Some(path), None) => Some(ProgressSource::FILE(path)),
+        (None, Some(command)) => Some(ProgressSource::EXEC(command)),
+        (None, None) => None,
+    };
+
+    let visualizer_mode = match args.visualizer.as_deref() {
+        Some("spectrum") => Some(visualizer::VisualizerMode::SPECTRUM),
+        Some("vu") => Some(visualizer::VisualizerMode::VU),
+        Some(_) => {
+            eprintln!("Invalid visualizer value (spectrum or vu)");
+            return;
+        }
+        None => None,
+    };
+
+    if args.control_stdin && args.visualizer.is_some() && args.audio_command == "-" {
+        eprintln!("--control-stdin is not available with the audio read from stdin");
+        return;
+    }
+
+    if args.visualizer_fps == 0 || args.visualizer_fps > visualizer::AUDIO_RATE {
+        eprintln!("Invalid visualizer fps value");
+        return;
+    }
+
+    let screensaver = match args.screensaver.as_deref() {
+        Some("life") => Some(screensaver::Screensaver::LIFE),
+        Some("plasma") => Some(screensaver::Screensaver::PLASMA),
+        Some("fire") => Some(screensaver::Screensaver::FIRE),
+        Some("matrix") => Some(screensaver::Screensaver::MATRIX),
+        Some(_) => {
+            eprintln!("Invalid screensaver value (life, plasma, fire or matrix)");
+            return;
+        }
+        None => None,
+    };
+
+    if args.duration == Some(0) {
+        eprintln!("Invalid duration value (at least 1 second)");
+        return;
+    }
+    if args.duration_clear && args.duration.is_none() {
+        eprintln!("--duration-clear requires --duration");
+        return;
+    }
+
+    if args.watch && args.file.is_none() && args.game.is_none() && args.template.is_none() {
+        eprintln!("--watch requires --file or --template");
+        return;
+    }
+
+    let mut relay_channels = Vec::new();
+    if let Some(relay) = args.relay {
+        relay_channels.push(relay::RelayChannel {
+            port: relay,
+            priority: 0,
+            policy: relay::RelayPolicy::INTERRUPT,
+        });
+    } else if !args.relay_channel.is_empty() {
+        eprintln!("--relay-channel requires --relay");
+        return;
+    }
+    if args.relay.is_none() && (args.idle_file.is_some() || args.idle_clock) {
+        eprintln!("--idle-file and --idle-clock require --relay");
+        return;
+    }
+    if args.idle_file.is_some() && args.idle_clock {
+        eprintln!("Only one of --idle-file and --idle-clock");
+        return;
+    }
+    for channel in &args.relay_channel {
+        match relay::parse_channel(channel) {
+            Ok(x) => relay_channels.push(x),
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
+        }
+    }
+
+    let notifications_bus = match args.notifications.as_deref() {
+        Some("session") => Some(notifications::Bus::SESSION),
+        Some("system") => Some(notifications::Bus::SYSTEM),
+        Some(_) => {
+            eprintln!("Invalid notifications value (session or system)");
+            return;
+        }
+        None => None,
+    };
+
+    if args.slideshow.is_none() && (args.shuffle || !args.include.is_empty() || args.preload) {
+        eprintln!("--shuffle, --include and --preload require --slideshow");
+        return;
+    }
+    if args.interval == 0 {
+        eprintln!("Invalid interval value");
+        return;
+    }
+    let slideshow = match args.slideshow.as_deref() {
+        Some(dir) => {
+            match slideshow::Slideshow::new(dir, &args.include, args.shuffle, args.no_repeat_last) {
+                Ok(x) => Some(x),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return;
+                }
+            }
+        }
+        None => None,
+    };
+    let test_pattern = match args.test_pattern.as_deref() {
+        Some("grid") => Some(imageutils::TestPattern::GRID),
+        Some("gradient") => Some(imageutils::TestPattern::GRADIENT),
+        Some("rgb") => Some(imageutils::TestPattern::RGB),
+        Some("pixel-walk") => Some(imageutils::TestPattern::PIXELWALK),
+        Some(_) => {
+            eprintln!("Invalid test pattern value (grid, gradient, rgb or pixel-walk)");
+            return;
+        }
+        None => None,
+    };
+    if args.test_pattern_fps == 0 {
+        eprintln!("Invalid test pattern fps value");
+        return;
+    }
+
+    if args.screensaver_fps == 0 {
+        eprintln!("Invalid screensaver fps value");
+        return;
+    }
+
+    let effect = match args.effect.as_deref() {
+        Some("fireworks") => Some(particles::Effect::FIREWORKS),
+        Some("snow") => Some(particles::Effect::SNOW),
+        Some("starfield") => Some(particles::Effect::STARFIELD),
+        Some(_) => {
+            eprintln!("Invalid effect value (fireworks, snow or starfield)");
+            return;
+        }
+        None => None,
+    };
+
+    if args.effect_speed <= 0.0 {
+        eprintln!("Invalid effect speed value");
+        return;
+    }
+
+    let effect_colors = match args
+        .effect_colors
+        .as_deref()
+        .map(|colors| {
+            colors
+                .split(';')
+                .map(parse_color)
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .transpose()
+    {
+        Ok(x) => x.unwrap_or_default(),
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    let source_format = match source::parse_source_format(&args.source_format) {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    let color_cycle = match args.color_cycle.as_deref() {
+        Some("rainbow") => Some(ColorCycle::RAINBOW),
+        Some("gradient") => Some(ColorCycle::GRADIENT),
+        Some(x) => {
+            eprintln!("Invalid color cycle: {} (rainbow or gradient)", x);
+            return;
+        }
+        None => None,
+    };
+
+    if color_cycle.is_some() && args.cycle_speed == 0 {
+        eprintln!("Invalid cycle speed value");
+        return;
+    }
+
+    let compression = match args.compress.as_deref() {
+        Some("zlib") => Some(protocol::FrameCompression::ZLIB),
+        Some("lz4") => Some(protocol::FrameCompression::LZ4),
+        Some(x) => {
+            eprintln!("Invalid compression: {} (zlib or lz4)", x);
+            return;
+        }
+        None => None,
+    };
+
+    // the version proposed to the server in the handshake (with a token or a compression),
+    // used at once otherwise
+    let handshake_version = match compression {
+        Some(_) => Some(protocol::COMPRESSION_VERSION),
+        None if args.auth_token.is_some() => Some(protocol::HANDSHAKE_VERSION),
+        None => None,
+    };
+    let protocol_version = match (args.protocol_version, handshake_version) {
+        (Some(version), _) if version == 0 || version > protocol::MAX_PROTOCOL_VERSION => {
+            eprintln!("Unsupported protocol version {}", version);
+            return;
+        }
+        (Some(version), Some(required)) if version < required => {
+            eprintln!(
+                "--auth-token and --compress require the protocol version {}",
+                required
+            );
+            return;
+        }
+        (Some(version), _) => version,
+        (None, Some(_)) => protocol::MAX_PROTOCOL_VERSION,
+        (None, None) => protocol::PROTOCOL_VERSION,
+    };
+
+    if args.keepalive == Some(0) {
+        eprintln!("Invalid keepalive value");
+        return;
+    }
+
+    let active_hours = match args.active_hours.is_empty() {
+        true => None,
+        false => match schedule::parse_active_hours(&args.active_hours) {
+            Ok(x) => Some(x),
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
+        },
+    };
+    if args.inactive_brightness > 100 {
+        eprintln!("Invalid inactive brightness value (0-100)");
+        return;
+    }
+    let auto_dim = match &args.auto_dim {
+        Some(x) => match dimming::parse_auto_dim(x, args.latitude, args.longitude) {
+            Ok(x) => Some(dimming::DimController::new(x, args.auto_dim_min)),
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
+        },
+        None => None,
+    };
+    if args.auto_dim_min > 100 {
+        eprintln!("Invalid auto dim min value (0-100)");
+        return;
+    }
+
+    let stats = match (args.stats, &args.stats_json) {
+        (Some(0), _) => {
+            eprintln!("Invalid stats value");
+            return;
+        }
+        (Some(x), _) => Some(x),
+        (None, Some(_)) => Some(1),
+        (None, None) => None,
+    };
+
+    if args.subpixel_scroll == 0 {
+        eprintln!("Invalid subpixel scroll value");
+        return;
+    }
+    let reveal = match args.reveal.as_deref() {
+        Some("typewriter") => Some(TextReveal::TYPEWRITER),
+        Some("fade-in") => Some(TextReveal::FADEIN),
+        Some("slide-in") => Some(TextReveal::SLIDEIN),
+        Some(x) => {
+            eprintln!("Invalid reveal: {} (typewriter, fade-in or slide-in)", x);
+            return;
+        }
+        None => None,
+    };
+    let text_animation = TextAnimation {
+        subpixel: args.subpixel_scroll,
+        gap: args.scroll_gap,
+        separator: args.scroll_separator.clone().unwrap_or_default(),
+        continuous: args.scroll_continuous,
+        reveal,
+        reveal_duration: args.reveal_duration,
+        hold: args.hold_ms,
+        min_text_height: args.min_text_height,
+        animate_threshold: args.animate_threshold,
+        // the text modes only, the clock and the countdown keep their colors
+        color_cycle: None,
+        cycle_speed: args.cycle_speed,
+    };
+
+    if args.script_fps == 0 {
+        eprintln!("Invalid script fps value");
+        return;
+    }
+
+    let timezone = match args.timezone {
+        Some(ref x) => match x.parse::<Tz>() {
+            Ok(x) => Some(x),
+            Err(_) => {
+                eprintln!("Invalid timezone: {}", x);
+                return;
+            }
+        },
+        None => None,
+    };
+
+    if args.clock_style.is_some() && !args.clock {
+        eprintln!("--clock-style requires --clock");
+        return;
+    }
+    let clock_style = match args
+        .clock_style
+        .as_deref()
+        .map(clockstyle::parse_clock_style)
+        .transpose()
+    {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    let alarm = match args.alarm {
+        Some(ref x) => match NaiveTime::parse_from_str(x, "%H:%M") {
+            Ok(x) => Some(x),
+            Err(_) => {
+                eprintln!("Invalid alarm time: {} (expected HH:MM)", x);
+                return;
+            }
+        },
+        None => None,
+    };
+
+    // unknown placeholders are reported before anything is displayed
+    for format in [
+        &args.countdown_format,
+        &args.countdown_format_0_day,
+        &args.countdown_format_0_hour,
+        &args.countdown_format_0_minute,
+    ] {
+        if let Err(e) = strfdelta(TimeDelta::zero(), format) {
+            eprintln!("{}", e);
+            return;
+        }
+    }
 
-    // at least one
-    let mut nplay = 0;
-    if args.clear {
-        nplay += 1;
+    if args.poll_json.is_none() && (args.jq_path.is_some() || args.poll_template.is_some()) {
+        eprintln!("--jq-path and --poll-template require --poll-json");
+        return;
     }
-    if args.file.is_some() {
-        nplay += 1;
+    if args.jq_path.is_some() && args.poll_template.is_some() {
+        eprintln!("Only one of --jq-path and --poll-template");
+        return;
     }
-    if args.text.is_some() {
-        nplay += 1;
+    if args.poll_json.is_some() && args.jq_path.is_none() && args.poll_template.is_none() {
+        eprintln!("--poll-json requires --jq-path or --poll-template");
+        return;
     }
-    if args.clock {
-        nplay += 1;
+    if args.refresh == Some(0) {
+        eprintln!("Invalid refresh value");
+        return;
     }
-    if args.countdown.is_some() {
-        nplay += 1;
+    if args.ping_host.is_some() && !args.netinfo {
+        eprintln!("--ping-host requires --netinfo");
+        return;
+    }
+    if args.ticker_interval == 0 {
+        eprintln!("Invalid ticker interval value");
+        return;
     }
+    let ticker_symbols = match args.ticker.as_deref().map(ticker::parse_symbols) {
+        Some(Ok(x)) => Some(x),
+        Some(Err(e)) => {
+            eprintln!("{}", e);
+            return;
+        }
+        None => None,
+    };
 
-    if nplay == 0 {
-        eprintln!("Missing something to play");
+    if args.countdown_cycle == 0 {
+        eprintln!("Invalid countdown cycle value");
         return;
     }
 
-    if nplay > 1 {
-        eprintln!("Only one action required");
+    let mut countdowns: Vec<(Option<String>, CountdownTarget)> = args
+        .countdown
+        .iter()
+        .map(|x| {
+            let (label, date) = split_label(x);
+            (label, CountdownTarget::DATE(date))
+        })
+        .collect();
+    for x in &args.countdown_cron {
+        let (label, expression) = split_label(x);
+        match cron::parse_cron(&expression) {
+            Ok(schedule) => countdowns.push((label, CountdownTarget::CRON(schedule))),
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
+        }
+    }
+    countdowns.extend(
+        args.ics
+            .iter()
+            .map(|x| (None, CountdownTarget::ICS(x.clone()))),
+    );
+    if args.ics_refresh == 0 {
+        eprintln!("Invalid ics refresh value");
+        return;
+    }
+
+    if ![0, 90, 180, 270].contains(&args.rotate) {
+        eprintln!("Invalid rotate value (0, 90, 180 or 270)");
         return;
     }
 
-    let server_address = format!("{}:{}", args.host, args.port);
-    let client = match TcpStream::connect(server_address) {
-        Ok(stream) => stream,
+    let background_color = match args
+        .background_color
+        .as_deref()
+        .map(parse_color)
+        .transpose()
+    {
+        Ok(x) => x,
         Err(e) => {
-            eprintln!("Erreur de connexion au serveur: {}", e);
+            eprintln!("{}", e);
             return;
         }
     };
 
-    //
-    let mut layer = DMDLayer::MAIN;
-
-    let mut dmd_width;
-    let mut dmd_height;
-
-    if args.hd {
-        dmd_width = 256;
-        dmd_height = 64;
-    } else {
-        dmd_width = 128;
-        dmd_height = 32;
-    }
+    let ticker_color = match args.ticker_color.as_deref().map(parse_color).transpose() {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
 
-    match args.width {
-        Some(x) => {
-            dmd_width = x;
+    let crop = match args.crop.as_deref().map(parse_crop).transpose() {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
         }
-        None => {}
     };
 
-    match args.height {
-        Some(x) => {
-            dmd_height = x;
+    //
+    let mut layer = DMDLayer::MAIN;
+
+    let size = match args.size.as_deref().map(parse_dmd_size).transpose() {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
         }
-        None => {}
     };
+    // --width and --height, --size, the panel, --hd, then the size of the environment
+    let (size, env_size) = match matches.value_source("size") {
+        Some(ValueSource::EnvVariable) => (None, size),
+        _ => (size, None),
+    };
+    let default_size = match args.hd {
+        true => (256, 64),
+        false => env_size.unwrap_or((128, 32)),
+    };
+    let dmd_width = args
+        .width
+        .or(size.map(|x| x.0))
+        .or(panel.width)
+        .unwrap_or(default_size.0);
+    let dmd_height = args
+        .height
+        .or(size.map(|x| x.1))
+        .or(panel.height)
+        .unwrap_or(default_size.1);
+    if let Err(e) = imageutils::check_dmd_size(dmd_width, dmd_height) {
+        eprintln!("{}", e);
+        return;
+    }
 
     if args.overlay {
         layer = DMDLayer::SECOND;
     }
 
-    let background_color = Rgba([0, 0, 0, 255]);
+    let background_color = background_color.unwrap_or(Rgba([0, 0, 0, 255]));
     let text_color = Rgba([args.red, args.green, args.blue, 0]);
 
-    // compute the header only once while it is always the same one
-    let header = get_header(
-        dmd_width as u16,
-        dmd_height as u16,
-        layer,
-        imageutils::get_dmd_buffer_size(dmd_width, dmd_height),
-    );
-
     let text_align;
 
-    match args.align {
+    match &args.align {
         Some(align) => match align.as_str() {
             "center" => text_align = imageutils::TextAlign::CENTER,
             "left" => text_align = imageutils::TextAlign::LEFT,
@@ -809,169 +4433,689 @@ fn main() {
         }
     };
 
-    let gradient = match args.gradient {
-        Some(gradient_path) => match Reader::open(gradient_path) {
-            Ok(gradient_fd) => match gradient_fd.decode() {
-                Ok(img) => {
-                    Some(img.resize_exact(dmd_width, dmd_height, imageops::FilterType::Lanczos3))
+    let image_fit = match &args.fit {
+        Some(fit) => match fit.as_str() {
+            "contain" => imageutils::ImageFit::CONTAIN,
+            "cover" => imageutils::ImageFit::COVER,
+            "stretch" => imageutils::ImageFit::STRETCH,
+            "crop" => imageutils::ImageFit::CROP,
+            _ => {
+                eprintln!("Invalid fit value (contain, cover, stretch or crop)");
+                return;
+            }
+        },
+        None => imageutils::ImageFit::CONTAIN,
+    };
+
+    let palette = match args.palette {
+        Some(ref path) => match palette::load_palette(path, args.palette_index) {
+            Ok(x) => Some(x),
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
+        },
+        None => None,
+    };
+
+    let image_scale = match args.scale.as_str() {
+        "smooth" => imageutils::ImageScale::SMOOTH,
+        "nearest" => imageutils::ImageScale::NEAREST,
+        "integer" => imageutils::ImageScale::INTEGER(None),
+        x => match x.strip_suffix('x').map(|x| x.parse::<u32>()) {
+            Some(Ok(factor)) if factor > 0 => imageutils::ImageScale::INTEGER(Some(factor)),
+            _ => {
+                eprintln!("Invalid scale value (smooth, nearest, integer or 2x, 3x...)");
+                return;
+            }
+        },
+    };
+
+    let image_filter = match args.filter.as_str() {
+        "nearest" => imageops::FilterType::Nearest,
+        "triangle" => imageops::FilterType::Triangle,
+        "catmullrom" => imageops::FilterType::CatmullRom,
+        "gaussian" => imageops::FilterType::Gaussian,
+        "lanczos" => imageops::FilterType::Lanczos3,
+        _ => {
+            eprintln!("Invalid filter value (nearest, triangle, catmullrom, gaussian or lanczos)");
+            return;
+        }
+    };
+
+    let transparent_color = match args.transparent_color.as_deref().map(parse_color) {
+        Some(Ok(x)) => Some(x),
+        Some(Err(e)) => {
+            eprintln!("{}", e);
+            return;
+        }
+        None => None,
+    };
+
+    let image_options = imageutils::ImageOptions {
+        fit: image_fit,
+        transparent_color,
+        scale: image_scale,
+        filter: image_filter,
+        crop,
+        palette,
+        orientation: imageutils::Orientation {
+            rotate: args.rotate,
+            flip_h: args.flip_h,
+            flip_v: args.flip_v,
+        },
+        colors,
+    };
+    let (render_width, render_height) =
+        imageutils::oriented_size(&image_options.orientation, dmd_width, dmd_height);
+    let frame_cache =
+        (args.frame_cache || args.frame_cache_dir.is_some() || args.preload).then(|| {
+            framecache::FrameCache::new(
+                &args.frame_cache_dir,
+                imageutils::conversion_settings(&image_options),
+                dmd_width,
+                dmd_height,
+                args.frame_cache_size * 1024 * 1024,
+            )
+        });
+
+    let gradient = match &args.gradient {
+        Some(gradient) => {
+            match imageutils::generate_gradient(gradient, render_width, render_height) {
+                Some(Ok(x)) => Some(x),
+                Some(Err(e)) => {
+                    eprintln!("{}", e);
+                    return;
                 }
+                None => match Reader::open(gradient) {
+                    Ok(gradient_fd) => match gradient_fd.decode() {
+                        Ok(img) => Some(img.resize_exact(
+                            render_width,
+                            render_height,
+                            imageops::FilterType::Lanczos3,
+                        )),
+                        Err(e) => {
+                            eprintln!("unable to apply gradient: {}", e.to_string());
+                            None
+                        }
+                    },
+                    Err(e) => {
+                        eprintln!("unable to apply gradient: {}", e.to_string());
+                        None
+                    }
+                },
+            }
+        }
+        None => None,
+    };
+
+    // the text is composed over the background image, or a plain color
+    let background = match &args.background_file {
+        Some(background_path) => match Reader::open(background_path) {
+            Ok(background_fd) => match background_fd.decode() {
+                Ok(img) => Some(
+                    img.resize_exact(render_width, render_height, imageops::FilterType::Lanczos3)
+                        .to_rgba8(),
+                ),
                 Err(e) => {
-                    eprintln!("unable to apply gradient: {}", e.to_string());
+                    eprintln!("unable to apply background: {}", e);
                     None
                 }
             },
             Err(e) => {
-                eprintln!("unable to apply gradient: {}", e.to_string());
+                eprintln!("unable to apply background: {}", e);
                 None
             }
         },
         None => None,
-    };
+    }
+    .unwrap_or_else(|| RgbaImage::from_pixel(render_width, render_height, background_color));
 
-    match args.file {
-        Some(file) => {
-            let duration_default = 2000; // time in case a single image is mixted with animations (2 seconds)
+    let raw_size = raw_size.unwrap_or((dmd_width, dmd_height));
 
-            let _ = match handle_case_file(
-                header,
-                dmd_width,
-                dmd_height,
-                &client,
-                file,
-                args.once,
-                duration_default,
+    let zones = match args
+        .zone
+        .iter()
+        .map(|zone| layout::parse_zone(zone, render_width, render_height))
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+    let template = match args
+        .template
+        .as_ref()
+        .map(|path| template::load_template(path))
+    {
+        Some(Ok(x)) => Some(x),
+        Some(Err(e)) => {
+            eprintln!("{}", e);
+            return;
+        }
+        None => None,
+    };
+    let template_vars = match template::parse_vars(&args.var) {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+    let drawing = match args.draw.as_ref().map(|script| {
+        let mut img = background.clone();
+        draw::draw_script(&mut img, script, text_color).map(|_| img)
+    }) {
+        Some(Ok(x)) => Some(x),
+        Some(Err(e)) => {
+            eprintln!("{}", e);
+            return;
+        }
+        None => None,
+    };
+    // the marquee of a game is played as an image file
+    let file = match args.game {
+        Some(ref game) => {
+            let dirs = if args.marquee_dir.is_empty() {
+                marquee::DEFAULT_MARQUEE_DIRS
+                    .iter()
+                    .map(|x| x.to_string())
+                    .collect()
+            } else {
+                args.marquee_dir.clone()
+            };
+            match marquee::find_marquee(
+                game,
+                &args.system,
+                &dirs,
+                &args.marquee_url,
+                &args.marquee_cache,
             ) {
-                Ok(x) => {
-                    was_animation = x;
-                }
+                Ok(x) => Some(x),
                 Err(e) => {
-                    eprintln!("{}", e.to_string());
+                    eprintln!("{}", e);
+                    return;
                 }
-            };
+            }
         }
-        None => {}
+        None => args.file.clone(),
+    };
+    if args.end_frame.is_some_and(|x| x < args.start_frame) {
+        eprintln!("Invalid end frame: before the start frame");
+        return;
+    }
+    if args.trim_ms == Some(0) {
+        eprintln!("Invalid trim value");
+        return;
+    }
+    let frame_range = FrameRange {
+        start: args.start_frame,
+        end: args.end_frame,
+        trim_ms: args.trim_ms,
+        fps: args.fps,
     };
+    let fps = args.fps.unwrap_or(DEFAULT_FPS);
 
-    match args.text {
-        Some(text) => {
-            let mut dsp_text = text.clone();
-            if args.caps {
-                dsp_text = text.to_uppercase().replace("\\N", "\\n");
-            }
-            let _ = match send_image_text(
-                &client,
-                header,
-                dmd_width,
-                dmd_height,
-                &dsp_text,
-                &args.font,
-                &gradient,
-                text_color,
-                background_color,
-                &text_align,
-                args.line_spacing,
-                args.moving_text,
-                args.fixed_text,
-                args.speed,
-                args.once,
-            ) {
-                Ok(x) => {
-                    was_animation = x;
-                }
-                Err(e) => {
-                    eprintln!("{}", e.to_string());
-                }
+    // texts evaluated again with --refresh
+    let text_source = match (&args.text, &args.texts, &args.exec) {
+        (Some(text), _, _) => Some(TextSource::TEXT(text.clone())),
+        (None, Some(texts), _) => Some(TextSource::TEXTS(
+            texts.split('|').map(|x| x.to_string()).collect(),
+        )),
+        (None, None, Some(command)) => Some(TextSource::EXEC(command.clone())),
+        (None, None, None) => args.poll_json.as_ref().map(|url| {
+            let template = match &args.jq_path {
+                Some(path) => format!("{{json:{}}}", path),
+                None => args.poll_template.clone().unwrap_or_default(),
             };
+            TextSource::POLL(url.clone(), template)
+        }),
+    }
+    .or(ticker_symbols.map(TextSource::TICKER))
+    .or(args
+        .netinfo
+        .then(|| TextSource::NETINFO(args.ping_host.clone())));
+
+    let play = if let Some(file) = file {
+        Play::FILE(file)
+    } else if let Some(path) = &args.source {
+        Play::SOURCE(path.clone(), source_format)
+    } else if args.relay.is_some() {
+        let idle = match (&args.idle_file, args.idle_clock) {
+            (Some(file), _) => Some(RelayIdle::FILE(file.clone())),
+            (None, true) => Some(RelayIdle::CLOCK),
+            (None, false) => None,
+        };
+        Play::RELAY(relay_channels, idle)
+    } else if let Some(spritesheet) = &args.spritesheet {
+        match sprite_size {
+            Some(x) => Play::SPRITESHEET(spritesheet.clone(), x),
+            None => {
+                eprintln!("Missing --sprite-size for the sprite sheet");
+                return;
+            }
         }
-        None => {}
+    } else if let Some(text_source) = text_source {
+        Play::TEXT(text_source, color_cycle)
+    } else if !zones.is_empty() {
+        Play::LAYOUT(zones)
+    } else if let Some(progress) = args.progress {
+        Play::PROGRESS(progress)
+    } else if let Some(source) = progress_source {
+        Play::WATCHPROGRESS(source)
+    } else if let Some(drawing) = drawing {
+        Play::DRAW(drawing)
+    } else if let Some(mode) = visualizer_mode {
+        Play::VISUALIZER(mode)
+    } else if let Some(screensaver) = screensaver {
+        Play::SCREENSAVER(screensaver)
+    } else if let Some(slideshow) = slideshow {
+        Play::SLIDESHOW(slideshow)
+    } else if let Some(pattern) = test_pattern {
+        Play::TESTPATTERN(pattern)
+    } else if args.identify {
+        Play::IDENTIFY(host.clone(), port)
+    } else if let Some(effect) = effect {
+        Play::EFFECT(effect, effect_colors)
+    } else if let Some(script) = &args.script {
+        Play::SCRIPT(script.clone())
+    } else if let Some(template) = template {
+        Play::TEMPLATE(template)
+    } else if let Some(base) = &args.base {
+        let base = match base.as_str() {
+            "clock" => Ok(compositor::BaseLayer::CLOCK),
+            _ => files_to_frames(base.clone(), fps, raw_size, DEFAULT_DURATION)
+                .map(compositor::BaseLayer::IMAGE),
+        };
+        let source = match (&args.ticker_text, &args.ticker_rss) {
+            (Some(text), None) => Ok(compositor::TickerSource::TEXT(text.clone())),
+            (None, Some(url)) => Ok(compositor::TickerSource::RSS(url.clone())),
+            (Some(_), Some(_)) => Err(String::from("Only one of --ticker-text and --ticker-rss")),
+            (None, None) => Err(String::from("Missing --ticker-text or --ticker-rss")),
+        };
+        match base.and_then(|base| source.map(|source| (base, source))) {
+            Ok((base, source)) => Play::LAYERS(
+                base,
+                compositor::Ticker {
+                    source,
+                    height: args.ticker_height.unwrap_or(render_height / 2),
+                    color: ticker_color.unwrap_or(text_color),
+                    refresh: args.ticker_refresh,
+                },
+            ),
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
+        }
+    } else if args.clock || alarm.is_some() {
+        let alarm = alarm.map(|time| Alarm {
+            time,
+            text: args.alarm_text.clone(),
+            duration: args.alarm_duration,
+            command: args.alarm_command.clone(),
+        });
+        Play::CLOCK(clock_style, alarm)
+    } else if !countdowns.is_empty() {
+        Play::COUNTDOWN(Countdowns {
+            targets: countdowns,
+            cycle: args.countdown_cycle,
+            lines: args.countdown_lines,
+            header: args.countdown_header.clone(),
+            format: args.countdown_format.clone(),
+            format_0_day: args.countdown_format_0_day.clone(),
+            format_0_hour: args.countdown_format_0_hour.clone(),
+            format_0_minute: args.countdown_format_0_minute.clone(),
+            timezone,
+            ics_refresh: args.ics_refresh,
+        })
+    } else if args.clear {
+        Play::CLEAR
+    } else {
+        Play::NOTIFICATIONS
+    };
+
+    // the font is read once, by the modes and the overlays writing a text only
+    let overlays =
+        args.overlay_file.is_some() || notifications_bus.is_some() || args.alerts.is_some();
+    let font = match writes_text(&play) || overlays {
+        true => match imageutils::load_font(&args.font) {
+            Ok(x) => x,
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
+        },
+        false => imageutils::builtin_font(),
+    };
+    let options = TextOptions {
+        style: imageutils::TextStyle {
+            font,
+            color: text_color,
+            align: text_align,
+            line_spacing: args.line_spacing,
+            letter_spacing: args.letter_spacing,
+            tabular_digits: args.tabular_digits,
+        },
+        gradient,
+        background,
+        moving: args.moving_text,
+        fixed: args.fixed_text,
+        speed: args.speed,
+        animation: text_animation,
+    };
+    let clock = ClockOptions {
+        format: args.clock_format.clone(),
+        h12: args.h12,
+        no_seconds: args.no_seconds,
+        timezone,
+    };
+    let files = FileOptions {
+        fps,
+        raw_size,
+        range: frame_range,
+        cache: frame_cache,
     };
 
-    if args.clock {
-        handle_clock(
-            &client,
-            header,
+    if args.print_layout {
+        let text = args.text.as_ref().map(|text| {
+            let text = template::expand_placeholders(
+                &template::substitute_vars(text, &template_vars),
+                &timezone,
+            );
+            match args.caps {
+                true => text.to_uppercase().replace("\\N", "\\n"),
+                false => text,
+            }
+        });
+        let result = match text {
+            Some(text) => print_text_layout(&text, &options, render_width, render_height),
+            None => Err(String::from("--print-layout requires --text")),
+        };
+        if let Err(e) = result {
+            eprintln!("{}", e);
+        }
+        return;
+    }
+
+    // the dmd is split in tiles from left to right, each one on its own server
+    let targets = if args.tile.is_empty() {
+        vec![(host.clone(), port)]
+    } else {
+        match args
+            .tile
+            .iter()
+            .map(|x| parse_target(x, port))
+            .collect::<Result<Vec<(String, u16)>, String>>()
+        {
+            Ok(x) => x,
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
+        }
+    };
+    if dmd_width % targets.len() as u32 != 0 {
+        eprintln!(
+            "The dmd width ({}) must be a multiple of the number of tiles ({})",
             dmd_width,
-            dmd_height,
-            &args.font,
-            &gradient,
-            text_color,
-            background_color,
-            &text_align,
-            args.line_spacing,
-            args.moving_text,
-            args.fixed_text,
-            args.speed,
-            args.clock_format,
-            args.h12,
-            args.no_seconds,
+            targets.len()
         );
+        return;
     }
 
-    match args.countdown {
-        Some(countdown) => {
-            match handle_countdown(
-                &client,
-                header,
-                dmd_width,
-                dmd_height,
-                &args.font,
-                &gradient,
-                text_color,
-                background_color,
-                &text_align,
-                args.line_spacing,
-                args.moving_text,
-                args.fixed_text,
-                args.speed,
-                countdown,
-                args.countdown_header,
-                args.countdown_format,
-                args.countdown_format_0_minute,
-                args.countdown_format_0_hour,
-                args.countdown_format_0_day,
+    let mut servers = Vec::new();
+    if !args.dry_run && args.dump_raw.is_none() && args.serial.is_none() {
+        for (host, port) in targets {
+            match connect_server(
+                &host,
+                port,
+                args.connect_timeout,
+                args.retries,
+                args.retry_delay,
+                match args.wait_for_server {
+                    true => Some(args.wait_timeout),
+                    false => None,
+                },
+                &cancel,
             ) {
-                Ok(_) => {}
+                Ok(stream) => servers.push((host, stream)),
                 Err(e) => {
-                    eprintln!("{}", e.to_string());
+                    eprintln!("Erreur de connexion au serveur: {}", e);
+                    return;
                 }
             }
         }
-        None => {}
+    }
+
+    // the bus name is taken before playing, so that another notification daemon is reported at once
+    let (notification_sender, notifications) = mpsc::channel();
+    let notification_connection = match &notifications_bus {
+        Some(bus) => match notifications::serve(bus, notification_sender.clone()) {
+            Ok(x) => Some(x),
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
+        },
+        None => None,
+    };
+    let alerts_listener = match args.alerts {
+        Some(port) => match alerts::listen(port, &args.alerts_secret) {
+            Ok(x) => Some(x),
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
+        },
+        None => None,
     };
 
-    if args.clear {
-        was_animation = true;
+    // the answer to the handshake is read with a timeout, nothing else is read from the server
+    for (_, server) in servers.iter() {
+        if handshake_version.is_some()
+            && let Err(e) = server.set_read_timeout(Some(Duration::from_millis(
+                args.connect_timeout.unwrap_or(HANDSHAKE_TIMEOUT) as u64,
+            )))
+        {
+            eprintln!("Erreur de connexion au serveur: {}", e);
+            return;
+        }
+    }
 
-        let _ = match send_image_text(
-            &client,
-            header,
-            dmd_width,
-            dmd_height,
-            "",
-            &args.font,
-            &gradient,
-            background_color,
-            background_color,
-            &imageutils::TextAlign::CENTER,
-            0,
-            args.moving_text,
-            args.fixed_text,
-            args.speed,
-            args.once,
-        ) {
-            Ok(_) => {}
+    // where the frames go, then what is done with them on their way, from the dmd to what is played
+    let connected = !servers.is_empty();
+    let mut client: Box<dyn FrameSink> = if let Some(path) = &args.dump_raw {
+        match sink::ExportSink::create(path) {
+            Ok(x) => Box::new(x),
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
+        }
+    } else if let Some(path) = &args.serial {
+        match sink::DeviceSink::open(path) {
+            Ok(x) => Box::new(x),
             Err(e) => {
-                eprintln!("{}", e.to_string());
+                eprintln!("{}", e);
+                return;
+            }
+        }
+    } else if servers.is_empty() {
+        Box::new(sink::NullSink)
+    } else {
+        // with tls, the frames (and the token) are encrypted on their way to the servers,
+        // and compressed before. each server gets the version it accepted
+        let upstream_options = UpstreamOptions {
+            tls: args.tls,
+            tls_ca: &args.tls_ca,
+            insecure: args.insecure,
+            token: &args.auth_token,
+            version: protocol_version,
+            compression,
+        };
+        let mut upstreams = Vec::new();
+        for (host, server) in servers {
+            match open_upstream(server, &host, &upstream_options) {
+                Ok(x) => upstreams.push(x),
+                Err(e) => {
+                    eprintln!("Erreur de connexion au serveur: {}", e);
+                    return;
+                }
             }
+        }
+        match upstreams.len() {
+            1 => Box::new(upstreams.remove(0)),
+            _ => Box::new(sink::TiledSink::new(upstreams)),
+        }
+    };
+    if let Some(delay) = args.keepalive {
+        let keepalive = preview::KeepaliveSink::new(client, Duration::from_secs(delay as u64));
+        client = Box::new(TickedSink::start(keepalive, preview::KEEPALIVE_POLL));
+    }
+    if args.dry_run || args.snapshot.is_some() || args.status_json.is_some() || stats.is_some() {
+        let preview_options = preview::PreviewOptions {
+            print: args.dry_run,
+            snapshot: args.snapshot.clone(),
+            status: args.status_json.clone(),
+            mode: mode.to_string(),
+            text: args
+                .text
+                .clone()
+                .or(args.texts.clone())
+                .or(args.exec.clone()),
+            connected,
+            stats,
+            stats_json: args.stats_json.clone(),
+        };
+        client = Box::new(preview::PreviewSink::new(client, preview_options));
+    }
+    if active_hours.is_some() || auto_dim.is_some() || args.anti_burn {
+        let adjust_options = preview::AdjustOptions {
+            active_hours,
+            inactive_brightness: args.inactive_brightness,
+            auto_dim,
+            anti_burn: args.anti_burn,
         };
+        let adjusted = preview::AdjustedSink::new(client, adjust_options);
+        client = Box::new(TickedSink::start(adjusted, preview::SCHEDULE_POLL));
+    }
+    // the overlays are previewed, blanked, dimmed and shifted like the frames they are shown over
+    let (overlay_sender, overlays) = mpsc::channel();
+    if args.overlay_file.is_some() || notifications_bus.is_some() || alerts_listener.is_some() {
+        let overlay = preview::OverlaySink::new(client, overlays, &cancel);
+        client = Box::new(TickedSink::start(overlay, preview::OVERLAY_POLL));
+    }
+    // the frames of the playing feed the watchdog, not the ones repeated by the sinks
+    let watchdog = systemd::Watchdog::from_env();
+    if watchdog.is_enabled() {
+        client = Box::new(systemd::WatchdogSink::new(client, watchdog.clone()));
+    }
+
+    // compute the header only once while it is always the same one
+    let header = get_header(
+        dmd_width as u16,
+        dmd_height as u16,
+        layer,
+        imageutils::get_dmd_buffer_size(dmd_width, dmd_height),
+        protocol_version,
+    );
+
+    // connected to the servers: ready, unless the relay still has to listen
+    if args.relay.is_none()
+        && let Err(e) = systemd::notify("READY=1")
+    {
+        eprintln!("{}", e);
+    }
+    watchdog.start(&cancel);
+    if let Some(duration) = args.duration {
+        cancel::cancel_after(&cancel, Duration::from_secs(duration as u64));
+    }
+    let control = control::PlayControl::default();
+    if args.control_stdin {
+        control::read_commands(&control);
+    }
+
+    let dmd = Dmd {
+        client: &*client,
+        header,
+        width: dmd_width,
+        height: dmd_height,
+        image_options: &image_options,
+        rate: args.rate,
+        control: &control,
+        watchdog: &watchdog,
+        cancel: &cancel,
+    };
+
+    // the overlays and the notifications are written in the middle, without spacing
+    let overlay = OverlayStyle {
+        header,
+        width: dmd_width,
+        height: dmd_height,
+        style: imageutils::TextStyle {
+            color: text_color,
+            align: imageutils::TextAlign::CENTER,
+            line_spacing: 0,
+            letter_spacing: 0,
+            tabular_digits: false,
+            ..options.style.clone()
+        },
+        background: background_color,
+        image_options: image_options.clone(),
+    };
+    if let Some(path) = args.overlay_file.clone() {
+        let overlay = overlay.clone();
+        let duration = Duration::from_millis(args.overlay_time);
+        let (overlay_sender, cancel) = (overlay_sender.clone(), cancel.clone());
+        thread::spawn(move || {
+            watch_overlay_file(&path, &overlay, duration, overlay_sender, &cancel)
+        });
+    }
+    if let Some(listener) = alerts_listener {
+        let secret = args.alerts_secret.clone();
+        let (notification_sender, cancel) = (notification_sender.clone(), cancel.clone());
+        thread::spawn(move || alerts::serve(listener, secret, notification_sender, &cancel));
+    }
+    // the notifications are shown until all their sources are gone
+    drop(notification_sender);
+    if notification_connection.is_some() || args.alerts.is_some() {
+        let duration = Duration::from_millis(args.overlay_time);
+        let cancel = cancel.clone();
+        thread::spawn(move || {
+            // the bus name is kept as long as the notifications are shown
+            let _connection = notification_connection;
+            show_notifications(notifications, &overlay, duration, overlay_sender, &cancel)
+        });
+    }
+
+    // set to true to disable overlay sleep time at the end
+    let was_animation = match play_mode(&dmd, play, &args, &options, &clock, &files, &template_vars)
+    {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("{}", e);
+            false
+        }
+    };
+
+    if args.duration_clear {
+        let black = RgbaImage::from_pixel(render_width, render_height, Rgba([0, 0, 0, 255]));
+        if let Err(e) = send_render(&dmd, &black) {
+            eprintln!("{}", e);
+        }
     }
 
     // at the end, if we have overlay, we sleep
     if args.overlay && was_animation == false {
-        thread::sleep(Duration::from_millis(args.overlay_time));
+        cancel.sleep(Duration::from_millis(args.overlay_time));
     }
+    // the overlays and the alerts stop with the playing
+    cancel.cancel();
 
-    let _ = match client.shutdown(std::net::Shutdown::Write) {
+    let _ = match client.close() {
         Ok(_) => {}
         Err(e) => {
             eprintln!("{}", e.to_string());
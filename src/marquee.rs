@@ -0,0 +1,260 @@
+use std::{
+    env, fs,
+    io::Read,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+// marquee directories searched by default, {system} is replaced by the system of the game
+pub const DEFAULT_MARQUEE_DIRS: [&str; 2] = [
+    "/userdata/system/configs/dmd/marquees/{system}",
+    "/userdata/roms/{system}/images",
+];
+
+// file names less similar to the game than this are ignored
+const MIN_SIMILARITY: f32 = 0.75;
+const IMAGE_EXTENSIONS: [&str; 6] = ["png", "gif", "jpg", "jpeg", "bmp", "webp"];
+// suffixes added by the scrapers to the rom name
+const NAME_SUFFIXES: [&str; 4] = ["-marquee", "-wheel", "-logo", "-image"];
+// larger downloads are refused, a marquee is a few hundred kilobytes
+const MAX_DOWNLOAD: u64 = 16 * 1024 * 1024;
+
+// lowercase words of the name, without the tags: "Street Fighter II (USA) [!]" is "street fighter ii"
+fn normalize(name: &str) -> String {
+    let mut name = name.to_lowercase();
+    for suffix in NAME_SUFFIXES {
+        if let Some(x) = name.strip_suffix(suffix) {
+            name = x.to_string();
+        }
+    }
+
+    let mut result = String::new();
+    let mut depth = 0;
+    for c in name.chars() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth = (depth - 1).max(0),
+            _ if depth > 0 => {}
+            _ if c.is_alphanumeric() => result.push(c),
+            _ => result.push(' '),
+        }
+    }
+    result.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            current[j + 1] = (previous[j] + cost)
+                .min(previous[j + 1] + 1)
+                .min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+// 1.0 for the same normalized names, 0.0 for nothing in common
+fn similarity(a: &str, b: &str) -> f32 {
+    let length = a.chars().count().max(b.chars().count());
+    if length == 0 {
+        return 0.0;
+    }
+    1.0 - levenshtein(a, b) as f32 / length as f32
+}
+
+pub fn is_image(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+// the image of the directory whose name is the closest to the game
+fn find_in_directory(dir: &Path, game: &str) -> Option<(f32, PathBuf)> {
+    let entries = fs::read_dir(dir).ok()?;
+    let mut best: Option<(f32, PathBuf)> = None;
+
+    for path in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+        if !is_image(&path) {
+            continue;
+        }
+        let Some(stem) = path.file_stem().and_then(|x| x.to_str()) else {
+            continue;
+        };
+        let mut score = similarity(game, &normalize(stem));
+        // the scrapers produce several images per game, the marquee is preferred
+        if stem.to_lowercase().ends_with("-marquee") {
+            score += 0.01;
+        }
+        if score >= MIN_SIMILARITY && best.as_ref().is_none_or(|(x, _)| score > *x) {
+            best = Some((score, path));
+        }
+    }
+    best
+}
+
+fn default_cache_dir() -> String {
+    match env::var("XDG_CACHE_HOME") {
+        Ok(x) => format!("{}/dmd-play/marquees", x),
+        Err(_) => format!(
+            "{}/.cache/dmd-play/marquees",
+            env::var("HOME").unwrap_or_default()
+        ),
+    }
+}
+
+fn url_encode(value: &str) -> String {
+    let mut result = String::new();
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                result.push(byte as char)
+            }
+            _ => result.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    result
+}
+
+// download the image given by the scraper url, it is kept in the cache directory
+fn download_marquee(url: &str, cache_path: &Path) -> Result<PathBuf, String> {
+    let mut bytes = Vec::new();
+    match ureq::get(url).timeout(Duration::from_secs(10)).call() {
+        Ok(response) => {
+            let mut reader = response.into_reader().take(MAX_DOWNLOAD + 1);
+            if let Err(e) = reader.read_to_end(&mut bytes) {
+                return Err(format!("Error: {}: {}", url, e));
+            }
+            if bytes.len() as u64 > MAX_DOWNLOAD {
+                return Err(format!(
+                    "Error: {}: larger than {} bytes",
+                    url, MAX_DOWNLOAD
+                ));
+            }
+        }
+        Err(e) => return Err(format!("Error: {}: {}", url, e)),
+    }
+
+    let extension = match image::guess_format(&bytes) {
+        Ok(image::ImageFormat::Gif) => "gif",
+        Ok(image::ImageFormat::Jpeg) => "jpg",
+        Ok(image::ImageFormat::Png) => "png",
+        Ok(image::ImageFormat::WebP) => "webp",
+        Ok(image::ImageFormat::Bmp) => "bmp",
+        _ => return Err(format!("Error: {}: not an image", url)),
+    };
+    let path = cache_path.with_extension(extension);
+    if let Some(parent) = path.parent()
+        && let Err(e) = fs::create_dir_all(parent)
+    {
+        return Err(format!("Error: {}: {}", parent.display(), e));
+    }
+    if let Err(e) = fs::write(&path, &bytes) {
+        return Err(format!("Error: {}: {}", path.display(), e));
+    }
+    Ok(path)
+}
+
+// path of the marquee of a game: the closest name in the marquee directories,
+// or the image downloaded from the scraper url ({game} and {system} are replaced)
+pub fn find_marquee(
+    game: &str,
+    system: &Option<String>,
+    dirs: &[String],
+    scraper_url: &Option<String>,
+    cache_dir: &Option<String>,
+) -> Result<String, String> {
+    let normalized = normalize(game);
+    if normalized.is_empty() {
+        return Err(format!("Invalid game name: {}", game));
+    }
+    let system_dir = system.clone().unwrap_or_default();
+    // the system is a directory name in the marquee directories and in the cache
+    if system_dir == ".." || system_dir.contains(['/', '\\']) {
+        return Err(format!("Invalid system: {}", system_dir));
+    }
+
+    let mut best: Option<(f32, PathBuf)> = None;
+    for dir in dirs {
+        // directories of a system are skipped when the system is unknown
+        if dir.contains("{system}") && system.is_none() {
+            continue;
+        }
+        let dir = dir.replace("{system}", &system_dir);
+        if let Some((score, path)) = find_in_directory(Path::new(&dir), &normalized)
+            && best.as_ref().is_none_or(|(x, _)| score > *x)
+        {
+            best = Some((score, path));
+        }
+    }
+    if let Some((_, path)) = best {
+        return Ok(path.to_string_lossy().to_string());
+    }
+
+    let Some(scraper_url) = scraper_url else {
+        return Err(format!("No marquee found for {}", game));
+    };
+
+    // downloaded marquees are looked up by their exact name
+    let cache_dir = cache_dir.clone().unwrap_or_else(default_cache_dir);
+    let cache_path = Path::new(&cache_dir)
+        .join(&system_dir)
+        .join(normalized.replace(' ', "_"));
+    for extension in IMAGE_EXTENSIONS {
+        let path = cache_path.with_extension(extension);
+        if path.is_file() {
+            return Ok(path.to_string_lossy().to_string());
+        }
+    }
+
+    let url = scraper_url
+        .replace("{game}", &url_encode(game))
+        .replace("{system}", &url_encode(&system_dir));
+    let path = download_marquee(&url, &cache_path)?;
+    Ok(path.to_string_lossy().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        assert_eq!(
+            normalize("Street Fighter II (USA) [!]"),
+            "street fighter ii"
+        );
+        assert_eq!(normalize("sf2-Marquee"), "sf2");
+        assert_eq!(normalize("Pac-Man (Rev 1) (Japan)"), "pac man");
+        assert_eq!(similarity("pac man", "pac man"), 1.0);
+        assert_eq!(similarity("", ""), 0.0);
+        assert!(similarity("street fighter ii", "street fighter iii") >= MIN_SIMILARITY);
+        assert!(similarity("street fighter ii", "final fight") < MIN_SIMILARITY);
+    }
+
+    #[test]
+    fn directory() {
+        let dir = env::temp_dir().join(format!("dmd-play-marquees-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["sf2.png", "sf2-marquee.png", "final fight.png", "sf2.txt"] {
+            fs::write(dir.join(name), b"").unwrap();
+        }
+        let found = |game: &str| {
+            find_in_directory(&dir, &normalize(game))
+                .map(|(_, x)| x.file_name().unwrap().to_string_lossy().to_string())
+        };
+        // the marquee of the scrapers is preferred to the other images of the game
+        assert_eq!(found("SF2 (World)").as_deref(), Some("sf2-marquee.png"));
+        assert_eq!(found("Final Fight").as_deref(), Some("final fight.png"));
+        assert_eq!(found("Metal Slug"), None);
+        let _ = fs::remove_dir_all(&dir);
+
+        assert!(find_marquee("sf2", &Some(String::from("../..")), &[], &None, &None).is_err());
+    }
+}
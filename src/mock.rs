@@ -0,0 +1,240 @@
+use crate::{
+    cancel::CancelToken,
+    imageutils, preview,
+    protocol::{self, FrameCompression, MODE_RGB565},
+};
+use std::{
+    io::{BufReader, ErrorKind, Read},
+    net::{TcpListener, TcpStream},
+    thread,
+    time::Duration,
+};
+
+// how often a stop is checked while no client connects
+const ACCEPT_POLL: Duration = Duration::from_millis(100);
+
+// the frames of a client, checked like a server would, until it disconnects.
+// returns the number of frames received
+fn handle_connection(
+    stream: TcpStream,
+    connection: u32,
+    token: &Option<String>,
+    show: bool,
+) -> Result<u32, String> {
+    let mut writer = match stream.try_clone() {
+        Ok(x) => x,
+        Err(e) => return Err(e.to_string()),
+    };
+    let mut reader = BufReader::new(stream);
+    let mut start = [0u8; protocol::VERSION_OFFSET + 1];
+    if reader.read_exact(&mut start).is_err() {
+        // closed without sending anything
+        return Ok(0);
+    }
+    let mut reader = (&start[..]).chain(reader);
+
+    // only the frames sent after a handshake have a version above 1
+    let mut version = protocol::PROTOCOL_VERSION;
+    let mut compression = None;
+    if start[protocol::VERSION_OFFSET] >= protocol::HANDSHAKE_VERSION {
+        (version, compression) = protocol::answer_handshake(&mut reader, &mut writer, token)?;
+        println!(
+            "connection {}: version {}, compression {}",
+            connection,
+            version,
+            match compression {
+                Some(FrameCompression::ZLIB) => "zlib",
+                Some(FrameCompression::LZ4) => "lz4",
+                None => "none",
+            }
+        );
+    }
+
+    let mut n = 0;
+    while let Some((header, payload)) = protocol::read_frame(&mut reader)? {
+        n += 1;
+        if header.version > version {
+            return Err(format!(
+                "Frame {}: version {} above the version {} of the connection",
+                n, header.version, version
+            ));
+        }
+        let payload = match (header.compressed, compression) {
+            (false, _) => payload,
+            (true, Some(compression)) => {
+                protocol::decompress_frame(&payload, compression, header.max_frame_size())?
+            }
+            (true, None) => {
+                return Err(format!(
+                    "Frame {}: compressed without a compression negotiated",
+                    n
+                ));
+            }
+        };
+        let (width, height) = (header.width as u32, header.height as u32);
+        // the size of the other modes is not checked
+        if header.mode == MODE_RGB565
+            && payload.len() != imageutils::get_dmd_buffer_size(width, height) as usize
+        {
+            return Err(format!(
+                "Frame {}: {} bytes for a {}x{} rgb565 frame",
+                n,
+                payload.len(),
+                width,
+                height
+            ));
+        }
+
+        println!(
+            "connection {} frame {}: {}x{} mode {} version {}, {} layer, {} bytes{}",
+            connection,
+            n,
+            width,
+            height,
+            header.mode,
+            header.version,
+            if header.buffered { "main" } else { "second" },
+            header.nbytes,
+            if header.compressed { " compressed" } else { "" }
+        );
+        if show && header.mode == MODE_RGB565 {
+            preview::print_image(&imageutils::dmdimage2image(&payload, width, height));
+        }
+    }
+    Ok(n)
+}
+
+// --mock-server: a dmd server printing and checking the frames it receives, for the tests
+// without a dmd. with once, it stops after the first client, with an error for an invalid stream
+pub fn serve(
+    listener: TcpListener,
+    token: &Option<String>,
+    show: bool,
+    once: bool,
+    cancel: &CancelToken,
+) -> Result<(), String> {
+    if let Ok(address) = listener.local_addr() {
+        eprintln!("Mock server listening on port {}", address.port());
+    }
+    // not blocked in accept, to stop when asked
+    if let Err(e) = listener.set_nonblocking(true) {
+        return Err(e.to_string());
+    }
+
+    let mut connection = 0;
+    while !cancel.is_cancelled() {
+        let stream = match listener.accept() {
+            Ok((x, _)) => x,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                cancel.sleep(ACCEPT_POLL);
+                continue;
+            }
+            Err(e) => return Err(e.to_string()),
+        };
+        if let Err(e) = stream.set_nonblocking(false) {
+            return Err(e.to_string());
+        }
+        connection += 1;
+
+        if once {
+            let n = handle_connection(stream, connection, token, show)
+                .map_err(|e| format!("Connection {}: {}", connection, e))?;
+            println!("connection {}: closed after {} frames", connection, n);
+            return Ok(());
+        }
+        let token = token.clone();
+        thread::spawn(
+            move || match handle_connection(stream, connection, &token, show) {
+                Ok(n) => println!("connection {}: closed after {} frames", connection, n),
+                Err(e) => eprintln!("Connection {}: {}", connection, e),
+            },
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{get_header, DMDLayer};
+
+    // a client connected to handle_connection, which is returned once the client is done
+    fn connect(client: impl FnOnce(TcpStream), token: Option<String>) -> Result<u32, String> {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            handle_connection(stream, 1, &token, false)
+        });
+        client(TcpStream::connect(("127.0.0.1", port)).unwrap());
+        server.join().unwrap()
+    }
+
+    #[test]
+    fn frames() {
+        let received = connect(
+            |mut stream| {
+                let header = get_header(4, 2, DMDLayer::MAIN, 16, 1);
+                protocol::write_frame(&mut stream, &header, &[0xff; 16]).unwrap();
+                let header = get_header(4, 2, DMDLayer::SECOND, 16, 1);
+                protocol::write_frame(&mut stream, &header, &[0; 16]).unwrap();
+            },
+            None,
+        );
+        assert_eq!(received, Ok(2));
+    }
+
+    #[test]
+    fn invalid_frame_size() {
+        let received = connect(
+            |mut stream| {
+                let header = get_header(4, 2, DMDLayer::MAIN, 15, 1);
+                protocol::write_frame(&mut stream, &header, &[0; 15]).unwrap();
+            },
+            None,
+        );
+        assert!(received.is_err());
+    }
+
+    #[test]
+    fn compressed_frames() {
+        let received = connect(
+            |mut stream| {
+                let (version, compression) = protocol::negotiate(
+                    &mut stream,
+                    "secret",
+                    protocol::MAX_PROTOCOL_VERSION,
+                    Some(FrameCompression::LZ4),
+                )
+                .unwrap();
+                assert_eq!(version, protocol::COMPRESSION_VERSION);
+                assert_eq!(compression, Some(FrameCompression::LZ4));
+
+                let payload = protocol::compress_frame(&[0; 8192], FrameCompression::LZ4).unwrap();
+                let mut header = protocol::DmdStreamHeader::from_bytes(&get_header(
+                    128,
+                    32,
+                    DMDLayer::MAIN,
+                    payload.len() as u32,
+                    version,
+                ))
+                .unwrap();
+                header.compressed = true;
+                protocol::write_frame(&mut stream, &header.to_bytes(), &payload).unwrap();
+            },
+            Some(String::from("secret")),
+        );
+        assert_eq!(received, Ok(1));
+    }
+
+    #[test]
+    fn refused_token() {
+        let received = connect(
+            |mut stream| {
+                assert!(protocol::negotiate(&mut stream, "wrong", 2, None).is_err());
+            },
+            Some(String::from("secret")),
+        );
+        assert!(received.is_err());
+    }
+}
@@ -0,0 +1,97 @@
+use std::{fs, net::UdpSocket, process::Command};
+
+// the address routing to the internet. connecting an udp socket sends nothing
+fn local_ip() -> Option<String> {
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("8.8.8.8:80").ok()?;
+    let ip = socket.local_addr().ok()?.ip();
+    match ip.is_unspecified() {
+        true => None,
+        false => Some(ip.to_string()),
+    }
+}
+
+// the interface and the link quality (0-70) of /proc/net/wireless, the first interface connected
+fn parse_wireless(content: &str) -> Option<(String, u32)> {
+    content.lines().skip(2).find_map(|line| {
+        let (interface, values) = line.split_once(':')?;
+        let quality = values.split_whitespace().nth(1)?;
+        let quality = quality.trim_end_matches('.').parse::<f32>().ok()?;
+        Some((interface.trim().to_string(), quality.max(0.0) as u32))
+    })
+}
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    match output.status.success() {
+        true => Some(String::from_utf8_lossy(&output.stdout).to_string()),
+        false => None,
+    }
+}
+
+// iwgetid when wireless-tools are installed, iw otherwise
+fn ssid(interface: &str) -> Option<String> {
+    let ssid = match command_output("iwgetid", &[interface, "-r"]) {
+        Some(x) => x.trim().to_string(),
+        None => command_output("iw", &["dev", interface, "link"])?
+            .lines()
+            .find_map(|x| x.trim().strip_prefix("SSID:").map(|x| x.trim().to_string()))?,
+    };
+    match ssid.is_empty() {
+        true => None,
+        false => Some(ssid),
+    }
+}
+
+// the round trip time of "64 bytes from ...: icmp_seq=1 ttl=117 time=12.3 ms"
+fn parse_ping(output: &str) -> Option<f32> {
+    let (_, rest) = output.split_once("time=")?;
+    rest.split_whitespace().next()?.parse::<f32>().ok()
+}
+
+fn ping(host: &str) -> Option<f32> {
+    parse_ping(&command_output("ping", &["-c", "1", "-W", "2", host])?)
+}
+
+// the ip on the first line, the wifi network with its signal and the ping time on the second one
+pub fn netinfo_text(ping_host: &Option<String>) -> String {
+    let ip = local_ip().unwrap_or(String::from("no network"));
+    let mut details = Vec::new();
+    let wireless = fs::read_to_string("/proc/net/wireless").unwrap_or_default();
+    if let Some((interface, quality)) = parse_wireless(&wireless) {
+        let signal = format!("{}%", (quality * 100 / 70).min(100));
+        match ssid(&interface) {
+            Some(ssid) => details.push(format!("{} {}", ssid, signal)),
+            None => details.push(format!("wifi {}", signal)),
+        }
+    }
+    if let Some(host) = ping_host {
+        match ping(host) {
+            Some(time) => details.push(format!("{:.0} ms", time)),
+            None => details.push(String::from("no ping")),
+        }
+    }
+    match details.is_empty() {
+        true => ip,
+        false => format!("{}\\n{}", ip, details.join(" ")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parsing() {
+        let wireless = "Inter-| sta-|   Quality        |   Discarded packets               | Missed | WE\n \
+                        face | tus | link level noise |  nwid  crypt   frag  retry   misc | beacon | 22\n \
+                        wlan0: 0000   52.  -58.  -256        0      0      0      0     12        0\n";
+        assert_eq!(parse_wireless(wireless), Some((String::from("wlan0"), 52)));
+        assert_eq!(parse_wireless(""), None);
+        assert_eq!(
+            parse_ping("64 bytes from 1.1.1.1: icmp_seq=1 ttl=57 time=12.3 ms\n"),
+            Some(12.3)
+        );
+        assert_eq!(parse_ping("1 packets transmitted, 0 received"), None);
+    }
+}
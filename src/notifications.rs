@@ -0,0 +1,129 @@
+use std::{collections::HashMap, sync::mpsc::Sender};
+use zbus::{
+    blocking::connection,
+    fdo::{RequestNameFlags, RequestNameReply},
+    interface,
+    zvariant::OwnedValue,
+};
+
+const NAME: &str = "org.freedesktop.Notifications";
+const PATH: &str = "/org/freedesktop/Notifications";
+
+// the bus the notifications are received on
+pub enum Bus {
+    SESSION,
+    SYSTEM,
+}
+
+// a desktop notification, to display
+pub struct Notification {
+    pub summary: String,
+    pub body: String,
+    pub icon: Option<String>, // image file of the application
+    pub timeout: Option<u32>, // in ms, the default one when none
+    pub flash: bool,          // flashed before being shown, like the alerts
+}
+
+struct NotificationServer {
+    notifications: Sender<Notification>,
+    next_id: u32,
+}
+
+// the icon of the notification, when it is a file: the icon names of a theme are not looked for
+fn icon_path(app_icon: &str, hints: &HashMap<String, OwnedValue>) -> Option<String> {
+    let image_path = hints
+        .get("image-path")
+        .and_then(|x| String::try_from(x.clone()).ok());
+    [image_path.as_deref(), Some(app_icon)]
+        .into_iter()
+        .flatten()
+        .map(|x| x.strip_prefix("file://").unwrap_or(x))
+        .find(|x| x.starts_with('/'))
+        .map(String::from)
+}
+
+// the methods of the specification, the actions are not supported
+#[interface(name = "org.freedesktop.Notifications")]
+impl NotificationServer {
+    fn get_capabilities(&self) -> Vec<String> {
+        vec![String::from("body"), String::from("icon-static")]
+    }
+
+    // the arguments of the method on the bus, they cannot be grouped
+    #[allow(clippy::too_many_arguments)]
+    fn notify(
+        &mut self,
+        _app_name: String,
+        replaces_id: u32,
+        app_icon: String,
+        summary: String,
+        body: String,
+        _actions: Vec<String>,
+        hints: HashMap<String, OwnedValue>,
+        expire_timeout: i32,
+    ) -> u32 {
+        let notification = Notification {
+            summary,
+            body,
+            icon: icon_path(&app_icon, &hints),
+            // 0 is never expiring on a desktop, but would hide what is played for good
+            timeout: match expire_timeout {
+                x if x > 0 => Some(x as u32),
+                _ => None,
+            },
+            flash: false,
+        };
+        if self.notifications.send(notification).is_err() {
+            eprintln!("Notification lost: nothing is played anymore");
+        }
+        match replaces_id {
+            0 => {
+                self.next_id += 1;
+                self.next_id
+            }
+            x => x,
+        }
+    }
+
+    fn close_notification(&self, _id: u32) {}
+
+    fn get_server_information(&self) -> (String, String, String, String) {
+        (
+            String::from("dmd-play"),
+            String::from("batocera"),
+            String::from(env!("CARGO_PKG_VERSION")),
+            String::from("1.2"),
+        )
+    }
+}
+
+// own the notifications name on the bus, the notifications are received until the connection is dropped
+pub fn serve(
+    bus: &Bus,
+    notifications: Sender<Notification>,
+) -> Result<zbus::blocking::Connection, String> {
+    let server = NotificationServer {
+        notifications,
+        next_id: 0,
+    };
+    let builder = match bus {
+        Bus::SESSION => connection::Builder::session(),
+        Bus::SYSTEM => connection::Builder::system(),
+    };
+    let connection = match builder
+        .and_then(|x| x.serve_at(PATH, server))
+        .and_then(|x| x.build())
+    {
+        Ok(x) => x,
+        Err(e) => return Err(format!("Unable to receive the notifications: {}", e)),
+    };
+    // the name is not waited for while another notification daemon owns it
+    match connection.request_name_with_flags(NAME, RequestNameFlags::DoNotQueue.into()) {
+        Ok(RequestNameReply::PrimaryOwner) | Ok(RequestNameReply::AlreadyOwner) => Ok(connection),
+        Ok(_) => Err(format!(
+            "Unable to receive the notifications: {} is owned by another daemon",
+            NAME
+        )),
+        Err(e) => Err(format!("Unable to receive the notifications: {}", e)),
+    }
+}
@@ -0,0 +1,87 @@
+use crate::parse_color;
+use image::Rgba;
+use std::fs;
+
+// big endian values of a pin2dmd file
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl Reader<'_> {
+    fn read(&mut self, size: usize) -> Result<&[u8], String> {
+        if self.position + size > self.bytes.len() {
+            return Err(String::from("Truncated palette file"));
+        }
+        let value = &self.bytes[self.position..self.position + size];
+        self.position += size;
+        Ok(value)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.read(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let value = self.read(2)?;
+        Ok(u16::from_be_bytes([value[0], value[1]]))
+    }
+}
+
+// pin2dmd.pal: version, palettes (index, number of colors, type, rgb colors), then the
+// mappings and masks of the colorization, which need the original rom frames and are ignored
+fn parse_pin2dmd(bytes: &[u8], index: Option<u16>) -> Result<Vec<Rgba<u8>>, String> {
+    let mut reader = Reader { bytes, position: 0 };
+    let _version = reader.u8()?;
+    let npalettes = reader.u16()?;
+
+    let mut palettes = Vec::new();
+    for _ in 0..npalettes {
+        let palette_index = reader.u16()?;
+        let ncolors = reader.u16()?;
+        // 1 and 2 are the default palettes
+        let palette_type = reader.u8()?;
+        let colors: Vec<Rgba<u8>> = reader
+            .read(ncolors as usize * 3)?
+            .chunks_exact(3)
+            .map(|x| Rgba([x[0], x[1], x[2], 255]))
+            .collect();
+        palettes.push((palette_index, palette_type, colors));
+    }
+
+    let palette = match index {
+        Some(index) => palettes.into_iter().find(|(x, _, _)| *x == index),
+        None => {
+            let default = palettes.iter().position(|(_, x, _)| *x == 1 || *x == 2);
+            palettes.into_iter().nth(default.unwrap_or(0))
+        }
+    };
+    match palette {
+        Some((_, _, colors)) if !colors.is_empty() => Ok(colors),
+        _ => Err(String::from("Palette not found in the palette file")),
+    }
+}
+
+// a pin2dmd .pal file, or a text file with one R,G,B color per line, from black to white
+pub fn load_palette(path: &str, index: Option<u16>) -> Result<Vec<Rgba<u8>>, String> {
+    let bytes = match fs::read(path) {
+        Ok(x) => x,
+        Err(e) => return Err(format!("Error: {}: {}", path, e)),
+    };
+
+    let colors = if path.to_lowercase().ends_with(".pal") {
+        parse_pin2dmd(&bytes, index).map_err(|e| format!("Error: {}: {}", path, e))?
+    } else {
+        String::from_utf8_lossy(&bytes)
+            .lines()
+            .map(|x| x.trim())
+            .filter(|x| !x.is_empty() && !x.starts_with('#'))
+            .map(parse_color)
+            .collect::<Result<Vec<_>, _>>()?
+    };
+
+    if colors.len() < 2 {
+        return Err(format!("Error: {}: at least 2 colors are required", path));
+    }
+    Ok(colors)
+}
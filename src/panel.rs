@@ -0,0 +1,51 @@
+use serde::Deserialize;
+use std::{collections::HashMap, fs};
+
+pub const DEFAULT_CONFIG: &str = "/userdata/system/configs/dmd/dmd-play.toml";
+
+// a dmd of the cabinet ([panel.topper] host="192.168.1.20" width=192 height=64),
+// the options given on the command line come first
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Panel {
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub color_correct: Option<Vec<f32>>, // R,G,B gains or a 3x3 matrix, like --color-correct
+}
+
+#[derive(Deserialize)]
+struct Config {
+    #[serde(default)]
+    panel: HashMap<String, Panel>,
+}
+
+// the panel called name in the config file
+pub fn load_panel(path: &str, name: &str) -> Result<Panel, String> {
+    let content = match fs::read_to_string(path) {
+        Ok(x) => x,
+        Err(e) => return Err(format!("Error: {}: {}", path, e)),
+    };
+    let mut config: Config = match toml::from_str(&content) {
+        Ok(x) => x,
+        Err(e) => return Err(format!("Error: {}: {}", path, e)),
+    };
+    match config.panel.remove(name) {
+        Some(x) => Ok(x),
+        None => {
+            let mut names: Vec<&String> = config.panel.keys().collect();
+            names.sort();
+            Err(format!(
+                "Unknown panel {} in {} (available: {})",
+                name,
+                path,
+                names
+                    .iter()
+                    .map(|x| x.as_str())
+                    .collect::<Vec<&str>>()
+                    .join(", ")
+            ))
+        }
+    }
+}
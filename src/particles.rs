@@ -0,0 +1,246 @@
+use crate::{playback, render_frame, screensaver::Random, Dmd};
+use image::{Rgba, RgbaImage};
+use std::{borrow::Cow, f32::consts::PI};
+
+pub enum Effect {
+    FIREWORKS,
+    SNOW,
+    STARFIELD,
+}
+
+// effects are computed at a fixed frame rate, --effect-speed changes the moves only
+const EFFECT_FPS: u32 = 25;
+// with --once, an effect plays this number of frames
+const FRAMES_PER_CYCLE: u32 = 5 * EFFECT_FPS;
+// the density is a number of particles for a 128x32 dmd
+const REFERENCE_AREA: f32 = 128.0 * 32.0;
+
+// what is animated, and how
+pub struct EffectOptions<'a> {
+    pub effect: &'a Effect,
+    pub density: u32, // particles for a 128x32 dmd
+    pub speed: f32,
+    pub colors: &'a [Rgba<u8>], // random ones when empty
+}
+
+struct Particle {
+    x: f32,
+    y: f32,
+    z: f32, // depth for the stars, time to live for the fireworks
+    vx: f32,
+    vy: f32,
+    color: Rgba<u8>,
+    rocket: bool,
+}
+
+fn random_color(random: &mut Random, colors: &[Rgba<u8>]) -> Rgba<u8> {
+    if colors.is_empty() {
+        // a bright color of any hue
+        let hue = random.unit() * 2.0 * PI;
+        let channel = |phase: f32| (160.0 + 95.0 * (hue + phase).sin()) as u8;
+        Rgba([
+            channel(0.0),
+            channel(2.0 * PI / 3.0),
+            channel(4.0 * PI / 3.0),
+            255,
+        ])
+    } else {
+        colors[random.below(colors.len() as u32) as usize]
+    }
+}
+
+fn scale_color(color: Rgba<u8>, level: f32) -> Rgba<u8> {
+    let level = level.clamp(0.0, 1.0);
+    Rgba([
+        (color[0] as f32 * level) as u8,
+        (color[1] as f32 * level) as u8,
+        (color[2] as f32 * level) as u8,
+        255,
+    ])
+}
+
+// particles add their light, so that overlapping ones get brighter
+fn light_pixel(img: &mut RgbaImage, x: f32, y: f32, color: Rgba<u8>) {
+    let (x, y) = (x.round() as i32, y.round() as i32);
+    if x < 0 || y < 0 || x >= img.width() as i32 || y >= img.height() as i32 {
+        return;
+    }
+    let pixel = img.get_pixel_mut(x as u32, y as u32);
+    for c in 0..3 {
+        pixel[c] = pixel[c].saturating_add(color[c]);
+    }
+}
+
+fn new_flake(random: &mut Random, width: u32, height: u32, color: Rgba<u8>, top: bool) -> Particle {
+    Particle {
+        x: random.unit() * width as f32,
+        y: if top {
+            -1.0
+        } else {
+            random.unit() * height as f32
+        },
+        z: 0.3 + random.unit() * 0.7,
+        vx: 0.0,
+        vy: 0.0,
+        color,
+        rocket: false,
+    }
+}
+
+fn new_star(random: &mut Random, color: Rgba<u8>, far: bool) -> Particle {
+    Particle {
+        x: random.unit() * 2.0 - 1.0,
+        y: random.unit() * 2.0 - 1.0,
+        z: if far { 1.0 } else { 0.1 + random.unit() * 0.9 },
+        vx: 0.0,
+        vy: 0.0,
+        color,
+        rocket: false,
+    }
+}
+
+fn update(
+    options: &EffectOptions,
+    particles: &mut Vec<Particle>,
+    random: &mut Random,
+    img: &mut RgbaImage,
+    frame: u32,
+) {
+    let (effect, density, speed, colors) = (
+        options.effect,
+        options.density,
+        options.speed,
+        options.colors,
+    );
+    let (width, height) = img.dimensions();
+    let count = ((density as f32 * width as f32 * height as f32 / REFERENCE_AREA) as usize).max(1);
+    let white = Rgba([255, 255, 255, 255]);
+
+    match effect {
+        Effect::SNOW => {
+            while particles.len() < count {
+                let color = colors.first().copied().unwrap_or(white);
+                particles.push(new_flake(random, width, height, color, false));
+            }
+            for (i, flake) in particles.iter_mut().enumerate() {
+                // the closest flakes (higher z) are brighter and faster
+                flake.y += 0.4 * flake.z * speed;
+                flake.x += 0.3 * (frame as f32 / 15.0 + i as f32).sin() * flake.z * speed;
+                if flake.y > height as f32 {
+                    *flake = new_flake(random, width, height, flake.color, true);
+                }
+                light_pixel(img, flake.x, flake.y, scale_color(flake.color, flake.z));
+            }
+        }
+        Effect::STARFIELD => {
+            while particles.len() < count {
+                let color = colors.first().copied().unwrap_or(white);
+                particles.push(new_star(random, color, false));
+            }
+            let (cx, cy) = (width as f32 / 2.0, height as f32 / 2.0);
+            for star in particles.iter_mut() {
+                star.z -= 0.01 * speed;
+                let (sx, sy) = (cx + star.x / star.z * cx, cy + star.y / star.z * cx);
+                if star.z <= 0.01
+                    || sx < 0.0
+                    || sy < 0.0
+                    || sx >= width as f32
+                    || sy >= height as f32
+                {
+                    *star = new_star(random, star.color, true);
+                    continue;
+                }
+                light_pixel(img, sx, sy, scale_color(star.color, 1.0 - star.z));
+            }
+        }
+        Effect::FIREWORKS => {
+            // density is the number of rockets per minute
+            if random.unit() < density as f32 / (60.0 * EFFECT_FPS as f32) {
+                particles.push(Particle {
+                    x: width as f32 * (0.1 + 0.8 * random.unit()),
+                    y: height as f32,
+                    z: 0.0,
+                    vx: 0.0,
+                    // high enough to explode in the upper half
+                    vy: -(2.0 * 0.05 * height as f32 * (0.5 + 0.4 * random.unit())).sqrt(),
+                    color: random_color(random, colors),
+                    rocket: true,
+                });
+            }
+
+            let mut sparks = Vec::new();
+            for particle in particles.iter_mut() {
+                particle.x += particle.vx * speed;
+                particle.y += particle.vy * speed;
+                particle.vy += 0.05 * speed; // gravity
+                if particle.rocket {
+                    if particle.vy >= 0.0 {
+                        // apex: explode
+                        let nsparks = 20 + random.below(20);
+                        for _ in 0..nsparks {
+                            let angle = random.unit() * 2.0 * PI;
+                            let force = 0.3 + random.unit() * 0.6;
+                            sparks.push(Particle {
+                                x: particle.x,
+                                y: particle.y,
+                                z: 1.0,
+                                vx: angle.cos() * force,
+                                vy: angle.sin() * force,
+                                color: particle.color,
+                                rocket: false,
+                            });
+                        }
+                        particle.z = -1.0;
+                    } else {
+                        light_pixel(img, particle.x, particle.y, white);
+                    }
+                } else {
+                    particle.z -= 0.02 * speed;
+                    particle.vx *= 0.97;
+                    particle.vy *= 0.97;
+                    light_pixel(
+                        img,
+                        particle.x,
+                        particle.y,
+                        scale_color(particle.color, particle.z),
+                    );
+                }
+            }
+            particles.retain(|x| x.z >= 0.0 && x.y < height as f32);
+            particles.append(&mut sparks);
+        }
+    }
+}
+
+// particles over the background, forever or for a few seconds with --once
+pub fn play_effect(
+    dmd: &Dmd,
+    options: &EffectOptions,
+    background: &RgbaImage,
+    once: bool,
+) -> Result<(), String> {
+    let mut random = Random::new();
+    let mut particles = Vec::new();
+
+    // forever, or a cycle with --once
+    let count = if once { FRAMES_PER_CYCLE } else { u32::MAX };
+    let frames = (0..count).map(|frame| {
+        let mut img = background.clone();
+        update(options, &mut particles, &mut random, &mut img, frame);
+        render_frame(dmd.width, dmd.height, &img, dmd.image_options).map(|x| {
+            (
+                Cow::Owned(x.into_vec()),
+                playback::frame_duration(EFFECT_FPS),
+            )
+        })
+    });
+
+    playback::play(
+        dmd.client,
+        dmd.header,
+        frames,
+        dmd.rate,
+        dmd.control,
+        dmd.cancel,
+    )
+}
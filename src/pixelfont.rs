@@ -0,0 +1,310 @@
+// a 5x7 pixel font built in, rendered the same everywhere: the font of the golden tests, of
+// --font builtin with the builtin-font feature, and the characters of the clock styles. the truetype
+// font is made from the bitmaps, a square contour per run of lit pixels
+
+pub const NAME: &str = "builtin";
+
+// printable ascii from the space, a byte per column, the lowest bit at the top
+const GLYPHS: [[u8; 5]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], // space
+    [0x00, 0x00, 0x5f, 0x00, 0x00], // !
+    [0x00, 0x07, 0x00, 0x07, 0x00], // "
+    [0x14, 0x7f, 0x14, 0x7f, 0x14], // #
+    [0x24, 0x2a, 0x7f, 0x2a, 0x12], // $
+    [0x23, 0x13, 0x08, 0x64, 0x62], // %
+    [0x36, 0x49, 0x55, 0x22, 0x50], // &
+    [0x00, 0x05, 0x03, 0x00, 0x00], // '
+    [0x00, 0x1c, 0x22, 0x41, 0x00], // (
+    [0x00, 0x41, 0x22, 0x1c, 0x00], // )
+    [0x08, 0x2a, 0x1c, 0x2a, 0x08], // *
+    [0x08, 0x08, 0x3e, 0x08, 0x08], // +
+    [0x00, 0x50, 0x30, 0x00, 0x00], // ,
+    [0x08, 0x08, 0x08, 0x08, 0x08], // -
+    [0x00, 0x60, 0x60, 0x00, 0x00], // .
+    [0x20, 0x10, 0x08, 0x04, 0x02], // /
+    [0x3e, 0x51, 0x49, 0x45, 0x3e], // 0
+    [0x00, 0x42, 0x7f, 0x40, 0x00], // 1
+    [0x42, 0x61, 0x51, 0x49, 0x46], // 2
+    [0x21, 0x41, 0x45, 0x4b, 0x31], // 3
+    [0x18, 0x14, 0x12, 0x7f, 0x10], // 4
+    [0x27, 0x45, 0x45, 0x45, 0x39], // 5
+    [0x3c, 0x4a, 0x49, 0x49, 0x30], // 6
+    [0x01, 0x71, 0x09, 0x05, 0x03], // 7
+    [0x36, 0x49, 0x49, 0x49, 0x36], // 8
+    [0x06, 0x49, 0x49, 0x29, 0x1e], // 9
+    [0x00, 0x36, 0x36, 0x00, 0x00], // :
+    [0x00, 0x56, 0x36, 0x00, 0x00], // ;
+    [0x00, 0x08, 0x14, 0x22, 0x41], // <
+    [0x14, 0x14, 0x14, 0x14, 0x14], // =
+    [0x41, 0x22, 0x14, 0x08, 0x00], // >
+    [0x02, 0x01, 0x51, 0x09, 0x06], // ?
+    [0x32, 0x49, 0x79, 0x41, 0x3e], // @
+    [0x7e, 0x11, 0x11, 0x11, 0x7e], // A
+    [0x7f, 0x49, 0x49, 0x49, 0x36], // B
+    [0x3e, 0x41, 0x41, 0x41, 0x22], // C
+    [0x7f, 0x41, 0x41, 0x22, 0x1c], // D
+    [0x7f, 0x49, 0x49, 0x49, 0x41], // E
+    [0x7f, 0x09, 0x09, 0x01, 0x01], // F
+    [0x3e, 0x41, 0x41, 0x51, 0x32], // G
+    [0x7f, 0x08, 0x08, 0x08, 0x7f], // H
+    [0x00, 0x41, 0x7f, 0x41, 0x00], // I
+    [0x20, 0x40, 0x41, 0x3f, 0x01], // J
+    [0x7f, 0x08, 0x14, 0x22, 0x41], // K
+    [0x7f, 0x40, 0x40, 0x40, 0x40], // L
+    [0x7f, 0x02, 0x04, 0x02, 0x7f], // M
+    [0x7f, 0x04, 0x08, 0x10, 0x7f], // N
+    [0x3e, 0x41, 0x41, 0x41, 0x3e], // O
+    [0x7f, 0x09, 0x09, 0x09, 0x06], // P
+    [0x3e, 0x41, 0x51, 0x21, 0x5e], // Q
+    [0x7f, 0x09, 0x19, 0x29, 0x46], // R
+    [0x46, 0x49, 0x49, 0x49, 0x31], // S
+    [0x01, 0x01, 0x7f, 0x01, 0x01], // T
+    [0x3f, 0x40, 0x40, 0x40, 0x3f], // U
+    [0x1f, 0x20, 0x40, 0x20, 0x1f], // V
+    [0x7f, 0x20, 0x18, 0x20, 0x7f], // W
+    [0x63, 0x14, 0x08, 0x14, 0x63], // X
+    [0x03, 0x04, 0x78, 0x04, 0x03], // Y
+    [0x61, 0x51, 0x49, 0x45, 0x43], // Z
+    [0x00, 0x00, 0x7f, 0x41, 0x41], // [
+    [0x02, 0x04, 0x08, 0x10, 0x20], // backslash
+    [0x41, 0x41, 0x7f, 0x00, 0x00], // ]
+    [0x04, 0x02, 0x01, 0x02, 0x04], // ^
+    [0x40, 0x40, 0x40, 0x40, 0x40], // _
+    [0x00, 0x01, 0x02, 0x04, 0x00], // `
+    [0x20, 0x54, 0x54, 0x54, 0x78], // a
+    [0x7f, 0x48, 0x44, 0x44, 0x38], // b
+    [0x38, 0x44, 0x44, 0x44, 0x20], // c
+    [0x38, 0x44, 0x44, 0x48, 0x7f], // d
+    [0x38, 0x54, 0x54, 0x54, 0x18], // e
+    [0x08, 0x7e, 0x09, 0x01, 0x02], // f
+    [0x08, 0x14, 0x54, 0x54, 0x3c], // g
+    [0x7f, 0x08, 0x04, 0x04, 0x78], // h
+    [0x00, 0x44, 0x7d, 0x40, 0x00], // i
+    [0x20, 0x40, 0x44, 0x3d, 0x00], // j
+    [0x00, 0x7f, 0x10, 0x28, 0x44], // k
+    [0x00, 0x41, 0x7f, 0x40, 0x00], // l
+    [0x7c, 0x04, 0x18, 0x04, 0x78], // m
+    [0x7c, 0x08, 0x04, 0x04, 0x78], // n
+    [0x38, 0x44, 0x44, 0x44, 0x38], // o
+    [0x7c, 0x14, 0x14, 0x14, 0x08], // p
+    [0x08, 0x14, 0x14, 0x18, 0x7c], // q
+    [0x7c, 0x08, 0x04, 0x04, 0x08], // r
+    [0x48, 0x54, 0x54, 0x54, 0x20], // s
+    [0x04, 0x3f, 0x44, 0x40, 0x20], // t
+    [0x3c, 0x40, 0x40, 0x20, 0x7c], // u
+    [0x1c, 0x20, 0x40, 0x20, 0x1c], // v
+    [0x3c, 0x40, 0x30, 0x40, 0x3c], // w
+    [0x44, 0x28, 0x10, 0x28, 0x44], // x
+    [0x0c, 0x50, 0x50, 0x50, 0x3c], // y
+    [0x44, 0x64, 0x54, 0x4c, 0x44], // z
+    [0x00, 0x08, 0x36, 0x41, 0x00], // {
+    [0x00, 0x00, 0x7f, 0x00, 0x00], // |
+    [0x00, 0x41, 0x36, 0x08, 0x00], // }
+    [0x02, 0x01, 0x02, 0x04, 0x02], // ~
+];
+
+const FIRST_CHAR: u32 = 0x20;
+const ROWS: i16 = 7;
+const COLUMNS: i16 = 5;
+const PIXEL: i16 = 128; // font units
+const UNITS_PER_EM: u16 = 1024;
+const ADVANCE: u16 = 6 * PIXEL as u16; // a column between the letters
+const DESCENT: i16 = -PIXEL;
+
+// the columns of a printable ascii character
+pub fn columns(c: char) -> Option<&'static [u8; 5]> {
+    GLYPHS.get((c as u32).checked_sub(FIRST_CHAR)? as usize)
+}
+
+fn push16(data: &mut Vec<u8>, value: u16) {
+    data.extend_from_slice(&value.to_be_bytes());
+}
+
+fn push32(data: &mut Vec<u8>, value: u32) {
+    data.extend_from_slice(&value.to_be_bytes());
+}
+
+// x, y (of the bottom left corner, y up) and width of each run of lit pixels of a row, in font units
+fn runs(columns: &[u8; 5]) -> Vec<(i16, i16, i16)> {
+    let mut runs = Vec::new();
+    for row in 0..ROWS {
+        let y = (ROWS - 1 - row) * PIXEL;
+        let mut start = None;
+        for column in 0..=COLUMNS {
+            let lit = column < COLUMNS && columns[column as usize] & (1 << row) != 0;
+            match (lit, start) {
+                (true, None) => start = Some(column),
+                (false, Some(x)) => {
+                    runs.push((x * PIXEL, y, (column - x) * PIXEL));
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+    }
+    runs
+}
+
+// a simple glyph, a clockwise square per run. returns it with its left side bearing
+fn glyph(columns: &[u8; 5]) -> (Vec<u8>, i16) {
+    let runs = runs(columns);
+    let mut data = Vec::new();
+    if runs.is_empty() {
+        return (data, 0);
+    }
+
+    let x_min = runs.iter().map(|x| x.0).min().unwrap_or(0);
+    let y_min = runs.iter().map(|x| x.1).min().unwrap_or(0);
+    let x_max = runs.iter().map(|x| x.0 + x.2).max().unwrap_or(0);
+    let y_max = runs.iter().map(|x| x.1 + PIXEL).max().unwrap_or(0);
+    for value in [runs.len() as i16, x_min, y_min, x_max, y_max] {
+        push16(&mut data, value as u16);
+    }
+    for n in 0..runs.len() {
+        push16(&mut data, (n * 4 + 3) as u16); // end points of the contours
+    }
+    push16(&mut data, 0); // no instructions
+
+    let points: Vec<(i16, i16)> = runs
+        .iter()
+        .flat_map(|&(x, y, width)| {
+            [
+                (x, y),
+                (x, y + PIXEL),
+                (x + width, y + PIXEL),
+                (x + width, y),
+            ]
+        })
+        .collect();
+    data.extend(points.iter().map(|_| 0x01)); // on curve, coordinates as 16 bits deltas
+    let mut previous = 0;
+    for (x, _) in &points {
+        push16(&mut data, (x - previous) as u16);
+        previous = *x;
+    }
+    previous = 0;
+    for (_, y) in &points {
+        push16(&mut data, (y - previous) as u16);
+        previous = *y;
+    }
+    (data, x_min)
+}
+
+fn checksum(table: &[u8]) -> u32 {
+    table.chunks(4).fold(0u32, |sum, x| {
+        let mut word = [0u8; 4];
+        word[..x.len()].copy_from_slice(x);
+        sum.wrapping_add(u32::from_be_bytes(word))
+    })
+}
+
+// the truetype font, glyph 0 is the missing glyph (empty) and the others the ascii characters
+pub fn ttf() -> Vec<u8> {
+    let nglyphs = GLYPHS.len() as u16 + 1;
+
+    let mut glyf = Vec::new();
+    let mut loca = Vec::new();
+    let mut hmtx = Vec::new();
+    // the missing glyph is empty
+    push32(&mut loca, 0);
+    push32(&mut loca, 0);
+    push16(&mut hmtx, ADVANCE);
+    push16(&mut hmtx, 0);
+    for columns in &GLYPHS {
+        let (data, left_side_bearing) = glyph(columns);
+        glyf.extend(data);
+        push32(&mut loca, glyf.len() as u32);
+        push16(&mut hmtx, ADVANCE);
+        push16(&mut hmtx, left_side_bearing as u16);
+    }
+
+    let mut head = Vec::new();
+    push32(&mut head, 0x00010000); // version
+    push32(&mut head, 0x00010000); // revision
+    push32(&mut head, 0); // checksum adjustment, not needed to read the font
+    push32(&mut head, 0x5f0f3cf5); // magic number
+    push16(&mut head, 0x0003); // baseline at 0, left side bearing at 0
+    push16(&mut head, UNITS_PER_EM);
+    head.extend([0u8; 16]); // created and modified dates
+    for value in [0, 0, COLUMNS * PIXEL, ROWS * PIXEL] {
+        push16(&mut head, value as u16); // bounding box
+    }
+    push16(&mut head, 0); // style
+    push16(&mut head, ROWS as u16); // smallest readable size
+    push16(&mut head, 2); // direction hint
+    push16(&mut head, 1); // long offsets in loca
+    push16(&mut head, 0); // glyph data format
+
+    let mut hhea = Vec::new();
+    push32(&mut hhea, 0x00010000);
+    push16(&mut hhea, (ROWS * PIXEL) as u16); // ascender
+    push16(&mut hhea, DESCENT as u16);
+    push16(&mut hhea, 0); // line gap
+    push16(&mut hhea, ADVANCE);
+    push16(&mut hhea, 0); // min left side bearing
+    push16(&mut hhea, 0); // min right side bearing
+    push16(&mut hhea, (COLUMNS * PIXEL) as u16); // max extent
+    push16(&mut hhea, 1); // caret slope rise
+    hhea.extend([0u8; 14]); // caret slope run and offset, reserved, metric data format
+    push16(&mut hhea, nglyphs);
+
+    let mut maxp = Vec::new();
+    push32(&mut maxp, 0x00010000);
+    push16(&mut maxp, nglyphs);
+    let max_contours = GLYPHS.iter().map(|x| runs(x).len()).max().unwrap_or(0) as u16;
+    push16(&mut maxp, max_contours * 4); // points
+    push16(&mut maxp, max_contours);
+    maxp.extend([0u8; 4]); // composite glyphs
+    push16(&mut maxp, 1); // zones
+    maxp.extend([0u8; 16]); // instructions and components
+
+    // format 12, unicode full repertoire: a single range of characters
+    let mut cmap = Vec::new();
+    push16(&mut cmap, 0); // version
+    push16(&mut cmap, 1); // one encoding
+    push16(&mut cmap, 3); // windows
+    push16(&mut cmap, 10); // unicode full repertoire
+    push32(&mut cmap, 12); // offset of the subtable
+    push16(&mut cmap, 12); // format
+    push16(&mut cmap, 0);
+    push32(&mut cmap, 28); // length
+    push32(&mut cmap, 0); // language
+    push32(&mut cmap, 1); // groups
+    push32(&mut cmap, FIRST_CHAR);
+    push32(&mut cmap, FIRST_CHAR + GLYPHS.len() as u32 - 1);
+    push32(&mut cmap, 1); // first glyph
+
+    // sorted by tag
+    let tables: [(&[u8; 4], Vec<u8>); 7] = [
+        (b"cmap", cmap),
+        (b"glyf", glyf),
+        (b"head", head),
+        (b"hhea", hhea),
+        (b"hmtx", hmtx),
+        (b"loca", loca),
+        (b"maxp", maxp),
+    ];
+
+    let mut font = Vec::new();
+    let ntables = tables.len() as u16;
+    let search_range = 16 * (1 << (15 - ntables.leading_zeros()));
+    push32(&mut font, 0x00010000);
+    push16(&mut font, ntables);
+    push16(&mut font, search_range);
+    push16(&mut font, 15 - ntables.leading_zeros() as u16); // entry selector
+    push16(&mut font, ntables * 16 - search_range);
+
+    let mut offset = 12 + 16 * tables.len();
+    for (tag, table) in &tables {
+        font.extend_from_slice(*tag);
+        push32(&mut font, checksum(table));
+        push32(&mut font, offset as u32);
+        push32(&mut font, table.len() as u32);
+        offset += table.len().next_multiple_of(4);
+    }
+    for (_, table) in &tables {
+        font.extend(table);
+        font.resize(font.len().next_multiple_of(4), 0);
+    }
+    font
+}
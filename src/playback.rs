@@ -0,0 +1,186 @@
+use crate::{cancel::CancelToken, control::PlayControl, sink::FrameSink, DMD_HEADER_SIZE};
+use std::{
+    borrow::Cow,
+    time::{Duration, Instant},
+};
+
+// frames skipped in a row at most, while late
+const MAX_SKIPPED: u32 = 4;
+
+// a rgb565 frame and how long it is displayed, in ms at the normal rate
+pub type TimedFrame<'a> = (Cow<'a, [u8]>, u32);
+
+// the frames of a content mode, one after the other, until the mode is done
+pub trait FrameSource<'a>: Iterator<Item = Result<TimedFrame<'a>, String>> {}
+
+impl<'a, I: Iterator<Item = Result<TimedFrame<'a>, String>>> FrameSource<'a> for I {}
+
+// the duration of the frames of a mode played at fps frames per second
+pub fn frame_duration(fps: u32) -> u32 {
+    1000 / fps.max(1)
+}
+
+// send each frame of the source and wait for its duration, until the source is done or the playing is cancelled.
+// a paused playing waits before the next frame
+pub fn play<'a>(
+    client: &dyn FrameSink,
+    header: [u8; DMD_HEADER_SIZE],
+    frames: impl FrameSource<'a>,
+    rate: f32,
+    control: &PlayControl,
+    cancel: &CancelToken,
+) -> Result<(), String> {
+    for frame in frames {
+        let (img565, duration) = frame?;
+        control.wait(cancel);
+        if let Err(e) = client.send(header, &img565) {
+            return Err(e.to_string());
+        }
+        if cancel.sleep(Duration::from_millis((duration as f32 / rate) as u64)) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// like play, on the clock of the animation: each frame is sent at its time whatever the sending took,
+// and while the sink is late, the frames whose time is already over are skipped (not the last one).
+// too late to catch up, the clock starts again from the frame sent. the clock stops while paused
+pub fn play_in_time<'a>(
+    client: &dyn FrameSink,
+    header: [u8; DMD_HEADER_SIZE],
+    frames: impl FrameSource<'a>,
+    rate: f32,
+    control: &PlayControl,
+    cancel: &CancelToken,
+) -> Result<(), String> {
+    let mut started = Instant::now();
+    let mut end = Duration::ZERO;
+    let mut skipped = 0;
+    let mut frames = frames.peekable();
+    while let Some(frame) = frames.next() {
+        let (img565, duration) = frame?;
+        started += control.wait(cancel);
+        end += Duration::from_secs_f32(duration as f32 / 1000.0 / rate);
+        let late = duration > 0 && started.elapsed() >= end;
+        if late && skipped < MAX_SKIPPED && frames.peek().is_some() {
+            skipped += 1;
+            continue;
+        }
+        if late && skipped == MAX_SKIPPED {
+            started = Instant::now().checked_sub(end).unwrap_or(started);
+        }
+        skipped = 0;
+        if let Err(e) = client.send(header, &img565) {
+            return Err(e.to_string());
+        }
+        if cancel.sleep(end.saturating_sub(started.elapsed())) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::CaptureSink;
+    use std::{io, thread};
+
+    // a sink taking this time per frame
+    struct SlowSink(CaptureSink, Duration);
+
+    impl FrameSink for SlowSink {
+        fn send(&self, header: [u8; DMD_HEADER_SIZE], frame: &[u8]) -> io::Result<()> {
+            thread::sleep(self.1);
+            self.0.send(header, frame)
+        }
+    }
+
+    // the numbers of the frames sent, of duration ms each
+    fn sent_frames(sink_time: Duration, count: u8, duration: u32) -> Vec<u8> {
+        let sink = SlowSink(CaptureSink::default(), sink_time);
+        let frames = (0..count).map(|n| Ok((Cow::Owned(vec![n]), duration)));
+        play_in_time(
+            &sink,
+            [0; DMD_HEADER_SIZE],
+            frames,
+            1.0,
+            &PlayControl::default(),
+            &CancelToken::default(),
+        )
+        .unwrap();
+        sink.0.frames().iter().map(|(_, x)| x[0]).collect()
+    }
+
+    #[test]
+    fn skipped_frames() {
+        // on time, every frame is sent
+        assert_eq!(
+            sent_frames(Duration::ZERO, 4, 50),
+            (0..4).collect::<Vec<_>>()
+        );
+
+        // 25 ms per frame: the animation keeps its 80 ms with 4 frames at most, the last one is shown
+        let sent = sent_frames(Duration::from_millis(25), 8, 10);
+        assert!(sent.len() <= 4);
+        assert_eq!(sent.first(), Some(&0));
+        assert_eq!(sent.last(), Some(&7));
+
+        // far too late, no more than MAX_SKIPPED frames are skipped in a row
+        let sent = sent_frames(Duration::from_millis(60), 16, 10);
+        assert!(sent.len() < 16);
+        assert_eq!(sent.last(), Some(&15));
+        assert!(sent
+            .windows(2)
+            .all(|x| x[1] - x[0] <= MAX_SKIPPED as u8 + 1));
+    }
+
+    // cancelled, an endless source stops after the frame being played
+    #[test]
+    fn cancelled() {
+        let sink = CaptureSink::default();
+        let cancel = CancelToken::default();
+        let canceller = cancel.clone();
+        let frames = (0u8..).map(|n| {
+            if n == 2 {
+                canceller.cancel();
+            }
+            Ok((Cow::Owned(vec![n]), 10))
+        });
+        play(
+            &sink,
+            [0; DMD_HEADER_SIZE],
+            frames,
+            1.0,
+            &PlayControl::default(),
+            &cancel,
+        )
+        .unwrap();
+        assert_eq!(sink.frames().len(), 3);
+    }
+
+    // paused, a frame is shown at each next-frame, and the clock waits for the rest to resume
+    #[test]
+    fn paused() {
+        let sink = CaptureSink::default();
+        let control = PlayControl::default();
+        control.command("pause").unwrap();
+        assert!(control.command("stop").is_err());
+        thread::scope(|scope| {
+            let player = scope.spawn(|| {
+                let frames = (0u8..4).map(|n| Ok((Cow::Owned(vec![n]), 10)));
+                let cancel = CancelToken::default();
+                play_in_time(&sink, [0; DMD_HEADER_SIZE], frames, 1.0, &control, &cancel)
+            });
+            thread::sleep(Duration::from_millis(100));
+            assert!(sink.frames().is_empty());
+            control.command("next-frame").unwrap();
+            thread::sleep(Duration::from_millis(100));
+            assert_eq!(sink.frames().len(), 1);
+            control.command("resume").unwrap();
+            player.join().unwrap().unwrap();
+        });
+        assert_eq!(sink.frames().len(), 4);
+    }
+}
@@ -0,0 +1,138 @@
+use crate::template;
+use serde_json::Value;
+use std::{collections::HashMap, time::Duration};
+
+pub fn fetch_json(url: &str) -> Result<Value, String> {
+    let body = match ureq::get(url).timeout(Duration::from_secs(10)).call() {
+        Ok(response) => match response.into_string() {
+            Ok(x) => x,
+            Err(e) => return Err(format!("Error: {}: {}", url, e)),
+        },
+        Err(e) => return Err(e.to_string()),
+    };
+    serde_json::from_str(&body).map_err(|e| format!("Error: {}: {}", url, e))
+}
+
+// the value at a path like jq: .games[0].home.score (the first dot is optional)
+pub fn select<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    let mut value = value;
+    for part in path.trim().trim_start_matches('.').split('.') {
+        let (key, mut indexes) = match part.find('[') {
+            Some(x) => (&part[..x], &part[x..]),
+            None => (part, ""),
+        };
+        if !key.is_empty() {
+            value = value.get(key)?;
+        }
+        while let Some(rest) = indexes.strip_prefix('[') {
+            let (index, next) = rest.split_once(']')?;
+            value = value.get(index.parse::<usize>().ok()?)?;
+            indexes = next;
+        }
+    }
+    Some(value)
+}
+
+// a string without its quotes, null as nothing, the objects and arrays as json
+pub fn value_text(value: &Value) -> String {
+    match value {
+        Value::String(x) => x.clone(),
+        Value::Null => String::new(),
+        x => x.to_string(),
+    }
+}
+
+// replace {json:PATH} by the value at the path, a missing one by nothing
+pub fn render_template(template: &str, value: &Value) -> String {
+    let mut result = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{json:") {
+        let end = match rest[start..].find('}') {
+            Some(x) => start + x,
+            None => break,
+        };
+        result.push_str(&rest[..start]);
+        let path = &rest[start + "{json:".len()..end];
+        result.push_str(&select(value, path).map(value_text).unwrap_or_default());
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    result
+}
+
+// a progress status, {"label": "Downloading", "percent": 42} or a number alone: the percent (0-100)
+// and the template with {name} replaced by the values of the object, {label} empty without one
+pub fn progress_status(content: &str, progress_template: &str) -> Result<(f32, String), String> {
+    let value: Value = match serde_json::from_str(content) {
+        Ok(x) => x,
+        Err(e) => return Err(format!("Invalid progress status: {}", e)),
+    };
+    let (percent, vars) = match &value {
+        Value::Object(object) => (
+            object.get("percent").and_then(|x| x.as_f64()),
+            object
+                .iter()
+                .map(|(name, x)| (name.clone(), value_text(x)))
+                .collect::<HashMap<String, String>>(),
+        ),
+        x => (x.as_f64(), HashMap::new()),
+    };
+    match percent {
+        Some(percent) => {
+            let mut vars = vars;
+            vars.insert(String::from("percent"), format!("{:.0}", percent));
+            vars.entry(String::from("label")).or_default();
+            Ok((
+                (percent as f32).clamp(0.0, 100.0),
+                template::substitute_vars(progress_template, &vars),
+            ))
+        }
+        None => Err(String::from("Invalid progress status: no percent")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths() {
+        let value: Value = serde_json::from_str(
+            r#"{"games": [{"home": {"name": "Lyon", "score": 2}, "live": true}], "note": null}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            render_template(
+                "{json:.games[0].home.name} {json:games[0].home.score}",
+                &value
+            ),
+            "Lyon 2"
+        );
+        assert_eq!(render_template("{json:.games[0].live}", &value), "true");
+        assert_eq!(
+            render_template("[{json:.note}{json:.missing[3]}]", &value),
+            "[]"
+        );
+        assert_eq!(
+            render_template("{json:.games[0].home}", &value),
+            r#"{"name":"Lyon","score":2}"#
+        );
+    }
+
+    #[test]
+    fn progress() {
+        assert_eq!(
+            progress_status(
+                r#"{"label": "Downloading", "percent": 42.4}"#,
+                "{label} {percent}%"
+            ),
+            Ok((42.4, String::from("Downloading 42%")))
+        );
+        assert_eq!(
+            progress_status("130\n", "{label}"),
+            Ok((100.0, String::new()))
+        );
+        assert!(progress_status(r#"{"label": "Downloading"}"#, "{label}").is_err());
+        assert!(progress_status("", "{label}").is_err());
+    }
+}
@@ -0,0 +1,582 @@
+use crate::{
+    cancel::CancelToken,
+    dimming::DimController,
+    imageutils,
+    protocol::{DmdStreamHeader, MODE_RGB565},
+    schedule::ActiveHours,
+    sink::{self, FrameSink, SentFrame, Ticked},
+    DMD_HEADER_SIZE,
+};
+use chrono::Local;
+use image::{ImageFormat, RgbaImage};
+use std::{
+    borrow::Cow,
+    fs, io, process,
+    sync::{mpsc::Receiver, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+// longest delay before an overlay is shown
+pub const OVERLAY_POLL: Duration = Duration::from_millis(100);
+// longest delay before the dmd is blanked, dimmed, shifted or restored (active hours, auto dim, anti burn)
+pub const SCHEDULE_POLL: Duration = Duration::from_secs(1);
+// longest delay after the keepalive delay before the last frame is sent again
+pub const KEEPALIVE_POLL: Duration = Duration::from_secs(1);
+// --anti-burn: the content moves by a pixel around its place at each period,
+// and a black frame is shown a moment regularly
+const SHIFT_PERIOD: Duration = Duration::from_secs(60);
+const SHIFTS: [(i32, i32); 5] = [(0, 0), (1, 0), (0, 1), (-1, 0), (0, -1)];
+const BLACK_PERIOD: Duration = Duration::from_secs(30 * 60);
+const BLACK_DURATION: Duration = Duration::from_secs(1);
+
+// a frame to show over what is played: its header, its rgb565 content and how long it is shown
+pub type OverlayFrame = ([u8; DMD_HEADER_SIZE], Box<[u8]>, Duration);
+
+// what is done with the frames on their way to the dmd
+pub struct PreviewOptions {
+    pub print: bool,                // in the terminal
+    pub snapshot: Option<String>,   // png file of the last frame
+    pub status: Option<String>,     // json file of the status
+    pub mode: String,               // what is played, for the status
+    pub text: Option<String>,       // for the status
+    pub connected: bool,            // to a dmd, for the status
+    pub stats: Option<u32>,         // seconds between two reports of the statistics
+    pub stats_json: Option<String>, // json file of the statistics, printed in the terminal otherwise
+}
+
+// how the frames are adjusted to the time of the day and to the wear of the panel
+pub struct AdjustOptions {
+    pub active_hours: Option<ActiveHours>, // the frames are dimmed outside of them
+    pub inactive_brightness: u8,           // in %, outside of the active hours
+    pub auto_dim: Option<DimController>,   // brightness during the active hours
+    pub anti_burn: bool,                   // shifted frames, and black ones at times
+}
+
+impl AdjustOptions {
+    // in % of the brightness of the frames
+    fn brightness(&self) -> u8 {
+        match &self.active_hours {
+            Some(x) if !x.is_active(&Local::now()) => self.inactive_brightness,
+            _ => self.auto_dim.as_ref().map_or(100, |x| x.level()),
+        }
+    }
+
+    // the offset of the frames, started is the start of the anti burn
+    fn shift(&self, started: Instant) -> (i32, i32) {
+        match self.anti_burn {
+            true => {
+                let period = started.elapsed().as_secs() / SHIFT_PERIOD.as_secs();
+                SHIFTS[period as usize % SHIFTS.len()]
+            }
+            false => (0, 0),
+        }
+    }
+}
+
+// what was sent to the server since the last report
+struct SendStats {
+    start: Instant,
+    frames: u64,
+    bytes: u64,
+    late: u64,             // sent after the next one came, skipped by the eye
+    latency: Duration,     // total time to send the frames
+    max_latency: Duration, // of a single frame
+}
+
+impl SendStats {
+    fn new() -> SendStats {
+        SendStats {
+            start: Instant::now(),
+            frames: 0,
+            bytes: 0,
+            late: 0,
+            latency: Duration::ZERO,
+            max_latency: Duration::ZERO,
+        }
+    }
+
+    // a frame of bytes sent in latency, interval after the previous one
+    fn add(&mut self, bytes: usize, latency: Duration, interval: Option<Duration>) {
+        // the time to render the first frame is not counted
+        if self.frames == 0 && interval.is_none() {
+            self.start = Instant::now();
+        }
+        self.frames += 1;
+        self.bytes += bytes as u64;
+        self.latency += latency;
+        self.max_latency = self.max_latency.max(latency);
+        if interval.is_some_and(|x| latency > x) {
+            self.late += 1;
+        }
+    }
+}
+
+// print the statistics, or save them as a json file
+fn report_stats(stats: &SendStats, json_path: &Option<String>) -> Result<(), String> {
+    let seconds = stats.start.elapsed().as_secs_f64();
+    let fps = stats.frames as f64 / seconds;
+    let bytes_per_second = stats.bytes as f64 / seconds;
+    let latency = match stats.frames {
+        0 => 0.0,
+        n => stats.latency.as_secs_f64() * 1000.0 / n as f64,
+    };
+    let max_latency = stats.max_latency.as_secs_f64() * 1000.0;
+
+    let path = match json_path {
+        Some(x) => x,
+        None => {
+            eprintln!(
+                "stats: {:.1} fps, {:.1} KB/s, {} late frames, send latency {:.2} ms (max {:.2} ms)",
+                fps,
+                bytes_per_second / 1000.0,
+                stats.late,
+                latency,
+                max_latency
+            );
+            return Ok(());
+        }
+    };
+    let json = serde_json::json!({
+        "fps": (fps * 10.0).round() / 10.0,
+        "bytes_per_second": bytes_per_second.round(),
+        "frames": stats.frames,
+        "late_frames": stats.late,
+        "latency_ms": (latency * 100.0).round() / 100.0,
+        "max_latency_ms": (max_latency * 100.0).round() / 100.0,
+    });
+    let tmp_path = format!("{}.tmp", path);
+    if let Err(e) = fs::write(&tmp_path, format!("{}\n", json)) {
+        return Err(format!("Error: {}: {}", tmp_path, e));
+    }
+    match fs::rename(&tmp_path, path) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("Error: {}: {}", path, e)),
+    }
+}
+
+// two pixels per character: the upper half block in the color of the top pixel,
+// on the color of the bottom pixel
+pub fn print_image(img: &RgbaImage) {
+    let mut output = String::new();
+    for y in (0..img.height()).step_by(2) {
+        for x in 0..img.width() {
+            let top = img.get_pixel(x, y);
+            let bottom = match y + 1 < img.height() {
+                true => *img.get_pixel(x, y + 1),
+                false => image::Rgba([0, 0, 0, 255]),
+            };
+            output.push_str(&format!(
+                "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m\u{2580}",
+                top[0], top[1], top[2], bottom[0], bottom[1], bottom[2]
+            ));
+        }
+        output.push_str("\x1b[0m\n");
+    }
+    print!("{}", output);
+}
+
+// save the frame as a png file, replaced at once for the readers
+fn save_snapshot(path: &str, img: &RgbaImage) -> Result<(), String> {
+    let tmp_path = format!("{}.tmp", path);
+    if let Err(e) = img.save_with_format(&tmp_path, ImageFormat::Png) {
+        return Err(format!("Error: {}: {}", tmp_path, e));
+    }
+    match fs::rename(&tmp_path, path) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("Error: {}: {}", path, e)),
+    }
+}
+
+// machine readable state of dmd-play, for the scripts checking what is displayed
+fn write_status(
+    path: &str,
+    options: &PreviewOptions,
+    connected: bool,
+    playing: bool,
+    size: (u32, u32),
+    frames: u64,
+    fps: f64,
+) -> Result<(), String> {
+    let status = serde_json::json!({
+        "pid": process::id(),
+        "connected": connected,
+        "playing": playing,
+        "width": size.0,
+        "height": size.1,
+        "mode": options.mode,
+        "text": options.text,
+        "frames": frames,
+        "fps": (fps * 10.0).round() / 10.0,
+    });
+    let tmp_path = format!("{}.tmp", path);
+    if let Err(e) = fs::write(&tmp_path, format!("{}\n", status)) {
+        return Err(format!("Error: {}: {}", tmp_path, e));
+    }
+    match fs::rename(&tmp_path, path) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("Error: {}: {}", path, e)),
+    }
+}
+
+// the rgb565 frames are dimmed outside of the active hours or by the auto dim, and shifted by the anti burn
+fn adjusted_payload<'a>(
+    frame_header: &DmdStreamHeader,
+    payload: &'a [u8],
+    (brightness, (dx, dy)): (u8, (i32, i32)),
+) -> Cow<'a, [u8]> {
+    let (width, height) = (frame_header.width as u32, frame_header.height as u32);
+    if (brightness >= 100 && (dx, dy) == (0, 0))
+        || frame_header.mode != MODE_RGB565
+        || payload.len() != imageutils::get_dmd_buffer_size(width, height) as usize
+    {
+        return Cow::Borrowed(payload);
+    }
+    let mut payload = imageutils::shift_dmdimage(payload, width, height, dx, dy);
+    imageutils::dim_dmdimage(&mut payload, brightness);
+    Cow::Owned(payload)
+}
+
+// the preview of the frames sent: printed in the terminal, the last one saved as a png file,
+// the status saved as a json file and the statistics of the sending reported
+pub struct PreviewSink {
+    inner: Box<dyn FrameSink>,
+    options: PreviewOptions,
+    state: Mutex<PreviewState>,
+}
+
+struct PreviewState {
+    n: u64,
+    // the snapshot and the status are saved once per second at most, and when the sink is closed
+    last_image: Option<RgbaImage>,
+    last_save: Option<Instant>,
+    size: (u32, u32),
+    // frames per second, measured each second
+    fps: f64,
+    fps_frames: u64,
+    fps_start: Instant,
+    // statistics of the frames sent, reported at each period
+    stats: SendStats,
+    last_received: Option<Instant>,
+}
+
+impl PreviewSink {
+    pub fn new(inner: Box<dyn FrameSink>, options: PreviewOptions) -> PreviewSink {
+        PreviewSink {
+            inner,
+            options,
+            state: Mutex::new(PreviewState {
+                n: 0,
+                last_image: None,
+                last_save: None,
+                size: (0, 0),
+                fps: 0.0,
+                fps_frames: 0,
+                fps_start: Instant::now(),
+                stats: SendStats::new(),
+                last_received: None,
+            }),
+        }
+    }
+}
+
+impl FrameSink for PreviewSink {
+    fn send(&self, header: [u8; DMD_HEADER_SIZE], frame: &[u8]) -> io::Result<()> {
+        let options = &self.options;
+        let mut state = sink::lock(&self.state)?;
+        let frame_header = sink::parse_header(&header)?;
+        let (mode, width, height) = (
+            frame_header.mode,
+            frame_header.width as u32,
+            frame_header.height as u32,
+        );
+
+        let received = Instant::now();
+        if let Err(e) = self.inner.send(header, frame) {
+            if let Some(path) = &options.status {
+                write_status(path, options, false, false, state.size, state.n, 0.0)
+                    .map_err(io::Error::other)?;
+            }
+            return Err(e);
+        }
+
+        if let Some(period) = options.stats {
+            let interval = state.last_received.map(|x| received - x);
+            state
+                .stats
+                .add(DMD_HEADER_SIZE + frame.len(), received.elapsed(), interval);
+            if state.stats.start.elapsed() >= Duration::from_secs(period as u64) {
+                report_stats(&state.stats, &options.stats_json).map_err(io::Error::other)?;
+                state.stats = SendStats::new();
+            }
+        }
+        state.last_received = Some(received);
+
+        state.n += 1;
+        state.size = (width, height);
+        state.fps_frames += 1;
+        if state.fps_start.elapsed() >= Duration::from_secs(1) {
+            state.fps = state.fps_frames as f64 / state.fps_start.elapsed().as_secs_f64();
+            (state.fps_frames, state.fps_start) = (0, Instant::now());
+        }
+        let img = match mode == MODE_RGB565
+            && frame.len() == imageutils::get_dmd_buffer_size(width, height) as usize
+        {
+            true => Some(imageutils::dmdimage2image(frame, width, height)),
+            false => None,
+        };
+        if options.print {
+            println!("frame {} ({}x{})", state.n, width, height);
+            match &img {
+                Some(img) => print_image(img),
+                None => println!("mode {} not previewed", mode),
+            }
+        }
+        if state
+            .last_save
+            .is_none_or(|x| x.elapsed() >= Duration::from_secs(1))
+        {
+            if let (Some(path), Some(img)) = (&options.snapshot, &img) {
+                save_snapshot(path, img).map_err(io::Error::other)?;
+            }
+            if let Some(path) = &options.status {
+                write_status(
+                    path,
+                    options,
+                    options.connected,
+                    true,
+                    state.size,
+                    state.n,
+                    state.fps,
+                )
+                .map_err(io::Error::other)?;
+            }
+            state.last_save = Some(Instant::now());
+            state.last_image = None;
+        } else {
+            state.last_image = img;
+        }
+        Ok(())
+    }
+
+    fn close(&self) -> io::Result<()> {
+        let closed = self.inner.close();
+        let options = &self.options;
+        let mut state = sink::lock(&self.state)?;
+        if let (Some(path), Some(img)) = (&options.snapshot, state.last_image.take()) {
+            save_snapshot(path, &img).map_err(io::Error::other)?;
+        }
+        if let Some(path) = &options.status {
+            write_status(
+                path,
+                options,
+                options.connected,
+                false,
+                state.size,
+                state.n,
+                state.fps,
+            )
+            .map_err(io::Error::other)?;
+        }
+        if options.stats.is_some() && state.stats.frames > 0 {
+            report_stats(&state.stats, &options.stats_json).map_err(io::Error::other)?;
+        }
+        closed
+    }
+}
+
+// --keepalive: the last frame is sent again when nothing was sent during the delay,
+// so that an idle connection is not dropped
+pub struct KeepaliveSink {
+    inner: Box<dyn FrameSink>,
+    delay: Duration,
+    last: Mutex<(Option<SentFrame>, Instant)>, // the last frame and when it was sent
+}
+
+impl KeepaliveSink {
+    pub fn new(inner: Box<dyn FrameSink>, delay: Duration) -> KeepaliveSink {
+        KeepaliveSink {
+            inner,
+            delay,
+            last: Mutex::new((None, Instant::now())),
+        }
+    }
+}
+
+impl FrameSink for KeepaliveSink {
+    fn send(&self, header: [u8; DMD_HEADER_SIZE], frame: &[u8]) -> io::Result<()> {
+        let mut last = sink::lock(&self.last)?;
+        self.inner.send(header, frame)?;
+        *last = (Some((header, frame.into())), Instant::now());
+        Ok(())
+    }
+
+    fn close(&self) -> io::Result<()> {
+        let _last = sink::lock(&self.last)?;
+        self.inner.close()
+    }
+}
+
+impl Ticked for KeepaliveSink {
+    fn tick(&self) -> io::Result<()> {
+        let mut last = sink::lock(&self.last)?;
+        if let (Some((header, frame)), sent) = &mut *last
+            && sent.elapsed() >= self.delay
+        {
+            self.inner.send(*header, frame)?;
+            *sent = Instant::now();
+        }
+        Ok(())
+    }
+}
+
+// the overlays (overlay file, notifications, alerts) are shown over what is played, then the
+// last frame again: what is played is paused meanwhile, as its frames wait for the sink.
+// the overlays stop with the playing: the ones waiting are dropped once it is cancelled
+// or the sink is closed
+pub struct OverlaySink {
+    inner: Box<dyn FrameSink>,
+    overlays: Mutex<Receiver<OverlayFrame>>,
+    last: Mutex<Option<SentFrame>>,
+    closed: CancelToken,
+}
+
+impl OverlaySink {
+    pub fn new(
+        inner: Box<dyn FrameSink>,
+        overlays: Receiver<OverlayFrame>,
+        cancel: &CancelToken,
+    ) -> OverlaySink {
+        OverlaySink {
+            inner,
+            overlays: Mutex::new(overlays),
+            last: Mutex::new(None),
+            closed: cancel.clone(),
+        }
+    }
+}
+
+impl FrameSink for OverlaySink {
+    fn send(&self, header: [u8; DMD_HEADER_SIZE], frame: &[u8]) -> io::Result<()> {
+        let mut last = sink::lock(&self.last)?;
+        self.inner.send(header, frame)?;
+        *last = Some((header, frame.into()));
+        Ok(())
+    }
+
+    fn close(&self) -> io::Result<()> {
+        self.closed.cancel();
+        let _last = sink::lock(&self.last)?;
+        self.inner.close()
+    }
+}
+
+impl Ticked for OverlaySink {
+    // the overlays waiting are shown one after the other
+    fn tick(&self) -> io::Result<()> {
+        let last = sink::lock(&self.last)?;
+        let overlays = sink::lock(&self.overlays)?;
+        let mut shown = false;
+        while let Ok((header, frame, duration)) = overlays.try_recv() {
+            self.inner.send(header, &frame)?;
+            if self.closed.sleep(duration) {
+                return Ok(());
+            }
+            shown = true;
+        }
+        match &*last {
+            Some((header, frame)) if shown => self.inner.send(*header, frame),
+            _ => Ok(()),
+        }
+    }
+}
+
+// --active-hours, --auto-dim and --anti-burn: the rgb565 frames are dimmed and shifted.
+// the last frame is sent again when the brightness or the shift changes
+pub struct AdjustedSink {
+    inner: Box<dyn FrameSink>,
+    state: Mutex<AdjustState>,
+}
+
+struct AdjustState {
+    options: AdjustOptions,
+    started: Instant, // of the anti burn
+    adjustment: (u8, (i32, i32)),
+    last: Option<SentFrame>, // as it was played
+    last_black: Instant,
+}
+
+impl AdjustState {
+    fn adjustment(&self) -> (u8, (i32, i32)) {
+        (self.options.brightness(), self.options.shift(self.started))
+    }
+
+    fn send_last(&self, inner: &dyn FrameSink) -> io::Result<()> {
+        match &self.last {
+            Some((header, frame)) => {
+                let frame_header = sink::parse_header(header)?;
+                inner.send(
+                    *header,
+                    &adjusted_payload(&frame_header, frame, self.adjustment),
+                )
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl AdjustedSink {
+    pub fn new(inner: Box<dyn FrameSink>, options: AdjustOptions) -> AdjustedSink {
+        let started = Instant::now();
+        AdjustedSink {
+            inner,
+            state: Mutex::new(AdjustState {
+                adjustment: (options.brightness(), options.shift(started)),
+                options,
+                started,
+                last: None,
+                last_black: Instant::now(),
+            }),
+        }
+    }
+}
+
+impl FrameSink for AdjustedSink {
+    fn send(&self, header: [u8; DMD_HEADER_SIZE], frame: &[u8]) -> io::Result<()> {
+        let mut state = sink::lock(&self.state)?;
+        let frame_header = sink::parse_header(&header)?;
+        self.inner.send(
+            header,
+            &adjusted_payload(&frame_header, frame, state.adjustment),
+        )?;
+        state.last = Some((header, frame.into()));
+        Ok(())
+    }
+
+    fn close(&self) -> io::Result<()> {
+        let _state = sink::lock(&self.state)?;
+        self.inner.close()
+    }
+}
+
+impl Ticked for AdjustedSink {
+    fn tick(&self) -> io::Result<()> {
+        let mut state = sink::lock(&self.state)?;
+        let adjustment = state.adjustment();
+        if adjustment != state.adjustment {
+            state.adjustment = adjustment;
+            state.send_last(&*self.inner)?;
+        }
+        // the black frame is shown like an overlay, with the header of the last frame
+        if state.options.anti_burn
+            && state.last_black.elapsed() >= BLACK_PERIOD
+            && let Some((header, frame)) = &state.last
+        {
+            self.inner.send(*header, &vec![0u8; frame.len()])?;
+            thread::sleep(BLACK_DURATION);
+            state.send_last(&*self.inner)?;
+            state.last_black = Instant::now();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,607 @@
+use crate::DMD_HEADER_SIZE;
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use std::io::{self, ErrorKind, IoSlice, Read, Write};
+
+pub const KEYWORD: &[u8] = b"DMDStream";
+// version of the frames sent without handshake
+pub const PROTOCOL_VERSION: u8 = 1;
+// the first version with a handshake
+pub const HANDSHAKE_VERSION: u8 = 2;
+// the first version with compressed frames
+pub const COMPRESSION_VERSION: u8 = 3;
+// the highest version dmd-play can send
+pub const MAX_PROTOCOL_VERSION: u8 = 3;
+
+// positions in the header, the version is at the same place in the handshake
+pub const VERSION_OFFSET: usize = 10;
+const MODE_OFFSET: usize = 11;
+const WIDTH_OFFSET: usize = 15;
+const HEIGHT_OFFSET: usize = 17;
+const BUFFERED_OFFSET: usize = 19;
+const DISCONNECT_OFFSET: usize = 20;
+const NBYTES_OFFSET: usize = 21;
+
+// the only mode sent by dmd-play, and the only one that can be rescaled
+pub const MODE_RGB565: u32 = 3;
+// high bit of the mode of the compressed frames
+const MODE_COMPRESSED: u32 = 0x8000_0000;
+// largest frame read from a client, 4 bytes per pixel for 1024x1024: a larger size is refused
+// before anything is allocated
+pub const MAX_FRAME_SIZE: usize = 4 * 1024 * 1024;
+
+// compression of the frames, proposed in the handshake
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FrameCompression {
+    ZLIB = 1,
+    LZ4 = 2, // lz4 block, after the size of the frame (4 bytes, little endian)
+}
+
+// the header sent before each frame
+#[derive(Clone, Debug, PartialEq)]
+pub struct DmdStreamHeader {
+    pub version: u8,
+    pub mode: u32,
+    pub width: u16,
+    pub height: u16,
+    pub buffered: bool,          // displayed on the main layer
+    pub disconnect_others: bool, // the other clients of the main layer are disconnected
+    pub compressed: bool,        // with the compression of the connection
+    pub nbytes: u32,             // size of the frame, once compressed
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+impl DmdStreamHeader {
+    // the largest frame of this size: 4 bytes per pixel, whatever the mode
+    pub fn max_frame_size(&self) -> usize {
+        (self.width as usize * self.height as usize * 4).min(MAX_FRAME_SIZE)
+    }
+
+    // the keyword (10 bytes), the version (1 byte), the mode (4 bytes), the width and the height
+    // (2 bytes each), the buffered and disconnect others flags (1 byte each) and the frame size
+    // (4 bytes), in big endian. the mode of a compressed frame has its high bit set
+    pub fn to_bytes(&self) -> [u8; DMD_HEADER_SIZE] {
+        let mut bytes = [0u8; DMD_HEADER_SIZE];
+        bytes[..KEYWORD.len()].copy_from_slice(KEYWORD);
+        bytes[VERSION_OFFSET] = self.version;
+        let mode = match self.compressed {
+            true => self.mode | MODE_COMPRESSED,
+            false => self.mode,
+        };
+        bytes[MODE_OFFSET..MODE_OFFSET + 4].copy_from_slice(&mode.to_be_bytes());
+        bytes[WIDTH_OFFSET..WIDTH_OFFSET + 2].copy_from_slice(&self.width.to_be_bytes());
+        bytes[HEIGHT_OFFSET..HEIGHT_OFFSET + 2].copy_from_slice(&self.height.to_be_bytes());
+        bytes[BUFFERED_OFFSET] = self.buffered as u8;
+        bytes[DISCONNECT_OFFSET] = self.disconnect_others as u8;
+        bytes[NBYTES_OFFSET..NBYTES_OFFSET + 4].copy_from_slice(&self.nbytes.to_be_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; DMD_HEADER_SIZE]) -> Result<DmdStreamHeader, String> {
+        if &bytes[..KEYWORD.len()] != KEYWORD || bytes[KEYWORD.len()] != 0 {
+            return Err(String::from("Invalid DMDStream header"));
+        }
+        let mode = read_u32(bytes, MODE_OFFSET);
+        Ok(DmdStreamHeader {
+            version: bytes[VERSION_OFFSET],
+            mode: mode & !MODE_COMPRESSED,
+            width: read_u16(bytes, WIDTH_OFFSET),
+            height: read_u16(bytes, HEIGHT_OFFSET),
+            buffered: bytes[BUFFERED_OFFSET] != 0,
+            disconnect_others: bytes[DISCONNECT_OFFSET] != 0,
+            compressed: mode & MODE_COMPRESSED != 0,
+            nbytes: read_u32(bytes, NBYTES_OFFSET),
+        })
+    }
+}
+
+// the first packet of an authenticated connection:
+// the keyword (10 bytes), the highest version supported (1 byte), the token length (2 bytes) and the token,
+// then from the version 3, the compression proposed (1 byte, 0 for none)
+fn get_handshake(
+    version: u8,
+    token: &str,
+    compression: Option<FrameCompression>,
+) -> Result<Vec<u8>, String> {
+    let token = token.as_bytes();
+    let len: u16 = match token.len().try_into() {
+        Ok(x) => x,
+        Err(_) => return Err(String::from("Authentication token too long")),
+    };
+
+    let mut bytes = vec![0u8; KEYWORD.len() + 1];
+    bytes[..KEYWORD.len()].copy_from_slice(KEYWORD);
+    bytes.push(version);
+    bytes.extend_from_slice(&len.to_be_bytes());
+    bytes.extend_from_slice(token);
+    if version >= COMPRESSION_VERSION {
+        bytes.push(compression.map_or(0, |x| x as u8));
+    }
+    Ok(bytes)
+}
+
+// the handshake, as received by a server
+#[derive(Debug, PartialEq)]
+pub struct Handshake {
+    pub version: u8,
+    pub token: String,
+    pub compression: Option<FrameCompression>, // none for an unknown compression too
+}
+
+pub fn read_handshake<R: Read + ?Sized>(stream: &mut R) -> Result<Handshake, String> {
+    // the keyword, the version and the token length
+    let mut start = [0u8; KEYWORD.len() + 4];
+    if let Err(e) = stream.read_exact(&mut start) {
+        return Err(format!("Incomplete handshake: {}", e));
+    }
+    if &start[..KEYWORD.len()] != KEYWORD || start[KEYWORD.len()] != 0 {
+        return Err(String::from("Invalid DMDStream handshake"));
+    }
+    let version = start[VERSION_OFFSET];
+
+    let mut token = vec![0u8; read_u16(&start, VERSION_OFFSET + 1) as usize];
+    if let Err(e) = stream.read_exact(&mut token) {
+        return Err(format!("Incomplete handshake: {}", e));
+    }
+    let token = match String::from_utf8(token) {
+        Ok(x) => x,
+        Err(_) => return Err(String::from("Invalid authentication token")),
+    };
+
+    let mut compression = [0u8; 1];
+    if version >= COMPRESSION_VERSION
+        && let Err(e) = stream.read_exact(&mut compression)
+    {
+        return Err(format!("Incomplete handshake: {}", e));
+    }
+    Ok(Handshake {
+        version,
+        token,
+        compression: match compression[0] {
+            1 => Some(FrameCompression::ZLIB),
+            2 => Some(FrameCompression::LZ4),
+            _ => None,
+        },
+    })
+}
+
+// the answer of a server to the handshake of a client: the version of the connection, up to the one
+// proposed (0 when the token is refused), then from the version 3, the compression accepted.
+// returns the version and the compression, an error when the token is refused
+pub fn answer_handshake<R: Read + ?Sized, W: Write + ?Sized>(
+    reader: &mut R,
+    writer: &mut W,
+    token: &Option<String>,
+) -> Result<(u8, Option<FrameCompression>), String> {
+    let handshake = read_handshake(reader)?;
+    let accepted = token.as_ref().is_none_or(|x| *x == handshake.token);
+    let version = handshake.version.min(MAX_PROTOCOL_VERSION);
+
+    let mut answer = vec![if accepted { version } else { 0 }];
+    if accepted && version >= COMPRESSION_VERSION {
+        answer.push(handshake.compression.map_or(0, |x| x as u8));
+    }
+    if let Err(e) = writer.write_all(&answer).and_then(|_| writer.flush()) {
+        return Err(e.to_string());
+    }
+    if !accepted {
+        return Err(String::from("Authentication refused"));
+    }
+    Ok((version, handshake.compression))
+}
+
+// send the token to the server and read the version of the frames to send, up to version,
+// answered by the server in one byte (0 when the token is refused), then from the version 3,
+// the compression accepted (1 byte, 0 for raw frames)
+pub fn negotiate<S: Read + Write>(
+    stream: &mut S,
+    token: &str,
+    version: u8,
+    compression: Option<FrameCompression>,
+) -> Result<(u8, Option<FrameCompression>), String> {
+    let handshake = get_handshake(version, token, compression)?;
+    if let Err(e) = stream.write_all(&handshake).and_then(|_| stream.flush()) {
+        return Err(format!("Handshake failed: {}", e));
+    }
+
+    let mut answer = [0u8; 1];
+    if let Err(e) = stream.read_exact(&mut answer) {
+        return Err(format!("No handshake answer from the server: {}", e));
+    }
+    let answer = match answer[0] {
+        0 => return Err(String::from("Authentication refused by the server")),
+        answer if answer > version => {
+            return Err(format!(
+                "Unsupported protocol version {} requested by the server",
+                answer
+            ))
+        }
+        answer => answer,
+    };
+    if answer < COMPRESSION_VERSION {
+        return Ok((answer, None));
+    }
+
+    let mut accepted = [0u8; 1];
+    if let Err(e) = stream.read_exact(&mut accepted) {
+        return Err(format!("No handshake answer from the server: {}", e));
+    }
+    match (accepted[0], compression) {
+        (0, _) => Ok((answer, None)),
+        (x, Some(compression)) if x == compression as u8 => Ok((answer, Some(compression))),
+        (x, _) => Err(format!(
+            "Unsupported compression {} requested by the server",
+            x
+        )),
+    }
+}
+
+// the header and the frame in a single write most of the time, instead of two small packets
+pub fn write_frame<W: Write + ?Sized>(
+    stream: &mut W,
+    header: &[u8],
+    payload: &[u8],
+) -> io::Result<()> {
+    let mut bufs = [IoSlice::new(header), IoSlice::new(payload)];
+    let mut bufs = &mut bufs[..];
+    while !bufs.is_empty() {
+        match stream.write_vectored(bufs) {
+            Ok(0) => return Err(io::Error::from(ErrorKind::WriteZero)),
+            Ok(n) => IoSlice::advance_slices(&mut bufs, n),
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    stream.flush()
+}
+
+// the next frame of a stream: its header and its payload, as sent (compressed or not).
+// none when the stream is closed before a header
+pub fn read_frame<R: Read + ?Sized>(
+    stream: &mut R,
+) -> Result<Option<(DmdStreamHeader, Vec<u8>)>, String> {
+    let mut header = [0u8; DMD_HEADER_SIZE];
+    let mut position = 0;
+    while position < header.len() {
+        match stream.read(&mut header[position..]) {
+            Ok(0) if position == 0 => return Ok(None),
+            Ok(0) => return Err(String::from("Incomplete header")),
+            Ok(n) => position += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(format!("Incomplete header: {}", e)),
+        }
+    }
+    let frame_header = DmdStreamHeader::from_bytes(&header)?;
+    if frame_header.nbytes as usize > frame_header.max_frame_size() {
+        return Err(format!(
+            "Frame too large: {} bytes for {}x{}",
+            frame_header.nbytes, frame_header.width, frame_header.height
+        ));
+    }
+
+    let mut payload = vec![0u8; frame_header.nbytes as usize];
+    if let Err(e) = stream.read_exact(&mut payload) {
+        return Err(format!("Incomplete frame: {}", e));
+    }
+    Ok(Some((frame_header, payload)))
+}
+
+// the frame compressed, none when it is not smaller
+pub fn compress_frame(payload: &[u8], compression: FrameCompression) -> Option<Vec<u8>> {
+    let compressed = match compression {
+        FrameCompression::ZLIB => {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
+            encoder
+                .write_all(payload)
+                .and_then(|_| encoder.finish())
+                .ok()?
+        }
+        FrameCompression::LZ4 => lz4_flex::compress_prepend_size(payload),
+    };
+    match compressed.len() < payload.len() {
+        true => Some(compressed),
+        false => None,
+    }
+}
+
+// the frame as it was before its compression, refused when it would be larger than max_size
+pub fn decompress_frame(
+    payload: &[u8],
+    compression: FrameCompression,
+    max_size: usize,
+) -> Result<Vec<u8>, String> {
+    let too_large = || format!("Invalid compressed frame: larger than {} bytes", max_size);
+    let frame = match compression {
+        FrameCompression::ZLIB => {
+            let mut frame = Vec::new();
+            ZlibDecoder::new(payload)
+                .take(max_size as u64 + 1)
+                .read_to_end(&mut frame)
+                .map_err(|e| format!("Invalid compressed frame: {}", e))?;
+            frame
+        }
+        // the size is prepended, checked before it is allocated
+        FrameCompression::LZ4 => match payload.first_chunk::<4>() {
+            Some(size) if u32::from_le_bytes(*size) as usize > max_size => {
+                return Err(too_large());
+            }
+            _ => lz4_flex::decompress_size_prepended(payload)
+                .map_err(|e| format!("Invalid compressed frame: {}", e))?,
+        },
+    };
+    if frame.len() > max_size {
+        return Err(too_large());
+    }
+    Ok(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::{collection::vec, prelude::*};
+    use std::io::Cursor;
+
+    fn any_header() -> impl Strategy<Value = DmdStreamHeader> {
+        (
+            any::<u8>(),
+            0..MODE_COMPRESSED,
+            any::<u16>(),
+            any::<u16>(),
+            any::<(bool, bool, bool)>(),
+            any::<u32>(),
+        )
+            .prop_map(
+                |(
+                    version,
+                    mode,
+                    width,
+                    height,
+                    (buffered, disconnect_others, compressed),
+                    nbytes,
+                )| {
+                    DmdStreamHeader {
+                        version,
+                        mode,
+                        width,
+                        height,
+                        buffered,
+                        disconnect_others,
+                        compressed,
+                        nbytes,
+                    }
+                },
+            )
+    }
+
+    fn any_compression() -> impl Strategy<Value = Option<FrameCompression>> {
+        prop_oneof![
+            Just(None),
+            Just(Some(FrameCompression::ZLIB)),
+            Just(Some(FrameCompression::LZ4)),
+        ]
+    }
+
+    // a server answering the handshake: what it reads, and its answer to read
+    struct Server {
+        received: Vec<u8>,
+        answer: Cursor<Vec<u8>>,
+    }
+
+    impl Read for Server {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.answer.read(buf)
+        }
+    }
+
+    impl Write for Server {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.received.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // the layout read by the servers, whatever the version
+    #[test]
+    fn header_layout() {
+        let mut header = DmdStreamHeader {
+            version: 1,
+            mode: MODE_RGB565,
+            width: 128,
+            height: 32,
+            buffered: true,
+            disconnect_others: true,
+            compressed: false,
+            nbytes: 8192,
+        };
+        assert_eq!(
+            &header.to_bytes(),
+            b"DMDStream\0\x01\0\0\0\x03\0\x80\0\x20\x01\x01\0\0\x20\0"
+        );
+
+        header.version = 3;
+        header.width = 256;
+        header.height = 64;
+        header.buffered = false;
+        header.disconnect_others = false;
+        header.compressed = true;
+        header.nbytes = 0x0102_0304;
+        assert_eq!(
+            &header.to_bytes(),
+            b"DMDStream\0\x03\x80\0\0\x03\x01\0\0\x40\0\0\x01\x02\x03\x04"
+        );
+    }
+
+    // the size of a frame is checked before its payload is allocated and read
+    #[test]
+    fn frame_too_large() {
+        let header = DmdStreamHeader {
+            version: 1,
+            mode: MODE_RGB565,
+            width: 128,
+            height: 32,
+            buffered: true,
+            disconnect_others: true,
+            compressed: false,
+            nbytes: u32::MAX,
+        };
+        assert!(read_frame(&mut &header.to_bytes()[..]).is_err());
+        let header = DmdStreamHeader {
+            nbytes: 128 * 32 * 4 + 1,
+            ..header
+        };
+        assert!(read_frame(&mut &header.to_bytes()[..]).is_err());
+    }
+
+    #[test]
+    fn handshake_layout() {
+        assert_eq!(
+            get_handshake(2, "abc", None).unwrap(),
+            b"DMDStream\0\x02\0\x03abc"
+        );
+        assert_eq!(
+            get_handshake(3, "", Some(FrameCompression::LZ4)).unwrap(),
+            b"DMDStream\0\x03\0\0\x02"
+        );
+        assert!(get_handshake(3, &"x".repeat(65536), None).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn header_round_trip(header in any_header()) {
+            let bytes = header.to_bytes();
+            prop_assert_eq!(&bytes[..KEYWORD.len()], KEYWORD);
+            prop_assert_eq!(DmdStreamHeader::from_bytes(&bytes), Ok(header));
+        }
+
+        #[test]
+        fn header_invalid_keyword(
+            header in any_header(),
+            position in 0..=KEYWORD.len(),
+            value in any::<u8>(),
+        ) {
+            let mut bytes = header.to_bytes();
+            prop_assume!(bytes[position] != value);
+            bytes[position] = value;
+            prop_assert!(DmdStreamHeader::from_bytes(&bytes).is_err());
+        }
+
+        #[test]
+        fn frames_round_trip(frames in vec((any_header(), vec(any::<u8>(), 0..2048)), 0..8)) {
+            let mut stream = Vec::new();
+            let frames: Vec<(DmdStreamHeader, Vec<u8>)> = frames
+                .into_iter()
+                .map(|(header, payload)| {
+                    // 2048 bytes fit in any frame of 32x16 pixels or more
+                    let header = DmdStreamHeader {
+                        width: header.width.max(32),
+                        height: header.height.max(16),
+                        nbytes: payload.len() as u32,
+                        ..header
+                    };
+                    (header, payload)
+                })
+                .collect();
+            for (header, payload) in &frames {
+                write_frame(&mut stream, &header.to_bytes(), payload).unwrap();
+            }
+
+            let mut reader = &stream[..];
+            for frame in frames {
+                prop_assert_eq!(read_frame(&mut reader), Ok(Some(frame)));
+            }
+            prop_assert_eq!(read_frame(&mut reader), Ok(None));
+        }
+
+        #[test]
+        fn frame_truncated(
+            header in any_header(),
+            payload in vec(any::<u8>(), 1..512),
+            cut in any::<prop::sample::Index>(),
+        ) {
+            let header = DmdStreamHeader {
+                width: header.width.max(32),
+                height: header.height.max(16),
+                nbytes: payload.len() as u32,
+                ..header
+            };
+            let mut stream = Vec::new();
+            write_frame(&mut stream, &header.to_bytes(), &payload).unwrap();
+
+            let end = 1 + cut.index(stream.len() - 1);
+            prop_assert!(read_frame(&mut &stream[..end]).is_err());
+        }
+
+        #[test]
+        fn compression_round_trip(payload in vec(0..4u8, 0..16384)) {
+            for compression in [FrameCompression::ZLIB, FrameCompression::LZ4] {
+                if let Some(compressed) = compress_frame(&payload, compression) {
+                    prop_assert!(compressed.len() < payload.len());
+                    prop_assert_eq!(
+                        decompress_frame(&compressed, compression, payload.len()),
+                        Ok(payload.clone())
+                    );
+                    if !payload.is_empty() {
+                        prop_assert!(decompress_frame(&compressed, compression, payload.len() - 1).is_err());
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn handshake_round_trip(
+            version in 1..=MAX_PROTOCOL_VERSION,
+            token in "\\PC{0,64}",
+            compression in any_compression(),
+        ) {
+            let handshake = get_handshake(version, &token, compression).unwrap();
+            let expected = Handshake {
+                version,
+                token,
+                compression: compression.filter(|_| version >= COMPRESSION_VERSION),
+            };
+            prop_assert_eq!(read_handshake(&mut &handshake[..]), Ok(expected));
+        }
+
+        // the server chooses a version up to the one proposed, and the compression proposed or none
+        #[test]
+        fn negotiate_versions(
+            version in 1..=MAX_PROTOCOL_VERSION,
+            answer in 0..=MAX_PROTOCOL_VERSION + 1,
+            token in "[ -~]{0,64}",
+            compression in any_compression(),
+            accepted in any_compression(),
+        ) {
+            let mut server = Server {
+                received: Vec::new(),
+                answer: Cursor::new(vec![answer, accepted.map_or(0, |x| x as u8)]),
+            };
+            let negotiated = negotiate(&mut server, &token, version, compression);
+            prop_assert_eq!(server.received, get_handshake(version, &token, compression).unwrap());
+
+            let expected = if answer == 0 || answer > version {
+                None
+            } else if answer < COMPRESSION_VERSION || accepted.is_none() {
+                Some((answer, None))
+            } else if accepted == compression {
+                Some((answer, accepted))
+            } else {
+                None
+            };
+            prop_assert_eq!(negotiated.ok(), expected);
+        }
+    }
+}
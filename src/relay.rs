@@ -0,0 +1,486 @@
+use crate::{
+    cancel::CancelToken,
+    imageutils,
+    playback::FrameSource,
+    protocol::{self, FrameCompression, MODE_RGB565},
+    sink::FrameSink,
+    systemd, Dmd, DMD_HEADER_SIZE,
+};
+use std::{
+    io::{BufReader, Chain, Cursor, ErrorKind, Read},
+    net::{Shutdown, TcpListener, TcpStream},
+    sync::{Condvar, Mutex},
+    thread,
+    time::Duration,
+};
+
+// how often the cancellation is checked while no source connects
+const ACCEPT_POLL: Duration = Duration::from_millis(100);
+
+// what a source does when the dmd is used by a source of higher or equal priority
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RelayPolicy {
+    INTERRUPT, // plays at once, the sources of lower priority are paused until it is closed
+    QUEUE,     // waits for the sources of higher or equal priority to be closed
+    DROP,      // refused
+}
+
+// a port of the relay, its sources have the same priority and policy
+pub struct RelayChannel {
+    pub port: u16,
+    pub priority: i32,
+    pub policy: RelayPolicy,
+}
+
+// PORT:PRIORITY[:POLICY], the policy is interrupt by default
+pub fn parse_channel(spec: &str) -> Result<RelayChannel, String> {
+    let invalid = || format!("Invalid relay channel: {} (PORT:PRIORITY[:POLICY])", spec);
+    let parts: Vec<&str> = spec.split(':').collect();
+    if parts.len() < 2 || parts.len() > 3 {
+        return Err(invalid());
+    }
+    let port = parts[0].parse::<u16>().map_err(|_| invalid())?;
+    let priority = parts[1].parse::<i32>().map_err(|_| invalid())?;
+    let policy = match parts.get(2) {
+        None | Some(&"interrupt") => RelayPolicy::INTERRUPT,
+        Some(&"queue") => RelayPolicy::QUEUE,
+        Some(&"drop") => RelayPolicy::DROP,
+        Some(_) => {
+            return Err(format!(
+                "Invalid relay policy in {} (interrupt, queue or drop)",
+                spec
+            ))
+        }
+    };
+    Ok(RelayChannel {
+        port,
+        priority,
+        policy,
+    })
+}
+
+// a connected source, and its last frame to show it again when it resumes
+struct Source {
+    id: usize,
+    priority: i32,
+    last: Option<([u8; DMD_HEADER_SIZE], Box<[u8]>)>,
+}
+
+// the connection to the server and the stack of the connected sources, in connection order:
+// only the sources of the highest priority are forwarded. the connections, queued ones included,
+// are shut down when the relay is cancelled
+struct Playback<'a> {
+    upstream: &'a dyn FrameSink,
+    sources: Vec<Source>,
+    connections: Vec<(usize, TcpStream)>,
+    next_id: usize,
+}
+
+impl Playback<'_> {
+    fn top_priority(&self) -> Option<i32> {
+        self.sources.iter().map(|x| x.priority).max()
+    }
+
+    // the source is closed, the sources it was hiding show their last frame again
+    fn remove(&mut self, id: usize) {
+        self.connections.retain(|(x, _)| *x != id);
+        let before = self.top_priority();
+        self.sources.retain(|x| x.id != id);
+        let after = self.top_priority();
+        if after >= before {
+            return;
+        }
+        let frames = self
+            .sources
+            .iter()
+            .filter(|x| Some(x.priority) == after)
+            .filter_map(|x| x.last.as_ref());
+        for (header, payload) in frames {
+            if let Err(e) = self.upstream.send(*header, payload) {
+                eprintln!("{}", e);
+            }
+        }
+    }
+}
+
+// how the frames of the sources are checked and rescaled
+struct SourceOptions<'a> {
+    width: u32, // size of the dmd
+    height: u32,
+    image_options: &'a imageutils::ImageOptions,
+    token: &'a Option<String>, // required in the handshake of the sources
+}
+
+// the frames of a source, after its handshake
+struct SourceStream {
+    reader: Chain<Cursor<[u8; protocol::VERSION_OFFSET + 1]>, BufReader<TcpStream>>,
+    version: u8,
+    compression: Option<FrameCompression>,
+}
+
+// answer the handshake of a source, like a server would. with a token, the sources without it
+// are refused before they are played. none when the source is closed without sending anything
+fn open_source(source: TcpStream, token: &Option<String>) -> Result<Option<SourceStream>, String> {
+    let mut writer = source.try_clone().map_err(|e| e.to_string())?;
+    let mut reader = BufReader::new(source);
+    let mut start = [0u8; protocol::VERSION_OFFSET + 1];
+    if reader.read_exact(&mut start).is_err() {
+        return Ok(None);
+    }
+    let mut reader = Cursor::new(start).chain(reader);
+
+    // only the frames sent after a handshake have a version above 1
+    let (version, compression) = if start[protocol::VERSION_OFFSET] >= protocol::HANDSHAKE_VERSION {
+        protocol::answer_handshake(&mut reader, &mut writer, token)?
+    } else if token.is_some() {
+        return Err(String::from("Authentication refused: no handshake"));
+    } else {
+        (protocol::PROTOCOL_VERSION, None)
+    };
+    Ok(Some(SourceStream {
+        reader,
+        version,
+        compression,
+    }))
+}
+
+// forward the frames of one incoming connection, until it is closed
+fn relay_connection(
+    mut source: SourceStream,
+    id: usize,
+    playback: &Mutex<Playback<'_>>,
+    resumed: &Condvar,
+    options: &SourceOptions,
+    cancel: &CancelToken,
+) -> Result<(), String> {
+    let (dmd_width, dmd_height, image_options) =
+        (options.width, options.height, options.image_options);
+
+    loop {
+        let (mut frame_header, payload) = match protocol::read_frame(&mut source.reader)? {
+            Some(x) => x,
+            // closed by the source
+            None => return Ok(()),
+        };
+        if frame_header.version > source.version {
+            return Err(format!(
+                "Frame of version {} above the version {} of the connection",
+                frame_header.version, source.version
+            ));
+        }
+        // the server gets the frame compressed with its own connection, if any
+        let payload = match (frame_header.compressed, source.compression) {
+            (false, _) => payload,
+            (true, Some(compression)) => {
+                protocol::decompress_frame(&payload, compression, frame_header.max_frame_size())?
+            }
+            (true, None) => {
+                return Err(String::from(
+                    "Frame compressed without a compression negotiated",
+                ));
+            }
+        };
+        frame_header.compressed = false;
+        frame_header.nbytes = payload.len() as u32;
+        let mut header = frame_header.to_bytes();
+        let mode = frame_header.mode;
+        let width = frame_header.width as u32;
+        let height = frame_header.height as u32;
+        let nbytes = frame_header.nbytes as usize;
+
+        // frames of another size are rescaled, the layer flags of the source are kept
+        let payload = if (width, height) == (dmd_width, dmd_height) {
+            payload.into_boxed_slice()
+        } else if mode == MODE_RGB565
+            && nbytes == imageutils::get_dmd_buffer_size(width, height) as usize
+        {
+            let img = imageutils::dmdimage2image(&payload, width, height);
+            let img = imageutils::fit_image(&img, image_options, dmd_width, dmd_height)?;
+            let img565 = imageutils::image2dmdimage(
+                &img,
+                &imageutils::TextAlign::CENTER,
+                &image_options.colors,
+                dmd_width,
+                dmd_height,
+            )?;
+            frame_header.width = dmd_width as u16;
+            frame_header.height = dmd_height as u16;
+            frame_header.nbytes = img565.len() as u32;
+            header = frame_header.to_bytes();
+            img565
+        } else {
+            return Err(format!(
+                "Unable to rescale a {}x{} frame of mode {}",
+                width, height, mode
+            ));
+        };
+
+        // a paused source is not read until it resumes, like a client blocked by the server
+        let playback = match playback.lock() {
+            Ok(x) => x,
+            Err(_) => return Err(String::from("Upstream connection lost")),
+        };
+        let mut playback = match resumed.wait_while(playback, |x| {
+            let priority = x.sources.iter().find(|x| x.id == id).map(|x| x.priority);
+            priority < x.top_priority() && !cancel.is_cancelled()
+        }) {
+            Ok(x) => x,
+            Err(_) => return Err(String::from("Upstream connection lost")),
+        };
+        if cancel.is_cancelled() {
+            return Ok(());
+        }
+        if let Err(e) = playback.upstream.send(header, &payload) {
+            return Err(e.to_string());
+        }
+        if let Some(source) = playback.sources.iter_mut().find(|x| x.id == id) {
+            source.last = Some((header, payload));
+        }
+    }
+}
+
+// a source of a channel: its handshake is answered, then it joins the playback stack according to
+// the policy of the channel and its frames are forwarded until it is closed
+fn serve_source(
+    source: TcpStream,
+    id: usize,
+    channel: &RelayChannel,
+    playback: &Mutex<Playback<'_>>,
+    resumed: &Condvar,
+    options: &SourceOptions,
+    cancel: &CancelToken,
+) -> Result<(), String> {
+    let source = match open_source(source, options.token) {
+        Ok(Some(x)) => x,
+        Ok(None) => return Ok(()),
+        Err(e) => return Err(format!("Source refused on port {}: {}", channel.port, e)),
+    };
+    if !admit_source(playback, resumed, channel, id, cancel)? {
+        return match cancel.is_cancelled() {
+            true => Ok(()),
+            false => Err(format!(
+                "Source refused on port {}: the dmd is busy",
+                channel.port
+            )),
+        };
+    }
+    relay_connection(source, id, playback, resumed, options, cancel)
+}
+
+// the idle content is played while no source is connected, and resumes when the last one is closed
+fn play_idle<'a>(
+    playback: &Mutex<Playback<'_>>,
+    resumed: &Condvar,
+    header: [u8; DMD_HEADER_SIZE],
+    mut frames: Box<dyn FrameSource<'a> + Send + 'a>,
+    cancel: &CancelToken,
+) -> Result<(), String> {
+    loop {
+        let playback = match playback.lock() {
+            Ok(x) => x,
+            Err(_) => return Err(String::from("Upstream connection lost")),
+        };
+        let playback = match resumed.wait_while(playback, |x| {
+            !x.sources.is_empty() && !cancel.is_cancelled()
+        }) {
+            Ok(x) => x,
+            Err(_) => return Err(String::from("Upstream connection lost")),
+        };
+        if cancel.is_cancelled() {
+            return Ok(());
+        }
+        // the next frame is taken once idle, a clock shows the time when it resumes
+        let (img565, duration) = match frames.next() {
+            Some(x) => x?,
+            None => return Ok(()),
+        };
+        if let Err(e) = playback.upstream.send(header, &img565) {
+            return Err(e.to_string());
+        }
+        drop(playback);
+        if cancel.sleep(Duration::from_millis(duration as u64)) {
+            return Ok(());
+        }
+    }
+}
+
+// a new connection, kept to be shut down when the relay is cancelled. returns its id
+fn add_connection(playback: &Mutex<Playback<'_>>, source: &TcpStream) -> Result<usize, String> {
+    let connection = source.try_clone().map_err(|e| e.to_string())?;
+    let mut playback = match playback.lock() {
+        Ok(x) => x,
+        Err(_) => return Err(String::from("Upstream connection lost")),
+    };
+    let id = playback.next_id;
+    playback.next_id += 1;
+    playback.connections.push((id, connection));
+    Ok(id)
+}
+
+// the source joins the playback stack according to the policy of its channel,
+// false when it is refused or the relay is cancelled while it is queued
+fn admit_source(
+    playback: &Mutex<Playback<'_>>,
+    resumed: &Condvar,
+    channel: &RelayChannel,
+    id: usize,
+    cancel: &CancelToken,
+) -> Result<bool, String> {
+    let playback = match playback.lock() {
+        Ok(x) => x,
+        Err(_) => return Err(String::from("Upstream connection lost")),
+    };
+    let busy = |x: &Playback| x.top_priority() >= Some(channel.priority);
+    let mut playback = match channel.policy {
+        RelayPolicy::INTERRUPT => playback,
+        RelayPolicy::DROP if busy(&playback) => return Ok(false),
+        RelayPolicy::DROP => playback,
+        RelayPolicy::QUEUE => {
+            match resumed.wait_while(playback, |x| busy(x) && !cancel.is_cancelled()) {
+                Ok(x) => x,
+                Err(_) => return Err(String::from("Upstream connection lost")),
+            }
+        }
+    };
+    if cancel.is_cancelled() {
+        return Ok(false);
+    }
+    playback.sources.push(Source {
+        id,
+        priority: channel.priority,
+        last: None,
+    });
+    Ok(true)
+}
+
+// wait for the cancellation, then wake up the waiting threads and end the connections,
+// so that the threads of the relay return
+fn stop_on_cancel(playback: &Mutex<Playback<'_>>, resumed: &Condvar, cancel: &CancelToken) {
+    while !cancel.sleep(ACCEPT_POLL) {}
+    if let Ok(playback) = playback.lock() {
+        for (_, connection) in &playback.connections {
+            let _ = connection.shutdown(Shutdown::Both);
+        }
+    }
+    resumed.notify_all();
+}
+
+// the next connection of the listener, polled to return none once the relay is cancelled.
+// the relay waiting for its sources feeds the watchdog
+fn accept_source(
+    listener: &TcpListener,
+    watchdog: &systemd::Watchdog,
+    cancel: &CancelToken,
+) -> Option<TcpStream> {
+    loop {
+        watchdog.feed();
+        match listener.accept() {
+            Ok((x, _)) => match x.set_nonblocking(false) {
+                Ok(_) => return Some(x),
+                Err(e) => eprintln!("{}", e),
+            },
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                if cancel.sleep(ACCEPT_POLL) {
+                    return None;
+                }
+            }
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+}
+
+// accept DMDStream connections on the ports of the channels and forward their frames to the server,
+// rescaled to the dmd size. the sources of a higher priority cut the others, which resume afterwards.
+// the idle content, sent with the header, fills the dmd while no source is connected.
+// without a token, the relay only listens on this host. it runs until it is cancelled
+pub fn play_relay<'a>(
+    dmd: &Dmd,
+    channels: &[RelayChannel],
+    idle: Option<Box<dyn FrameSource<'a> + Send + 'a>>,
+    token: &Option<String>,
+) -> Result<(), String> {
+    let header = dmd.header;
+    let options = SourceOptions {
+        width: dmd.width,
+        height: dmd.height,
+        image_options: dmd.image_options,
+        token,
+    };
+    let address = match token {
+        Some(_) => "0.0.0.0",
+        None => "127.0.0.1",
+    };
+    // the sockets passed by systemd are used for the ports they listen on
+    let mut activated = systemd::activated_listeners();
+    let mut listeners = Vec::new();
+    for channel in channels {
+        let position = activated
+            .iter()
+            .position(|x| x.local_addr().is_ok_and(|x| x.port() == channel.port));
+        let listener = match position {
+            Some(n) => Ok(activated.remove(n)),
+            None => TcpListener::bind((address, channel.port)),
+        };
+        match listener.and_then(|x| x.set_nonblocking(true).map(|_| x)) {
+            Ok(x) => listeners.push((x, channel)),
+            Err(e) => return Err(format!("Unable to listen on port {}: {}", channel.port, e)),
+        }
+    }
+    if let Err(e) = systemd::notify("READY=1") {
+        eprintln!("{}", e);
+    }
+    let playback = Mutex::new(Playback {
+        upstream: dmd.client,
+        sources: Vec::new(),
+        connections: Vec::new(),
+        next_id: 0,
+    });
+    let resumed = Condvar::new();
+
+    let (watchdog, cancel) = (dmd.watchdog, dmd.cancel);
+
+    thread::scope(|scope| {
+        scope.spawn(|| stop_on_cancel(&playback, &resumed, cancel));
+        if let Some(frames) = idle {
+            let playback = &playback;
+            let resumed = &resumed;
+            scope.spawn(move || {
+                if let Err(e) = play_idle(playback, resumed, header, frames, cancel) {
+                    eprintln!("{}", e);
+                }
+            });
+        }
+        for (listener, channel) in listeners {
+            let playback = &playback;
+            let resumed = &resumed;
+            let options = &options;
+            scope.spawn(move || {
+                while let Some(source) = accept_source(&listener, watchdog, cancel) {
+                    let id = match add_connection(playback, &source) {
+                        Ok(x) => x,
+                        Err(e) => {
+                            eprintln!("{}", e);
+                            continue;
+                        }
+                    };
+                    // each source has its own thread, like on the server
+                    scope.spawn(move || {
+                        let served =
+                            serve_source(source, id, channel, playback, resumed, options, cancel);
+                        if let Err(e) = served {
+                            // the connections are shut down in the middle of a frame
+                            if !cancel.is_cancelled() {
+                                eprintln!("{}", e);
+                            }
+                        }
+                        if let Ok(mut playback) = playback.lock() {
+                            playback.remove(id);
+                        }
+                        resumed.notify_all();
+                    });
+                }
+            });
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,143 @@
+use std::time::Duration;
+
+// minimal rss/atom reader: only the titles of the items are used
+pub fn fetch_titles(url: &str) -> Result<Vec<String>, String> {
+    let body = match ureq::get(url).timeout(Duration::from_secs(10)).call() {
+        Ok(response) => match response.into_string() {
+            Ok(x) => x,
+            Err(e) => return Err(format!("Error: {}: {}", url, e)),
+        },
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let titles = parse_titles(&body);
+    if titles.is_empty() {
+        return Err(format!("Error: {}: no item found", url));
+    }
+    Ok(titles)
+}
+
+// the title of each item, an item without a title is left out
+fn parse_titles(xml: &str) -> Vec<String> {
+    let mut titles = Vec::new();
+    let mut rest = xml;
+
+    // rss uses <item>, atom uses <entry>
+    while let Some((pos, name)) = find_tag(rest, &["item", "entry"]) {
+        let item = &rest[pos..];
+        let end = item.find(&format!("</{}>", name)).unwrap_or(item.len());
+        if let Some(title) = element_text(&item[..end], "title") {
+            let title = decode_text(title);
+            if !title.is_empty() {
+                titles.push(title);
+            }
+        }
+        rest = &item[end..];
+    }
+    titles
+}
+
+// the first start tag of one of the names (<item> or <item attr="x">, not <items>), and its name
+fn find_tag<'a>(text: &str, names: &[&'a str]) -> Option<(usize, &'a str)> {
+    names
+        .iter()
+        .filter_map(|name| {
+            let open = format!("<{}", name);
+            let mut from = 0;
+            while let Some(x) = text[from..].find(&open) {
+                let at = from + x;
+                let next = text[at + open.len()..].chars().next();
+                if next.is_some_and(|c| c == '>' || c == '/' || c.is_whitespace()) {
+                    return Some((at, *name));
+                }
+                from = at + open.len();
+            }
+            None
+        })
+        .min_by_key(|(at, _)| *at)
+}
+
+// the content of the first element of this name, none without it or when it is not closed
+fn element_text<'a>(text: &'a str, name: &str) -> Option<&'a str> {
+    let (start, _) = find_tag(text, &[name])?;
+    let start_end = start + text[start..].find('>')?;
+    if text[..start_end].ends_with('/') {
+        return Some("");
+    }
+    let content = &text[start_end + 1..];
+    let end = content.find(&format!("</{}>", name))?;
+    Some(&content[..end])
+}
+
+fn decode_text(text: &str) -> String {
+    let text = text.trim();
+    let text = match text.strip_prefix("<![CDATA[") {
+        Some(x) => x.strip_suffix("]]>").unwrap_or(x),
+        None => text,
+    };
+
+    let mut decoded = String::new();
+    let mut rest = text;
+    while let Some(pos) = rest.find('&') {
+        decoded.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+        let entity_end = match rest.find(';') {
+            Some(x) if x < 10 => x,
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+                continue;
+            }
+        };
+        let entity = &rest[1..entity_end];
+        let value = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .and_then(|x| u32::from_str_radix(x, 16).ok())
+                .or_else(|| entity.strip_prefix('#').and_then(|x| x.parse().ok()))
+                .and_then(char::from_u32),
+        };
+        match value {
+            Some(c) => {
+                decoded.push(c);
+                rest = &rest[entity_end + 1..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+
+    // titles are displayed on a single line
+    decoded.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn titles() {
+        let rss = "<rss><channel><title>Channel</title><items>3</items>\
+                   <item><titles>x</titles><title><![CDATA[First <b>news</b>]]></title></item>\
+                   <item><link>https://example.com</link></item>\
+                   <item><title>Tom &amp; Jerry &#233;t&#xE9; &lt;3 &bogus;</title></item>\
+                   </channel></rss>";
+        assert_eq!(
+            parse_titles(rss),
+            vec!["First <b>news</b>", "Tom & Jerry été <3 &bogus;"]
+        );
+
+        let atom = "<feed><title>Feed</title><entry xml:lang=\"en\"><title type=\"html\">\n  Atom\n  entry </title></entry>\
+                    <entry><title/></entry></feed>";
+        assert_eq!(parse_titles(atom), vec!["Atom entry"]);
+        assert!(parse_titles("<rss><channel><title>Channel</title></channel></rss>").is_empty());
+    }
+}
@@ -0,0 +1,126 @@
+use crate::cron;
+use chrono::{DateTime, Datelike, TimeZone, Timelike};
+
+// a window of --active-hours: the weekdays it starts on (0 is sunday), its start and end in minutes.
+// an end before the start is on the next day
+struct Window {
+    weekdays: Vec<bool>,
+    start: u32,
+    end: u32,
+}
+
+// the hours the dmd is on, outside of them it is blanked or dimmed
+pub struct ActiveHours {
+    windows: Vec<Window>,
+}
+
+// HH:MM in minutes, 24:00 being the end of the day
+fn parse_time(time: &str) -> Option<u32> {
+    let (hour, minute) = time.split_once(':')?;
+    let (hour, minute) = (hour.parse::<u32>().ok()?, minute.parse::<u32>().ok()?);
+    match (hour, minute) {
+        (24, 0) => Some(24 * 60),
+        (0..24, 0..60) => Some(hour * 60 + minute),
+        _ => None,
+    }
+}
+
+// [WEEKDAYS] HH:MM-HH:MM, the weekdays like in cron (MON-FRI, SAT,SUN), every day by default
+fn parse_window(spec: &str) -> Result<Window, String> {
+    let invalid = || format!("Invalid active hours: {} ([WEEKDAYS] HH:MM-HH:MM)", spec);
+    let (weekdays, hours) = match spec.trim().rsplit_once(' ') {
+        Some((weekdays, hours)) => (cron::parse_weekdays(weekdays.trim())?, hours),
+        None => (vec![true; 7], spec.trim()),
+    };
+    let (start, end) = hours.split_once('-').ok_or_else(invalid)?;
+    match (parse_time(start), parse_time(end)) {
+        (Some(start), Some(end)) if start != end && start < 24 * 60 => Ok(Window {
+            weekdays,
+            start,
+            end,
+        }),
+        _ => Err(invalid()),
+    }
+}
+
+pub fn parse_active_hours(specs: &[String]) -> Result<ActiveHours, String> {
+    Ok(ActiveHours {
+        windows: specs
+            .iter()
+            .map(|x| parse_window(x))
+            .collect::<Result<_, _>>()?,
+    })
+}
+
+impl ActiveHours {
+    pub fn is_active<T: TimeZone>(&self, now: &DateTime<T>) -> bool {
+        let minute = now.hour() * 60 + now.minute();
+        let weekday = now.weekday().num_days_from_sunday() as usize;
+        let yesterday = (weekday + 6) % 7;
+        self.windows.iter().any(|x| match x.start < x.end {
+            true => x.weekdays[weekday] && (x.start..x.end).contains(&minute),
+            // over midnight: the evening of its day, or the morning after
+            false => {
+                (x.weekdays[weekday] && minute >= x.start)
+                    || (x.weekdays[yesterday] && minute < x.end)
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{NaiveDate, Utc};
+
+    // 2024-01-01 is a monday
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        NaiveDate::from_ymd_opt(2024, 1, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+            .and_utc()
+    }
+
+    #[test]
+    fn every_day() {
+        let hours = parse_active_hours(&[String::from("08:00-23:00")]).unwrap();
+        assert!(!hours.is_active(&at(1, 7, 59)));
+        assert!(hours.is_active(&at(1, 8, 0)));
+        assert!(hours.is_active(&at(7, 22, 59)));
+        assert!(!hours.is_active(&at(7, 23, 0)));
+    }
+
+    #[test]
+    fn weekdays_over_midnight() {
+        let hours = parse_active_hours(&[
+            String::from("MON-FRI 07:00-22:00"),
+            String::from("SAT,SUN 09:00-01:00"),
+        ])
+        .unwrap();
+        assert!(hours.is_active(&at(5, 21, 0))); // friday
+        assert!(!hours.is_active(&at(5, 23, 0)));
+        assert!(!hours.is_active(&at(6, 8, 0))); // saturday
+        assert!(hours.is_active(&at(6, 23, 30)));
+        assert!(hours.is_active(&at(7, 0, 30))); // sunday, after saturday
+        assert!(hours.is_active(&at(8, 0, 30))); // monday, after sunday
+        assert!(!hours.is_active(&at(8, 1, 30)));
+    }
+
+    #[test]
+    fn invalid() {
+        for spec in [
+            "08:00",
+            "8-23",
+            "08:00-08:00",
+            "25:00-26:00",
+            "XYZ 08:00-09:00",
+        ] {
+            assert!(
+                parse_active_hours(&[String::from(spec)]).is_err(),
+                "{}",
+                spec
+            );
+        }
+    }
+}
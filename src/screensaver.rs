@@ -0,0 +1,277 @@
+use crate::{playback, render_frame, Dmd};
+use image::{Rgba, RgbaImage};
+use std::{
+    borrow::Cow,
+    f32::consts::PI,
+    iter,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub enum Screensaver {
+    LIFE,
+    PLASMA,
+    FIRE,
+    MATRIX,
+}
+
+// xorshift, good enough for the effects and no dependency needed
+pub struct Random(u64);
+
+impl Random {
+    pub fn new() -> Random {
+        let seed = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(x) => x.as_nanos() as u64,
+            Err(_) => 0,
+        };
+        Random(seed | 1)
+    }
+
+    pub fn next(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 32) as u32
+    }
+
+    // between 0 and max excluded
+    pub fn below(&mut self, max: u32) -> u32 {
+        self.next() % max.max(1)
+    }
+
+    // between 0.0 and 1.0
+    pub fn unit(&mut self) -> f32 {
+        self.next() as f32 / u32::MAX as f32
+    }
+}
+
+// state of the running effect
+enum State {
+    LIFE(Vec<bool>, Vec<Vec<bool>>, u32), // cells, recent generations, generation count
+    PLASMA,
+    FIRE(Vec<u8>),                // heat of each pixel
+    MATRIX(Vec<(f32, f32, u32)>), // head position, speed and length of each column
+}
+
+// the game of life restarts when it is stable, dead, or after this number of generations
+const LIFE_MAX_GENERATIONS: u32 = 1000;
+// plasma frames for a full period
+const PLASMA_PERIOD: u32 = 256;
+// fire and matrix have no real end, a cycle is a fixed number of frames
+const FRAMES_PER_CYCLE: u32 = 300;
+
+fn scale_color(color: Rgba<u8>, level: f32) -> Rgba<u8> {
+    Rgba([
+        (color[0] as f32 * level) as u8,
+        (color[1] as f32 * level) as u8,
+        (color[2] as f32 * level) as u8,
+        255,
+    ])
+}
+
+fn life_seed(random: &mut Random, width: u32, height: u32) -> Vec<bool> {
+    (0..width * height)
+        .map(|_| random.below(100) < 30)
+        .collect()
+}
+
+fn life_step(cells: &[bool], width: u32, height: u32) -> Vec<bool> {
+    let (w, h) = (width as i32, height as i32);
+    let mut next = vec![false; cells.len()];
+    for y in 0..h {
+        for x in 0..w {
+            let mut neighbours = 0;
+            for (dx, dy) in [
+                (-1, -1),
+                (0, -1),
+                (1, -1),
+                (-1, 0),
+                (1, 0),
+                (-1, 1),
+                (0, 1),
+                (1, 1),
+            ] {
+                // the edges wrap around
+                let (nx, ny) = ((x + dx + w) % w, (y + dy + h) % h);
+                if cells[(ny * w + nx) as usize] {
+                    neighbours += 1;
+                }
+            }
+            let alive = cells[(y * w + x) as usize];
+            next[(y * w + x) as usize] = neighbours == 3 || (alive && neighbours == 2);
+        }
+    }
+    next
+}
+
+fn plasma_color(value: f32) -> Rgba<u8> {
+    let channel = |phase: f32| (128.0 + 127.0 * (PI * value + phase).sin()) as u8;
+    Rgba([
+        channel(0.0),
+        channel(2.0 * PI / 3.0),
+        channel(4.0 * PI / 3.0),
+        255,
+    ])
+}
+
+// black, red, yellow then white
+fn fire_color(heat: u8) -> Rgba<u8> {
+    let heat = heat as u32;
+    Rgba([
+        (heat * 3).min(255) as u8,
+        (heat * 3).saturating_sub(255).min(255) as u8,
+        (heat * 3).saturating_sub(510).min(255) as u8,
+        255,
+    ])
+}
+
+fn matrix_column(random: &mut Random, height: u32) -> (f32, f32, u32) {
+    (
+        -(random.below(height * 2) as f32),
+        0.3 + random.below(70) as f32 / 100.0,
+        height / 4 + random.below(height / 2 + 1),
+    )
+}
+
+// compute the next image of the effect, returns true at the end of a cycle
+fn next_frame(
+    state: &mut State,
+    frame: u32,
+    random: &mut Random,
+    img: &mut RgbaImage,
+    color: Rgba<u8>,
+) -> bool {
+    let (width, height) = img.dimensions();
+
+    match state {
+        State::LIFE(cells, history, generation) => {
+            for (i, alive) in cells.iter().enumerate() {
+                if *alive {
+                    img.put_pixel(i as u32 % width, i as u32 / width, color);
+                }
+            }
+            let next = life_step(cells, width, height);
+            // a still or blinking pattern is the end of the cycle
+            let stable = history.contains(&next) || !next.contains(&true);
+            *generation += 1;
+            if stable || *generation >= LIFE_MAX_GENERATIONS {
+                *cells = life_seed(random, width, height);
+                history.clear();
+                *generation = 0;
+                return true;
+            }
+            history.push(std::mem::replace(cells, next));
+            if history.len() > 2 {
+                history.remove(0);
+            }
+            false
+        }
+        State::PLASMA => {
+            let t = frame as f32 * 2.0 * PI / PLASMA_PERIOD as f32;
+            for (x, y, pixel) in img.enumerate_pixels_mut() {
+                let (fx, fy) = (x as f32, y as f32);
+                let value = (fx / 16.0 + t).sin()
+                    + (fy / 8.0 + t).sin()
+                    + ((fx + fy) / 16.0 + t).sin()
+                    + ((fx * fx + fy * fy).sqrt() / 8.0 + t).sin();
+                *pixel = plasma_color(value / 2.0);
+            }
+            (frame + 1).is_multiple_of(PLASMA_PERIOD)
+        }
+        State::FIRE(heat) => {
+            let (w, h) = (width as usize, height as usize);
+            // the bottom line is the source of the fire
+            for x in 0..w {
+                heat[(h - 1) * w + x] = 200 + random.below(56) as u8;
+            }
+            // each pixel takes the heat of the one below, cooled and shifted a bit
+            for y in 0..h - 1 {
+                for x in 0..w {
+                    let below = heat[(y + 1) * w + x];
+                    // the smaller the dmd, the faster it cools so that the flames keep their shape
+                    let cooling = (random.below(3) * (256 / height).max(1)).min(255) as u8;
+                    let target_x = (x + random.below(3) as usize + w - 1) % w;
+                    heat[y * w + target_x] = below.saturating_sub(cooling);
+                }
+            }
+            for (i, value) in heat.iter().enumerate() {
+                img.put_pixel((i % w) as u32, (i / w) as u32, fire_color(*value));
+            }
+            (frame + 1).is_multiple_of(FRAMES_PER_CYCLE)
+        }
+        State::MATRIX(columns) => {
+            for (x, (head, speed, length)) in columns.iter_mut().enumerate() {
+                for i in 0..*length {
+                    let y = *head as i32 - i as i32;
+                    if !(0..height as i32).contains(&y) {
+                        continue;
+                    }
+                    // the head is bright, the trail fades and flickers
+                    let level = 1.0 - i as f32 / *length as f32;
+                    let flicker = 0.7 + random.below(30) as f32 / 100.0;
+                    let pixel = if i == 0 {
+                        Rgba([200, 255, 200, 255])
+                    } else {
+                        scale_color(color, level * flicker)
+                    };
+                    img.put_pixel(x as u32, y as u32, pixel);
+                }
+                *head += *speed;
+                if *head - *length as f32 > height as f32 {
+                    *head = -(random.below(height) as f32);
+                    *speed = 0.3 + random.below(70) as f32 / 100.0;
+                }
+            }
+            (frame + 1).is_multiple_of(FRAMES_PER_CYCLE)
+        }
+    }
+}
+
+// procedural animations, forever or for one cycle with --once
+pub fn play_screensaver(
+    dmd: &Dmd,
+    screensaver: &Screensaver,
+    fps: u32,
+    text_color: Rgba<u8>,
+    background: &RgbaImage,
+    once: bool,
+) -> Result<(), String> {
+    let (width, height) = background.dimensions();
+    let color = Rgba([text_color[0], text_color[1], text_color[2], 255]);
+    let mut random = Random::new();
+
+    let mut state = match screensaver {
+        Screensaver::LIFE => State::LIFE(life_seed(&mut random, width, height), Vec::new(), 0),
+        Screensaver::PLASMA => State::PLASMA,
+        Screensaver::FIRE => State::FIRE(vec![0; (width * height) as usize]),
+        Screensaver::MATRIX => State::MATRIX(
+            (0..width)
+                .map(|_| matrix_column(&mut random, height))
+                .collect(),
+        ),
+    };
+
+    let mut frame = 0;
+    let mut done = false;
+    let frames = iter::from_fn(|| {
+        if done {
+            return None;
+        }
+        let mut img = background.clone();
+        let end_of_cycle = next_frame(&mut state, frame, &mut random, &mut img, color);
+        done = once && end_of_cycle;
+        frame += 1;
+        Some(
+            render_frame(dmd.width, dmd.height, &img, dmd.image_options)
+                .map(|x| (Cow::Owned(x.into_vec()), playback::frame_duration(fps))),
+        )
+    });
+
+    playback::play(
+        dmd.client,
+        dmd.header,
+        frames,
+        dmd.rate,
+        dmd.control,
+        dmd.cancel,
+    )
+}
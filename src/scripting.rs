@@ -0,0 +1,425 @@
+use crate::{imageutils, playback, render_frame, Dmd, TextOptions};
+use image::{Rgba, RgbaImage};
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Scope};
+use std::{borrow::Cow, cell::RefCell, collections::HashMap, fs, iter, rc::Rc, time::Instant};
+
+// rhai script generating the frames, it must define:
+//   fn render(frame, time) { ... }  // frame number and seconds since the start (float)
+// render() returns true at the end of a cycle, --once stops there.
+// The canvas is kept between the frames, the functions available are:
+//   width(), height(), variable(name)
+//   clear(), clear(r, g, b), pixel(x, y, r, g, b)
+//   line(x1, y1, x2, y2, r, g, b), rect(x, y, w, h, r, g, b), fill(x, y, w, h, r, g, b)
+//   circle(x, y, radius, r, g, b), disc(x, y, radius, r, g, b), polygon([x1, y1, x2, y2, ...], r, g, b)
+//   text(x, y, w, h, text, r, g, b)
+// all the numbers are integers, except time. The centers of the circles are kept within
+// MAX_OUTSIDE pixels around the canvas, the texts within MAX_TEXT_SIZE pixels
+
+// what a script may use, a runaway one fails instead of blocking the dmd
+const MAX_OPERATIONS: u64 = 1_000_000; // per call of render()
+const MAX_CALL_LEVELS: usize = 32;
+const MAX_STRING_SIZE: usize = 64 * 1024;
+const MAX_ARRAY_SIZE: usize = 64 * 1024;
+const MAX_MAP_SIZE: usize = 4 * 1024;
+const MAX_OUTSIDE: i64 = 1024;
+const MAX_TEXT_SIZE: u32 = 1024;
+
+fn rgb(r: i64, g: i64, b: i64) -> Rgba<u8> {
+    Rgba([
+        r.clamp(0, 255) as u8,
+        g.clamp(0, 255) as u8,
+        b.clamp(0, 255) as u8,
+        255,
+    ])
+}
+
+fn clamp_size(size: i64) -> u32 {
+    size.clamp(0, u32::MAX as i64) as u32
+}
+
+// the drawing functions clip to the canvas, any i32 is drawn
+fn coordinate(value: i64) -> i32 {
+    value.clamp(i32::MIN as i64, i32::MAX as i64) as i32
+}
+
+// the circles are drawn around their center, it is kept close to the canvas
+fn center(value: i64, size: u32) -> i32 {
+    value.clamp(-MAX_OUTSIDE, size as i64 + MAX_OUTSIDE) as i32
+}
+
+fn register_functions(
+    engine: &mut Engine,
+    canvas: &Rc<RefCell<RgbaImage>>,
+    background: &RgbaImage,
+    vars: &HashMap<String, String>,
+    style: &imageutils::TextStyle,
+) {
+    let (width, height) = background.dimensions();
+    engine.register_fn("width", move || width as i64);
+    engine.register_fn("height", move || height as i64);
+
+    let vars = vars.clone();
+    engine.register_fn("variable", move |name: &str| {
+        vars.get(name).cloned().unwrap_or_default()
+    });
+
+    let (c, background) = (canvas.clone(), background.clone());
+    engine.register_fn("clear", move || {
+        *c.borrow_mut() = background.clone();
+    });
+    let c = canvas.clone();
+    engine.register_fn("clear", move |r: i64, g: i64, b: i64| {
+        for pixel in c.borrow_mut().pixels_mut() {
+            *pixel = rgb(r, g, b);
+        }
+    });
+    let c = canvas.clone();
+    engine.register_fn("pixel", move |x: i64, y: i64, r: i64, g: i64, b: i64| {
+        imageutils::fill_rect(
+            &mut c.borrow_mut(),
+            coordinate(x),
+            coordinate(y),
+            1,
+            1,
+            rgb(r, g, b),
+        );
+    });
+    let c = canvas.clone();
+    engine.register_fn(
+        "line",
+        move |x1: i64, y1: i64, x2: i64, y2: i64, r: i64, g: i64, b: i64| {
+            imageutils::draw_line(
+                &mut c.borrow_mut(),
+                coordinate(x1),
+                coordinate(y1),
+                coordinate(x2),
+                coordinate(y2),
+                rgb(r, g, b),
+            );
+        },
+    );
+    let c = canvas.clone();
+    engine.register_fn(
+        "rect",
+        move |x: i64, y: i64, w: i64, h: i64, r: i64, g: i64, b: i64| {
+            imageutils::draw_rect(
+                &mut c.borrow_mut(),
+                coordinate(x),
+                coordinate(y),
+                clamp_size(w),
+                clamp_size(h),
+                rgb(r, g, b),
+            );
+        },
+    );
+    let c = canvas.clone();
+    engine.register_fn(
+        "fill",
+        move |x: i64, y: i64, w: i64, h: i64, r: i64, g: i64, b: i64| {
+            imageutils::fill_rect(
+                &mut c.borrow_mut(),
+                coordinate(x),
+                coordinate(y),
+                clamp_size(w),
+                clamp_size(h),
+                rgb(r, g, b),
+            );
+        },
+    );
+    let c = canvas.clone();
+    engine.register_fn(
+        "circle",
+        move |x: i64, y: i64, radius: i64, r: i64, g: i64, b: i64| {
+            imageutils::draw_circle(
+                &mut c.borrow_mut(),
+                center(x, width),
+                center(y, height),
+                clamp_size(radius),
+                rgb(r, g, b),
+                false,
+            );
+        },
+    );
+    let c = canvas.clone();
+    engine.register_fn(
+        "disc",
+        move |x: i64, y: i64, radius: i64, r: i64, g: i64, b: i64| {
+            imageutils::draw_circle(
+                &mut c.borrow_mut(),
+                center(x, width),
+                center(y, height),
+                clamp_size(radius),
+                rgb(r, g, b),
+                true,
+            );
+        },
+    );
+    let c = canvas.clone();
+    engine.register_fn("polygon", move |points: Array, r: i64, g: i64, b: i64| {
+        let values: Vec<i32> = points
+            .iter()
+            .filter_map(|x| x.as_int().ok())
+            .map(coordinate)
+            .collect();
+        let points: Vec<(i32, i32)> = values.chunks_exact(2).map(|x| (x[0], x[1])).collect();
+        imageutils::fill_polygon(&mut c.borrow_mut(), &points, rgb(r, g, b));
+    });
+
+    // the texts start on the left
+    let (c, style) = (
+        canvas.clone(),
+        imageutils::TextStyle {
+            align: imageutils::TextAlign::LEFT,
+            ..style.clone()
+        },
+    );
+    engine.register_fn(
+        "text",
+        move |x: i64, y: i64, w: i64, h: i64, text: &str, r: i64, g: i64, b: i64| {
+            let (w, h) = (
+                clamp_size(w).min(MAX_TEXT_SIZE),
+                clamp_size(h).min(MAX_TEXT_SIZE),
+            );
+            // a text out of the canvas is not rendered
+            if w == 0
+                || h == 0
+                || x >= width as i64
+                || y >= height as i64
+                || x + w as i64 <= 0
+                || y + h as i64 <= 0
+            {
+                return;
+            }
+            let style = imageutils::TextStyle {
+                color: rgb(r, g, b),
+                ..style.clone()
+            };
+            // a text that cannot be rendered is skipped
+            if let Ok((dyn_img, _start, _new_width)) =
+                imageutils::generate_text_image(text, &style, &None, w, h)
+            {
+                imageutils::blend_image(
+                    &dyn_img.to_rgba8(),
+                    &mut c.borrow_mut(),
+                    x as i32,
+                    y as i32,
+                );
+            }
+        },
+    );
+}
+
+pub fn play_script(
+    dmd: &Dmd,
+    script_path: &str,
+    vars: &HashMap<String, String>,
+    options: &TextOptions,
+    fps: u32,
+    once: bool,
+) -> Result<(), String> {
+    let background = &options.background;
+    let script = match fs::read_to_string(script_path) {
+        Ok(x) => x,
+        Err(e) => return Err(format!("Error: {}: {}", script_path, e)),
+    };
+
+    let canvas = Rc::new(RefCell::new(background.clone()));
+    let mut engine = Engine::new();
+    engine
+        .set_max_operations(MAX_OPERATIONS)
+        .set_max_call_levels(MAX_CALL_LEVELS)
+        .set_max_string_size(MAX_STRING_SIZE)
+        .set_max_array_size(MAX_ARRAY_SIZE)
+        .set_max_map_size(MAX_MAP_SIZE);
+    // a script in the middle of a frame stops with the playing
+    let cancel = dmd.cancel.clone();
+    engine.on_progress(move |_| cancel.is_cancelled().then_some(Dynamic::UNIT));
+    register_functions(&mut engine, &canvas, background, vars, &options.style);
+
+    let ast = match engine.compile(&script) {
+        Ok(x) => x,
+        Err(e) => return Err(format!("Error: {}: {}", script_path, e)),
+    };
+    let mut scope = Scope::new();
+    // top level statements run once, before the first frame
+    match engine.run_ast_with_scope(&mut scope, &ast) {
+        Ok(_) => {}
+        Err(e) if matches!(*e, EvalAltResult::ErrorTerminated(_, _)) => return Ok(()),
+        Err(e) => return Err(format!("Error: {}: {}", script_path, e)),
+    }
+
+    let started = Instant::now();
+    let mut frame: i64 = 0;
+    let mut done = false;
+    let frames = iter::from_fn(|| {
+        if done {
+            return None;
+        }
+        let time = started.elapsed().as_secs_f64() * dmd.rate as f64;
+        let end_of_cycle =
+            match engine.call_fn::<rhai::Dynamic>(&mut scope, &ast, "render", (frame, time)) {
+                Ok(x) => x.as_bool().unwrap_or(false),
+                Err(e) if matches!(*e, EvalAltResult::ErrorTerminated(_, _)) => return None,
+                Err(e) => return Some(Err(format!("Error: {}: {}", script_path, e))),
+            };
+        done = once && end_of_cycle;
+        frame += 1;
+
+        let img = canvas.borrow().clone();
+        Some(
+            render_frame(dmd.width, dmd.height, &img, dmd.image_options)
+                .map(|x| (Cow::Owned(x.into_vec()), playback::frame_duration(fps))),
+        )
+    });
+
+    playback::play(
+        dmd.client,
+        dmd.header,
+        frames,
+        dmd.rate,
+        dmd.control,
+        dmd.cancel,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cancel::CancelToken, control::PlayControl, get_header, pixelfont, sink::CaptureSink,
+        systemd::Watchdog, DMDLayer, TextAnimation, ANIMATE_THRESHOLD,
+    };
+    use std::{env, process};
+
+    fn run(name: &str, script: &str) -> (Result<(), String>, Vec<Box<[u8]>>) {
+        let path = env::temp_dir().join(format!("dmd-play-{}-{}.rhai", name, process::id()));
+        fs::write(&path, script).unwrap();
+        let sink = CaptureSink::default();
+        let image_options = imageutils::ImageOptions {
+            fit: imageutils::ImageFit::CONTAIN,
+            crop: None,
+            orientation: imageutils::Orientation {
+                rotate: 0,
+                flip_h: false,
+                flip_v: false,
+            },
+            palette: None,
+            scale: imageutils::ImageScale::SMOOTH,
+            filter: image::imageops::FilterType::Nearest,
+            transparent_color: None,
+            colors: None,
+        };
+        let dmd = Dmd {
+            client: &sink,
+            header: get_header(
+                32,
+                8,
+                DMDLayer::MAIN,
+                imageutils::get_dmd_buffer_size(32, 8),
+                1,
+            ),
+            width: 32,
+            height: 8,
+            image_options: &image_options,
+            rate: 1.0,
+            control: &PlayControl::default(),
+            watchdog: &Watchdog::default(),
+            cancel: &CancelToken::default(),
+        };
+        let options = TextOptions {
+            style: imageutils::TextStyle {
+                font: imageutils::load_font(pixelfont::NAME).unwrap(),
+                color: Rgba([255, 255, 255, 255]),
+                align: imageutils::TextAlign::CENTER,
+                line_spacing: 0,
+                letter_spacing: 0,
+                tabular_digits: false,
+            },
+            gradient: None,
+            background: RgbaImage::from_pixel(32, 8, Rgba([0, 0, 0, 255])),
+            moving: false,
+            fixed: false,
+            speed: 20,
+            animation: TextAnimation {
+                subpixel: 1,
+                gap: None,
+                separator: String::new(),
+                continuous: false,
+                reveal: None,
+                reveal_duration: 0,
+                hold: None,
+                min_text_height: None,
+                animate_threshold: ANIMATE_THRESHOLD,
+                color_cycle: None,
+                cycle_speed: 90,
+            },
+        };
+        let result = play_script(
+            &dmd,
+            path.to_str().unwrap(),
+            &HashMap::new(),
+            &options,
+            100,
+            true,
+        );
+        fs::remove_file(&path).unwrap();
+        let frames = sink.frames().into_iter().map(|(_, x)| x).collect();
+        (result, frames)
+    }
+
+    #[test]
+    fn frames_until_the_end_of_the_cycle() {
+        let (result, frames) = run(
+            "cycle",
+            "fn render(frame, time) {
+                clear(0, 0, 0);
+                fill(frame * 4, 0, 4, 8, 255, 0, 0);
+                text(16, 0, 16, 8, \"A\", 0, 0, 255);
+                frame == 2
+            }",
+        );
+        assert!(result.is_ok());
+        assert_eq!(frames.len(), 3);
+        for (n, frame) in frames.iter().enumerate() {
+            let img = imageutils::dmdimage2image(frame, 32, 8);
+            assert_eq!(img.get_pixel(n as u32 * 4 + 1, 4), &Rgba([255, 0, 0, 255]));
+            assert_eq!(img.get_pixel(n as u32 * 4 + 5, 4), &Rgba([0, 0, 0, 255]));
+            assert!((16..32).any(|x| (0..8).any(|y| img.get_pixel(x, y)[2] > 0)));
+        }
+    }
+
+    #[test]
+    fn runaway_script() {
+        let (result, frames) = run("loop", "fn render(frame, time) { loop {} }");
+        assert!(result.is_err());
+        assert!(frames.is_empty());
+        let (result, _) = run("string", "let s = \"x\"; loop { s += s; }");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn shapes_out_of_the_canvas() {
+        let (result, frames) = run(
+            "disc",
+            "fn render(frame, time) { disc(0, 0, 1000000000, 255, 255, 255); true }",
+        );
+        assert!(result.is_ok());
+        let img = imageutils::dmdimage2image(&frames[0], 32, 8);
+        assert!(img.pixels().all(|x| x == &Rgba([255, 255, 255, 255])));
+
+        let (result, frames) = run(
+            "line",
+            "fn render(frame, time) {
+                line(-2147483648, 0, 2147483647, 0, 255, 255, 255);
+                circle(-9000000000, 4, 9000000000, 255, 255, 255);
+                fill(2147483647, 2147483647, 2147483647, 2147483647, 255, 255, 255);
+                text(2147483647, 0, 9000000000, 9000000000, \"A\", 255, 255, 255);
+                true
+            }",
+        );
+        assert!(result.is_ok());
+        let img = imageutils::dmdimage2image(&frames[0], 32, 8);
+        assert!((0..32).all(|x| img.get_pixel(x, 0) == &Rgba([255, 255, 255, 255])));
+        assert!((1..8).all(|y| (0..32).all(|x| img.get_pixel(x, y)[0] == 0)));
+    }
+}
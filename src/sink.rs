@@ -0,0 +1,404 @@
+use crate::{
+    cancel::CancelToken,
+    protocol::{self, DmdStreamHeader, FrameCompression, MODE_RGB565},
+    DMD_HEADER_SIZE,
+};
+use rustls::{ClientConnection, StreamOwned};
+use std::{
+    borrow::Cow,
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Write},
+    net::{Shutdown, TcpStream},
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+    time::Duration,
+};
+
+// large enough for the header and a 256x64 frame
+const SERVER_BUFFER_SIZE: usize = DMD_HEADER_SIZE + 256 * 64 * 2;
+
+// where the frames go: a server, a file or a device, through the decorators (preview, overlays...).
+// shared by the threads of a mode (relay, overlays), a frame is written at once
+pub trait FrameSink: Send + Sync {
+    fn send(&self, header: [u8; DMD_HEADER_SIZE], frame: &[u8]) -> io::Result<()>;
+
+    // nothing more is sent, the frames are flushed
+    fn close(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<T: FrameSink + ?Sized> FrameSink for &T {
+    fn send(&self, header: [u8; DMD_HEADER_SIZE], frame: &[u8]) -> io::Result<()> {
+        (**self).send(header, frame)
+    }
+
+    fn close(&self) -> io::Result<()> {
+        (**self).close()
+    }
+}
+
+impl<T: FrameSink + ?Sized> FrameSink for Box<T> {
+    fn send(&self, header: [u8; DMD_HEADER_SIZE], frame: &[u8]) -> io::Result<()> {
+        (**self).send(header, frame)
+    }
+
+    fn close(&self) -> io::Result<()> {
+        (**self).close()
+    }
+}
+
+// a decorator doing something between the frames too, like sending the last one again
+pub trait Ticked: FrameSink {
+    fn tick(&self) -> io::Result<()>;
+}
+
+// a decorator, with a thread calling its tick at each period until the sink is closed
+pub struct TickedSink<T> {
+    sink: Arc<T>,
+    closed: CancelToken,
+}
+
+impl<T: Ticked + 'static> TickedSink<T> {
+    pub fn start(sink: T, period: Duration) -> TickedSink<T> {
+        let sink = Arc::new(sink);
+        let closed = CancelToken::default();
+        let (ticked, stop) = (sink.clone(), closed.clone());
+        thread::spawn(move || {
+            while !stop.sleep(period) {
+                if let Err(e) = ticked.tick() {
+                    // the next frame sent gets the error too
+                    eprintln!("{}", e);
+                    return;
+                }
+            }
+        });
+        TickedSink { sink, closed }
+    }
+}
+
+impl<T: Ticked> FrameSink for TickedSink<T> {
+    fn send(&self, header: [u8; DMD_HEADER_SIZE], frame: &[u8]) -> io::Result<()> {
+        self.sink.send(header, frame)
+    }
+
+    fn close(&self) -> io::Result<()> {
+        self.closed.cancel();
+        self.sink.close()
+    }
+}
+
+// the lock of a sink, an error when a thread panicked holding it
+pub fn lock<T>(mutex: &Mutex<T>) -> io::Result<MutexGuard<'_, T>> {
+    mutex
+        .lock()
+        .map_err(|_| io::Error::other("Frame sink lost"))
+}
+
+pub fn parse_header(header: &[u8; DMD_HEADER_SIZE]) -> io::Result<DmdStreamHeader> {
+    DmdStreamHeader::from_bytes(header).map_err(io::Error::other)
+}
+
+// the connection to a server, plain tcp or tls
+pub enum ServerStream {
+    Plain(TcpStream),
+    Encrypted(Box<StreamOwned<ClientConnection, TcpStream>>),
+}
+
+impl ServerStream {
+    // the server sees the end of the stream: tls is closed first, so that the end is not
+    // taken for a truncation
+    fn close(&mut self) -> io::Result<()> {
+        let socket = match self {
+            ServerStream::Plain(x) => x,
+            ServerStream::Encrypted(x) => {
+                x.conn.send_close_notify();
+                x.flush()?;
+                &x.sock
+            }
+        };
+        socket.shutdown(Shutdown::Write)
+    }
+}
+
+impl Write for ServerStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            ServerStream::Plain(x) => x.write(buf),
+            ServerStream::Encrypted(x) => x.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ServerStream::Plain(x) => x.flush(),
+            ServerStream::Encrypted(x) => x.flush(),
+        }
+    }
+}
+
+// a server (tls or plain tcp), with the version and the compression negotiated with it
+pub struct ServerSink {
+    // a whole frame is written at once, in a single tls record
+    stream: Mutex<BufWriter<ServerStream>>,
+    version: u8,
+    compression: Option<FrameCompression>,
+}
+
+impl ServerSink {
+    pub fn new(
+        stream: ServerStream,
+        version: u8,
+        compression: Option<FrameCompression>,
+    ) -> ServerSink {
+        ServerSink {
+            stream: Mutex::new(BufWriter::with_capacity(SERVER_BUFFER_SIZE, stream)),
+            version,
+            compression,
+        }
+    }
+}
+
+impl FrameSink for ServerSink {
+    // the server gets the version it accepted, the frames are sent raw when the compression
+    // does not make them smaller
+    fn send(&self, header: [u8; DMD_HEADER_SIZE], frame: &[u8]) -> io::Result<()> {
+        let frame_header = DmdStreamHeader {
+            version: self.version,
+            ..parse_header(&header)?
+        };
+        let (header, frame) = match self
+            .compression
+            .and_then(|x| protocol::compress_frame(frame, x))
+        {
+            Some(compressed) => {
+                let compressed_header = DmdStreamHeader {
+                    compressed: true,
+                    nbytes: compressed.len() as u32,
+                    ..frame_header
+                };
+                (compressed_header.to_bytes(), Cow::Owned(compressed))
+            }
+            None => (frame_header.to_bytes(), Cow::Borrowed(frame)),
+        };
+        match self.stream.lock() {
+            Ok(mut stream) => protocol::write_frame(&mut *stream, &header, &frame),
+            Err(_) => Err(io::Error::other("Server connection lost")),
+        }
+    }
+
+    // the frames are flushed, then the server sees the end of the stream
+    fn close(&self) -> io::Result<()> {
+        match self.stream.lock() {
+            Ok(mut stream) => {
+                stream.flush()?;
+                stream.get_mut().close()
+            }
+            Err(_) => Err(io::Error::other("Server connection lost")),
+        }
+    }
+}
+
+// several servers side by side: each one gets its slice of the frame, from left to right
+pub struct TiledSink<T> {
+    tiles: Vec<T>,
+}
+
+impl<T: FrameSink> TiledSink<T> {
+    pub fn new(tiles: Vec<T>) -> TiledSink<T> {
+        TiledSink { tiles }
+    }
+}
+
+impl<T: FrameSink> FrameSink for TiledSink<T> {
+    // all the slices are written before the next frame is
+    fn send(&self, header: [u8; DMD_HEADER_SIZE], frame: &[u8]) -> io::Result<()> {
+        let frame_header = parse_header(&header)?;
+        let (width, height) = (frame_header.width as usize, frame_header.height as usize);
+        let ntiles = self.tiles.len();
+        if frame_header.mode != MODE_RGB565
+            || frame.len() != width * height * 2
+            || width % ntiles != 0
+        {
+            return Err(io::Error::other(format!(
+                "Unable to split a {}x{} frame of mode {} in {} tiles",
+                width, height, frame_header.mode, ntiles
+            )));
+        }
+
+        let tile_width = width / ntiles;
+        for (n, sink) in self.tiles.iter().enumerate() {
+            let tile: Vec<u8> = (0..height)
+                .flat_map(|y| {
+                    let start = (y * width + n * tile_width) * 2;
+                    frame[start..start + tile_width * 2].iter().copied()
+                })
+                .collect();
+            let tile_header = DmdStreamHeader {
+                width: tile_width as u16,
+                nbytes: tile.len() as u32,
+                ..frame_header.clone()
+            };
+            sink.send(tile_header.to_bytes(), &tile)?;
+        }
+        Ok(())
+    }
+
+    fn close(&self) -> io::Result<()> {
+        for sink in &self.tiles {
+            sink.close()?;
+        }
+        Ok(())
+    }
+}
+
+// --dry-run without a server: the frames are only previewed
+pub struct NullSink;
+
+impl FrameSink for NullSink {
+    fn send(&self, _header: [u8; DMD_HEADER_SIZE], _frame: &[u8]) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// --dump-raw: the rgb565 frames one after the other, a .raw file played again with --file
+pub struct ExportSink {
+    file: Mutex<BufWriter<File>>,
+}
+
+impl ExportSink {
+    pub fn create(path: &str) -> Result<ExportSink, String> {
+        match File::create(path) {
+            Ok(x) => Ok(ExportSink {
+                file: Mutex::new(BufWriter::new(x)),
+            }),
+            Err(e) => Err(format!("Error: {}: {}", path, e)),
+        }
+    }
+}
+
+impl FrameSink for ExportSink {
+    fn send(&self, _header: [u8; DMD_HEADER_SIZE], frame: &[u8]) -> io::Result<()> {
+        match self.file.lock() {
+            Ok(mut file) => file.write_all(frame),
+            Err(_) => Err(io::Error::other("Export file lost")),
+        }
+    }
+
+    fn close(&self) -> io::Result<()> {
+        match self.file.lock() {
+            Ok(mut file) => file.flush(),
+            Err(_) => Err(io::Error::other("Export file lost")),
+        }
+    }
+}
+
+// --serial: the DMDStream frames written to a device (serial port configured beforehand) or a fifo
+pub struct DeviceSink {
+    device: Mutex<File>,
+}
+
+impl DeviceSink {
+    pub fn open(path: &str) -> Result<DeviceSink, String> {
+        match OpenOptions::new().write(true).open(path) {
+            Ok(x) => Ok(DeviceSink {
+                device: Mutex::new(x),
+            }),
+            Err(e) => Err(format!("Error: {}: {}", path, e)),
+        }
+    }
+}
+
+impl FrameSink for DeviceSink {
+    fn send(&self, header: [u8; DMD_HEADER_SIZE], frame: &[u8]) -> io::Result<()> {
+        match self.device.lock() {
+            Ok(mut device) => protocol::write_frame(&mut *device, &header, frame),
+            Err(_) => Err(io::Error::other("Device lost")),
+        }
+    }
+}
+
+// a header and its frame
+pub type SentFrame = ([u8; DMD_HEADER_SIZE], Box<[u8]>);
+
+// keeps the frames sent, for the tests
+#[cfg(test)]
+#[derive(Default)]
+pub struct CaptureSink {
+    frames: Mutex<Vec<SentFrame>>,
+}
+
+#[cfg(test)]
+impl CaptureSink {
+    pub fn frames(&self) -> Vec<SentFrame> {
+        self.frames.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+impl FrameSink for CaptureSink {
+    fn send(&self, header: [u8; DMD_HEADER_SIZE], frame: &[u8]) -> io::Result<()> {
+        self.frames.lock().unwrap().push((header, frame.into()));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::Read, net::TcpListener};
+
+    fn header(width: u16, height: u16) -> [u8; DMD_HEADER_SIZE] {
+        DmdStreamHeader {
+            version: 1,
+            mode: MODE_RGB565,
+            width,
+            height,
+            buffered: true,
+            disconnect_others: true,
+            compressed: false,
+            nbytes: width as u32 * height as u32 * 2,
+        }
+        .to_bytes()
+    }
+
+    // a 4x2 frame on 2 servers: the 2 left pixels of each line go to the first one
+    #[test]
+    fn tiles() {
+        let tiled = TiledSink::new(vec![CaptureSink::default(), CaptureSink::default()]);
+        let frame: Vec<u8> = (0..16).collect();
+        tiled.send(header(4, 2), &frame).unwrap();
+
+        let tiles: Vec<SentFrame> = tiled.tiles.iter().flat_map(|x| x.frames()).collect();
+        assert_eq!(tiles.len(), 2);
+        assert_eq!(&tiles[0].1[..], [0, 1, 2, 3, 8, 9, 10, 11]);
+        assert_eq!(&tiles[1].1[..], [4, 5, 6, 7, 12, 13, 14, 15]);
+        for (tile_header, tile) in tiles {
+            let tile_header = parse_header(&tile_header).unwrap();
+            assert_eq!((tile_header.width, tile_header.height), (2, 2));
+            assert_eq!(tile_header.nbytes as usize, tile.len());
+        }
+
+        // a frame of another size than the dmd one is not split
+        assert!(tiled.send(header(3, 2), &frame[..12]).is_err());
+    }
+
+    // the server gets its version, and the frame compressed when it is smaller, then the end
+    // of the stream on close
+    #[test]
+    fn server_frames() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut accepted, _) = listener.accept().unwrap();
+        let server = ServerSink::new(ServerStream::Plain(client), 3, Some(FrameCompression::ZLIB));
+        server.send(header(32, 16), &[0; 32 * 16 * 2]).unwrap();
+        server.close().unwrap();
+
+        let mut written = Vec::new();
+        accepted.read_to_end(&mut written).unwrap();
+        let sent_header = parse_header(written[..DMD_HEADER_SIZE].try_into().unwrap()).unwrap();
+        assert_eq!(sent_header.version, 3);
+        assert!(sent_header.compressed);
+        assert_eq!(sent_header.nbytes as usize, written.len() - DMD_HEADER_SIZE);
+    }
+}
@@ -0,0 +1,250 @@
+use crate::{marquee, screensaver::Random};
+use serde::Deserialize;
+use std::{
+    collections::{HashSet, VecDeque},
+    fs,
+    path::{Path, PathBuf},
+};
+
+// the weights of the images, in the slideshow directory
+pub const PLAYLIST_FILE: &str = "slideshow.toml";
+
+// the images of a directory one after the other, in their name order or shuffled.
+// the directory is scanned again before each image, the files added are played in the current pass
+pub struct Slideshow {
+    dir: PathBuf,
+    include: Vec<String>,
+    shuffle: bool,
+    no_repeat_last: usize,
+    random: Random,
+    played: HashSet<PathBuf>,   // the images already played in this pass
+    history: VecDeque<PathBuf>, // the last images played, the most recent last
+    error: Option<String>,      // of the playlist file, printed once
+}
+
+// [[weight]] files="sf2*" weight=3, the first entry matching the path of an image (relative to the
+// directory) or its name gives its weight, 1 without any. an image of weight 0 is not played
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Weight {
+    files: String,
+    weight: f32,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct Playlist {
+    #[serde(default)]
+    weight: Vec<Weight>,
+}
+
+fn load_playlist(path: &Path) -> Result<Playlist, String> {
+    let content = match fs::read_to_string(path) {
+        Ok(x) => x,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Playlist::default()),
+        Err(e) => return Err(format!("Error: {}: {}", path.display(), e)),
+    };
+    let playlist: Playlist = match toml::from_str(&content) {
+        Ok(x) => x,
+        Err(e) => return Err(format!("Error: {}: {}", path.display(), e)),
+    };
+    if playlist.weight.iter().any(|x| !(0.0..).contains(&x.weight)) {
+        return Err(format!(
+            "Error: {}: invalid weight (0.0 or more)",
+            path.display()
+        ));
+    }
+    Ok(playlist)
+}
+
+// the index of the weight where value (between 0.0 and the total of the weights) falls
+fn weighted_pick(weights: &[f32], value: f32) -> usize {
+    let mut total = 0.0;
+    for (i, weight) in weights.iter().enumerate() {
+        total += weight;
+        if value < total {
+            return i;
+        }
+    }
+    weights.iter().rposition(|x| *x > 0.0).unwrap_or(0)
+}
+
+// * for any characters and ? for a single one, like the shell
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let (pattern, name): (Vec<char>, Vec<char>) =
+        (pattern.chars().collect(), name.chars().collect());
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None; // position of the last * and the name matched from there
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            // the * takes one more character
+            star = Some((star_p, star_n + 1));
+            p = star_p + 1;
+            n = star_n + 1;
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+// the images under the directory, recursively, whose name matches one of the patterns (all of them without pattern)
+fn scan(dir: &Path, include: &[String], paths: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for path in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+        if path.is_dir() {
+            scan(&path, include, paths);
+            continue;
+        }
+        let name = path.file_name().and_then(|x| x.to_str()).unwrap_or("");
+        if marquee::is_image(&path)
+            && (include.is_empty() || include.iter().any(|x| wildcard_match(x, name)))
+        {
+            paths.push(path);
+        }
+    }
+}
+
+impl Slideshow {
+    pub fn new(
+        dir: &str,
+        include: &[String],
+        shuffle: bool,
+        no_repeat_last: usize,
+    ) -> Result<Slideshow, String> {
+        if !Path::new(dir).is_dir() {
+            return Err(format!("Error: {}: not a directory", dir));
+        }
+        load_playlist(&Path::new(dir).join(PLAYLIST_FILE))?;
+        Ok(Slideshow {
+            dir: PathBuf::from(dir),
+            include: include.to_vec(),
+            shuffle,
+            no_repeat_last,
+            random: Random::new(),
+            played: HashSet::new(),
+            history: VecDeque::new(),
+            error: None,
+        })
+    }
+
+    // the images to play and their weight, the playlist file is read again with them
+    fn images(&mut self) -> Vec<(PathBuf, f32)> {
+        let mut paths = Vec::new();
+        scan(&self.dir, &self.include, &mut paths);
+        paths.sort();
+
+        // the previous weights would be as wrong as none, the error is only printed
+        let playlist = match load_playlist(&self.dir.join(PLAYLIST_FILE)) {
+            Ok(x) => {
+                self.error = None;
+                x
+            }
+            Err(e) => {
+                if self.error.as_ref() != Some(&e) {
+                    eprintln!("{}", e);
+                }
+                self.error = Some(e);
+                Playlist::default()
+            }
+        };
+        paths
+            .into_iter()
+            .map(|path| {
+                let relative = path.strip_prefix(&self.dir).unwrap_or(&path);
+                let relative = relative.to_string_lossy();
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                let weight = playlist
+                    .weight
+                    .iter()
+                    .find(|x| {
+                        wildcard_match(&x.files, &relative) || wildcard_match(&x.files, &name)
+                    })
+                    .map_or(1.0, |x| x.weight);
+                (path, weight)
+            })
+            .filter(|(_, weight)| *weight > 0.0)
+            .collect()
+    }
+
+    // the images played, in their name order
+    pub fn playlist(&mut self) -> Vec<PathBuf> {
+        self.images().into_iter().map(|(x, _)| x).collect()
+    }
+
+    // the next image, and whether it starts a new pass. none when the directory has no image.
+    // shuffled, the images are drawn by their weight and a pass ends once they were all played
+    pub fn next(&mut self) -> Option<(PathBuf, bool)> {
+        let images = self.images();
+        if images.is_empty() {
+            return None;
+        }
+        let new_pass = images.iter().all(|(x, _)| self.played.contains(x));
+        if new_pass {
+            self.played.clear();
+        }
+
+        let path = match self.shuffle {
+            true => {
+                // the last ones are not drawn again, one image at least is left
+                let recent = self.no_repeat_last.min(images.len() - 1);
+                let candidates: Vec<&(PathBuf, f32)> = images
+                    .iter()
+                    .filter(|(x, _)| !self.history.iter().rev().take(recent).any(|y| y == x))
+                    .collect();
+                let weights: Vec<f32> = candidates.iter().map(|(_, x)| *x).collect();
+                let value = self.random.unit() * weights.iter().sum::<f32>();
+                candidates[weighted_pick(&weights, value)].0.clone()
+            }
+            false => match images.iter().find(|(x, _)| !self.played.contains(x)) {
+                Some((x, _)) => x.clone(),
+                None => return None,
+            },
+        };
+        self.played.insert(path.clone());
+        self.history.push_back(path.clone());
+        if self.history.len() > self.no_repeat_last {
+            self.history.pop_front();
+        }
+        Some((path, new_pass))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards() {
+        assert!(wildcard_match("*.gif", "sf2.gif"));
+        assert!(wildcard_match("*", "sf2.png"));
+        assert!(wildcard_match("sf?-*.png", "sf2-marquee.png"));
+        assert!(wildcard_match("*a*b", "xaab"));
+        assert!(!wildcard_match("*.gif", "sf2.png"));
+        assert!(!wildcard_match("sf?.png", "sf.png"));
+    }
+
+    #[test]
+    fn weights() {
+        let weights = [1.0, 0.0, 3.0];
+        assert_eq!(weighted_pick(&weights, 0.0), 0);
+        assert_eq!(weighted_pick(&weights, 0.99), 0);
+        assert_eq!(weighted_pick(&weights, 1.0), 2);
+        assert_eq!(weighted_pick(&weights, 4.0), 2);
+
+        let playlist: Playlist = toml::from_str(
+            "[[weight]]\nfiles = \"sf2*\"\nweight = 3\n[[weight]]\nfiles = \"*\"\nweight = 0.5\n",
+        )
+        .unwrap();
+        assert_eq!(playlist.weight.len(), 2);
+        assert_eq!(playlist.weight[0].weight, 3.0);
+    }
+}
@@ -0,0 +1,157 @@
+use crate::{imageutils, send_frame, Dmd};
+use image::{Rgba, RgbaImage};
+use std::{
+    fs,
+    fs::File,
+    io::{BufReader, Read},
+    time::Duration,
+};
+
+// pixel format of the frames written by the emulator
+pub enum SourceFormat {
+    GRAY2,  // 1 byte per pixel, 0-3
+    GRAY4,  // 1 byte per pixel, 0-15
+    RGB24,  // 3 bytes per pixel
+    RGB565, // 2 bytes per pixel, like the dmd server
+}
+
+pub fn parse_source_format(format: &str) -> Result<SourceFormat, String> {
+    match format {
+        "gray2" => Ok(SourceFormat::GRAY2),
+        "gray4" => Ok(SourceFormat::GRAY4),
+        "rgb24" => Ok(SourceFormat::RGB24),
+        "rgb565" => Ok(SourceFormat::RGB565),
+        _ => Err(format!(
+            "Invalid source format: {} (gray2, gray4, rgb24 or rgb565)",
+            format
+        )),
+    }
+}
+
+fn frame_size(format: &SourceFormat, width: u32, height: u32) -> usize {
+    let pixels = (width * height) as usize;
+    match format {
+        SourceFormat::GRAY2 | SourceFormat::GRAY4 => pixels,
+        SourceFormat::RGB24 => pixels * 3,
+        SourceFormat::RGB565 => pixels * 2,
+    }
+}
+
+// shades are displayed in the text color, unless a palette recolors them
+fn shade(level: u8, max: u8, color: Rgba<u8>, tint: bool) -> Rgba<u8> {
+    let level = level.min(max) as u32;
+    if tint {
+        let channel = |x: u8| (x as u32 * level / max as u32) as u8;
+        Rgba([channel(color[0]), channel(color[1]), channel(color[2]), 255])
+    } else {
+        let gray = (255 * level / max as u32) as u8;
+        Rgba([gray, gray, gray, 255])
+    }
+}
+
+fn source_image(
+    bytes: &[u8],
+    format: &SourceFormat,
+    width: u32,
+    height: u32,
+    color: Rgba<u8>,
+    tint: bool,
+) -> RgbaImage {
+    match format {
+        SourceFormat::GRAY2 => RgbaImage::from_fn(width, height, |x, y| {
+            shade(bytes[(y * width + x) as usize], 3, color, tint)
+        }),
+        SourceFormat::GRAY4 => RgbaImage::from_fn(width, height, |x, y| {
+            shade(bytes[(y * width + x) as usize], 15, color, tint)
+        }),
+        SourceFormat::RGB24 => RgbaImage::from_fn(width, height, |x, y| {
+            let i = (y * width + x) as usize * 3;
+            Rgba([bytes[i], bytes[i + 1], bytes[i + 2], 255])
+        }),
+        SourceFormat::RGB565 => imageutils::dmdimage2image(bytes, width, height),
+    }
+}
+
+// forward the frames of an emulator: a named pipe is read frame by frame until it is closed,
+// a file (a shared memory segment in /dev/shm) is read again at each tick
+pub fn play_source(
+    dmd: &Dmd,
+    path: &str,
+    format: &SourceFormat,
+    source_size: (u32, u32),
+    fps: u32,
+    text_color: Rgba<u8>,
+    once: bool,
+) -> Result<(), String> {
+    let (dmd_width, dmd_height, image_options) = (dmd.width, dmd.height, dmd.image_options);
+    let (width, height) = source_size;
+    let size = frame_size(format, width, height);
+    if size == 0 || fps == 0 {
+        return Err(String::from("Invalid source size or fps"));
+    }
+    let tint = image_options.palette.is_none();
+
+    let is_file = match fs::metadata(path) {
+        Ok(x) => x.is_file(),
+        Err(e) => return Err(format!("Error: {}: {}", path, e)),
+    };
+    let mut stream = if is_file {
+        None
+    } else {
+        match File::open(path) {
+            Ok(x) => Some(BufReader::new(x)),
+            Err(e) => return Err(format!("Error: {}: {}", path, e)),
+        }
+    };
+
+    let mut bytes = vec![0u8; size];
+    let mut previous_frame: Option<Box<[u8]>> = None;
+    loop {
+        match stream.as_mut() {
+            Some(stream) => {
+                if stream.read_exact(&mut bytes).is_err() {
+                    // the emulator is gone
+                    return Ok(());
+                }
+            }
+            None => match fs::read(path) {
+                Ok(x) if x.len() >= size => bytes.copy_from_slice(&x[..size]),
+                // not written yet
+                Ok(_) => {
+                    if dmd.cancel.sleep(Duration::from_millis(1000 / fps as u64)) {
+                        return Ok(());
+                    }
+                    continue;
+                }
+                Err(e) => return Err(format!("Error: {}: {}", path, e)),
+            },
+        }
+
+        let img = source_image(&bytes, format, width, height, text_color, tint);
+        let img = imageutils::fit_image(&img, image_options, dmd_width, dmd_height)?;
+        let img565 = imageutils::image2dmdimage(
+            &img,
+            &imageutils::TextAlign::CENTER,
+            &image_options.colors,
+            dmd_width,
+            dmd_height,
+        )?;
+        if previous_frame.as_ref() != Some(&img565) {
+            if let Err(e) = send_frame(dmd.client, dmd.header, &img565) {
+                return Err(e.to_string());
+            }
+            previous_frame = Some(img565);
+        }
+
+        if once {
+            return Ok(());
+        }
+        let cancelled = match stream {
+            Some(_) => dmd.cancel.is_cancelled(),
+            None => dmd.cancel.sleep(Duration::from_millis(1000 / fps as u64)),
+        };
+        if cancelled {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use crate::{cancel::CancelToken, sink::FrameSink, DMD_HEADER_SIZE};
+use std::{
+    env, io,
+    net::TcpListener,
+    os::{
+        fd::FromRawFd,
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixDatagram},
+    },
+    process,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+// the first socket passed by systemd
+const LISTEN_FDS_START: i32 = 3;
+
+// the variable is for this process, and not one of its parents
+fn for_this_process(name: &str) -> bool {
+    env::var(name).is_ok_and(|x| x == process::id().to_string())
+}
+
+// the sockets passed by systemd (socket activation), in the order of the .socket unit
+pub fn activated_listeners() -> Vec<TcpListener> {
+    if !for_this_process("LISTEN_PID") {
+        return Vec::new();
+    }
+    let n = env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|x| x.parse::<i32>().ok())
+        .unwrap_or(0);
+    (LISTEN_FDS_START..LISTEN_FDS_START + n)
+        // the descriptors are given to this process, and taken only once
+        .map(|fd| unsafe { TcpListener::from_raw_fd(fd) })
+        .collect()
+}
+
+// tell systemd about the state of dmd-play (READY=1, WATCHDOG=1), when started by a notify service
+pub fn notify(state: &str) -> Result<(), String> {
+    let path = match env::var("NOTIFY_SOCKET") {
+        Ok(x) => x,
+        Err(_) => return Ok(()),
+    };
+    // @ is a socket of the abstract namespace
+    let address = match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name.as_bytes()),
+        None => SocketAddr::from_pathname(&path),
+    };
+    let result = UnixDatagram::unbound().and_then(|socket| {
+        address.and_then(|address| socket.send_to_addr(state.as_bytes(), &address))
+    });
+    match result {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("Unable to notify systemd: {}", e)),
+    }
+}
+
+// the watchdog of the service, fed by the progress of the playing: the frames sent, and the relay
+// accepting its sources. systemd is notified at half its interval while the playing was fed during
+// the interval, no frame is failing or blocked for as long, and the playing is not cancelled:
+// WatchdogSec has to be longer than the time between two frames. the clones share it
+#[derive(Clone, Default)]
+pub struct Watchdog(Option<Arc<(Duration, Mutex<WatchdogState>)>>);
+
+struct WatchdogState {
+    fed: Instant,
+    sending: Option<Instant>, // since when the frame being sent is sent
+    failed: bool,             // the last frame could not be sent
+}
+
+impl Watchdog {
+    // the watchdog of the service when systemd set one for this process, a disabled one otherwise
+    pub fn from_env() -> Watchdog {
+        let usec = match env::var("WATCHDOG_USEC")
+            .ok()
+            .and_then(|x| x.parse::<u64>().ok())
+        {
+            Some(x) if x > 0 => x,
+            _ => return Watchdog::default(),
+        };
+        if env::var("WATCHDOG_PID").is_ok() && !for_this_process("WATCHDOG_PID") {
+            return Watchdog::default();
+        }
+        let state = WatchdogState {
+            fed: Instant::now(),
+            sending: None,
+            failed: false,
+        };
+        Watchdog(Some(Arc::new((
+            Duration::from_micros(usec),
+            Mutex::new(state),
+        ))))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.0.is_some()
+    }
+
+    fn update(&self, f: impl FnOnce(&mut WatchdogState)) {
+        if let Some((_, state)) = self.0.as_deref()
+            && let Ok(mut state) = state.lock()
+        {
+            f(&mut state);
+        }
+    }
+
+    pub fn feed(&self) {
+        self.update(|x| x.fed = Instant::now());
+    }
+
+    // notify systemd until the playing is cancelled, as long as it goes on
+    pub fn start(&self, cancel: &CancelToken) {
+        let Some(watchdog) = self.0.clone() else {
+            return;
+        };
+        let cancel = cancel.clone();
+        thread::spawn(move || {
+            let (interval, state) = &*watchdog;
+            while !cancel.sleep(*interval / 2) {
+                let alive = state.lock().is_ok_and(|x| {
+                    x.fed.elapsed() < *interval
+                        && !x.failed
+                        && x.sending.is_none_or(|x| x.elapsed() < *interval)
+                });
+                if alive && let Err(e) = notify("WATCHDOG=1") {
+                    eprintln!("{}", e);
+                }
+            }
+        });
+    }
+}
+
+// the frames sent feed the watchdog, the ones failing or blocked do not
+pub struct WatchdogSink {
+    inner: Box<dyn FrameSink>,
+    watchdog: Watchdog,
+}
+
+impl WatchdogSink {
+    pub fn new(inner: Box<dyn FrameSink>, watchdog: Watchdog) -> WatchdogSink {
+        WatchdogSink { inner, watchdog }
+    }
+}
+
+impl FrameSink for WatchdogSink {
+    fn send(&self, header: [u8; DMD_HEADER_SIZE], frame: &[u8]) -> io::Result<()> {
+        self.watchdog.update(|x| x.sending = Some(Instant::now()));
+        let result = self.inner.send(header, frame);
+        self.watchdog.update(|x| {
+            x.sending = None;
+            x.failed = result.is_err();
+            if result.is_ok() {
+                x.fed = Instant::now();
+            }
+        });
+        result
+    }
+
+    fn close(&self) -> io::Result<()> {
+        self.inner.close()
+    }
+}
@@ -0,0 +1,453 @@
+use crate::{
+    clock_text, files_to_frames, imageutils, layout, parse_color, send_frame, watch, ClockOptions,
+    Dmd, TextOptions, DEFAULT_DURATION,
+};
+use chrono::Local;
+use chrono_tz::Tz;
+use image::{Rgba, RgbaImage};
+use serde::Deserialize;
+use std::{collections::HashMap, env, fs, time::Duration};
+
+// a length in pixels (32), or in percent of the dmd size ("40%")
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Length {
+    PIXELS(u32),
+    TEXT(String),
+}
+
+#[derive(Deserialize)]
+struct Area {
+    #[serde(default = "zero")]
+    x: Length,
+    #[serde(default = "zero")]
+    y: Length,
+    #[serde(default = "full")]
+    width: Length,
+    #[serde(default = "full")]
+    height: Length,
+}
+
+fn zero() -> Length {
+    Length::PIXELS(0)
+}
+
+fn full() -> Length {
+    Length::TEXT(String::from("100%"))
+}
+
+// the elements are drawn in the order of the file, the last one on top
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Element {
+    TEXT {
+        #[serde(flatten)]
+        area: Area,
+        text: String,
+        color: Option<String>,
+        align: Option<String>,
+    },
+    IMAGE {
+        #[serde(flatten)]
+        area: Area,
+        file: String,
+        align: Option<String>,
+    },
+    RECT {
+        #[serde(flatten)]
+        area: Area,
+        color: String,
+        #[serde(default)]
+        fill: bool,
+    },
+    PROGRESS {
+        #[serde(flatten)]
+        area: Area,
+        value: String,
+        #[serde(default = "hundred")]
+        max: String,
+        color: Option<String>,
+        background: Option<String>,
+    },
+}
+
+fn hundred() -> String {
+    String::from("100")
+}
+
+#[derive(Deserialize)]
+pub struct Template {
+    // color behind the elements (R,G,B), default is the --background-* options
+    background: Option<String>,
+    // font of the texts, default is --font
+    font: Option<String>,
+    #[serde(default, rename = "element")]
+    elements: Vec<Element>,
+}
+
+pub fn load_template(path: &str) -> Result<Template, String> {
+    let content = match fs::read_to_string(path) {
+        Ok(x) => x,
+        Err(e) => return Err(format!("Error: {}: {}", path, e)),
+    };
+
+    if path.to_lowercase().ends_with(".json") {
+        serde_json::from_str(&content).map_err(|e| format!("Error: {}: {}", path, e))
+    } else {
+        toml::from_str(&content).map_err(|e| format!("Error: {}: {}", path, e))
+    }
+}
+
+// "name=value" pairs given with --var
+pub fn parse_vars(vars: &[String]) -> Result<HashMap<String, String>, String> {
+    let mut values = HashMap::new();
+    for var in vars {
+        match var.split_once('=') {
+            Some((name, value)) if !name.is_empty() => {
+                values.insert(name.to_string(), value.to_string());
+            }
+            _ => return Err(format!("Invalid variable: {} (expected name=value)", var)),
+        }
+    }
+    Ok(values)
+}
+
+// replace {name} by its value, {time} and {date} are always available, unknown names are kept
+fn substitute(text: &str, vars: &HashMap<String, String>, clock: &ClockOptions) -> String {
+    let now = Local::now();
+    let date = ClockOptions {
+        format: Some(String::from("%Y-%m-%d")),
+        ..clock.clone()
+    };
+    let result = text
+        .replace("{time}", &clock_text(&now, clock))
+        .replace("{date}", &clock_text(&now, &date));
+    expand_placeholders(&substitute_vars(&result, vars), &clock.timezone)
+}
+
+// replace {name} by the value of the variable given with --var
+pub fn substitute_vars(text: &str, vars: &HashMap<String, String>) -> String {
+    let mut result = text.to_string();
+    for (name, value) in vars {
+        result = result.replace(&format!("{{{}}}", name), value);
+    }
+    result
+}
+
+// replace {env:NAME}, {time:FORMAT} (strftime) and {file:PATH} (first line of the file)
+// by their current value, other braces are kept
+pub fn expand_placeholders(text: &str, timezone: &Option<Tz>) -> String {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('{') {
+        let end = match rest[start..].find('}') {
+            Some(x) => start + x,
+            None => break,
+        };
+        result.push_str(&rest[..start]);
+        let placeholder = &rest[start + 1..end];
+        match placeholder.split_once(':') {
+            Some(("env", name)) => result.push_str(&env::var(name).unwrap_or_default()),
+            Some(("time", format)) => {
+                let clock = ClockOptions {
+                    format: Some(format.to_string()),
+                    h12: false,
+                    no_seconds: false,
+                    timezone: *timezone,
+                };
+                result.push_str(&clock_text(&Local::now(), &clock))
+            }
+            // a missing file is displayed as empty, it may be written later
+            Some(("file", path)) => result.push_str(
+                fs::read_to_string(path)
+                    .unwrap_or_default()
+                    .lines()
+                    .next()
+                    .unwrap_or_default(),
+            ),
+            _ => result.push_str(&rest[start..=end]),
+        }
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    result
+}
+
+// text depending on the current time, the environment or files must be rendered again
+pub fn is_dynamic(text: &str) -> bool {
+    text.contains("{time") || text.contains("{env:") || text.contains("{file:")
+}
+
+fn uses_time(template: &Template) -> bool {
+    template.elements.iter().any(|element| match element {
+        Element::TEXT { text, .. } => is_dynamic(text),
+        Element::PROGRESS { value, max, .. } => is_dynamic(value) || is_dynamic(max),
+        _ => false,
+    })
+}
+
+fn length(value: &Length, total: u32) -> Result<u32, String> {
+    match value {
+        Length::PIXELS(x) => Ok(*x),
+        Length::TEXT(x) => layout::parse_length(x, total),
+    }
+}
+
+fn area_rect(
+    area: &Area,
+    render_width: u32,
+    render_height: u32,
+) -> Result<(u32, u32, u32, u32), String> {
+    let x = length(&area.x, render_width)?;
+    let y = length(&area.y, render_height)?;
+    let width = length(&area.width, render_width)?;
+    let height = length(&area.height, render_height)?;
+    if width == 0 || height == 0 {
+        return Err(String::from("Invalid template element: empty area"));
+    }
+    Ok((x, y, width, height))
+}
+
+fn parse_align(align: &Option<String>) -> Result<imageutils::TextAlign, String> {
+    match align.as_deref() {
+        None | Some("center") => Ok(imageutils::TextAlign::CENTER),
+        Some("left") => Ok(imageutils::TextAlign::LEFT),
+        Some("right") => Ok(imageutils::TextAlign::RIGHT),
+        Some(x) => Err(format!("Invalid template alignment: {}", x)),
+    }
+}
+
+// blend an image at a given position of the frame
+fn draw_image(frame: &RgbaImage, img: RgbaImage, x: u32, y: u32) -> RgbaImage {
+    let mut frame = frame.clone();
+    imageutils::blend_image(&img, &mut frame, x as i32, y as i32);
+    frame
+}
+
+// the style of the texts, with the font of the template, loaded once for all its texts
+fn template_style(
+    template: &Template,
+    style: &imageutils::TextStyle,
+) -> Result<imageutils::TextStyle, String> {
+    Ok(imageutils::TextStyle {
+        font: match &template.font {
+            Some(path) => imageutils::load_font(path)?,
+            None => style.font.clone(),
+        },
+        ..style.clone()
+    })
+}
+
+pub fn render_template(
+    template: &Template,
+    vars: &HashMap<String, String>,
+    style: &imageutils::TextStyle,
+    background: &RgbaImage,
+    clock: &ClockOptions,
+    fps: u32,
+    raw_size: (u32, u32),
+) -> Result<RgbaImage, String> {
+    let (render_width, render_height) = background.dimensions();
+    let mut frame = match &template.background {
+        Some(color) => RgbaImage::from_pixel(render_width, render_height, parse_color(color)?),
+        None => background.clone(),
+    };
+
+    for element in &template.elements {
+        match element {
+            Element::TEXT {
+                area,
+                text,
+                color,
+                align,
+            } => {
+                let (x, y, width, height) = area_rect(area, render_width, render_height)?;
+                let style = imageutils::TextStyle {
+                    color: match color {
+                        Some(x) => parse_color(x)?,
+                        None => style.color,
+                    },
+                    align: parse_align(align)?,
+                    ..style.clone()
+                };
+                let (dyn_img, _start, _new_width) = imageutils::generate_text_image(
+                    &substitute(text, vars, clock),
+                    &style,
+                    &None,
+                    width,
+                    height,
+                )?;
+                frame = draw_image(&frame, dyn_img.to_rgba8(), x, y);
+            }
+            Element::IMAGE { area, file, align } => {
+                let (x, y, width, height) = area_rect(area, render_width, render_height)?;
+                // animations are not supported in templates, only the first frame is used
+                let frames = files_to_frames(file.clone(), fps, raw_size, DEFAULT_DURATION)?;
+                let Some(first) = frames.first() else {
+                    return Err(format!("Error: {}: no image", file));
+                };
+                let img =
+                    imageutils::image2dmdsize(first.buffer(), &parse_align(align)?, width, height);
+                frame = draw_image(&frame, img, x, y);
+            }
+            Element::RECT { area, color, fill } => {
+                let (x, y, width, height) = area_rect(area, render_width, render_height)?;
+                if *fill {
+                    imageutils::fill_rect(
+                        &mut frame,
+                        x as i32,
+                        y as i32,
+                        width,
+                        height,
+                        parse_color(color)?,
+                    );
+                } else {
+                    imageutils::draw_rect(
+                        &mut frame,
+                        x as i32,
+                        y as i32,
+                        width,
+                        height,
+                        parse_color(color)?,
+                    );
+                }
+            }
+            Element::PROGRESS {
+                area,
+                value,
+                max,
+                color,
+                background,
+            } => {
+                let (x, y, width, height) = area_rect(area, render_width, render_height)?;
+                let value = substitute(value, vars, clock);
+                let max = substitute(max, vars, clock);
+                let (value, max) = match (value.trim().parse::<f32>(), max.trim().parse::<f32>()) {
+                    (Ok(value), Ok(max)) if max > 0.0 => (value, max),
+                    _ => {
+                        return Err(format!("Invalid progress value: {} / {}", value, max));
+                    }
+                };
+                let color = match color {
+                    Some(x) => parse_color(x)?,
+                    None => Rgba([style.color[0], style.color[1], style.color[2], 255]),
+                };
+
+                if let Some(background) = background {
+                    imageutils::fill_rect(
+                        &mut frame,
+                        x as i32,
+                        y as i32,
+                        width,
+                        height,
+                        parse_color(background)?,
+                    );
+                }
+                let filled = (width as f32 * (value / max).clamp(0.0, 1.0)).round() as u32;
+                imageutils::fill_rect(&mut frame, x as i32, y as i32, filled, height, color);
+            }
+        }
+    }
+
+    Ok(frame)
+}
+
+// what the templates are rendered with
+pub struct TemplateOptions<'a> {
+    pub vars: &'a HashMap<String, String>,
+    pub text: &'a TextOptions,
+    pub clock: &'a ClockOptions,
+    pub fps: u32,             // of the directories and raw files
+    pub raw_size: (u32, u32), // of the raw files
+}
+
+// render the template and send it, again each second when it displays the time.
+// with a watcher, the template is loaded again from path each time it is written, on the same connection
+pub fn play_template(
+    dmd: &Dmd,
+    template: Template,
+    path: &str,
+    mut watcher: Option<watch::FileWatcher>,
+    options: &TemplateOptions,
+    once: bool,
+) -> Result<bool, String> {
+    let mut previous_frame: Option<Box<[u8]>> = None;
+    let mut template = template;
+    let mut style = template_style(&template, &options.text.style);
+
+    loop {
+        // a font that cannot be read is reported like the rest of the template
+        let frame = match &style {
+            Ok(style) => render_template(
+                &template,
+                options.vars,
+                style,
+                &options.text.background,
+                options.clock,
+                options.fps,
+                options.raw_size,
+            ),
+            Err(e) => Err(e.clone()),
+        };
+        let frame = frame.and_then(|frame| {
+            let frame = imageutils::orient_image(&frame, &dmd.image_options.orientation);
+            imageutils::image2dmdimage(
+                &frame,
+                &imageutils::TextAlign::CENTER,
+                &dmd.image_options.colors,
+                dmd.width,
+                dmd.height,
+            )
+        });
+        // a template that cannot be rendered is reported, the previous frame stays until it is fixed
+        let rendered = match (frame, &watcher) {
+            (Ok(img565), _) => {
+                if previous_frame.as_ref() != Some(&img565) {
+                    match send_frame(dmd.client, dmd.header, &img565) {
+                        Ok(_) => {}
+                        Err(e) => {
+                            return Err(e.to_string());
+                        }
+                    };
+                    previous_frame = Some(img565);
+                }
+                true
+            }
+            (Err(e), Some(_)) => {
+                eprintln!("{}", e);
+                false
+            }
+            (Err(e), None) => return Err(e),
+        };
+
+        let refreshed = rendered && !once && uses_time(&template);
+        let changed = match watcher.as_mut() {
+            None if !refreshed => return Ok(false),
+            None => {
+                if dmd.cancel.sleep(Duration::from_millis(1000)) {
+                    return Ok(false);
+                }
+                false
+            }
+            Some(watcher) if !refreshed => match watcher.wait(dmd.cancel)? {
+                true => true,
+                false => return Ok(false),
+            },
+            Some(watcher) => {
+                if dmd.cancel.sleep(Duration::from_millis(1000)) {
+                    return Ok(false);
+                }
+                watcher.changed()?
+            }
+        };
+        if changed {
+            match load_template(path) {
+                Ok(x) => {
+                    template = x;
+                    style = template_style(&template, &options.text.style);
+                }
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+    }
+}
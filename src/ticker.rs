@@ -0,0 +1,121 @@
+use crate::poll;
+
+// the chart api gives the last price and the previous close without a key, crypto included
+const QUOTE_URL: &str = "https://query1.finance.yahoo.com/v8/finance/chart/";
+
+const UP_COLOR: &str = "green";
+const DOWN_COLOR: &str = "red";
+
+// the symbols of --ticker (BTC-USD,AAPL)
+pub fn parse_symbols(symbols: &str) -> Result<Vec<String>, String> {
+    let symbols: Vec<String> = symbols
+        .split(',')
+        .map(|x| x.trim().to_uppercase())
+        .filter(|x| !x.is_empty())
+        .collect();
+    if symbols.is_empty() {
+        return Err(String::from("Invalid ticker: no symbol"));
+    }
+    Ok(symbols)
+}
+
+// the price and the previous close of a symbol
+fn fetch_quote(symbol: &str) -> Result<(f64, f64), String> {
+    let value = poll::fetch_json(&format!("{}{}", QUOTE_URL, symbol))?;
+    let meta = poll::select(&value, ".chart.result[0].meta")
+        .ok_or(format!("Error: no quote for {}", symbol))?;
+    let number = |path: &str| poll::select(meta, path).and_then(|x| x.as_f64());
+    match (
+        number("regularMarketPrice"),
+        number("chartPreviousClose").or_else(|| number("previousClose")),
+    ) {
+        (Some(price), Some(close)) => Ok((price, close)),
+        _ => Err(format!("Error: no quote for {}", symbol)),
+    }
+}
+
+// 64120 as 64,120, the prices below 1000 with their cents
+fn format_price(price: f64) -> String {
+    let text = match price.abs() >= 1000.0 {
+        true => format!("{:.0}", price.abs()),
+        false => format!("{:.2}", price.abs()),
+    };
+    let (units, decimals) = match text.split_once('.') {
+        Some((units, decimals)) => (units, format!(".{}", decimals)),
+        None => (text.as_str(), String::new()),
+    };
+    let mut grouped = String::new();
+    for (n, c) in units.chars().enumerate() {
+        if n > 0 && (units.len() - n) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(c);
+    }
+    let sign = if price < 0.0 { "-" } else { "" };
+    format!("{}{}{}", sign, grouped, decimals)
+}
+
+// BTC 64,120 ▲1.2%, the change colored with the color markup. BTC-USD is shown as BTC
+fn quote_text(symbol: &str, price: f64, close: f64) -> String {
+    let name = symbol.split('-').next().unwrap_or(symbol);
+    let change = match close == 0.0 {
+        true => 0.0,
+        false => (price - close) / close * 100.0,
+    };
+    let (arrow, color) = match change < 0.0 {
+        true => ('▼', DOWN_COLOR),
+        false => ('▲', UP_COLOR),
+    };
+    format!(
+        "{} {} {{color:{}}}{}{:.1}%{{/color}}",
+        name,
+        format_price(price),
+        color,
+        arrow,
+        change.abs()
+    )
+}
+
+// the quotes one after the other, a symbol failing is left out while the others are shown
+pub fn ticker_text(symbols: &[String]) -> Result<String, String> {
+    let mut quotes = Vec::new();
+    let mut error = None;
+    for symbol in symbols {
+        match fetch_quote(symbol) {
+            Ok((price, close)) => quotes.push(quote_text(symbol, price, close)),
+            Err(e) => {
+                eprintln!("{}", e);
+                error = Some(e);
+            }
+        }
+    }
+    match (quotes.is_empty(), error) {
+        (true, Some(e)) => Err(e),
+        _ => Ok(quotes.join("   ")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes() {
+        assert_eq!(format_price(64120.4), "64,120");
+        assert_eq!(format_price(1234567.0), "1,234,567");
+        assert_eq!(format_price(189.456), "189.46");
+        assert_eq!(
+            quote_text("BTC-USD", 64120.0, 63360.0),
+            "BTC 64,120 {color:green}▲1.2%{/color}"
+        );
+        assert_eq!(
+            quote_text("AAPL", 99.0, 100.0),
+            "AAPL 99.00 {color:red}▼1.0%{/color}"
+        );
+        assert_eq!(
+            parse_symbols(" btc-usd,,AAPL").unwrap(),
+            vec!["BTC-USD", "AAPL"]
+        );
+        assert!(parse_symbols(",").is_err());
+    }
+}
@@ -0,0 +1,113 @@
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    pki_types::{pem::PemObject, CertificateDer, ServerName, UnixTime},
+    ClientConfig, ClientConnection, DigitallySignedStruct, RootCertStore, SignatureScheme,
+    StreamOwned,
+};
+use std::{net::TcpStream, sync::Arc};
+
+// --insecure: any certificate is accepted, the connection is encrypted but not authenticated
+#[derive(Debug)]
+struct NoVerifier;
+
+impl ServerCertVerifier for NoVerifier {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        rustls::crypto::ring::default_provider()
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+// the certificates of a pem file, or the usual certificate authorities
+fn root_store(ca_file: &Option<String>) -> Result<RootCertStore, String> {
+    let mut store = RootCertStore::empty();
+    match ca_file {
+        Some(path) => {
+            let certificates = match CertificateDer::pem_file_iter(path) {
+                Ok(x) => x,
+                Err(e) => return Err(format!("Error: {}: {}", path, e)),
+            };
+            for certificate in certificates {
+                let certificate = certificate.map_err(|e| format!("Error: {}: {}", path, e))?;
+                if let Err(e) = store.add(certificate) {
+                    return Err(format!("Error: {}: {}", path, e));
+                }
+            }
+        }
+        None => store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+    }
+    Ok(store)
+}
+
+// the connection to the server wrapped in tls, the server certificate is checked against ca_file
+// (the usual certificate authorities by default), or not at all when insecure
+pub fn connect_tls(
+    stream: TcpStream,
+    host: &str,
+    ca_file: &Option<String>,
+    insecure: bool,
+) -> Result<StreamOwned<ClientConnection, TcpStream>, String> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder =
+        match ClientConfig::builder_with_provider(provider).with_safe_default_protocol_versions() {
+            Ok(x) => x,
+            Err(e) => return Err(e.to_string()),
+        };
+    let config = if insecure {
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(NoVerifier))
+            .with_no_client_auth()
+    } else {
+        builder
+            .with_root_certificates(root_store(ca_file)?)
+            .with_no_client_auth()
+    };
+
+    let server_name = match ServerName::try_from(host.to_string()) {
+        Ok(x) => x,
+        Err(e) => return Err(format!("Invalid host name {}: {}", host, e)),
+    };
+    let connection = match ClientConnection::new(Arc::new(config), server_name) {
+        Ok(x) => x,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    // the handshake is done now, so that an invalid certificate is reported before playing
+    let mut stream = StreamOwned::new(connection, stream);
+    while stream.conn.is_handshaking() {
+        if let Err(e) = stream.conn.complete_io(&mut stream.sock) {
+            return Err(format!("TLS handshake failed: {}", e));
+        }
+    }
+    Ok(stream)
+}
@@ -0,0 +1,297 @@
+use crate::{cancel::CancelToken, imageutils, send_render, Dmd};
+use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+use std::{
+    f32::consts::PI,
+    io::{stdin, BufReader, Read},
+    os::unix::process::CommandExt,
+    process::{Child, Command, Stdio},
+    sync::Mutex,
+    thread,
+    time::Duration,
+};
+
+// the audio is read as signed 16 bits little endian mono samples at this rate
+pub const AUDIO_RATE: u32 = 44100;
+const FFT_SIZE: usize = 2048;
+// frequency range of the spectrum, the bands are spread on a log scale
+const MIN_FREQUENCY: f32 = 40.0;
+const MAX_FREQUENCY: f32 = 16000.0;
+// levels below are not displayed
+const MIN_DB: f32 = -60.0;
+// longest delay before the audio command is killed once the playing is cancelled
+const CANCEL_POLL: Duration = Duration::from_millis(100);
+
+pub enum VisualizerMode {
+    SPECTRUM,
+    VU,
+}
+
+// in place iterative radix-2 fft, the size must be a power of 2
+fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+
+    // bit reversal permutation
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * PI / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (w_im, w_re) = (angle * k as f32).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+                let t_re = re[b] * w_re - im[b] * w_im;
+                let t_im = re[b] * w_im + im[b] * w_re;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+fn db_to_level(db: f32) -> f32 {
+    ((db - MIN_DB) / -MIN_DB).clamp(0.0, 1.0)
+}
+
+// level (0.0-1.0) of each band of the spectrum
+fn spectrum_levels(samples: &[f32], nbands: usize) -> Vec<f32> {
+    let n = samples.len();
+    // hann window, to limit the leaks between the bands
+    let mut re: Vec<f32> = samples
+        .iter()
+        .enumerate()
+        .map(|(i, x)| x * (0.5 - 0.5 * (2.0 * PI * i as f32 / (n - 1) as f32).cos()))
+        .collect();
+    let mut im = vec![0.0; n];
+    fft(&mut re, &mut im);
+
+    let bin_width = AUDIO_RATE as f32 / n as f32;
+    let ratio = MAX_FREQUENCY / MIN_FREQUENCY;
+    (0..nbands)
+        .map(|band| {
+            let low = MIN_FREQUENCY * ratio.powf(band as f32 / nbands as f32);
+            let high = MIN_FREQUENCY * ratio.powf((band + 1) as f32 / nbands as f32);
+            let first = ((low / bin_width) as usize).clamp(1, n / 2 - 1);
+            let last = ((high / bin_width) as usize).clamp(first + 1, n / 2);
+            let magnitude = (first..last)
+                .map(|bin| (re[bin] * re[bin] + im[bin] * im[bin]).sqrt())
+                .fold(0.0, f32::max);
+            // a full scale sine gives a magnitude of n/4 with the hann window
+            db_to_level(20.0 * (magnitude / (n as f32 / 4.0)).max(1e-9).log10())
+        })
+        .collect()
+}
+
+fn rms_level(samples: &[f32]) -> f32 {
+    let rms = (samples.iter().map(|x| x * x).sum::<f32>() / samples.len().max(1) as f32).sqrt();
+    db_to_level(20.0 * rms.max(1e-9).log10())
+}
+
+// classic meter colors: green, then yellow and red at the top
+fn meter_color(position: f32, gradient: &Option<DynamicImage>, x: u32, y: u32) -> Rgba<u8> {
+    if let Some(gradient) = gradient
+        .as_ref()
+        .filter(|gradient| x < gradient.width() && y < gradient.height())
+    {
+        let pixel = gradient.get_pixel(x, y);
+        return Rgba([pixel[0], pixel[1], pixel[2], 255]);
+    }
+    if position > 0.9 {
+        Rgba([255, 0, 0, 255])
+    } else if position > 0.7 {
+        Rgba([255, 255, 0, 255])
+    } else {
+        Rgba([0, 255, 0, 255])
+    }
+}
+
+fn render_spectrum(
+    levels: &[f32],
+    peaks: &[f32],
+    background: &RgbaImage,
+    gradient: &Option<DynamicImage>,
+) -> RgbaImage {
+    let mut img = background.clone();
+    let (width, height) = img.dimensions();
+    let bar_width = (width / levels.len() as u32).max(1);
+
+    for (band, (level, peak)) in levels.iter().zip(peaks.iter()).enumerate() {
+        let x_start = band as u32 * bar_width;
+        let bar_height = (level * height as f32).round() as u32;
+        // 1 pixel between the bars when there is enough room
+        let drawn_width = if bar_width > 2 {
+            bar_width - 1
+        } else {
+            bar_width
+        };
+        for y in height - bar_height..height {
+            for x in x_start..(x_start + drawn_width).min(width) {
+                let position = (height - y) as f32 / height as f32;
+                img.put_pixel(x, y, meter_color(position, gradient, x, y));
+            }
+        }
+        let peak_y = height - 1 - ((peak * (height - 1) as f32).round() as u32).min(height - 1);
+        if *peak > 0.0 {
+            imageutils::fill_rect(
+                &mut img,
+                x_start as i32,
+                peak_y as i32,
+                drawn_width,
+                1,
+                Rgba([255, 255, 255, 255]),
+            );
+        }
+    }
+    img
+}
+
+fn render_vu(
+    level: f32,
+    peak: f32,
+    background: &RgbaImage,
+    gradient: &Option<DynamicImage>,
+) -> RgbaImage {
+    let mut img = background.clone();
+    let (width, height) = img.dimensions();
+    let margin = (height / 8).max(1);
+    let bar_length = (level * width as f32).round() as u32;
+
+    for x in 0..bar_length.min(width) {
+        for y in margin..height.saturating_sub(margin) {
+            img.put_pixel(x, y, meter_color(x as f32 / width as f32, gradient, x, y));
+        }
+    }
+    if peak > 0.0 {
+        let peak_x = ((peak * (width - 1) as f32).round() as u32).min(width - 1);
+        imageutils::fill_rect(
+            &mut img,
+            peak_x as i32,
+            0,
+            1,
+            height,
+            Rgba([255, 255, 255, 255]),
+        );
+    }
+    img
+}
+
+// the audio command and what it started (a pipeline), in the process group of its shell
+fn kill_command(child: &mut Child) {
+    // the group was created with the shell, which is not waited yet
+    unsafe {
+        libc::kill(-(child.id() as i32), libc::SIGKILL);
+    }
+}
+
+// read the audio from a command (or stdin with "-") and display it until the end of the stream
+pub fn play_visualizer(
+    dmd: &Dmd,
+    mode: &VisualizerMode,
+    audio_command: &str,
+    fps: u32,
+    background: &RgbaImage,
+    gradient: &Option<DynamicImage>,
+) -> Result<(), String> {
+    let (child, mut reader): (_, Box<dyn Read>) = if audio_command == "-" {
+        (None, Box::new(BufReader::new(stdin())))
+    } else {
+        let mut child = match Command::new("sh")
+            .arg("-c")
+            .arg(audio_command)
+            .stdout(Stdio::piped())
+            .process_group(0)
+            .spawn()
+        {
+            Ok(x) => x,
+            Err(e) => return Err(format!("Unable to run {}: {}", audio_command, e)),
+        };
+        let stdout = child.stdout.take().ok_or("Unable to read the audio")?;
+        (Some(child), Box::new(BufReader::new(stdout)))
+    };
+
+    let hop = (AUDIO_RATE / fps.max(1)) as usize;
+    let nbands = (background.width() / 4).max(1) as usize;
+    let mut window = vec![0.0f32; FFT_SIZE];
+    let mut buffer = vec![0u8; hop * 2];
+    let mut levels = vec![0.0f32; nbands];
+    let mut peaks = vec![0.0f32; nbands];
+    let mut vu_level = 0.0f32;
+    let mut vu_peak = 0.0f32;
+    // the bars fall in about half a second, the peaks in about 2 seconds
+    let decay = 2.0 / fps as f32;
+    let peak_decay = 0.5 / fps as f32;
+
+    // the command is killed once the playing is cancelled: its stream ends, and the loop with it
+    let (cancel, finished) = (dmd.cancel, CancelToken::default());
+    let child = Mutex::new(child);
+    let result = thread::scope(|scope| {
+        scope.spawn(|| {
+            while !finished.sleep(CANCEL_POLL) {
+                if cancel.is_cancelled() {
+                    if let Ok(mut child) = child.lock()
+                        && let Some(child) = child.as_mut()
+                    {
+                        kill_command(child);
+                    }
+                    return;
+                }
+            }
+        });
+        let result = loop {
+            if cancel.is_cancelled() || reader.read_exact(&mut buffer).is_err() {
+                // end of the stream
+                break Ok(());
+            }
+            let samples: Vec<f32> = buffer
+                .chunks_exact(2)
+                .map(|x| i16::from_le_bytes([x[0], x[1]]) as f32 / 32768.0)
+                .collect();
+            let keep = FFT_SIZE.saturating_sub(samples.len());
+            window.drain(..FFT_SIZE - keep);
+            window.extend_from_slice(&samples[samples.len().saturating_sub(FFT_SIZE)..]);
+
+            let img = match mode {
+                VisualizerMode::SPECTRUM => {
+                    for (i, level) in spectrum_levels(&window, nbands).iter().enumerate() {
+                        levels[i] = level.max(levels[i] - decay);
+                        peaks[i] = levels[i].max(peaks[i] - peak_decay);
+                    }
+                    render_spectrum(&levels, &peaks, background, gradient)
+                }
+                VisualizerMode::VU => {
+                    vu_level = rms_level(&samples).max(vu_level - decay);
+                    vu_peak = vu_level.max(vu_peak - peak_decay);
+                    render_vu(vu_level, vu_peak, background, gradient)
+                }
+            };
+
+            // the pace is given by the audio stream itself
+            if let Err(e) = send_render(dmd, &img) {
+                break Err(e);
+            }
+        };
+        finished.cancel();
+        result
+    });
+
+    if let Ok(Some(mut child)) = child.into_inner() {
+        kill_command(&mut child);
+        let _ = child.wait();
+    }
+    result
+}
@@ -0,0 +1,74 @@
+use crate::cancel::CancelToken;
+use inotify::{Inotify, WatchMask};
+use std::{ffi::OsString, io::ErrorKind, path::Path, time::Duration};
+
+// the writes of a file closer than this are a single change
+const DEBOUNCE: Duration = Duration::from_millis(200);
+// how often the file is checked while waiting for a change
+const WAIT_POLL: Duration = Duration::from_millis(100);
+
+// a file replaced or written by another program. the directory is watched, so that a file
+// replaced by a rename (like most editors and scripts do) is still followed
+pub struct FileWatcher {
+    inotify: Inotify,
+    name: OsString,
+    buffer: [u8; 4096],
+}
+
+impl FileWatcher {
+    pub fn new(path: &str) -> Result<FileWatcher, String> {
+        let path = Path::new(path);
+        let name = match path.file_name() {
+            Some(x) => x.to_os_string(),
+            None => return Err(format!("Unable to watch {}", path.display())),
+        };
+        let dir = match path.parent() {
+            Some(x) if !x.as_os_str().is_empty() => x,
+            _ => Path::new("."),
+        };
+        let inotify = match Inotify::init() {
+            Ok(x) => x,
+            Err(e) => return Err(format!("Unable to watch {}: {}", path.display(), e)),
+        };
+        if let Err(e) = inotify
+            .watches()
+            .add(dir, WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO)
+        {
+            return Err(format!("Unable to watch {}: {}", dir.display(), e));
+        }
+        Ok(FileWatcher {
+            inotify,
+            name,
+            buffer: [0; 4096],
+        })
+    }
+
+    // whether the file was written since the last call, without waiting
+    pub fn changed(&mut self) -> Result<bool, String> {
+        match self.inotify.read_events(&mut self.buffer) {
+            Ok(events) => Ok(events
+                .into_iter()
+                .any(|x| x.name == Some(self.name.as_os_str()))),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    // wait for the next change of the file, once it has not been written for a while.
+    // false when the playing is cancelled meanwhile
+    pub fn wait(&mut self, cancel: &CancelToken) -> Result<bool, String> {
+        while !self.changed()? {
+            if cancel.sleep(WAIT_POLL) {
+                return Ok(false);
+            }
+        }
+        loop {
+            if cancel.sleep(DEBOUNCE) {
+                return Ok(false);
+            }
+            if !self.changed()? {
+                return Ok(true);
+            }
+        }
+    }
+}